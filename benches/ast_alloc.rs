@@ -0,0 +1,61 @@
+//! Heap allocations made while parsing.
+//!
+//! Expression operands live in the program's `ExprArena` rather than in a
+//! `Box` each, so parsing a long expression allocates a few times as the
+//! arena grows instead of once per node. Run with `cargo bench --bench
+//! ast_alloc`; the allocation counts are printed before the timings.
+
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+use edust::parser::Parser;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The system allocator, counting every allocation and reallocation
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+    
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+    
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn expression_allocations(c: &mut Criterion) {
+    // Only operators and literals, so every node other than the root is an
+    // operand and nothing else (argument lists, statements) allocates
+    let term = "1 + 2 * 3 ** 2 - -4 / 5 % 3 as i8 < 6 && 7 >= 8 || !9 != 10 == 11";
+    let expr = vec![term; 200].join(" + ");
+    let source = format!("func main() {{ return {}; }}", expr);
+    let tokens = edust::tokenize(&source).unwrap();
+    
+    let input = tokens.clone();
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let program = Parser::new(input).parse().unwrap();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    
+    // Boxing each operand would take one allocation per arena node
+    let nodes = program.exprs.len();
+    println!("parse_arena: {} operand nodes, {} allocations", nodes, allocations);
+    assert!(allocations < nodes / 10, "{} allocations for {} nodes", allocations, nodes);
+    
+    c.bench_function("parse_arena", |b| {
+        b.iter(|| Parser::new(black_box(tokens.clone())).parse().unwrap())
+    });
+}
+
+criterion_group!(benches, expression_allocations);
+criterion_main!(benches);
@@ -0,0 +1,130 @@
+//! Benchmarks for each stage of the compilation pipeline.
+//!
+//! Run with `cargo bench`; pass a filter such as `cargo bench parse` to run
+//! a single group.
+
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+use edust::codegen::CodeGenerator;
+use edust::parser::Parser;
+use edust::semantic::SemanticAnalyzer;
+
+/// A medium-sized program exercising every statement form and most operators
+const PROGRAM: &str = r#"
+func gcd(a, b) {
+    while b != 0 {
+        let t = b;
+        b = a % b;
+        a = t;
+    }
+    return a;
+}
+
+func fib(n) {
+    if n <= 1 {
+        return n;
+    }
+    return fib(n - 1) + fib(n - 2);
+}
+
+func is_prime(n) {
+    if n < 2 {
+        return 0;
+    }
+    let d = 2;
+    while d * d <= n {
+        if n % d == 0 {
+            return 0;
+        }
+        d = d + 1;
+    }
+    return 1;
+}
+
+func collatz(n) {
+    let steps = 0;
+    while n != 1 {
+        if n % 2 == 0 {
+            n = n / 2;
+        } else {
+            n = 3 * n + 1;
+        }
+        steps = steps + 1;
+    }
+    return steps;
+}
+
+func checksum(x) {
+    let h = x as u32;
+    h = (h * 31 + 7) % 1000003;
+    h = h ** 2 % 65521;
+    return (h as i16) + abs(-h) - min(h, 3) + max(h, 5);
+}
+
+func sum_to(n, acc) {
+    if n == 0 {
+        return acc;
+    }
+    return sum_to(n - 1, acc + n);
+}
+
+func main() {
+    let total = 0;
+    let i = 1;
+    while i <= 50 {
+        if is_prime(i) && !(i == 2) || i == 1 {
+            total = total + collatz(i);
+        } else {
+            total = total - gcd(i, 36);
+        }
+        let j = 0;
+        do {
+            total = total + checksum(i * j);
+            j = j + 1;
+            if j > 3 {
+                break;
+            }
+        } while j < 10;
+        i = i + 1;
+    }
+    return total + fib(15) + sum_to(1000, 0);
+}
+"#;
+
+fn pipeline_stages(c: &mut Criterion) {
+    let tokens = edust::tokenize(PROGRAM).unwrap();
+    let ast = edust::parse(PROGRAM).unwrap();
+    
+    c.bench_function("tokenize", |b| b.iter(|| edust::tokenize(black_box(PROGRAM)).unwrap()));
+    
+    c.bench_function("parse", |b| {
+        b.iter(|| Parser::new(black_box(tokens.clone())).parse().unwrap())
+    });
+    
+    c.bench_function("analyze", |b| {
+        b.iter(|| SemanticAnalyzer::new().analyze(black_box(&ast)).unwrap())
+    });
+    
+    // One generator is reused, as each compile starts a fresh module
+    let mut codegen = CodeGenerator::new();
+    c.bench_function("compile", |b| b.iter(|| codegen.compile(black_box(&ast)).unwrap()));
+    
+    c.bench_function("compile_and_run", |b| {
+        b.iter(|| edust::compile_and_run(black_box(PROGRAM)).unwrap())
+    });
+}
+
+fn expression_parsing(c: &mut Criterion) {
+    // One long expression mixing every precedence level, to isolate the
+    // expression parser from statement handling
+    let term = "a + b * c ** 2 ** d - -e / f % 3 as i8 < g && h >= i || !j != k == l";
+    let expr = vec![term; 50].join(" + ");
+    let source = format!("func main() {{ return {}; }}", expr);
+    let tokens = edust::tokenize(&source).unwrap();
+    
+    c.bench_function("parse_precedence", |b| {
+        b.iter(|| Parser::new(black_box(tokens.clone())).parse().unwrap())
+    });
+}
+
+criterion_group!(benches, pipeline_stages, expression_parsing);
+criterion_main!(benches);
@@ -0,0 +1,18 @@
+//! Feeds arbitrary input to the lexer and parser. Both must reject bad
+//! programs with an `Err`, never a panic.
+//!
+//! Run with `cargo +nightly fuzz run front_end` from the repository root.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Source files are UTF-8; anything else never reaches the lexer
+    let Ok(source) = std::str::from_utf8(data) else {
+        return;
+    };
+    
+    let _ = edust::tokenize(source);
+    let _ = edust::parse(source);
+});
@@ -1,68 +1,303 @@
+pub use crate::symbol::Symbol;
+use std::sync::Arc;
+
 /// Abstract Syntax Tree node definitions for Edust
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Program {
     pub functions: Vec<Function>,
+    
+    /// `import "path";` directives, resolved by `edust::load_program`
+    pub imports: Vec<Import>,
+    
+    /// Operands of every expression in the program. Shared, so analysis
+    /// and code generation can keep hold of it cheaply; passes that add
+    /// nodes use `Arc::make_mut`.
+    pub exprs: Arc<ExprArena>,
+}
+
+/// Index of an expression node in its program's `ExprArena`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ExprId(u32);
+
+/// Storage for sub-expressions. An `Expr` refers to its operands by
+/// `ExprId` rather than owning them in boxes, so a program's expressions
+/// sit together in one growing `Vec` instead of an allocation per node.
+#[derive(Debug, Clone, Default)]
+pub struct ExprArena {
+    nodes: Vec<Expr>,
+}
+
+impl ExprArena {
+    pub const fn new() -> Self {
+        ExprArena { nodes: Vec::new() }
+    }
+    
+    /// An arena with no nodes, for holders that have no program yet
+    pub fn empty() -> &'static ExprArena {
+        static EMPTY: ExprArena = ExprArena::new();
+        &EMPTY
+    }
+    
+    /// Stores `expr` and returns its id
+    pub fn alloc(&mut self, expr: Expr) -> ExprId {
+        let id = u32::try_from(self.nodes.len()).expect("more than u32::MAX expressions");
+        self.nodes.push(expr);
+        ExprId(id)
+    }
+    
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+    
+    /// Drops every node allocated after the first `len`, whose ids become
+    /// invalid. Used when the parser backtracks.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.nodes.truncate(len);
+    }
+}
+
+impl std::ops::Index<ExprId> for ExprArena {
+    type Output = Expr;
+    
+    fn index(&self, id: ExprId) -> &Expr {
+        &self.nodes[id.0 as usize]
+    }
+}
+
+impl std::ops::IndexMut<ExprId> for ExprArena {
+    fn index_mut(&mut self, id: ExprId) -> &mut Expr {
+        &mut self.nodes[id.0 as usize]
+    }
+}
+
+/// A top-level `import "path";`. The path is relative to the importing file.
+#[derive(Debug, Clone)]
+pub struct Import {
+    pub path: String,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct Function {
-    pub name: String,
-    pub params: Vec<String>,
+    pub name: Symbol,
+    pub params: Vec<Symbol>,
+    
+    /// Location of each parameter's name
+    pub param_spans: Vec<Span>,
+    
+    /// Default value of each parameter, if it has one. Calls may leave out
+    /// trailing parameters with defaults, which are then evaluated at the
+    /// call site.
+    pub defaults: Vec<Option<Expr>>,
+    
+    /// Name of the trailing `name...` parameter, if the function takes any
+    /// number of further arguments. The body reads them with `args_count()`
+    /// and `args_get(i)`.
+    pub variadic: Option<Symbol>,
     pub body: Block,
+    
+    /// Location of the `func` keyword
+    pub span: Span,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Block {
     pub statements: Vec<Statement>,
+    
+    /// A final expression without a `;`. In a function body it is the
+    /// function's return value.
+    pub tail: Option<Expr>,
 }
 
 #[derive(Debug, Clone)]
 pub enum Statement {
+    /// `let name = value;`, or `let name: u64 = value;` for a variable
+    /// whose division, remainder and comparisons are unsigned. `let name;`
+    /// has no value, and must be assigned on every path before it is read.
     VarDecl {
-        name: String,
+        name: Symbol,
+        ty: Option<Type>,
+        value: Option<Expr>,
+        span: Span,
+    },
+    /// `let (a, b) = [1, 2];` binds each name to the matching element
+    DestructureDecl {
+        names: Vec<Symbol>,
         value: Expr,
+        span: Span,
+    },
+    /// `static n = 0;` declares a variable that keeps its value across
+    /// calls. The constant initializer is applied once, before the program
+    /// runs.
+    StaticDecl {
+        name: Symbol,
+        value: Expr,
+        span: Span,
     },
     Assignment {
-        name: String,
+        name: Symbol,
         value: Expr,
+        span: Span,
+    },
+    /// `a, b = b, a` evaluates every value, left to right, before
+    /// assigning any of the names
+    MultiAssign {
+        names: Vec<Symbol>,
+        values: Vec<Expr>,
+        span: Span,
     },
     If {
         condition: Expr,
         then_block: Block,
         else_block: Option<Block>,
+        span: Span,
     },
+    /// `label` names the loop for `break label;` and `continue label;`
     While {
+        label: Option<Symbol>,
         condition: Expr,
         body: Block,
+        span: Span,
+    },
+    /// `for var in start..end { body }`: runs `body` with `var` set to
+    /// `start`, then adding 1 to it until it reaches `end`, which is
+    /// evaluated once. `iter` is always an `Expr::Range`.
+    ForIn {
+        label: Option<Symbol>,
+        var: Symbol,
+        iter: Expr,
+        body: Block,
+        span: Span,
+    },
+    /// Runs `body` once before testing `condition`
+    DoWhile {
+        label: Option<Symbol>,
+        body: Block,
+        condition: Expr,
+        span: Span,
     },
     Return {
         value: Expr,
+        span: Span,
+    },
+    /// Leaves the innermost enclosing loop, or the one named `label`. A
+    /// `loop` expression takes `value` as its result.
+    Break {
+        label: Option<Symbol>,
+        value: Option<Expr>,
+        span: Span,
+    },
+    /// Skips to the next iteration of the innermost enclosing loop, or of
+    /// the one named `label`
+    Continue {
+        label: Option<Symbol>,
+        span: Span,
     },
     ExprStmt {
         expr: Expr,
+        span: Span,
+    },
+    /// A function declared inside another function's body. It is hoisted
+    /// into the global function table and cannot see the enclosing locals.
+    FuncDecl {
+        func: Function,
+        span: Span,
     },
 }
 
 #[derive(Debug, Clone)]
 pub enum Expr {
     Number(i64),
-    Variable(String),
+    /// `"text"`: a string, which can be stored, joined with `+` and printed
+    Str(String),
+    Variable {
+        name: Symbol,
+        span: Span,
+    },
     Binary {
         op: BinOp,
-        left: Box<Expr>,
-        right: Box<Expr>,
+        left: ExprId,
+        right: ExprId,
     },
     Unary {
         op: UnaryOp,
-        operand: Box<Expr>,
+        operand: ExprId,
     },
     Call {
-        name: String,
+        name: Symbol,
         args: Vec<Expr>,
+        span: Span,
+    },
+    Cast {
+        expr: ExprId,
+        target: Type,
+    },
+    /// `[a, b, ...]`, only allowed as the value of a destructuring `let`
+    Array(Vec<Expr>),
+    /// `start..end`, the numbers from `start` up to but not including
+    /// `end`, only allowed after `in` in a `for` loop
+    Range {
+        start: ExprId,
+        end: ExprId,
+    },
+    /// `|x, y| body`: a function value that copies the enclosing variables
+    /// its body uses when it is created
+    Closure {
+        params: Vec<Symbol>,
+        param_spans: Vec<Span>,
+        body: ExprId,
+        span: Span,
+    },
+    /// `loop { ... }`: runs `body` until a `break`, whose value is the
+    /// loop's value
+    Loop {
+        label: Option<Symbol>,
+        body: Box<Block>,
+        span: Span,
+    },
+    /// `name = value` inside an expression, as in `a = b = 0`: assigns
+    /// the variable and yields the value assigned
+    Assign {
+        name: Symbol,
+        value: ExprId,
+        span: Span,
+    },
+    /// `if cond { a } else { b }` inside an expression: `a` if `cond` is
+    /// nonzero, otherwise `b`. Only the branch taken is evaluated.
+    If {
+        cond: ExprId,
+        then_val: ExprId,
+        else_val: ExprId,
     },
 }
 
+/// Source location of a node, taken from its first token
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Integer types that a value can be converted to with `as`.
+/// Values are always held in 64 bits; a cast wraps the value to the
+/// target width and sign- or zero-extends it back.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Type {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BinOp {
     // Arithmetic
@@ -71,6 +306,7 @@ pub enum BinOp {
     Mul,
     Div,
     Mod,
+    Pow,
     
     // Comparison
     Lt,
@@ -91,26 +327,508 @@ pub enum UnaryOp {
     Not,   // !
 }
 
+impl Type {
+    pub fn from_name(name: &str) -> Option<Type> {
+        match name {
+            "i8" => Some(Type::I8),
+            "i16" => Some(Type::I16),
+            "i32" => Some(Type::I32),
+            "i64" => Some(Type::I64),
+            "u8" => Some(Type::U8),
+            "u16" => Some(Type::U16),
+            "u32" => Some(Type::U32),
+            "u64" => Some(Type::U64),
+            _ => None,
+        }
+    }
+    
+    pub fn name(&self) -> &'static str {
+        match self {
+            Type::I8 => "i8",
+            Type::I16 => "i16",
+            Type::I32 => "i32",
+            Type::I64 => "i64",
+            Type::U8 => "u8",
+            Type::U16 => "u16",
+            Type::U32 => "u32",
+            Type::U64 => "u64",
+        }
+    }
+    
+    pub fn bits(&self) -> u32 {
+        match self {
+            Type::I8 | Type::U8 => 8,
+            Type::I16 | Type::U16 => 16,
+            Type::I32 | Type::U32 => 32,
+            Type::I64 | Type::U64 => 64,
+        }
+    }
+    
+    pub fn is_signed(&self) -> bool {
+        matches!(self, Type::I8 | Type::I16 | Type::I32 | Type::I64)
+    }
+}
+
+impl BinOp {
+    /// Whether the operator does arithmetic, rather than comparing or
+    /// combining truth values
+    pub fn is_arithmetic(self) -> bool {
+        matches!(self, BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Mod | BinOp::Pow)
+    }
+    
+    /// Apply the operator to two values, with the semantics of compiled
+    /// code. Returns `None` for division by zero or `i64::MIN / -1`, which
+    /// trap at run time; `i64::MIN % -1` is 0, as `srem` gives.
+    pub fn apply(self, lhs: i64, rhs: i64) -> Option<i64> {
+        if matches!(self, BinOp::Div | BinOp::Mod) && rhs == 0 {
+            return None;
+        }
+        
+        let result = match self {
+            BinOp::Add => lhs.wrapping_add(rhs),
+            BinOp::Sub => lhs.wrapping_sub(rhs),
+            BinOp::Mul => lhs.wrapping_mul(rhs),
+            BinOp::Div => lhs.checked_div(rhs)?,
+            BinOp::Mod => lhs.wrapping_rem(rhs),
+            BinOp::Pow => crate::runtime::edust_ipow(lhs, rhs),
+            
+            BinOp::Lt => (lhs < rhs) as i64,
+            BinOp::Le => (lhs <= rhs) as i64,
+            BinOp::Gt => (lhs > rhs) as i64,
+            BinOp::Ge => (lhs >= rhs) as i64,
+            BinOp::Eq => (lhs == rhs) as i64,
+            BinOp::Ne => (lhs != rhs) as i64,
+            
+            BinOp::And => (lhs != 0 && rhs != 0) as i64,
+            BinOp::Or => (lhs != 0 || rhs != 0) as i64,
+        };
+        
+        Some(result)
+    }
+    
+    /// Like `apply`, but treating both values as `u64`. Division, remainder
+    /// and the ordering comparisons differ; the other operators give the
+    /// same bits either way.
+    pub fn apply_unsigned(self, lhs: i64, rhs: i64) -> Option<i64> {
+        let (a, b) = (lhs as u64, rhs as u64);
+        let result = match self {
+            BinOp::Div => a.checked_div(b)? as i64,
+            BinOp::Mod => a.checked_rem(b)? as i64,
+            BinOp::Lt => (a < b) as i64,
+            BinOp::Le => (a <= b) as i64,
+            BinOp::Gt => (a > b) as i64,
+            BinOp::Ge => (a >= b) as i64,
+            _ => return self.apply(lhs, rhs),
+        };
+        
+        Some(result)
+    }
+}
+
+impl UnaryOp {
+    pub fn apply(self, val: i64) -> i64 {
+        match self {
+            UnaryOp::Neg => val.wrapping_neg(),
+            UnaryOp::Not => (val == 0) as i64,
+        }
+    }
+}
+
+impl Type {
+    /// Wrap `val` to this type's width and extend it back to 64 bits
+    pub fn wrap(self, val: i64) -> i64 {
+        match self {
+            Type::I8 => val as i8 as i64,
+            Type::I16 => val as i16 as i64,
+            Type::I32 => val as i32 as i64,
+            Type::U8 => val as u8 as i64,
+            Type::U16 => val as u16 as i64,
+            Type::U32 => val as u32 as i64,
+            Type::I64 | Type::U64 => val,
+        }
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
 impl Program {
     pub fn new() -> Self {
         Program {
             functions: Vec::new(),
+            imports: Vec::new(),
+            exprs: Arc::new(ExprArena::new()),
         }
     }
     
     pub fn add_function(&mut self, func: Function) {
         self.functions.push(func);
     }
+    
+    /// All functions in the program, including ones nested inside other
+    /// function bodies, in source order
+    pub fn all_functions(&self) -> Vec<&Function> {
+        let mut functions = Vec::new();
+        for func in &self.functions {
+            func.collect_functions(&mut functions);
+        }
+        functions
+    }
+}
+
+impl Function {
+    fn collect_functions<'a>(&'a self, out: &mut Vec<&'a Function>) {
+        out.push(self);
+        self.body.collect_nested_functions(out);
+    }
+}
+
+impl Block {
+    fn collect_nested_functions<'a>(&'a self, out: &mut Vec<&'a Function>) {
+        for stmt in &self.statements {
+            match stmt {
+                Statement::FuncDecl { func, .. } => func.collect_functions(out),
+                Statement::If {
+                    then_block,
+                    else_block,
+                    ..
+                } => {
+                    then_block.collect_nested_functions(out);
+                    if let Some(else_blk) = else_block {
+                        else_blk.collect_nested_functions(out);
+                    }
+                }
+                Statement::While { body, .. }
+                | Statement::DoWhile { body, .. }
+                | Statement::ForIn { body, .. } => body.collect_nested_functions(out),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Expr {
+    /// The binary operators and casts down the left side of this
+    /// expression, outermost first, and the operand below the last of
+    /// them. `a + b + c` nests one level per operator, so passes walk these
+    /// chains with a loop rather than recursing down thousands of levels.
+    pub fn left_spine<'a>(&'a self, exprs: &'a ExprArena) -> (Vec<&'a Expr>, &'a Expr) {
+        let mut spine = Vec::new();
+        let mut bottom = self;
+        while let Some(inner) = bottom.left_operand() {
+            spine.push(bottom);
+            bottom = &exprs[inner];
+        }
+        (spine, bottom)
+    }
+    
+    /// The operands along the `left_spine` of this expression, in the
+    /// order they run: the one at the bottom, then the right operand of
+    /// each operator on the way up
+    pub fn spine_operands<'a>(&'a self, exprs: &'a ExprArena) -> Vec<&'a Expr> {
+        let (spine, bottom) = self.left_spine(exprs);
+        let rights = spine.into_iter().rev().filter_map(|node| match node {
+            Expr::Binary { right, .. } => Some(&exprs[*right]),
+            _ => None,
+        });
+        std::iter::once(bottom).chain(rights).collect()
+    }
+    
+    /// The left operand of a binary operator, or the value of a cast: the
+    /// operand that `left_spine` follows
+    pub fn left_operand(&self) -> Option<ExprId> {
+        match self {
+            Expr::Binary { left: inner, .. } | Expr::Cast { expr: inner, .. } => Some(*inner),
+            _ => None,
+        }
+    }
+    
+    /// Whether this expression's value is a `u64`: a variable for which
+    /// `is_unsigned_var` holds, a cast to `u64`, or arithmetic on unsigned
+    /// operands
+    pub fn is_unsigned(&self, exprs: &ExprArena, is_unsigned_var: &dyn Fn(Symbol) -> bool) -> bool {
+        // Arithmetic down the left of `a + b + c` is checked bottom-up
+        // rather than recursing once per operator
+        let mut spine = Vec::new();
+        let mut bottom = self;
+        while let Expr::Binary { op, left, right } = bottom
+            && op.is_arithmetic()
+        {
+            spine.push((bottom, &exprs[*right]));
+            bottom = &exprs[*left];
+        }
+        
+        let mut unsigned = match bottom {
+            Expr::Variable { name, .. } | Expr::Assign { name, .. } => is_unsigned_var(*name),
+            Expr::Cast { target, .. } => *target == Type::U64,
+            Expr::If { then_val, else_val, .. } => {
+                exprs[*then_val].is_unsigned(exprs, is_unsigned_var)
+                    && exprs[*else_val].is_unsigned(exprs, is_unsigned_var)
+            }
+            _ => false,
+        };
+        let mut left = bottom;
+        for (node, right) in spine.into_iter().rev() {
+            let right_unsigned = right.is_unsigned(exprs, is_unsigned_var);
+            unsigned = operands_fit_unsigned(left, unsigned, right, right_unsigned);
+            left = node;
+        }
+        unsigned
+    }
+    
+    /// Whether this expression's value is a string: a literal, a variable
+    /// for which `is_string_var` holds, `to_str(...)` or `+` of strings.
+    /// Semantic analysis makes sure both operands of `+` agree.
+    pub fn is_string(&self, exprs: &ExprArena, is_string_var: &dyn Fn(Symbol) -> bool) -> bool {
+        let mut expr = self;
+        // Looks down the left of `a + b + c` without recursing
+        while let Expr::Binary { op: BinOp::Add, left, .. } = expr {
+            expr = &exprs[*left];
+        }
+        match expr {
+            Expr::Str(_) => true,
+            Expr::Variable { name, .. } | Expr::Assign { name, .. } => is_string_var(*name),
+            Expr::Call { name, .. } => name == "to_str",
+            // One branch may be `todo()`, which fits any type
+            Expr::If { then_val, else_val, .. } => {
+                exprs[*then_val].is_string(exprs, is_string_var)
+                    || exprs[*else_val].is_string(exprs, is_string_var)
+            }
+            _ => false,
+        }
+    }
+    
+    /// Names used in this expression that it does not bind itself, in
+    /// order of first use. Called names are included, so for a closure
+    /// body this is a superset of the variables it captures.
+    pub fn free_names(&self, exprs: &ExprArena) -> Vec<Symbol> {
+        let mut names = Vec::new();
+        self.collect_free_names(exprs, &mut Vec::new(), &mut names);
+        names
+    }
+    
+    fn collect_free_names(&self, exprs: &ExprArena, bound: &mut Vec<Symbol>, out: &mut Vec<Symbol>) {
+        fn add(name: Symbol, bound: &[Symbol], out: &mut Vec<Symbol>) {
+            if !bound.contains(&name) && !out.contains(&name) {
+                out.push(name);
+            }
+        }
+        
+        match self {
+            Expr::Number(_) | Expr::Str(_) => {}
+            Expr::Variable { name, .. } => add(*name, bound, out),
+            Expr::Binary { .. } | Expr::Cast { .. } => {
+                for operand in self.spine_operands(exprs) {
+                    operand.collect_free_names(exprs, bound, out);
+                }
+            }
+            Expr::Range { start, end } => {
+                exprs[*start].collect_free_names(exprs, bound, out);
+                exprs[*end].collect_free_names(exprs, bound, out);
+            }
+            Expr::Unary { operand, .. } => exprs[*operand].collect_free_names(exprs, bound, out),
+            Expr::Call { name, args, .. } => {
+                add(*name, bound, out);
+                for arg in args {
+                    arg.collect_free_names(exprs, bound, out);
+                }
+            }
+            Expr::Array(elements) => {
+                for element in elements {
+                    element.collect_free_names(exprs, bound, out);
+                }
+            }
+            Expr::Closure { params, body, .. } => {
+                let outer = bound.len();
+                bound.extend(params);
+                exprs[*body].collect_free_names(exprs, bound, out);
+                bound.truncate(outer);
+            }
+            Expr::Loop { body, .. } => body.collect_free_names(exprs, bound, out),
+            Expr::Assign { name, value, .. } => {
+                add(*name, bound, out);
+                exprs[*value].collect_free_names(exprs, bound, out);
+            }
+            Expr::If { cond, then_val, else_val } => {
+                for id in [cond, then_val, else_val] {
+                    exprs[*id].collect_free_names(exprs, bound, out);
+                }
+            }
+        }
+    }
+}
+
+impl Block {
+    /// Free names of the statements of a `loop` expression's body; the
+    /// variables it declares are bound until the end of the block
+    fn collect_free_names(&self, exprs: &ExprArena, bound: &mut Vec<Symbol>, out: &mut Vec<Symbol>) {
+        let outer = bound.len();
+        for stmt in &self.statements {
+            match stmt {
+                Statement::VarDecl { name, value, .. } => {
+                    if let Some(value) = value {
+                        value.collect_free_names(exprs, bound, out);
+                    }
+                    bound.push(*name);
+                }
+                Statement::StaticDecl { name, value, .. } => {
+                    value.collect_free_names(exprs, bound, out);
+                    bound.push(*name);
+                }
+                Statement::DestructureDecl { names, value, .. } => {
+                    value.collect_free_names(exprs, bound, out);
+                    bound.extend(names);
+                }
+                Statement::Assignment { name, value, span } => {
+                    Expr::Variable { name: *name, span: *span }.collect_free_names(exprs, bound, out);
+                    value.collect_free_names(exprs, bound, out);
+                }
+                Statement::MultiAssign { names, values, span } => {
+                    for (name, value) in names.iter().zip(values) {
+                        Expr::Variable { name: *name, span: *span }.collect_free_names(exprs, bound, out);
+                        value.collect_free_names(exprs, bound, out);
+                    }
+                }
+                Statement::If {
+                    condition,
+                    then_block,
+                    else_block,
+                    ..
+                } => {
+                    condition.collect_free_names(exprs, bound, out);
+                    then_block.collect_free_names(exprs, bound, out);
+                    if let Some(else_blk) = else_block {
+                        else_blk.collect_free_names(exprs, bound, out);
+                    }
+                }
+                Statement::While { condition, body, .. } | Statement::DoWhile { condition, body, .. } => {
+                    condition.collect_free_names(exprs, bound, out);
+                    body.collect_free_names(exprs, bound, out);
+                }
+                Statement::ForIn { var, iter, body, .. } => {
+                    iter.collect_free_names(exprs, bound, out);
+                    bound.push(*var);
+                    body.collect_free_names(exprs, bound, out);
+                    bound.pop();
+                }
+                Statement::Return { value, .. } | Statement::Break { value: Some(value), .. } => {
+                    value.collect_free_names(exprs, bound, out)
+                }
+                Statement::ExprStmt { expr, .. } => expr.collect_free_names(exprs, bound, out),
+                Statement::Break { value: None, .. } | Statement::Continue { .. } | Statement::FuncDecl { .. } => {}
+            }
+        }
+        if let Some(tail) = &self.tail {
+            tail.collect_free_names(exprs, bound, out);
+        }
+        bound.truncate(outer);
+    }
+}
+
+impl Statement {
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::VarDecl { span, .. }
+            | Statement::DestructureDecl { span, .. }
+            | Statement::StaticDecl { span, .. }
+            | Statement::Assignment { span, .. }
+            | Statement::MultiAssign { span, .. }
+            | Statement::If { span, .. }
+            | Statement::While { span, .. }
+            | Statement::DoWhile { span, .. }
+            | Statement::ForIn { span, .. }
+            | Statement::Return { span, .. }
+            | Statement::Break { span, .. }
+            | Statement::Continue { span, .. }
+            | Statement::ExprStmt { span, .. }
+            | Statement::FuncDecl { span, .. } => *span,
+        }
+    }
 }
 
 impl Block {
     pub fn new() -> Self {
         Block {
             statements: Vec::new(),
+            tail: None,
         }
     }
     
     pub fn add_statement(&mut self, stmt: Statement) {
         self.statements.push(stmt);
     }
-}
\ No newline at end of file
+}
+/// Whether an operator applied to `left` and `right` works on `u64`
+/// values: one operand is unsigned and the other is too, or is a
+/// non-negative number, which means the same either way
+pub fn unsigned_operands(
+    exprs: &ExprArena,
+    left: &Expr,
+    right: &Expr,
+    is_unsigned_var: &dyn Fn(Symbol) -> bool,
+) -> bool {
+    let left_unsigned = left.is_unsigned(exprs, is_unsigned_var);
+    let right_unsigned = right.is_unsigned(exprs, is_unsigned_var);
+    operands_fit_unsigned(left, left_unsigned, right, right_unsigned)
+}
+
+/// The left operand of each operator met while walking a `left_spine` from
+/// the bottom up, with what `is_unsigned` and `is_string` say about it.
+/// Both look down the whole spine, so asking them afresh at every level of
+/// `a + b + c + ...` would take quadratic time.
+pub struct LeftOperand<'a> {
+    expr: &'a Expr,
+    unsigned: bool,
+    string: bool,
+}
+
+impl<'a> LeftOperand<'a> {
+    pub fn new(
+        bottom: &'a Expr,
+        exprs: &ExprArena,
+        is_unsigned_var: &dyn Fn(Symbol) -> bool,
+        is_string_var: &dyn Fn(Symbol) -> bool,
+    ) -> Self {
+        LeftOperand {
+            expr: bottom,
+            unsigned: bottom.is_unsigned(exprs, is_unsigned_var),
+            string: bottom.is_string(exprs, is_string_var),
+        }
+    }
+    
+    /// Moves up to `node`, the next operator or cast up the spine, and
+    /// returns whether its operands are `u64` values, as `unsigned_operands`
+    /// decides, and whether it joins strings
+    pub fn climb(
+        &mut self,
+        node: &'a Expr,
+        exprs: &ExprArena,
+        is_unsigned_var: &dyn Fn(Symbol) -> bool,
+    ) -> (bool, bool) {
+        let (unsigned, concat) = match node {
+            Expr::Binary { op, right, .. } => {
+                let right = &exprs[*right];
+                let right_unsigned = right.is_unsigned(exprs, is_unsigned_var);
+                let unsigned = operands_fit_unsigned(self.expr, self.unsigned, right, right_unsigned);
+                (unsigned, *op == BinOp::Add && self.string)
+            }
+            _ => (false, false),
+        };
+        self.unsigned = match node {
+            Expr::Binary { op, .. } => op.is_arithmetic() && unsigned,
+            _ => node.is_unsigned(exprs, is_unsigned_var),
+        };
+        self.string = concat;
+        self.expr = node;
+        (unsigned, concat)
+    }
+}
+
+/// `unsigned_operands`, given whether each operand is a `u64`
+fn operands_fit_unsigned(left: &Expr, left_unsigned: bool, right: &Expr, right_unsigned: bool) -> bool {
+    let fits = |expr: &Expr, unsigned: bool| unsigned || matches!(expr, Expr::Number(n) if *n >= 0);
+    (left_unsigned || right_unsigned) && fits(left, left_unsigned) && fits(right, right_unsigned)
+}
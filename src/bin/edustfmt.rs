@@ -0,0 +1,28 @@
+use edust::format::format_source;
+use std::env;
+use std::fs;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    
+    if args.len() < 2 {
+        eprintln!("Usage: edustfmt <source-file>");
+        std::process::exit(1);
+    }
+    
+    let filename = &args[1];
+    
+    let source = fs::read_to_string(filename)
+        .unwrap_or_else(|e| {
+            eprintln!("Error reading file {}: {}", filename, e);
+            std::process::exit(1);
+        });
+    
+    match format_source(&source) {
+        Ok(formatted) => print!("{}", formatted),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -1,73 +1,767 @@
-use crate::ast;
+use crate::ast::{self, Symbol};
+use crate::debuginfo::{self, FunctionLines};
+use crate::optimize::eval_const_expr;
+use cranelift::codegen::control::ControlPlane;
+use cranelift::codegen::entity::{EntitySet, SecondaryMap};
+use cranelift::codegen::isa::{self, OwnedTargetIsa};
+use cranelift::codegen::write::{FuncWriter, PlainWriter, decorate_function, write_block_header};
 use cranelift::prelude::*;
 use cranelift_jit::{JITBuilder, JITModule};
-use cranelift_module::{FuncId, Linkage, Module};
-use std::collections::HashMap;
+use cranelift_module::{DataDescription, DataId, FuncId, Linkage, Module};
+use cranelift_object::{ObjectBuilder, ObjectModule};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::mem::ManuallyDrop;
+use std::ops::{Deref, DerefMut};
+use std::path::PathBuf;
+use std::sync::Arc;
+use target_lexicon::Triple;
+
+/// Trap code placed after calls to runtime functions that never return
+const UNREACHABLE_TRAP: TrapCode = TrapCode::unwrap_user(1);
+
+// A closure value points to its environment: the address of its code, its
+// parameter count, then each captured value, 8 bytes apiece
+const CLOSURE_CODE: i32 = 0;
+const CLOSURE_ARITY: i32 = 8;
+const CLOSURE_CAPTURES: i32 = 16;
+
+// Variables holding the variadic arguments of the function being compiled:
+// the address of their buffer and their count. Source names can't start
+// with a dot.
+const VARARGS: &str = ".varargs";
+const VARARGS_COUNT: &str = ".varargs.count";
 
 pub struct CodeGenerator {
+    // Target ISA, built once and shared by every module this generator creates
+    isa: OwnedTargetIsa,
+    
     builder_context: FunctionBuilderContext,
     ctx: codegen::Context,
-    module: JITModule,
+    module: Backend,
     
     // Function ID mappings
-    functions: HashMap<String, FuncId>,
+    functions: HashMap<Symbol, FuncId>,
     
-    // Variable mappings (stack slots) per function
-    variables: HashMap<String, Variable>,
+    // Default parameter values of each function, compiled into every call
+    // that leaves them out
+    defaults: HashMap<Symbol, Vec<Option<ast::Expr>>>,
+    
+    // Functions taking variadic arguments, which are passed as a pointer to
+    // a stack buffer holding them followed by their count
+    variadic: HashSet<Symbol>,
+    
+    // Variables of the function being compiled, one map per enclosing
+    // block (innermost last), mirroring the semantic analyzer's scopes
+    scopes: Vec<HashMap<Symbol, Binding>>,
     variable_counter: usize,
+    
+    // Variables of the function being compiled that were declared `u64`,
+    // and those holding strings
+    unsigned_vars: EntitySet<Variable>,
+    string_vars: EntitySet<Variable>,
+    
+    // Number of `static` cells declared in the current module
+    static_counter: usize,
+    
+    // Read-only data object of each distinct string literal in the module
+    string_literals: HashMap<String, DataId>,
+    
+    // Where self tail calls in the current function jump to, if it has any
+    tail_call: Option<TailCallTarget>,
+    
+    // Enclosing loops of the statement being compiled, innermost last
+    loops: Vec<LoopTarget>,
+    
+    // Abort once calls nest deeper than this; unchecked when `None`
+    recursion_limit: Option<u64>,
+    
+    // Run Cranelift on several functions at once
+    parallel: bool,
+    
+    // Source file that object files get DWARF line info for, if any, and
+    // the lines of each function compiled so far
+    debug_source: Option<PathBuf>,
+    line_tables: Vec<FunctionLines>,
+    
+    // Names of the blocks and values of the function being built
+    ir_names: IrNames,
+    
+    // Closure bodies met while compiling, to be compiled as functions of
+    // their own once the current function is done
+    pending_closures: Vec<PendingClosure>,
+    closure_counter: usize,
+    
+    // Expression nodes of the program being compiled
+    exprs: Arc<ast::ExprArena>,
+}
+
+/// The functions of a program compiled by `CodeGenerator::compile` or
+/// `compile_all`, callable from Rust by name. Every function takes and
+/// returns `i64`s.
+///
+/// The program owns the `JITModule` holding its code, so the generator
+/// that compiled it can go on to other programs, or be dropped, while it
+/// still runs. Dropping the program frees the code: addresses from
+/// `get_function_ptr` must not be called after that.
+pub struct JitProgram {
+    functions: HashMap<Symbol, JitFunction>,
+    module: ManuallyDrop<JITModule>,
+}
+
+struct JitFunction {
+    code: *const u8,
+    arity: usize,
+    variadic: bool,
+}
+
+impl JitProgram {
+    /// Most arguments `call` can pass
+    pub const MAX_CALL_ARGS: usize = 6;
+    
+    /// The address of the function `name`, to `transmute` to an
+    /// `extern "C" fn(i64, ...) -> i64` taking `arity(name)` arguments. A
+    /// variadic function takes two more: the address of an array holding
+    /// the further arguments, and their count. The address is only valid
+    /// while `self` is alive.
+    pub fn get_function_ptr(&self, name: &str) -> Option<*const u8> {
+        self.functions.get(&Symbol::intern(name)).map(|func| func.code)
+    }
+    
+    /// The number of parameters of the function `name`, not counting a
+    /// variadic one
+    pub fn arity(&self, name: &str) -> Option<usize> {
+        self.functions.get(&Symbol::intern(name)).map(|func| func.arity)
+    }
+    
+    /// Runs `main`, passing it the command-line argument count if it takes
+    /// a parameter, and returns its result. Can be called any number of
+    /// times.
+    pub fn run(&self) -> Result<i64, String> {
+        self.run_with_argc(crate::runtime::argc())
+    }
+    
+    /// Runs `main` like `run`, passing it `argc` if it takes a parameter
+    pub fn run_with_argc(&self, argc: i64) -> Result<i64, String> {
+        match self.arity("main") {
+            None => Err("No main function found".to_string()),
+            Some(0) => self.call("main", &[]),
+            Some(_) => self.call("main", &[argc]),
+        }
+    }
+    
+    /// Calls the function `name` with `args`, checking that it exists and
+    /// takes that many arguments
+    pub fn call(&self, name: &str, args: &[i64]) -> Result<i64, String> {
+        let func = self
+            .functions
+            .get(&Symbol::intern(name))
+            .ok_or_else(|| format!("Undefined function: {}", name))?;
+        if func.variadic && args.len() < func.arity {
+            return Err(format!(
+                "Function {} expects at least {} arguments, got {}",
+                name,
+                func.arity,
+                args.len()
+            ));
+        }
+        if !func.variadic && args.len() != func.arity {
+            return Err(format!(
+                "Function {} expects {} arguments, got {}",
+                name,
+                func.arity,
+                args.len()
+            ));
+        }
+        
+        // Variadic arguments stay in `args`, which outlives the call
+        let mut machine_args = args[..func.arity].to_vec();
+        if func.variadic {
+            let extra = &args[func.arity..];
+            machine_args.push(extra.as_ptr() as i64);
+            machine_args.push(extra.len() as i64);
+        }
+        
+        // SAFETY: the code was compiled for this host with the platform's
+        // calling convention, taking `arity` i64s (plus the variadic buffer
+        // and count) and returning one. `self.module` keeps the code alive,
+        // as the borrow of `self` outlasts the call.
+        let code = func.code;
+        let result = unsafe {
+            use std::mem::transmute;
+            match *machine_args {
+                [] => {
+                    let f: extern "C" fn() -> i64 = transmute(code);
+                    f()
+                }
+                [a] => {
+                    let f: extern "C" fn(i64) -> i64 = transmute(code);
+                    f(a)
+                }
+                [a, b] => {
+                    let f: extern "C" fn(i64, i64) -> i64 = transmute(code);
+                    f(a, b)
+                }
+                [a, b, c] => {
+                    let f: extern "C" fn(i64, i64, i64) -> i64 = transmute(code);
+                    f(a, b, c)
+                }
+                [a, b, c, d] => {
+                    let f: extern "C" fn(i64, i64, i64, i64) -> i64 = transmute(code);
+                    f(a, b, c, d)
+                }
+                [a, b, c, d, e] => {
+                    let f: extern "C" fn(i64, i64, i64, i64, i64) -> i64 = transmute(code);
+                    f(a, b, c, d, e)
+                }
+                [a, b, c, d, e, g] => {
+                    let f: extern "C" fn(i64, i64, i64, i64, i64, i64) -> i64 = transmute(code);
+                    f(a, b, c, d, e, g)
+                }
+                _ => {
+                    return Err(format!(
+                        "Function {} takes {} arguments; call passes at most {}",
+                        name,
+                        machine_args.len(),
+                        Self::MAX_CALL_ARGS
+                    ));
+                }
+            }
+        };
+        Ok(result)
+    }
+}
+
+impl std::fmt::Debug for JitProgram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut names: Vec<&str> = self.functions.keys().map(|name| name.as_str()).collect();
+        names.sort_unstable();
+        f.debug_struct("JitProgram").field("functions", &names).finish_non_exhaustive()
+    }
+}
+
+impl Drop for JitProgram {
+    fn drop(&mut self) {
+        // SAFETY: nothing can be running the code, as `call` borrows
+        // `self`, and addresses from `get_function_ptr` are documented to
+        // die with it. The module is not touched again.
+        unsafe { ManuallyDrop::take(&mut self.module).free_memory() };
+    }
+}
+
+/// Where the value of a name in scope lives
+#[derive(Clone, Copy)]
+enum Binding {
+    Local(Variable),
+    
+    // A `static` variable: a data object in the module, shared by every call
+    Static(DataId),
+}
+
+/// A closure body compiled as a function taking its environment first
+struct PendingClosure {
+    func: ast::Function,
+    captures: Vec<Capture>,
+}
+
+/// A variable copied into a closure's environment
+#[derive(Clone, Copy)]
+struct Capture {
+    name: Symbol,
+    
+    // Declared `u64` or holding a string, which the closure body's copy
+    // does too
+    unsigned: bool,
+    string: bool,
+}
+
+/// Where compiled functions go: executable memory on the host, or an
+/// object file for any target Cranelift supports
+enum Backend {
+    Jit(JITModule),
+    Object(ObjectModule),
+}
+
+impl Deref for Backend {
+    type Target = dyn Module;
+    
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Backend::Jit(module) => module,
+            Backend::Object(module) => module,
+        }
+    }
+}
+
+impl DerefMut for Backend {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            Backend::Jit(module) => module,
+            Backend::Object(module) => module,
+        }
+    }
+}
+
+/// A self-recursive call in tail position is lowered to a jump back to
+/// `block` after rebinding the parameter variables
+#[derive(Clone)]
+struct TailCallTarget {
+    name: Symbol,
+    params: Vec<Variable>,
+    block: Block,
+}
+
+/// Source-derived names for the blocks and values of the function being
+/// built, which `compile_to_ir` prints as comments so the IR reads the same
+/// on every run
+#[derive(Default)]
+struct IrNames {
+    // Role of each block, numbered by construct: `then1`, `loop_header2`
+    blocks: HashMap<Block, String>,
+    
+    // Variables each value was stored in, in order
+    values: HashMap<Value, Vec<Symbol>>,
+    
+    // Constructs numbered so far
+    constructs: usize,
+}
+
+/// Prints IR like Cranelift's own writer, adding an `IrNames` comment to
+/// every named block header and every instruction whose result is stored
+/// in a variable
+struct IrWriter<'a> {
+    names: &'a IrNames,
+}
+
+impl IrWriter<'_> {
+    /// The variables `values` are stored in, as `x = v1, y = v2`
+    fn describe(&self, values: &[Value]) -> Vec<String> {
+        let mut vars = Vec::new();
+        for value in values {
+            for name in self.names.values.get(value).into_iter().flatten() {
+                vars.push(format!("{} = {}", name, value));
+            }
+        }
+        vars
+    }
+    
+    /// Writes `line` with `comments` added to its `;` comment, which
+    /// Cranelift uses to show the values of constant operands
+    fn write_commented(w: &mut dyn std::fmt::Write, line: &str, comments: &[String]) -> std::fmt::Result {
+        if comments.is_empty() {
+            return w.write_str(line);
+        }
+        let separator = if line.contains(" ; ") { ", " } else { "  ; " };
+        writeln!(w, "{}{}{}", line.trim_end(), separator, comments.join(", "))
+    }
+}
+
+impl FuncWriter for IrWriter<'_> {
+    fn write_block_header(
+        &mut self,
+        w: &mut dyn std::fmt::Write,
+        func: &codegen::ir::Function,
+        block: Block,
+        indent: usize,
+    ) -> std::fmt::Result {
+        let mut line = String::new();
+        write_block_header(&mut line, func, block, indent)?;
+        let mut comments: Vec<String> = self.names.blocks.get(&block).cloned().into_iter().collect();
+        comments.extend(self.describe(func.dfg.block_params(block)));
+        Self::write_commented(w, &line, &comments)
+    }
+    
+    fn write_instruction(
+        &mut self,
+        w: &mut dyn std::fmt::Write,
+        func: &codegen::ir::Function,
+        aliases: &SecondaryMap<Value, Vec<Value>>,
+        inst: codegen::ir::Inst,
+        indent: usize,
+    ) -> std::fmt::Result {
+        let mut line = String::new();
+        PlainWriter.write_instruction(&mut line, func, aliases, inst, indent)?;
+        let comments = self.describe(func.dfg.inst_results(inst));
+        Self::write_commented(w, &line, &comments)
+    }
+}
+
+/// Where `break` and `continue` jump to inside a loop
+struct LoopTarget {
+    label: Option<Symbol>,
+    continue_block: Block,
+    break_block: Block,
+    
+    // A `loop` expression, whose break block takes the loop's value
+    yields_value: bool,
+    
+    // Whether any `break`/`continue` jumped here
+    broken: bool,
+    continued: bool,
+}
+
+impl LoopTarget {
+    fn new(label: Option<Symbol>, continue_block: Block, break_block: Block) -> Self {
+        LoopTarget {
+            label,
+            continue_block,
+            break_block,
+            yields_value: false,
+            broken: false,
+            continued: false,
+        }
+    }
+}
+
+impl Default for CodeGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CodeGenerator {
     pub fn new() -> Self {
-        let mut flag_builder = settings::builder();
-        flag_builder.set("use_colocated_libcalls", "false").unwrap();
-        flag_builder.set("is_pic", "false").unwrap();
+        Self::with_opt_level("none").unwrap()
+    }
+    
+    /// Creates a generator that optimizes at the given Cranelift level:
+    /// `"none"`, `"speed"` or `"speed_and_size"`
+    pub fn with_opt_level(level: &str) -> Result<Self, String> {
+        let flags = Self::flags(level, false)?;
         let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
             panic!("host machine is not supported: {}", msg);
         });
-        let isa = isa_builder
-            .finish(settings::Flags::new(flag_builder))
-            .unwrap();
+        let isa = isa_builder.finish(flags).unwrap();
         
-        let mut builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
-        
-        // Declare external C functions
-        builder.symbol("print_int", crate::runtime::print_int as *const u8);
-        
-        let module = JITModule::new(builder);
+        let module = Backend::Jit(Self::new_module(&isa));
+        Ok(Self::with_backend(isa, module))
+    }
+    
+    /// Creates a generator that writes object files for `triple`, such as
+    /// `"aarch64-apple-darwin"`, which need not match the host. Use
+    /// `compile_object` with it; only host generators can run code.
+    pub fn new_object_for(triple: &str) -> Result<Self, String> {
+        // `lookup_by_name` panics on a malformed triple, so check it first
+        triple
+            .parse::<Triple>()
+            .map_err(|e| format!("Invalid target triple {}: {}", triple, e))?;
+        let isa_builder = isa::lookup_by_name(triple)
+            .map_err(|e| format!("Unsupported target triple {}: {}", triple, e))?;
+        let isa = isa_builder
+            .finish(Self::flags("none", true)?)
+            .map_err(|e| format!("Unsupported target triple {}: {}", triple, e))?;
         
+        let module = Backend::Object(Self::new_object_module(&isa)?);
+        Ok(Self::with_backend(isa, module))
+    }
+    
+    fn flags(opt_level: &str, is_pic: bool) -> Result<settings::Flags, String> {
+        let mut flag_builder = settings::builder();
+        flag_builder.set("use_colocated_libcalls", "false").unwrap();
+        flag_builder.set("is_pic", if is_pic { "true" } else { "false" }).unwrap();
+        flag_builder
+            .set("opt_level", opt_level)
+            .map_err(|_| format!("Unknown optimization level: {}", opt_level))?;
+        Ok(settings::Flags::new(flag_builder))
+    }
+    
+    fn with_backend(isa: OwnedTargetIsa, module: Backend) -> Self {
         CodeGenerator {
+            isa,
             builder_context: FunctionBuilderContext::new(),
             ctx: module.make_context(),
             module,
             functions: HashMap::new(),
-            variables: HashMap::new(),
+            defaults: HashMap::new(),
+            variadic: HashSet::new(),
+            scopes: Vec::new(),
             variable_counter: 0,
+            unsigned_vars: EntitySet::new(),
+            string_vars: EntitySet::new(),
+            static_counter: 0,
+            string_literals: HashMap::new(),
+            tail_call: None,
+            loops: Vec::new(),
+            recursion_limit: None,
+            parallel: false,
+            debug_source: None,
+            line_tables: Vec::new(),
+            ir_names: IrNames::default(),
+            pending_closures: Vec::new(),
+            closure_counter: 0,
+            exprs: Arc::default(),
+        }
+    }
+    
+    /// Makes generated code abort with "maximum recursion depth exceeded"
+    /// once more than `limit` calls are active. Off by default, since it
+    /// costs two runtime calls per function call.
+    pub fn with_recursion_limit(mut self, limit: u64) -> Self {
+        self.recursion_limit = Some(limit);
+        self
+    }
+    
+    /// Compiles function bodies to machine code on several threads. IR is
+    /// still built one function at a time, as that declares runtime
+    /// functions and data in the shared module; Cranelift's lowering and
+    /// register allocation, where most of the time goes, then run in
+    /// parallel before the functions are defined in order. The generated
+    /// code is the same either way.
+    pub fn with_parallel(mut self, parallel: bool) -> Self {
+        self.parallel = parallel;
+        self
+    }
+    
+    /// Makes `compile_object` emit DWARF line info, mapping the generated
+    /// code to lines of `source` so a debugger can step through it
+    pub fn with_debug_info(mut self, source: impl Into<PathBuf>) -> Self {
+        self.debug_source = Some(source.into());
+        self
+    }
+    
+    /// The Cranelift optimization level code is generated with
+    pub fn opt_level(&self) -> String {
+        self.isa.flags().opt_level().to_string()
+    }
+    
+    /// Creates an empty JIT module for `isa` with the runtime symbols registered
+    fn new_module(isa: &OwnedTargetIsa) -> JITModule {
+        let mut builder =
+            JITBuilder::with_isa(isa.clone(), cranelift_module::default_libcall_names());
+        
+        // Declare external C functions
+        builder.symbol("print_int", crate::runtime::print_int as *const u8);
+        builder.symbol("print_hex", crate::runtime::print_hex as *const u8);
+        builder.symbol("print_bin", crate::runtime::print_bin as *const u8);
+        builder.symbol("print_sep", crate::runtime::print_sep as *const u8);
+        builder.symbol("print_newline", crate::runtime::print_newline as *const u8);
+        builder.symbol("edust_ipow", crate::runtime::edust_ipow as *const u8);
+        builder.symbol("edust_assert_failed", crate::runtime::edust_assert_failed as *const u8);
+        builder.symbol("edust_assert_eq_failed", crate::runtime::edust_assert_eq_failed as *const u8);
+        builder.symbol("edust_exit", crate::runtime::edust_exit as *const u8);
+        builder.symbol("edust_panic", crate::runtime::edust_panic as *const u8);
+        builder.symbol("edust_todo", crate::runtime::edust_todo as *const u8);
+        builder.symbol("edust_read_int_or", crate::runtime::edust_read_int_or as *const u8);
+        builder.symbol("edust_time_ms", crate::runtime::edust_time_ms as *const u8);
+        builder.symbol("edust_rand", crate::runtime::edust_rand as *const u8);
+        builder.symbol("edust_srand", crate::runtime::edust_srand as *const u8);
+        builder.symbol("print_str", crate::runtime::print_str as *const u8);
+        builder.symbol("edust_str_concat", crate::runtime::edust_str_concat as *const u8);
+        builder.symbol("edust_int_to_str", crate::runtime::edust_int_to_str as *const u8);
+        builder.symbol("edust_enter", crate::runtime::edust_enter as *const u8);
+        builder.symbol("edust_leave", crate::runtime::edust_leave as *const u8);
+        builder.symbol("edust_alloc", crate::runtime::edust_alloc as *const u8);
+        builder.symbol("edust_free", crate::runtime::edust_free as *const u8);
+        builder.symbol("edust_map_new", crate::runtime::edust_map_new as *const u8);
+        builder.symbol("edust_map_set", crate::runtime::edust_map_set as *const u8);
+        builder.symbol("edust_map_get", crate::runtime::edust_map_get as *const u8);
+        builder.symbol("edust_map_free", crate::runtime::edust_map_free as *const u8);
+        builder.symbol("edust_alloc_closure", crate::runtime::edust_alloc_closure as *const u8);
+        builder.symbol("edust_check_closure", crate::runtime::edust_check_closure as *const u8);
+        builder.symbol("edust_bad_closure_call", crate::runtime::edust_bad_closure_call as *const u8);
+        builder.symbol("edust_bad_vararg", crate::runtime::edust_bad_vararg as *const u8);
+        
+        JITModule::new(builder)
+    }
+    
+    /// Creates an empty object module for `isa`. Runtime functions stay
+    /// undefined symbols, to be resolved when the object is linked.
+    fn new_object_module(isa: &OwnedTargetIsa) -> Result<ObjectModule, String> {
+        let builder = ObjectBuilder::new(isa.clone(), "edust", cranelift_module::default_libcall_names())
+            .map_err(|e| e.to_string())?;
+        Ok(ObjectModule::new(builder))
+    }
+    
+    /// Starts over with a fresh module, reusing the already-built ISA.
+    ///
+    /// Code from earlier compilations is unaffected, as each `JitProgram`
+    /// owns the module it was compiled into.
+    pub fn reset(&mut self) {
+        self.module = match self.module {
+            Backend::Jit(_) => Backend::Jit(Self::new_module(&self.isa)),
+            // Cannot fail, as it already succeeded for this ISA
+            Backend::Object(_) => Backend::Object(Self::new_object_module(&self.isa).unwrap()),
+        };
+        self.clear();
+    }
+    
+    /// Forgets the program compiled so far, keeping the current module
+    fn clear(&mut self) {
+        self.ctx = self.module.make_context();
+        self.functions.clear();
+        self.defaults.clear();
+        self.variadic.clear();
+        self.scopes.clear();
+        self.variable_counter = 0;
+        self.static_counter = 0;
+        self.string_literals.clear();
+        self.line_tables.clear();
+        self.pending_closures.clear();
+        self.closure_counter = 0;
+    }
+    
+    /// Compiles `program`, which must have a `main` function, ready to
+    /// `run`. Each call compiles into its own module, so one generator can
+    /// be used for many independent programs.
+    pub fn compile(&mut self, program: &ast::Program) -> Result<JitProgram, String> {
+        let jit = self.compile_all(program)?;
+        if jit.arity("main").is_none() {
+            return Err("No main function found".to_string());
+        }
+        Ok(jit)
+    }
+    
+    /// Compiles `program` like `compile`, returning every function it
+    /// defines, nested ones included, along with their arities so they can
+    /// be called by name. `main` is optional.
+    ///
+    /// The returned program takes the module its code lives in, leaving
+    /// the generator with an empty one.
+    pub fn compile_all(&mut self, program: &ast::Program) -> Result<JitProgram, String> {
+        if !matches!(self.module, Backend::Jit(_)) {
+            return Err("Object file generators cannot run code; use compile_object".to_string());
+        }
+        
+        self.define_program(program)?;
+        let Backend::Jit(module) = &mut self.module else {
+            unreachable!()
+        };
+        module.finalize_definitions().map_err(|e| e.to_string())?;
+        
+        let mut functions = HashMap::new();
+        for func in program.all_functions() {
+            let code = module.get_finalized_function(self.functions[&func.name]);
+            let arity = func.params.len();
+            let variadic = func.variadic.is_some();
+            functions.insert(func.name, JitFunction { code, arity, variadic });
+        }
+        
+        let fresh = Backend::Jit(Self::new_module(&self.isa));
+        let Backend::Jit(module) = std::mem::replace(&mut self.module, fresh) else {
+            unreachable!()
+        };
+        self.clear();
+        Ok(JitProgram { functions, module: ManuallyDrop::new(module) })
+    }
+    
+    /// Compiles `program` into a relocatable object file for the target
+    /// given to `new_object_for`. Every function is exported under its own
+    /// name, and runtime functions such as `print_int` are left for the
+    /// linker.
+    pub fn compile_object(&mut self, program: &ast::Program) -> Result<Vec<u8>, String> {
+        if !matches!(self.module, Backend::Object(_)) {
+            return Err("compile_object needs a generator from new_object_for".to_string());
         }
+        
+        self.define_program(program)?;
+        
+        // Finishing consumes the module, so continue with a fresh one
+        let fresh = Backend::Object(Self::new_object_module(&self.isa)?);
+        let Backend::Object(module) = std::mem::replace(&mut self.module, fresh) else {
+            unreachable!()
+        };
+        let line_tables = std::mem::take(&mut self.line_tables);
+        self.clear();
+        
+        let mut product = module.finish();
+        if let Some(source) = &self.debug_source {
+            debuginfo::write_line_info(&mut product, &*self.isa, source, &line_tables)?;
+        }
+        
+        product.emit().map_err(|e| e.to_string())
     }
     
-    pub fn compile(&mut self, program: &ast::Program) -> Result<*const u8, String> {
-        // First pass: declare all functions
-        for func in &program.functions {
-            self.declare_function(&func.name, func.params.len())?;
+    /// Declares and defines every function in `program` in a fresh module
+    fn define_program(&mut self, program: &ast::Program) -> Result<(), String> {
+        if !self.functions.is_empty() {
+            self.reset();
+        }
+        self.exprs = Arc::clone(&program.exprs);
+        
+        // First pass: declare all functions, including nested ones
+        let functions = program.all_functions();
+        for func in &functions {
+            self.declare_source_function(func)?;
+        }
+        
+        if self.parallel {
+            return self.define_in_parallel(&functions);
         }
         
         // Second pass: compile all function bodies
-        for func in &program.functions {
-            self.compile_function(func)?;
+        for func in &functions {
+            self.compile_function(func, &[])?;
+        }
+        
+        // Closure bodies, which may create closures of their own
+        while let Some(closure) = self.pending_closures.pop() {
+            self.compile_function(&closure.func, &closure.captures)?;
+        }
+        
+        Ok(())
+    }
+    
+    /// The second pass of `define_program` for parallel generators: build
+    /// the IR of every function, compile it all at once, then define the
+    /// results in the same order as the serial pass
+    fn define_in_parallel(&mut self, functions: &[&ast::Function]) -> Result<(), String> {
+        let mut contexts = Vec::new();
+        for func in functions {
+            contexts.push(self.build_function(func, &[])?);
+        }
+        let mut closures = Vec::new();
+        while let Some(closure) = self.pending_closures.pop() {
+            contexts.push(self.build_function(&closure.func, &closure.captures)?);
+            closures.push(closure);
         }
         
-        // Finalize module
-        self.module.finalize_definitions().map_err(|e| e.to_string())?;
+        let isa = &*self.isa;
+        contexts.par_iter_mut().try_for_each(|ctx| {
+            ctx.compile(isa, &mut ControlPlane::default())
+                .map(|_| ())
+                .map_err(|e| format!("Compilation error: {}", e.inner))
+        })?;
         
-        // Get pointer to main function
-        let main_id = self.functions.get("main").ok_or("No main function")?;
-        let code = self.module.get_finalized_function(*main_id);
+        let all_functions = functions.iter().copied().chain(closures.iter().map(|c| &c.func));
+        for (func, ctx) in all_functions.zip(contexts) {
+            let func_id = self.functions[&func.name];
+            let code = ctx.compiled_code().unwrap();
+            self.module
+                .define_function_bytes(
+                    func_id,
+                    &ctx.func,
+                    code.buffer.alignment as u64,
+                    code.code_buffer(),
+                    code.buffer.relocs(),
+                )
+                .map_err(|e| e.to_string())?;
+            
+            if self.debug_source.is_some() && matches!(self.module, Backend::Object(_)) {
+                self.line_tables.push(FunctionLines::new(func_id, func, code));
+            }
+        }
         
-        Ok(code)
+        Ok(())
+    }
+    
+    /// Declares a function of the program, remembering what its calls need
+    /// to know beyond its signature
+    fn declare_source_function(&mut self, func: &ast::Function) -> Result<(), String> {
+        self.declare_function(func.name, Self::arity(func))?;
+        self.defaults.insert(func.name, func.defaults.clone());
+        if func.variadic.is_some() {
+            self.variadic.insert(func.name);
+        }
+        Ok(())
+    }
+    
+    /// Number of machine-level parameters of `func`: a variadic function
+    /// takes two more than it declares
+    fn arity(func: &ast::Function) -> usize {
+        func.params.len() + if func.variadic.is_some() { 2 } else { 0 }
     }
     
-    fn declare_function(&mut self, name: &str, param_count: usize) -> Result<(), String> {
+    fn declare_function(&mut self, name: Symbol, param_count: usize) -> Result<(), String> {
         // All functions return i64 and take i64 parameters
         self.ctx.func.signature.returns.push(AbiParam::new(types::I64));
         
@@ -77,10 +771,10 @@ impl CodeGenerator {
         
         let func_id = self
             .module
-            .declare_function(name, Linkage::Export, &self.ctx.func.signature)
+            .declare_function(name.as_str(), Linkage::Export, &self.ctx.func.signature)
             .map_err(|e| e.to_string())?;
         
-        self.functions.insert(name.to_string(), func_id);
+        self.functions.insert(name, func_id);
         
         // Clear context for next function
         self.ctx.func.signature.params.clear();
@@ -89,171 +783,746 @@ impl CodeGenerator {
         Ok(())
     }
     
-    fn compile_function(&mut self, func: &ast::Function) -> Result<(), String> {
-        // Reset variable tracking
-        self.variables.clear();
+    /// Build the Cranelift IR for every function in `program` and return it
+    /// as text, without defining anything in the module. Blocks are
+    /// commented with their role (`then1`, `loop_header2`) and instructions
+    /// with the variables their results are stored in, so the text only
+    /// changes when the program does.
+    pub fn compile_to_ir(&mut self, program: &ast::Program) -> Result<String, String> {
+        let mut ir = String::new();
+        self.build_each_function(program, |codegen, name, ctx| codegen.write_ir(&mut ir, name, ctx))?;
+        Ok(ir)
+    }
+    
+    /// Build the Cranelift IR for every function in `program`, as
+    /// `compile_to_ir` does, and return its control-flow graph as Graphviz
+    /// DOT: one cluster per function, a node per block and an edge per
+    /// branch or jump. Nodes are labeled with the block's role, and the
+    /// edges of a `brif` with which way the condition went.
+    pub fn emit_cfg_dot(&mut self, program: &ast::Program) -> Result<String, String> {
+        let mut dot = String::from("digraph cfg {\n    node [shape=box];\n");
+        self.build_each_function(program, |codegen, name, ctx| codegen.write_cfg(&mut dot, name, ctx))?;
+        dot.push_str("}\n");
+        Ok(dot)
+    }
+    
+    /// Builds every function in `program`, closures last, passing each to
+    /// `emit` without defining anything in the module
+    fn build_each_function(
+        &mut self,
+        program: &ast::Program,
+        mut emit: impl FnMut(&mut Self, Symbol, codegen::Context) -> Result<(), String>,
+    ) -> Result<(), String> {
+        if !self.functions.is_empty() {
+            self.reset();
+        }
+        self.exprs = Arc::clone(&program.exprs);
+        
+        let functions = program.all_functions();
+        for func in &functions {
+            self.declare_source_function(func)?;
+        }
+        
+        for func in &functions {
+            let ctx = self.build_function(func, &[])?;
+            emit(self, func.name, ctx)?;
+        }
+        while let Some(closure) = self.pending_closures.pop() {
+            let ctx = self.build_function(&closure.func, &closure.captures)?;
+            emit(self, closure.func.name, ctx)?;
+        }
+        
+        Ok(())
+    }
+    
+    /// Appends the function just built into `ctx` to `ir`, then hands the
+    /// context back
+    fn write_ir(&mut self, ir: &mut String, name: Symbol, mut ctx: codegen::Context) -> Result<(), String> {
+        ir.push_str(&format!("; {}\n", name));
+        let mut writer = IrWriter { names: &self.ir_names };
+        decorate_function(&mut writer, ir, &ctx.func).map_err(|e| e.to_string())?;
+        ir.push('\n');
+        self.module.clear_context(&mut ctx);
+        self.ctx = ctx;
+        Ok(())
+    }
+    
+    /// Appends the control-flow graph of the function just built into
+    /// `ctx` to `dot` as a cluster, then hands the context back
+    fn write_cfg(&mut self, dot: &mut String, name: Symbol, mut ctx: codegen::Context) -> Result<(), String> {
+        let func = &ctx.func;
+        let node = |block: Block| format!("\"{}.{}\"", name, block);
+        
+        dot.push_str(&format!("    subgraph \"cluster_{}\" {{\n        label = \"{}\";\n", name, name));
+        for block in func.layout.blocks() {
+            let label = match self.ir_names.blocks.get(&block) {
+                Some(role) => format!("{}: {}", block, role),
+                None => block.to_string(),
+            };
+            dot.push_str(&format!("        {} [label=\"{}\"];\n", node(block), label));
+        }
+        for block in func.layout.blocks() {
+            let Some(inst) = func.layout.last_inst(block) else {
+                continue;
+            };
+            let data = &func.dfg.insts[inst];
+            let is_brif = matches!(data, codegen::ir::InstructionData::Brif { .. });
+            for (i, dest) in data.branch_destination(&func.dfg.jump_tables).iter().enumerate() {
+                let label = match (is_brif, i) {
+                    (true, 0) => " [label=\"true\"]",
+                    (true, _) => " [label=\"false\"]",
+                    _ => "",
+                };
+                let target = dest.block(&func.dfg.value_lists);
+                dot.push_str(&format!("        {} -> {}{};\n", node(block), node(target), label));
+            }
+        }
+        dot.push_str("    }\n");
+        
+        self.module.clear_context(&mut ctx);
+        self.ctx = ctx;
+        Ok(())
+    }
+    
+    fn compile_function(&mut self, func: &ast::Function, captures: &[Capture]) -> Result<(), String> {
+        let func_id = *self.functions.get(&func.name).unwrap();
+        let mut ctx = self.build_function(func, captures)?;
+        
+        // Define the function
+        self.module
+            .define_function(func_id, &mut ctx)
+            .map_err(|e| e.to_string())?;
+        
+        if self.debug_source.is_some() && matches!(self.module, Backend::Object(_)) {
+            let code = ctx.compiled_code().unwrap();
+            self.line_tables.push(FunctionLines::new(func_id, func, code));
+        }
+        
+        // Clear context and hand it back
+        self.module.clear_context(&mut ctx);
+        self.ctx = ctx;
+        
+        Ok(())
+    }
+    
+    /// Build the IR for one function into a context taken from `self`.
+    /// For a closure body, `captures` names the values to load from the
+    /// environment passed as its first parameter.
+    fn build_function(
+        &mut self,
+        func: &ast::Function,
+        captures: &[Capture],
+    ) -> Result<codegen::Context, String> {
+        // Reset variable tracking; parameters get the outermost scope
+        self.scopes = vec![HashMap::new()];
         self.variable_counter = 0;
+        self.unsigned_vars.clear();
+        self.string_vars.clear();
+        self.ir_names = IrNames::default();
         
         // Setup function signature
         self.ctx.func.signature.returns.push(AbiParam::new(types::I64));
-        for _ in 0..func.params.len() {
+        for _ in 0..Self::arity(func) {
             self.ctx.func.signature.params.push(AbiParam::new(types::I64));
         }
         
-        let func_id = *self.functions.get(&func.name).unwrap();
-        
-        // Build function
-        let mut builder = FunctionBuilder::new(&mut self.ctx.func, &mut self.builder_context);
+        // Build function. The context and builder context are moved out of
+        // `self` while building so the compile_* helpers can borrow `self`.
+        let mut ctx = std::mem::replace(&mut self.ctx, self.module.make_context());
+        let mut builder_context = std::mem::take(&mut self.builder_context);
+        let mut builder = FunctionBuilder::new(&mut ctx.func, &mut builder_context);
         
-        let entry_block = builder.create_block();
+        let entry_block = self.named_block(&mut builder, "entry", 0);
         builder.append_block_params_for_function_params(entry_block);
         builder.switch_to_block(entry_block);
         builder.seal_block(entry_block);
+        self.set_line(&mut builder, func.span);
         
         // Declare parameters as variables
         let params = builder.block_params(entry_block).to_vec();
+        let mut param_vars = Vec::new();
         for (i, param_name) in func.params.iter().enumerate() {
-            let var = Variable::new(self.variable_counter);
-            self.variable_counter += 1;
-            self.variables.insert(param_name.clone(), var);
-            builder.declare_var(var, types::I64);
-            builder.def_var(var, params[i]);
+            let var = self.define_variable(&mut builder, *param_name, params[i]);
+            param_vars.push(var);
+        }
+        if func.variadic.is_some() {
+            let n = func.params.len();
+            for (name, value) in [(VARARGS, params[n]), (VARARGS_COUNT, params[n + 1])] {
+                self.define_variable(&mut builder, Symbol::intern(name), value);
+            }
+        }
+        for (i, capture) in captures.iter().enumerate() {
+            let offset = CLOSURE_CAPTURES + 8 * i as i32;
+            let val = builder.ins().load(types::I64, MemFlags::trusted(), params[0], offset);
+            let var = self.define_variable(&mut builder, capture.name, val);
+            if capture.unsigned {
+                self.unsigned_vars.insert(var);
+            }
+            if capture.string {
+                self.string_vars.insert(var);
+            }
+        }
+        
+        // Count this call against the recursion limit
+        if let Some(limit) = self.recursion_limit {
+            let enter = self.import_runtime(&mut builder, "edust_enter", 1, false)?;
+            let limit = builder.ins().iconst(types::I64, limit as i64);
+            builder.ins().call(enter, &[limit]);
+        }
+        
+        // Self tail calls loop back to a block just after the entry block.
+        // This comes after the depth check: a tail call reuses the frame.
+        // Variadic arguments live in the caller's frame, so calls to a
+        // variadic function stay real calls.
+        self.tail_call = None;
+        self.loops.clear();
+        if func.variadic.is_none() && Self::block_has_self_tail_call(&func.body, func.name) {
+            let body_bb = self.named_block(&mut builder, "tail_loop", 0);
+            builder.ins().jump(body_bb, &[]);
+            builder.switch_to_block(body_bb);
+            self.tail_call = Some(TailCallTarget {
+                name: func.name,
+                params: param_vars,
+                block: body_bb,
+            });
         }
         
         // Compile function body
-        let return_val = self.compile_block(&mut builder, &func.body)?;
+        let terminated = self.compile_block(&mut builder, &func.body)?;
+        
+        // All back edges are known now
+        if let Some(target) = self.tail_call.take() {
+            builder.seal_block(target.block);
+        }
         
-        // Default return 0 if no explicit return
-        let final_return = return_val.unwrap_or_else(|| builder.ins().iconst(types::I64, 0));
-        builder.ins().return_(&[final_return]);
+        // Default return 0 if control reaches the end of the body
+        if !terminated {
+            let zero = builder.ins().iconst(types::I64, 0);
+            self.emit_return(&mut builder, zero)?;
+        }
         
         // Finalize function
         builder.finalize();
+        self.builder_context = builder_context;
         
-        // Define the function
-        self.module
-            .define_function(func_id, &mut self.ctx)
-            .map_err(|e| e.to_string())?;
-        
-        // Clear context
-        self.module.clear_context(&mut self.ctx);
-        
-        Ok(())
+        Ok(ctx)
     }
     
+    /// Compiles the statements of a block in a new variable scope. Returns
+    /// `true` if the block ended with a terminator (e.g. `return`), in which
+    /// case the current Cranelift block is filled and any remaining
+    /// statements are unreachable.
     fn compile_block(
         &mut self,
         builder: &mut FunctionBuilder,
         block: &ast::Block,
-    ) -> Result<Option<Value>, String> {
-        let mut last_return = None;
+    ) -> Result<bool, String> {
+        self.scopes.push(HashMap::new());
         
+        let mut terminated = false;
         for stmt in &block.statements {
-            if let Some(ret_val) = self.compile_statement(builder, stmt)? {
-                last_return = Some(ret_val);
+            if self.compile_statement(builder, stmt)? {
+                terminated = true;
+                break;
             }
         }
         
-        Ok(last_return)
+        // A function body's tail expression is returned like `return`
+        if !terminated && let Some(tail) = &block.tail {
+            self.compile_return(builder, tail)?;
+            terminated = true;
+        }
+        
+        self.scopes.pop();
+        Ok(terminated)
     }
     
-    fn compile_statement(
-        &mut self,
-        builder: &mut FunctionBuilder,
-        stmt: &ast::Statement,
-    ) -> Result<Option<Value>, String> {
-        match stmt {
-            ast::Statement::VarDecl { name, value } => {
-                let val = self.compile_expr(builder, value)?;
-                
-                let var = Variable::new(self.variable_counter);
-                self.variable_counter += 1;
-                self.variables.insert(name.clone(), var);
+    /// Declares the variable `name` in the innermost scope, holding `val`
+    fn define_variable(&mut self, builder: &mut FunctionBuilder, name: Symbol, val: Value) -> Variable {
+        let var = Variable::new(self.variable_counter);
+        self.variable_counter += 1;
+        builder.declare_var(var, types::I64);
+        builder.def_var(var, val);
+        self.scopes.last_mut().unwrap().insert(name, Binding::Local(var));
+        self.ir_names.values.entry(val).or_default().push(name);
+        var
+    }
+    
+    /// Creates a block named after its `role` in construct `n` of the
+    /// function, e.g. `then1` or `loop_header2`, for `compile_to_ir`
+    fn named_block(&mut self, builder: &mut FunctionBuilder, role: &str, n: usize) -> Block {
+        let block = builder.create_block();
+        self.ir_names.blocks.insert(block, format!("{}{}", role, n));
+        block
+    }
+    
+    /// Number for the next construct that creates blocks, so that the
+    /// blocks of one `if` or loop share it
+    fn next_construct(&mut self) -> usize {
+        self.ir_names.constructs += 1;
+        self.ir_names.constructs
+    }
+    
+    /// Defines the data object backing `static name = init;` and binds
+    /// `name` to it in the innermost scope
+    fn declare_static(&mut self, name: Symbol, init: i64) -> Result<(), String> {
+        let data_name = format!("static.{}.{}", self.static_counter, name);
+        self.static_counter += 1;
+        let data_id = self
+            .module
+            .declare_data(&data_name, Linkage::Local, true, false)
+            .map_err(|e| e.to_string())?;
+        
+        let bytes = match self.isa.endianness() {
+            codegen::ir::Endianness::Little => init.to_le_bytes(),
+            codegen::ir::Endianness::Big => init.to_be_bytes(),
+        };
+        let mut data = DataDescription::new();
+        data.define(Box::new(bytes));
+        data.set_align(8);
+        self.module.define_data(data_id, &data).map_err(|e| e.to_string())?;
+        
+        self.scopes.last_mut().unwrap().insert(name, Binding::Static(data_id));
+        Ok(())
+    }
+    
+    /// Whether the innermost binding of `name` is a variable declared `u64`
+    fn is_unsigned_var(&self, name: Symbol) -> bool {
+        matches!(self.lookup_binding(name), Ok(Binding::Local(var)) if self.unsigned_vars.contains(var))
+    }
+    
+    /// Whether the innermost binding of `name` is a variable holding a string
+    fn is_string_var(&self, name: Symbol) -> bool {
+        matches!(self.lookup_binding(name), Ok(Binding::Local(var)) if self.string_vars.contains(var))
+    }
+    
+    /// The address of a string holding `text`, laid out like the runtime's
+    /// strings in a read-only data object. Equal literals share one.
+    fn string_literal(&mut self, builder: &mut FunctionBuilder, text: &str) -> Result<Value, String> {
+        let data_id = match self.string_literals.get(text) {
+            Some(data_id) => *data_id,
+            None => {
+                let data_name = format!("str.{}", self.string_literals.len());
+                let data_id = self
+                    .module
+                    .declare_data(&data_name, Linkage::Local, false, false)
+                    .map_err(|e| e.to_string())?;
+                
+                let len = text.len() as i64;
+                let mut bytes = match self.isa.endianness() {
+                    codegen::ir::Endianness::Little => len.to_le_bytes().to_vec(),
+                    codegen::ir::Endianness::Big => len.to_be_bytes().to_vec(),
+                };
+                bytes.extend_from_slice(text.as_bytes());
+                let mut data = DataDescription::new();
+                data.define(bytes.into_boxed_slice());
+                data.set_align(8);
+                self.module.define_data(data_id, &data).map_err(|e| e.to_string())?;
                 
-                builder.declare_var(var, types::I64);
+                self.string_literals.insert(text.to_string(), data_id);
+                data_id
+            }
+        };
+        Ok(self.static_address(builder, data_id))
+    }
+    
+    /// The innermost binding of `name`
+    fn lookup_binding(&self, name: Symbol) -> Result<Binding, String> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name).copied())
+            .ok_or_else(|| format!("Undefined variable: {}", name))
+    }
+    
+    /// The current value of the variable `name`
+    fn read_variable(&mut self, builder: &mut FunctionBuilder, name: Symbol) -> Result<Value, String> {
+        match self.lookup_binding(name)? {
+            Binding::Local(var) => Ok(builder.use_var(var)),
+            Binding::Static(data_id) => {
+                let addr = self.static_address(builder, data_id);
+                Ok(builder.ins().load(types::I64, MemFlags::trusted(), addr, 0))
+            }
+        }
+    }
+    
+    /// Assigns `val` to the variable `name`
+    fn write_variable(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        name: Symbol,
+        val: Value,
+    ) -> Result<(), String> {
+        match self.lookup_binding(name)? {
+            Binding::Local(var) => {
                 builder.def_var(var, val);
+                self.ir_names.values.entry(val).or_default().push(name);
+            }
+            Binding::Static(data_id) => {
+                let addr = self.static_address(builder, data_id);
+                builder.ins().store(MemFlags::trusted(), val, addr, 0);
+            }
+        }
+        Ok(())
+    }
+    
+    fn static_address(&mut self, builder: &mut FunctionBuilder, data_id: DataId) -> Value {
+        let global = self.module.declare_data_in_func(data_id, builder.func);
+        let pointer_type = self.module.target_config().pointer_type();
+        builder.ins().global_value(pointer_type, global)
+    }
+    
+    /// Attributes the instructions built from here on to `span`'s line,
+    /// when emitting debug info
+    fn set_line(&self, builder: &mut FunctionBuilder, span: ast::Span) {
+        if self.debug_source.is_some() {
+            builder.set_srcloc(codegen::ir::SourceLoc::new(span.line as u32));
+        }
+    }
+    
+    fn compile_statement(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        stmt: &ast::Statement,
+    ) -> Result<bool, String> {
+        self.set_line(builder, stmt.span());
+        
+        match stmt {
+            ast::Statement::VarDecl { name, ty, value, .. } => {
+                // The value is compiled first, so `let x = x + 1;` reads
+                // any outer `x`
+                // A variable declared without a value starts out as 0, which
+                // analysis makes sure is never read
+                let (val, is_string) = match value {
+                    Some(value) => (
+                        self.compile_expr(builder, value)?,
+                        value.is_string(&self.exprs, &|name| self.is_string_var(name)),
+                    ),
+                    None => (builder.ins().iconst(types::I64, 0), false),
+                };
+                let var = self.define_variable(builder, *name, val);
+                if *ty == Some(ast::Type::U64) {
+                    self.unsigned_vars.insert(var);
+                }
+                if is_string {
+                    self.string_vars.insert(var);
+                }
+                
+                Ok(false)
+            }
+            
+            ast::Statement::DestructureDecl { names, value, .. } => {
+                let ast::Expr::Array(elements) = value else {
+                    return Err("Can only destructure an array literal".to_string());
+                };
+                
+                // Every element is evaluated before any name is bound
+                let mut values = Vec::new();
+                for element in elements {
+                    values.push(self.compile_expr(builder, element)?);
+                }
+                for (name, val) in names.iter().zip(values) {
+                    self.define_variable(builder, *name, val);
+                }
                 
-                Ok(None)
+                Ok(false)
+            }
+            
+            ast::Statement::StaticDecl { name, value, .. } => {
+                let init = eval_const_expr(&self.exprs, value, &HashMap::new())
+                    .ok_or("Static initializer must be a constant expression")?;
+                self.declare_static(*name, init)?;
+                Ok(false)
             }
             
-            ast::Statement::Assignment { name, value } => {
+            ast::Statement::Assignment { name, value, .. } => {
                 let val = self.compile_expr(builder, value)?;
-                let var = *self.variables.get(name).unwrap();
-                builder.def_var(var, val);
-                Ok(None)
+                self.write_variable(builder, *name, val)?;
+                Ok(false)
+            }
+            
+            ast::Statement::MultiAssign { names, values, .. } => {
+                // Every value is evaluated before any name is assigned
+                let mut vals = Vec::new();
+                for value in values {
+                    vals.push(self.compile_expr(builder, value)?);
+                }
+                for (name, val) in names.iter().zip(vals) {
+                    self.write_variable(builder, *name, val)?;
+                }
+                Ok(false)
             }
             
             ast::Statement::If {
                 condition,
                 then_block,
                 else_block,
+                ..
             } => {
-                let cond_val = self.compile_expr(builder, condition)?;
+                // A constant condition only needs the branch it selects
+                if let Some(cond) = eval_const_expr(&self.exprs, condition, &HashMap::new()) {
+                    return match (cond != 0, else_block) {
+                        (true, _) => self.compile_block(builder, then_block),
+                        (false, Some(else_blk)) => self.compile_block(builder, else_blk),
+                        (false, None) => Ok(false),
+                    };
+                }
+                
+                let cond_val = self.compile_condition(builder, condition)?;
                 
-                let then_bb = builder.create_block();
-                let else_bb = builder.create_block();
-                let merge_bb = builder.create_block();
+                let n = self.next_construct();
+                let then_bb = self.named_block(builder, "then", n);
+                let else_bb = self.named_block(builder, "else", n);
+                let merge_bb = self.named_block(builder, "merge", n);
                 
                 builder.ins().brif(cond_val, then_bb, &[], else_bb, &[]);
                 
                 // Then block
                 builder.switch_to_block(then_bb);
                 builder.seal_block(then_bb);
-                self.compile_block(builder, then_block)?;
-                builder.ins().jump(merge_bb, &[]);
+                let then_terminated = self.compile_block(builder, then_block)?;
+                if !then_terminated {
+                    builder.ins().jump(merge_bb, &[]);
+                }
                 
                 // Else block
                 builder.switch_to_block(else_bb);
                 builder.seal_block(else_bb);
-                if let Some(else_blk) = else_block {
-                    self.compile_block(builder, else_blk)?;
+                let else_terminated = match else_block {
+                    Some(else_blk) => self.compile_block(builder, else_blk)?,
+                    None => false,
+                };
+                if !else_terminated {
+                    builder.ins().jump(merge_bb, &[]);
+                }
+                
+                // Both branches returned: nothing flows into the merge block
+                if then_terminated && else_terminated {
+                    return Ok(true);
                 }
-                builder.ins().jump(merge_bb, &[]);
                 
                 // Merge
                 builder.switch_to_block(merge_bb);
                 builder.seal_block(merge_bb);
                 
-                Ok(None)
+                Ok(false)
             }
             
-            ast::Statement::While { condition, body } => {
-                let header_bb = builder.create_block();
-                let loop_body_bb = builder.create_block();
-                let exit_bb = builder.create_block();
+            ast::Statement::While {
+                label,
+                condition,
+                body,
+                ..
+            } => {
+                let constant = eval_const_expr(&self.exprs, condition, &HashMap::new());
+                if constant == Some(0) {
+                    return Ok(false);
+                }
+                
+                let n = self.next_construct();
+                let header_bb = self.named_block(builder, "loop_header", n);
+                let loop_body_bb = self.named_block(builder, "loop_body", n);
+                let exit_bb = self.named_block(builder, "loop_exit", n);
                 
                 builder.ins().jump(header_bb, &[]);
                 
-                // Loop header
+                // Loop header; a constant true condition is never tested
                 builder.switch_to_block(header_bb);
-                let cond_val = self.compile_expr(builder, condition)?;
-                builder.ins().brif(cond_val, loop_body_bb, &[], exit_bb, &[]);
+                if constant.is_some() {
+                    builder.ins().jump(loop_body_bb, &[]);
+                } else {
+                    let cond_val = self.compile_condition(builder, condition)?;
+                    builder.ins().brif(cond_val, loop_body_bb, &[], exit_bb, &[]);
+                }
                 
                 // Loop body
                 builder.switch_to_block(loop_body_bb);
                 builder.seal_block(loop_body_bb);
-                self.compile_block(builder, body)?;
+                self.loops.push(LoopTarget::new(*label, header_bb, exit_bb));
+                let terminated = self.compile_block(builder, body)?;
+                self.loops.pop();
+                if !terminated {
+                    builder.ins().jump(header_bb, &[]);
+                }
+                
+                // Seal header after back edge
+                builder.seal_block(header_bb);
+                
+                // Exit
+                builder.switch_to_block(exit_bb);
+                builder.seal_block(exit_bb);
+                
+                Ok(false)
+            }
+            
+            ast::Statement::ForIn {
+                label,
+                var,
+                iter,
+                body,
+                ..
+            } => {
+                let ast::Expr::Range { start, end } = iter else {
+                    return Err("Can only iterate over a range".to_string());
+                };
+                
+                // Both bounds are evaluated once, before the first pass
+                let exprs = Arc::clone(&self.exprs);
+                let first = self.compile_expr(builder, &exprs[*start])?;
+                let end = self.compile_expr(builder, &exprs[*end])?;
+                
+                let n = self.next_construct();
+                let header_bb = self.named_block(builder, "loop_header", n);
+                let loop_body_bb = self.named_block(builder, "loop_body", n);
+                let step_bb = self.named_block(builder, "loop_step", n);
+                let exit_bb = self.named_block(builder, "loop_exit", n);
+                
+                // The loop variable lives in a scope of its own around the body
+                self.scopes.push(HashMap::new());
+                let var = self.define_variable(builder, *var, first);
+                builder.ins().jump(header_bb, &[]);
+                
+                // Loop header: run the body while the variable is below the end
+                builder.switch_to_block(header_bb);
+                let current = builder.use_var(var);
+                let cond_val = builder.ins().icmp(IntCC::SignedLessThan, current, end);
+                builder.ins().brif(cond_val, loop_body_bb, &[], exit_bb, &[]);
+                
+                // Loop body; `continue` goes to the step rather than the header
+                builder.switch_to_block(loop_body_bb);
+                builder.seal_block(loop_body_bb);
+                self.loops.push(LoopTarget::new(*label, step_bb, exit_bb));
+                let terminated = self.compile_block(builder, body)?;
+                self.loops.pop();
+                if !terminated {
+                    builder.ins().jump(step_bb, &[]);
+                }
+                
+                // Step to the next value and test it again
+                builder.switch_to_block(step_bb);
+                builder.seal_block(step_bb);
+                let current = builder.use_var(var);
+                let next = builder.ins().iadd_imm(current, 1);
+                builder.def_var(var, next);
                 builder.ins().jump(header_bb, &[]);
                 
                 // Seal header after back edge
                 builder.seal_block(header_bb);
+                self.scopes.pop();
                 
                 // Exit
                 builder.switch_to_block(exit_bb);
                 builder.seal_block(exit_bb);
                 
-                Ok(None)
+                Ok(false)
             }
             
-            ast::Statement::Return { value } => {
-                let val = self.compile_expr(builder, value)?;
-                Ok(Some(val))
+            ast::Statement::DoWhile {
+                label,
+                body,
+                condition,
+                ..
+            } => {
+                let n = self.next_construct();
+                let loop_body_bb = self.named_block(builder, "loop_body", n);
+                let cond_bb = self.named_block(builder, "loop_cond", n);
+                let exit_bb = self.named_block(builder, "loop_exit", n);
+                
+                // Enter the body before testing the condition
+                builder.ins().jump(loop_body_bb, &[]);
+                
+                // Loop body
+                builder.switch_to_block(loop_body_bb);
+                self.loops.push(LoopTarget::new(*label, cond_bb, exit_bb));
+                let terminated = self.compile_block(builder, body)?;
+                let target = self.loops.pop().unwrap();
+                if terminated && !target.broken && !target.continued {
+                    // Nothing reaches the condition or the exit
+                    builder.seal_block(loop_body_bb);
+                    return Ok(true);
+                }
+                if !terminated {
+                    builder.ins().jump(cond_bb, &[]);
+                }
+                
+                // Condition at the bottom, branching back to the body
+                builder.switch_to_block(cond_bb);
+                builder.seal_block(cond_bb);
+                match eval_const_expr(&self.exprs, condition, &HashMap::new()) {
+                    Some(0) => {
+                        builder.ins().jump(exit_bb, &[]);
+                    }
+                    Some(_) => {
+                        builder.ins().jump(loop_body_bb, &[]);
+                    }
+                    None => {
+                        let cond_val = self.compile_condition(builder, condition)?;
+                        builder.ins().brif(cond_val, loop_body_bb, &[], exit_bb, &[]);
+                    }
+                }
+                
+                // Seal body after back edge
+                builder.seal_block(loop_body_bb);
+                
+                // Exit
+                builder.switch_to_block(exit_bb);
+                builder.seal_block(exit_bb);
+                
+                Ok(false)
+            }
+            
+            ast::Statement::Return { value, .. } => {
+                self.compile_return(builder, value)?;
+                Ok(true)
             }
             
-            ast::Statement::ExprStmt { expr } => {
+            ast::Statement::Break { label, value, .. } => {
+                let val = match value {
+                    Some(value) => Some(self.compile_expr(builder, value)?),
+                    None => None,
+                };
+                let target = self.loop_target(*label).ok_or("break outside of a loop")?;
+                target.broken = true;
+                if target.yields_value {
+                    // A plain `break` gives the loop the value 0
+                    let val = val.unwrap_or_else(|| builder.ins().iconst(types::I64, 0));
+                    builder.ins().jump(target.break_block, &[val]);
+                } else {
+                    builder.ins().jump(target.break_block, &[]);
+                }
+                Ok(true)
+            }
+            
+            ast::Statement::Continue { label, .. } => {
+                let target = self.loop_target(*label).ok_or("continue outside of a loop")?;
+                target.continued = true;
+                builder.ins().jump(target.continue_block, &[]);
+                Ok(true)
+            }
+            
+            ast::Statement::ExprStmt { expr, .. } => {
+                // A call that never returns ends the block
+                if let ast::Expr::Call { name, args, span } = expr
+                    && Self::is_diverging_builtin(name.as_str())
+                {
+                    self.compile_diverging_call(builder, name.as_str(), args, *span)?;
+                    return Ok(true);
+                }
+                
                 self.compile_expr(builder, expr)?;
-                Ok(None)
+                Ok(false)
             }
+            
+            // Nested functions are compiled on their own by `compile`
+            ast::Statement::FuncDecl { .. } => Ok(false),
+        }
+    }
+    
+    /// The innermost enclosing loop, or the one named `label`
+    fn loop_target(&mut self, label: Option<Symbol>) -> Option<&mut LoopTarget> {
+        match label {
+            None => self.loops.last_mut(),
+            Some(label) => self.loops.iter_mut().rev().find(|target| target.label == Some(label)),
         }
     }
     
@@ -265,119 +1534,1383 @@ impl CodeGenerator {
         match expr {
             ast::Expr::Number(n) => Ok(builder.ins().iconst(types::I64, *n)),
             
-            ast::Expr::Variable(name) => {
-                let var = *self.variables.get(name).unwrap();
-                Ok(builder.use_var(var))
+            ast::Expr::Str(text) => self.string_literal(builder, text),
+            
+            ast::Expr::Variable { name, .. } => self.read_variable(builder, *name),
+            
+            ast::Expr::Assign { name, value, .. } => {
+                let exprs = Arc::clone(&self.exprs);
+                let val = self.compile_expr(builder, &exprs[*value])?;
+                self.write_variable(builder, *name, val)?;
+                Ok(val)
+            }
+            
+            ast::Expr::Binary { .. } | ast::Expr::Cast { .. } => {
+                let exprs = Arc::clone(&self.exprs);
+                let (spine, bottom) = expr.left_spine(&exprs);
+                let mut val = self.compile_expr(builder, bottom)?;
+                let mut operand = ast::LeftOperand::new(
+                    bottom,
+                    &exprs,
+                    &|name| self.is_unsigned_var(name),
+                    &|name| self.is_string_var(name),
+                );
+                for node in spine.into_iter().rev() {
+                    let (unsigned, concat) = operand.climb(node, &exprs, &|name| self.is_unsigned_var(name));
+                    val = match node {
+                        ast::Expr::Binary { op, right, .. } => {
+                            let rhs = self.compile_expr(builder, &exprs[*right])?;
+                            if concat {
+                                self.call_runtime(builder, "edust_str_concat", &[val, rhs])?
+                            } else {
+                                self.compile_binary(builder, *op, val, rhs, unsigned)?
+                            }
+                        }
+                        ast::Expr::Cast { target, .. } => Self::compile_cast(builder, val, *target),
+                        _ => unreachable!(),
+                    };
+                }
+                Ok(val)
             }
             
-            ast::Expr::Binary { op, left, right } => {
-                let lhs = self.compile_expr(builder, left)?;
-                let rhs = self.compile_expr(builder, right)?;
+            ast::Expr::Unary { op, operand } => {
+                let exprs = Arc::clone(&self.exprs);
+                let val = self.compile_expr(builder, &exprs[*operand])?;
                 
                 let result = match op {
-                    ast::BinOp::Add => builder.ins().iadd(lhs, rhs),
-                    ast::BinOp::Sub => builder.ins().isub(lhs, rhs),
-                    ast::BinOp::Mul => builder.ins().imul(lhs, rhs),
-                    ast::BinOp::Div => builder.ins().sdiv(lhs, rhs),
-                    ast::BinOp::Mod => builder.ins().srem(lhs, rhs),
-                    
-                    ast::BinOp::Lt => {
-                        let cmp = builder.ins().icmp(IntCC::SignedLessThan, lhs, rhs);
+                    ast::UnaryOp::Neg => builder.ins().ineg(val),
+                    ast::UnaryOp::Not => {
+                        let cmp = builder.ins().icmp_imm(IntCC::Equal, val, 0);
                         builder.ins().uextend(types::I64, cmp)
                     }
-                    ast::BinOp::Le => {
-                        let cmp = builder.ins().icmp(IntCC::SignedLessThanOrEqual, lhs, rhs);
-                        builder.ins().uextend(types::I64, cmp)
+                };
+                
+                Ok(result)
+            }
+            
+            ast::Expr::Call { name, args, span } => {
+                // Semantic analysis rejects names that are both a variable
+                // and a function, so a variable here holds a closure
+                if self.lookup_binding(*name).is_ok() {
+                    return self.compile_closure_call(builder, *name, args, *span);
+                }
+                
+                // Handle builtin print and write
+                if name == "print" || name == "write" {
+                    return self.compile_print_call(builder, args, name == "print");
+                }
+                
+                if name == "assert" {
+                    return self.compile_assert_call(builder, &args[0], *span);
+                }
+                
+                if name == "assert_eq" {
+                    return self.compile_assert_eq_call(builder, &args[0], &args[1], *span);
+                }
+                
+                // print_hex and print_bin print one value on its own line
+                // and produce 0
+                if name == "print_hex" || name == "print_bin" {
+                    let val = self.compile_expr(builder, &args[0])?;
+                    let print = self.import_runtime(builder, name.as_str(), 1, false)?;
+                    builder.ins().call(print, &[val]);
+                    let print_newline = self.import_runtime(builder, "print_newline", 0, false)?;
+                    builder.ins().call(print_newline, &[]);
+                    return Ok(builder.ins().iconst(types::I64, 0));
+                }
+                
+                // Integer intrinsics lower to single instructions
+                match name.as_str() {
+                    "abs" => {
+                        let val = self.compile_expr(builder, &args[0])?;
+                        return Ok(builder.ins().iabs(val));
                     }
-                    ast::BinOp::Gt => {
-                        let cmp = builder.ins().icmp(IntCC::SignedGreaterThan, lhs, rhs);
-                        builder.ins().uextend(types::I64, cmp)
+                    "popcount" => {
+                        let val = self.compile_expr(builder, &args[0])?;
+                        return Ok(builder.ins().popcnt(val));
                     }
-                    ast::BinOp::Ge => {
-                        let cmp = builder.ins().icmp(IntCC::SignedGreaterThanOrEqual, lhs, rhs);
-                        builder.ins().uextend(types::I64, cmp)
+                    "clz" => {
+                        let val = self.compile_expr(builder, &args[0])?;
+                        return Ok(builder.ins().clz(val));
                     }
-                    ast::BinOp::Eq => {
-                        let cmp = builder.ins().icmp(IntCC::Equal, lhs, rhs);
-                        builder.ins().uextend(types::I64, cmp)
+                    "ctz" => {
+                        let val = self.compile_expr(builder, &args[0])?;
+                        return Ok(builder.ins().ctz(val));
                     }
-                    ast::BinOp::Ne => {
-                        let cmp = builder.ins().icmp(IntCC::NotEqual, lhs, rhs);
-                        builder.ins().uextend(types::I64, cmp)
+                    "read_int_or" => {
+                        let default = self.compile_expr(builder, &args[0])?;
+                        return self.call_runtime(builder, "edust_read_int_or", &[default]);
                     }
-                    
-                    ast::BinOp::And => {
-                        let lhs_bool = builder.ins().icmp_imm(IntCC::NotEqual, lhs, 0);
-                        let rhs_bool = builder.ins().icmp_imm(IntCC::NotEqual, rhs, 0);
-                        let result = builder.ins().band(lhs_bool, rhs_bool);
-                        builder.ins().uextend(types::I64, result)
+                    "time_ms" => return self.call_runtime(builder, "edust_time_ms", &[]),
+                    "rand" => return self.call_runtime(builder, "edust_rand", &[]),
+                    "srand" => {
+                        let seed = self.compile_expr(builder, &args[0])?;
+                        let srand = self.import_runtime(builder, "edust_srand", 1, false)?;
+                        builder.ins().call(srand, &[seed]);
+                        return Ok(builder.ins().iconst(types::I64, 0));
                     }
-                    ast::BinOp::Or => {
-                        let lhs_bool = builder.ins().icmp_imm(IntCC::NotEqual, lhs, 0);
-                        let rhs_bool = builder.ins().icmp_imm(IntCC::NotEqual, rhs, 0);
-                        let result = builder.ins().bor(lhs_bool, rhs_bool);
-                        builder.ins().uextend(types::I64, result)
+                    "to_str" => {
+                        let val = self.compile_expr(builder, &args[0])?;
+                        return self.call_runtime(builder, "edust_int_to_str", &[val]);
                     }
-                };
+                    "alloc" => {
+                        let bytes = self.compile_expr(builder, &args[0])?;
+                        return self.call_runtime(builder, "edust_alloc", &[bytes]);
+                    }
+                    "free" => {
+                        let ptr = self.compile_expr(builder, &args[0])?;
+                        let free = self.import_runtime(builder, "edust_free", 1, false)?;
+                        builder.ins().call(free, &[ptr]);
+                        return Ok(builder.ins().iconst(types::I64, 0));
+                    }
+                    "len" => return self.compile_len(builder, &args[0]),
+                    "map_new" => return self.call_runtime(builder, "edust_map_new", &[]),
+                    "map_get" => {
+                        let handle = self.compile_expr(builder, &args[0])?;
+                        let key = self.compile_expr(builder, &args[1])?;
+                        return self.call_runtime(builder, "edust_map_get", &[handle, key]);
+                    }
+                    "map_set" | "map_free" => {
+                        let mut values = Vec::new();
+                        for arg in args {
+                            values.push(self.compile_expr(builder, arg)?);
+                        }
+                        let runtime_name = format!("edust_{}", name);
+                        let func = self.import_runtime(builder, &runtime_name, values.len(), false)?;
+                        builder.ins().call(func, &values);
+                        return Ok(builder.ins().iconst(types::I64, 0));
+                    }
+                    "args_count" => return self.read_variable(builder, Symbol::intern(VARARGS_COUNT)),
+                    "args_get" => {
+                        let index = self.compile_expr(builder, &args[0])?;
+                        return self.compile_args_get(builder, index, *span);
+                    }
+                    "load" => {
+                        let ptr = self.compile_expr(builder, &args[0])?;
+                        return Ok(builder.ins().load(types::I64, MemFlags::new(), ptr, 0));
+                    }
+                    "store" => {
+                        let ptr = self.compile_expr(builder, &args[0])?;
+                        let val = self.compile_expr(builder, &args[1])?;
+                        builder.ins().store(MemFlags::new(), val, ptr, 0);
+                        return Ok(builder.ins().iconst(types::I64, 0));
+                    }
+                    "sat_add" | "sat_sub" | "sat_mul" => {
+                        let lhs = self.compile_expr(builder, &args[0])?;
+                        let rhs = self.compile_expr(builder, &args[1])?;
+                        return Ok(Self::compile_saturating(builder, name.as_str(), lhs, rhs));
+                    }
+                    "min" | "max" => {
+                        let lhs = self.compile_expr(builder, &args[0])?;
+                        let rhs = self.compile_expr(builder, &args[1])?;
+                        return Ok(if name == "min" {
+                            builder.ins().smin(lhs, rhs)
+                        } else {
+                            builder.ins().smax(lhs, rhs)
+                        });
+                    }
+                    _ => {}
+                }
                 
-                Ok(result)
+                if Self::is_diverging_builtin(name.as_str()) {
+                    self.compile_diverging_call(builder, name.as_str(), args, *span)?;
+                    
+                    // Code using the result is unreachable; give it a fresh block
+                    let n = self.next_construct();
+                    let dead_bb = self.named_block(builder, "unreachable", n);
+                    builder.switch_to_block(dead_bb);
+                    builder.seal_block(dead_bb);
+                    return Ok(builder.ins().iconst(types::I64, 0));
+                }
+                
+                // Regular function call
+                // Every function is declared before any body is compiled
+                let callee_id = *self
+                    .functions
+                    .get(name)
+                    .ok_or_else(|| format!("Function {} was not declared", name))?;
+                let local_callee = self.module.declare_func_in_func(callee_id, builder.func);
+                let arg_values = self.compile_args(builder, *name, args)?;
+                
+                let call = builder.ins().call(local_callee, &arg_values);
+                Ok(builder.inst_results(call)[0])
             }
             
-            ast::Expr::Unary { op, operand } => {
-                let val = self.compile_expr(builder, operand)?;
+            ast::Expr::Array(_) => Err("Array literals can only be destructured".to_string()),
+            ast::Expr::Range { .. } => Err("Ranges can only be iterated over".to_string()),
+            
+            ast::Expr::Closure {
+                params,
+                param_spans,
+                body,
+                span,
+            } => {
+                let exprs = Arc::clone(&self.exprs);
+                self.compile_closure(builder, expr, params, param_spans, &exprs[*body], *span)
+            }
+            
+            ast::Expr::Loop { label, body, .. } => {
+                let n = self.next_construct();
+                let loop_body_bb = self.named_block(builder, "loop_body", n);
+                let exit_bb = self.named_block(builder, "loop_exit", n);
+                builder.append_block_param(exit_bb, types::I64);
                 
-                let result = match op {
-                    ast::UnaryOp::Neg => builder.ins().ineg(val),
-                    ast::UnaryOp::Not => {
-                        let cmp = builder.ins().icmp_imm(IntCC::Equal, val, 0);
-                        builder.ins().uextend(types::I64, cmp)
-                    }
-                };
+                builder.ins().jump(loop_body_bb, &[]);
                 
-                Ok(result)
+                // The body runs again until a `break` jumps to the exit
+                // with the loop's value
+                builder.switch_to_block(loop_body_bb);
+                self.loops.push(LoopTarget {
+                    yields_value: true,
+                    ..LoopTarget::new(*label, loop_body_bb, exit_bb)
+                });
+                let terminated = self.compile_block(builder, body)?;
+                self.loops.pop();
+                if !terminated {
+                    builder.ins().jump(loop_body_bb, &[]);
+                }
+                
+                // Seal body after back edge
+                builder.seal_block(loop_body_bb);
+                
+                builder.switch_to_block(exit_bb);
+                builder.seal_block(exit_bb);
+                Ok(builder.block_params(exit_bb)[0])
             }
             
-            ast::Expr::Call { name, args } => {
-                // Handle builtin print
-                if name == "print" {
-                    return self.compile_print_call(builder, &args[0]);
+            ast::Expr::If { cond, then_val, else_val } => {
+                let exprs = Arc::clone(&self.exprs);
+                
+                // A constant condition only needs the value it selects
+                if let Some(cond) = eval_const_expr(&exprs, &exprs[*cond], &HashMap::new()) {
+                    let taken = if cond != 0 { then_val } else { else_val };
+                    return self.compile_expr(builder, &exprs[*taken]);
                 }
                 
-                // Regular function call
-                let callee_id = *self.functions.get(name).unwrap();
-                let local_callee = self.module.declare_func_in_func(callee_id, builder.func);
+                let cond_val = self.compile_condition(builder, &exprs[*cond])?;
                 
-                let mut arg_values = Vec::new();
-                for arg in args {
-                    arg_values.push(self.compile_expr(builder, arg)?);
+                let n = self.next_construct();
+                let then_bb = self.named_block(builder, "then", n);
+                let else_bb = self.named_block(builder, "else", n);
+                let merge_bb = self.named_block(builder, "merge", n);
+                builder.append_block_param(merge_bb, types::I64);
+                
+                builder.ins().brif(cond_val, then_bb, &[], else_bb, &[]);
+                
+                // Each branch passes its value to the merge block
+                for (block, value) in [(then_bb, then_val), (else_bb, else_val)] {
+                    builder.switch_to_block(block);
+                    builder.seal_block(block);
+                    let val = self.compile_expr(builder, &exprs[*value])?;
+                    builder.ins().jump(merge_bb, &[val]);
                 }
                 
-                let call = builder.ins().call(local_callee, &arg_values);
-                Ok(builder.inst_results(call)[0])
+                builder.switch_to_block(merge_bb);
+                builder.seal_block(merge_bb);
+                Ok(builder.block_params(merge_bb)[0])
             }
         }
     }
     
-    fn compile_print_call(
+    /// Compiles the condition of a branch to a boolean: any nonzero value
+    /// is true
+    fn compile_condition(&mut self, builder: &mut FunctionBuilder, cond: &ast::Expr) -> Result<Value, String> {
+        let val = self.compile_expr(builder, cond)?;
+        Ok(builder.ins().icmp_imm(IntCC::NotEqual, val, 0))
+    }
+    
+    /// Allocates the environment of a closure, copying in the current
+    /// values of the variables it captures, and queues its body to be
+    /// compiled as a function of its own
+    fn compile_closure(
         &mut self,
         builder: &mut FunctionBuilder,
-        arg: &ast::Expr,
+        closure: &ast::Expr,
+        params: &[Symbol],
+        param_spans: &[ast::Span],
+        body: &ast::Expr,
+        span: ast::Span,
     ) -> Result<Value, String> {
-        let val = self.compile_expr(builder, arg)?;
+        let captures: Vec<Capture> = closure
+            .free_names(&self.exprs)
+            .into_iter()
+            .filter(|name| self.lookup_binding(*name).is_ok())
+            .map(|name| Capture {
+                name,
+                unsigned: self.is_unsigned_var(name),
+                string: self.is_string_var(name),
+            })
+            .collect();
         
-        // Declare print_int external function
+        let name = Symbol::intern(&format!("closure.{}", self.closure_counter));
+        self.closure_counter += 1;
         let mut sig = self.module.make_signature();
-        sig.params.push(AbiParam::new(types::I64));
+        for _ in 0..params.len() + 1 {
+            sig.params.push(AbiParam::new(types::I64));
+        }
         sig.returns.push(AbiParam::new(types::I64));
+        let func_id = self
+            .module
+            .declare_function(name.as_str(), Linkage::Local, &sig)
+            .map_err(|e| e.to_string())?;
+        self.functions.insert(name, func_id);
+        
+        // The environment parameter's name cannot clash with source names
+        let mut func_params = vec![Symbol::intern(".env")];
+        func_params.extend_from_slice(params);
+        let mut func_param_spans = vec![span];
+        func_param_spans.extend_from_slice(param_spans);
+        self.pending_closures.push(PendingClosure {
+            func: ast::Function {
+                name,
+                defaults: vec![None; func_params.len()],
+                variadic: None,
+                params: func_params,
+                param_spans: func_param_spans,
+                body: ast::Block {
+                    statements: Vec::new(),
+                    tail: Some(body.clone()),
+                },
+                span,
+            },
+            captures: captures.clone(),
+        });
+        
+        let size = builder.ins().iconst(types::I64, 8 * (2 + captures.len() as i64));
+        let env = self.call_runtime(builder, "edust_alloc_closure", &[size])?;
+        let local_func = self.module.declare_func_in_func(func_id, builder.func);
+        let code = builder.ins().func_addr(types::I64, local_func);
+        builder.ins().store(MemFlags::trusted(), code, env, CLOSURE_CODE);
+        let arity = builder.ins().iconst(types::I64, params.len() as i64);
+        builder.ins().store(MemFlags::trusted(), arity, env, CLOSURE_ARITY);
+        for (i, capture) in captures.iter().enumerate() {
+            let val = self.read_variable(builder, capture.name)?;
+            let offset = CLOSURE_CAPTURES + 8 * i as i32;
+            builder.ins().store(MemFlags::trusted(), val, env, offset);
+        }
+        
+        Ok(env)
+    }
+    
+    /// Calls the closure held in the variable `name`, aborting through
+    /// `edust_check_closure` if it holds something else, or through
+    /// `edust_bad_closure_call` if it takes a different number of arguments
+    fn compile_closure_call(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        name: Symbol,
+        args: &[ast::Expr],
+        span: ast::Span,
+    ) -> Result<Value, String> {
+        let env = self.read_variable(builder, name)?;
+        let mut arg_values = vec![env];
+        for arg in args {
+            arg_values.push(self.compile_expr(builder, arg)?);
+        }
+        
+        let check = self.import_runtime(builder, "edust_check_closure", 2, false)?;
+        let line = builder.ins().iconst(types::I64, span.line as i64);
+        builder.ins().call(check, &[env, line]);
+        
+        let arity = builder.ins().load(types::I64, MemFlags::trusted(), env, CLOSURE_ARITY);
+        let matches = builder.ins().icmp_imm(IntCC::Equal, arity, args.len() as i64);
+        let n = self.next_construct();
+        let fail_bb = self.named_block(builder, "check_failed", n);
+        let cont_bb = self.named_block(builder, "check_ok", n);
+        builder.set_cold_block(fail_bb);
+        builder.ins().brif(matches, cont_bb, &[], fail_bb, &[]);
+        
+        builder.switch_to_block(fail_bb);
+        builder.seal_block(fail_bb);
+        let bad_call = self.import_runtime(builder, "edust_bad_closure_call", 1, false)?;
+        builder.ins().call(bad_call, &[line]);
+        builder.ins().trap(UNREACHABLE_TRAP);
+        
+        builder.switch_to_block(cont_bb);
+        builder.seal_block(cont_bb);
+        let code = builder.ins().load(types::I64, MemFlags::trusted(), env, CLOSURE_CODE);
+        let mut sig = self.module.make_signature();
+        for _ in 0..arg_values.len() {
+            sig.params.push(AbiParam::new(types::I64));
+        }
+        sig.returns.push(AbiParam::new(types::I64));
+        let sig_ref = builder.import_signature(sig);
+        let call = builder.ins().call_indirect(sig_ref, code, &arg_values);
+        Ok(builder.inst_results(call)[0])
+    }
+    
+    /// Reads variadic argument `index` of the current function, aborting
+    /// through `edust_bad_vararg` if there is no such argument
+    fn compile_args_get(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        index: Value,
+        span: ast::Span,
+    ) -> Result<Value, String> {
+        let buffer = self.read_variable(builder, Symbol::intern(VARARGS))?;
+        let count = self.read_variable(builder, Symbol::intern(VARARGS_COUNT))?;
+        
+        // Unsigned, so negative indices are out of range too
+        let in_range = builder.ins().icmp(IntCC::UnsignedLessThan, index, count);
+        let n = self.next_construct();
+        let fail_bb = self.named_block(builder, "check_failed", n);
+        let cont_bb = self.named_block(builder, "check_ok", n);
+        builder.set_cold_block(fail_bb);
+        builder.ins().brif(in_range, cont_bb, &[], fail_bb, &[]);
+        
+        builder.switch_to_block(fail_bb);
+        builder.seal_block(fail_bb);
+        let bad_vararg = self.import_runtime(builder, "edust_bad_vararg", 3, false)?;
+        let line = builder.ins().iconst(types::I64, span.line as i64);
+        builder.ins().call(bad_vararg, &[index, count, line]);
+        builder.ins().trap(UNREACHABLE_TRAP);
+        
+        builder.switch_to_block(cont_bb);
+        builder.seal_block(cont_bb);
+        let offset = builder.ins().imul_imm(index, 8);
+        let addr = builder.ins().iadd(buffer, offset);
+        Ok(builder.ins().load(types::I64, MemFlags::trusted(), addr, 0))
+    }
+    
+    /// Lowers `sat_add`, `sat_sub` or `sat_mul`: on overflow the result is
+    /// clamped to `i64::MIN` or `i64::MAX`, whichever the exact result lies
+    /// beyond.
+    fn compile_saturating(builder: &mut FunctionBuilder, name: &str, lhs: Value, rhs: Value) -> Value {
+        let (result, overflow) = match name {
+            "sat_add" => builder.ins().sadd_overflow(lhs, rhs),
+            "sat_sub" => builder.ins().ssub_overflow(lhs, rhs),
+            _ => builder.ins().smul_overflow(lhs, rhs),
+        };
         
-        let print_func = self
+        // A sum or difference overflows in the direction of `lhs`; a
+        // product is negative when exactly one operand is
+        let sign = if name == "sat_mul" {
+            builder.ins().bxor(lhs, rhs)
+        } else {
+            lhs
+        };
+        let negative = builder.ins().icmp_imm(IntCC::SignedLessThan, sign, 0);
+        let min = builder.ins().iconst(types::I64, i64::MIN);
+        let max = builder.ins().iconst(types::I64, i64::MAX);
+        let bound = builder.ins().select(negative, min, max);
+        
+        builder.ins().select(overflow, bound, result)
+    }
+    
+    /// Applies `op` to two compiled operands, as `u64` values if `unsigned`
+    fn compile_binary(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        op: ast::BinOp,
+        lhs: Value,
+        rhs: Value,
+        unsigned: bool,
+    ) -> Result<Value, String> {
+        // Picks the signed or unsigned variant of a comparison
+        let cc = |signed, unsigned_cc| if unsigned { unsigned_cc } else { signed };
+        
+        let result = match op {
+            ast::BinOp::Add => builder.ins().iadd(lhs, rhs),
+            ast::BinOp::Sub => builder.ins().isub(lhs, rhs),
+            ast::BinOp::Mul => builder.ins().imul(lhs, rhs),
+            ast::BinOp::Div if unsigned => builder.ins().udiv(lhs, rhs),
+            ast::BinOp::Div => builder.ins().sdiv(lhs, rhs),
+            ast::BinOp::Mod if unsigned => builder.ins().urem(lhs, rhs),
+            ast::BinOp::Mod => builder.ins().srem(lhs, rhs),
+            ast::BinOp::Pow => self.call_runtime(builder, "edust_ipow", &[lhs, rhs])?,
+            
+            ast::BinOp::Lt => {
+                let cond = cc(IntCC::SignedLessThan, IntCC::UnsignedLessThan);
+                let cmp = builder.ins().icmp(cond, lhs, rhs);
+                builder.ins().uextend(types::I64, cmp)
+            }
+            ast::BinOp::Le => {
+                let cond = cc(IntCC::SignedLessThanOrEqual, IntCC::UnsignedLessThanOrEqual);
+                let cmp = builder.ins().icmp(cond, lhs, rhs);
+                builder.ins().uextend(types::I64, cmp)
+            }
+            ast::BinOp::Gt => {
+                let cond = cc(IntCC::SignedGreaterThan, IntCC::UnsignedGreaterThan);
+                let cmp = builder.ins().icmp(cond, lhs, rhs);
+                builder.ins().uextend(types::I64, cmp)
+            }
+            ast::BinOp::Ge => {
+                let cond = cc(IntCC::SignedGreaterThanOrEqual, IntCC::UnsignedGreaterThanOrEqual);
+                let cmp = builder.ins().icmp(cond, lhs, rhs);
+                builder.ins().uextend(types::I64, cmp)
+            }
+            ast::BinOp::Eq => {
+                let cmp = builder.ins().icmp(IntCC::Equal, lhs, rhs);
+                builder.ins().uextend(types::I64, cmp)
+            }
+            ast::BinOp::Ne => {
+                let cmp = builder.ins().icmp(IntCC::NotEqual, lhs, rhs);
+                builder.ins().uextend(types::I64, cmp)
+            }
+            
+            ast::BinOp::And => {
+                let lhs_bool = builder.ins().icmp_imm(IntCC::NotEqual, lhs, 0);
+                let rhs_bool = builder.ins().icmp_imm(IntCC::NotEqual, rhs, 0);
+                let result = builder.ins().band(lhs_bool, rhs_bool);
+                builder.ins().uextend(types::I64, result)
+            }
+            ast::BinOp::Or => {
+                let lhs_bool = builder.ins().icmp_imm(IntCC::NotEqual, lhs, 0);
+                let rhs_bool = builder.ins().icmp_imm(IntCC::NotEqual, rhs, 0);
+                let result = builder.ins().bor(lhs_bool, rhs_bool);
+                builder.ins().uextend(types::I64, result)
+            }
+        };
+        
+        Ok(result)
+    }
+    
+    /// Wraps a 64-bit value to the width of `target` and extends it back to
+    /// 64 bits, sign- or zero-filling depending on the target's signedness.
+    fn compile_cast(builder: &mut FunctionBuilder, val: Value, target: ast::Type) -> Value {
+        let narrow = match target.bits() {
+            8 => types::I8,
+            16 => types::I16,
+            32 => types::I32,
+            _ => return val, // Already 64 bits wide: no-op
+        };
+        
+        let reduced = builder.ins().ireduce(narrow, val);
+        if target.is_signed() {
+            builder.ins().sextend(types::I64, reduced)
+        } else {
+            builder.ins().uextend(types::I64, reduced)
+        }
+    }
+    
+    /// Imports a runtime helper taking `param_count` `i64` values and
+    /// returning one `i64` (or nothing, for helpers that never return).
+    fn import_runtime(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        name: &str,
+        param_count: usize,
+        returns_value: bool,
+    ) -> Result<codegen::ir::FuncRef, String> {
+        let mut sig = self.module.make_signature();
+        for _ in 0..param_count {
+            sig.params.push(AbiParam::new(types::I64));
+        }
+        if returns_value {
+            sig.returns.push(AbiParam::new(types::I64));
+        }
+        
+        let func = self
             .module
-            .declare_function("print_int", Linkage::Import, &sig)
+            .declare_function(name, Linkage::Import, &sig)
             .map_err(|e| e.to_string())?;
         
-        let local_print = self.module.declare_func_in_func(print_func, builder.func);
+        Ok(self.module.declare_func_in_func(func, builder.func))
+    }
+    
+    /// Return the value of `value`, turning self tail calls into a jump
+    fn compile_return(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        value: &ast::Expr,
+    ) -> Result<(), String> {
+        if let ast::Expr::Call { name, args, .. } = value
+            && let Some(target) = self.tail_call.clone()
+            && target.name == *name
+        {
+            // Evaluate every argument before rebinding any parameter
+            let arg_values = self.compile_args(builder, *name, args)?;
+            for (var, val) in target.params.iter().zip(arg_values) {
+                builder.def_var(*var, val);
+            }
+            builder.ins().jump(target.block, &[]);
+            return Ok(());
+        }
+        
+        let val = self.compile_expr(builder, value)?;
+        self.emit_return(builder, val)
+    }
+    
+    /// Compiles the arguments of a call to `name`, followed by the default
+    /// values of any trailing parameters the call leaves out. Arguments
+    /// beyond the parameters of a variadic function are stored in a stack
+    /// buffer, passed as its address and their count.
+    fn compile_args(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        name: Symbol,
+        args: &[ast::Expr],
+    ) -> Result<Vec<Value>, String> {
+        let mut arg_values = Vec::new();
+        for arg in args {
+            arg_values.push(self.compile_expr(builder, arg)?);
+        }
+        
+        let defaults = self.defaults.get(&name).cloned().unwrap_or_default();
+        for default in defaults.iter().skip(args.len()) {
+            let default = default
+                .as_ref()
+                .ok_or_else(|| format!("Missing argument in call to {}", name))?;
+            arg_values.push(self.compile_expr(builder, default)?);
+        }
         
-        let call = builder.ins().call(local_print, &[val]);
+        if self.variadic.contains(&name) {
+            let extra = arg_values.split_off(defaults.len());
+            let buffer = if extra.is_empty() {
+                builder.ins().iconst(types::I64, 0)
+            } else {
+                let size = 8 * extra.len() as u32;
+                let slot = builder.create_sized_stack_slot(StackSlotData::new(StackSlotKind::ExplicitSlot, size, 3));
+                for (i, value) in extra.iter().enumerate() {
+                    builder.ins().stack_store(*value, slot, 8 * i as i32);
+                }
+                builder.ins().stack_addr(types::I64, slot, 0)
+            };
+            arg_values.push(buffer);
+            arg_values.push(builder.ins().iconst(types::I64, extra.len() as i64));
+        }
+        Ok(arg_values)
+    }
+    
+    /// Return `val` from the current function, first leaving the call
+    /// depth counter when the recursion limit is on
+    fn emit_return(&mut self, builder: &mut FunctionBuilder, val: Value) -> Result<(), String> {
+        if self.recursion_limit.is_some() {
+            let leave = self.import_runtime(builder, "edust_leave", 0, false)?;
+            builder.ins().call(leave, &[]);
+        }
+        builder.ins().return_(&[val]);
+        Ok(())
+    }
+    
+    /// Calls a runtime helper taking and returning `i64` values.
+    fn call_runtime(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        name: &str,
+        args: &[Value],
+    ) -> Result<Value, String> {
+        let local_func = self.import_runtime(builder, name, args.len(), true)?;
+        let call = builder.ins().call(local_func, args);
         Ok(builder.inst_results(call)[0])
     }
-}
\ No newline at end of file
+    
+    /// Whether `block` contains `return name(...)`, a call to the enclosing
+    /// function in tail position. Nested function bodies are not searched.
+    fn block_has_self_tail_call(block: &ast::Block, name: Symbol) -> bool {
+        if let Some(ast::Expr::Call { name: callee, .. }) = &block.tail
+            && *callee == name
+        {
+            return true;
+        }
+        
+        block.statements.iter().any(|stmt| match stmt {
+            ast::Statement::Return {
+                value: ast::Expr::Call { name: callee, .. },
+                ..
+            } => *callee == name,
+            ast::Statement::If {
+                then_block,
+                else_block,
+                ..
+            } => {
+                Self::block_has_self_tail_call(then_block, name)
+                    || else_block
+                        .as_ref()
+                        .is_some_and(|blk| Self::block_has_self_tail_call(blk, name))
+            }
+            ast::Statement::While { body, .. }
+            | ast::Statement::DoWhile { body, .. }
+            | ast::Statement::ForIn { body, .. } => {
+                Self::block_has_self_tail_call(body, name)
+            }
+            _ => false,
+        })
+    }
+    
+    /// Lowers `len(array)` to the array's length. Arrays only exist as
+    /// literals, so it is a constant; the elements are still evaluated.
+    fn compile_len(&mut self, builder: &mut FunctionBuilder, array: &ast::Expr) -> Result<Value, String> {
+        let ast::Expr::Array(elements) = array else {
+            return Err("len() of something other than an array literal".to_string());
+        };
+        for element in elements {
+            self.compile_expr(builder, element)?;
+        }
+        Ok(builder.ins().iconst(types::I64, elements.len() as i64))
+    }
+    
+    /// Builtins that never return control to the caller
+    fn is_diverging_builtin(name: &str) -> bool {
+        matches!(name, "exit" | "panic" | "todo" | "unimplemented")
+    }
+    
+    /// Emits a call to a diverging builtin followed by a trap, filling the
+    /// current block. `todo` and `unimplemented` pass the call-site line.
+    fn compile_diverging_call(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        name: &str,
+        args: &[ast::Expr],
+        span: ast::Span,
+    ) -> Result<(), String> {
+        let mut arg_values = Vec::new();
+        for arg in args {
+            arg_values.push(self.compile_expr(builder, arg)?);
+        }
+        
+        let runtime_name = match name {
+            "exit" => "edust_exit",
+            "panic" => "edust_panic",
+            "todo" | "unimplemented" => {
+                arg_values.push(builder.ins().iconst(types::I64, span.line as i64));
+                "edust_todo"
+            }
+            _ => return Err(format!("Unknown diverging builtin: {}", name)),
+        };
+        
+        let func = self.import_runtime(builder, runtime_name, arg_values.len(), false)?;
+        builder.ins().call(func, &arg_values);
+        builder.ins().trap(UNREACHABLE_TRAP);
+        
+        Ok(())
+    }
+    
+    /// Lowers `assert(cond)` to a branch that calls `edust_assert_failed`
+    /// with the call-site line when `cond` is zero.
+    fn compile_assert_call(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        cond: &ast::Expr,
+        span: ast::Span,
+    ) -> Result<Value, String> {
+        let cond_val = self.compile_condition(builder, cond)?;
+        
+        let n = self.next_construct();
+        let fail_bb = self.named_block(builder, "check_failed", n);
+        let cont_bb = self.named_block(builder, "check_ok", n);
+        builder.set_cold_block(fail_bb);
+        
+        builder.ins().brif(cond_val, cont_bb, &[], fail_bb, &[]);
+        
+        // Failure path never returns
+        builder.switch_to_block(fail_bb);
+        builder.seal_block(fail_bb);
+        let assert_failed = self.import_runtime(builder, "edust_assert_failed", 1, false)?;
+        let line = builder.ins().iconst(types::I64, span.line as i64);
+        builder.ins().call(assert_failed, &[line]);
+        builder.ins().trap(UNREACHABLE_TRAP);
+        
+        builder.switch_to_block(cont_bb);
+        builder.seal_block(cont_bb);
+        
+        Ok(builder.ins().iconst(types::I64, 0))
+    }
+    
+    /// Lowers `assert_eq(lhs, rhs)` to a comparison that, when the values
+    /// differ, calls `edust_assert_eq_failed` with both and the line.
+    fn compile_assert_eq_call(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        lhs: &ast::Expr,
+        rhs: &ast::Expr,
+        span: ast::Span,
+    ) -> Result<Value, String> {
+        let lhs_val = self.compile_expr(builder, lhs)?;
+        let rhs_val = self.compile_expr(builder, rhs)?;
+        let equal = builder.ins().icmp(IntCC::Equal, lhs_val, rhs_val);
+        
+        let n = self.next_construct();
+        let fail_bb = self.named_block(builder, "check_failed", n);
+        let cont_bb = self.named_block(builder, "check_ok", n);
+        builder.set_cold_block(fail_bb);
+        
+        builder.ins().brif(equal, cont_bb, &[], fail_bb, &[]);
+        
+        builder.switch_to_block(fail_bb);
+        builder.seal_block(fail_bb);
+        let assert_failed = self.import_runtime(builder, "edust_assert_eq_failed", 3, false)?;
+        let line = builder.ins().iconst(types::I64, span.line as i64);
+        builder.ins().call(assert_failed, &[lhs_val, rhs_val, line]);
+        builder.ins().trap(UNREACHABLE_TRAP);
+        
+        builder.switch_to_block(cont_bb);
+        builder.seal_block(cont_bb);
+        
+        Ok(builder.ins().iconst(types::I64, 0))
+    }
+    
+    /// Print `args` separated by spaces, ending the line if `newline` is
+    /// set. The value is the last argument.
+    fn compile_print_call(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        args: &[ast::Expr],
+        newline: bool,
+    ) -> Result<Value, String> {
+        // Semantic analysis reports this too, but may have been skipped
+        if args.is_empty() {
+            let name = if newline { "print" } else { "write" };
+            return Err(format!("{}() requires at least 1 argument", name));
+        }
+        
+        let mut values = Vec::new();
+        for arg in args {
+            let is_string = arg.is_string(&self.exprs, &|name| self.is_string_var(name));
+            values.push((self.compile_expr(builder, arg)?, is_string));
+        }
+        
+        let print_sep = self.import_runtime(builder, "print_sep", 0, false)?;
+        let mut result = None;
+        for (i, (val, is_string)) in values.into_iter().enumerate() {
+            if i > 0 {
+                builder.ins().call(print_sep, &[]);
+            }
+            let print = if is_string { "print_str" } else { "print_int" };
+            result = Some(self.call_runtime(builder, print, &[val])?);
+        }
+        
+        if newline {
+            let print_newline = self.import_runtime(builder, "print_newline", 0, false)?;
+            builder.ins().call(print_newline, &[]);
+        }
+        
+        Ok(result.expect("print has an argument"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    
+    fn parse(source: &str) -> ast::Program {
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        Parser::new(tokens).parse().unwrap()
+    }
+    
+    #[test]
+    fn test_reuse_generator_across_programs() {
+        let mut codegen = CodeGenerator::new();
+        let mut entry_points = Vec::new();
+        
+        for i in 0..100 {
+            let source = format!(
+                "func helper() {{ return {}; }} func main() {{ return helper() * 2; }}",
+                i
+            );
+            let program = parse(&source);
+            entry_points.push(codegen.compile(&program).unwrap());
+        }
+        
+        // Every program, including ones compiled before a reset, still runs
+        for (i, jit) in entry_points.into_iter().enumerate() {
+            assert_eq!(jit.run(), Ok(i as i64 * 2));
+        }
+    }
+    
+    #[test]
+    fn test_call_functions_by_name() {
+        let source = r#"
+            func weighted(a, b) {
+                return a * 10 + b;
+            }
+            
+            func sum6(a, b, c, d, e, f) {
+                func twice(x) {
+                    return x * 2;
+                }
+                return a + b + c + d + e + twice(f);
+            }
+            
+            func seven() {
+                return 7;
+            }
+            
+            func scaled_sum(scale, terms...) {
+                let total = 0;
+                let i = 0;
+                while i < args_count() {
+                    total = total + args_get(i);
+                    i = i + 1;
+                }
+                return scale * total;
+            }
+        "#;
+        let program = parse(source);
+        let mut codegen = CodeGenerator::new();
+        let jit = codegen.compile_all(&program).unwrap();
+        
+        assert_eq!(jit.arity("scaled_sum"), Some(1));
+        assert_eq!(jit.call("scaled_sum", &[3]), Ok(0));
+        assert_eq!(jit.call("scaled_sum", &[3, 1, 2, 3, 4, 5, 6, 7, 8, 9]), Ok(135));
+        assert_eq!(
+            jit.call("scaled_sum", &[]),
+            Err("Function scaled_sum expects at least 1 arguments, got 0".to_string())
+        );
+        
+        assert_eq!(jit.arity("weighted"), Some(2));
+        assert_eq!(jit.call("weighted", &[4, 2]), Ok(42));
+        assert_eq!(jit.call("sum6", &[1, 2, 3, 4, 5, 6]), Ok(27));
+        assert_eq!(jit.call("twice", &[-4]), Ok(-8));
+        assert_eq!(jit.call("seven", &[]), Ok(7));
+        
+        assert_eq!(jit.call("missing", &[]), Err("Undefined function: missing".to_string()));
+        assert_eq!(
+            jit.call("weighted", &[1]),
+            Err("Function weighted expects 2 arguments, got 1".to_string())
+        );
+        
+        // The raw pointer works with the signature the arity implies
+        let weighted = jit.get_function_ptr("weighted").unwrap();
+        let weighted: extern "C" fn(i64, i64) -> i64 = unsafe { std::mem::transmute(weighted) };
+        assert_eq!(weighted(3, 9), 39);
+        assert!(jit.get_function_ptr("main").is_none());
+    
+    }
+    
+    #[test]
+    fn test_run_program_twice() {
+        let program = parse("func main() { return 6 * 7; }");
+        let mut codegen = CodeGenerator::new();
+        let jit = codegen.compile_all(&program).unwrap();
+        
+        // Neither compiling something else nor dropping the generator
+        // touches the program's code
+        codegen.compile_all(&parse("func main() { return 1; }")).unwrap();
+        drop(codegen);
+        assert_eq!(jit.run(), Ok(42));
+        assert_eq!(jit.run(), Ok(42));
+        
+        let jit = CodeGenerator::new().compile_all(&parse("func helper() { return 1; }")).unwrap();
+        assert_eq!(jit.run(), Err("No main function found".to_string()));
+    }
+    
+    #[test]
+    fn test_function_ptr_lives_with_program() {
+        let program = parse("func triple(n) { return n * 3; } func main() { return 0; }");
+        let mut codegen = CodeGenerator::new();
+        let jit = codegen.compile(&program).unwrap();
+        let triple = jit.get_function_ptr("triple").unwrap();
+        let triple: extern "C" fn(i64) -> i64 = unsafe { std::mem::transmute(triple) };
+        
+        // Other programs come and go, their code freed as each is dropped,
+        // while the one holding `triple` stays alive
+        for i in 0..50 {
+            let source = format!("func triple(n) {{ return {}; }} func main() {{ return triple(0); }}", i);
+            assert_eq!(codegen.compile(&parse(&source)).unwrap().run(), Ok(i));
+        }
+        drop(codegen);
+        assert_eq!(triple(14), 42);
+        
+        // `triple` must not be called past this point
+        drop(jit);
+    }
+    
+    #[test]
+    fn test_parallel_compilation() {
+        // A chain of functions, each calling the one before, plus closures
+        let mut source = String::from("func f0(n) { return n + 1; }\n");
+        for i in 1..200 {
+            source.push_str(&format!(
+                "func f{i}(n) {{ let g = |m| f{prev}(m) * 3 % 1000003; return g(n) + {i}; }}\n",
+                prev = i - 1
+            ));
+        }
+        source.push_str("func main() { return f199(7); }\n");
+        let program = parse(&source);
+        
+        let mut results = Vec::new();
+        for parallel in [false, true] {
+            let mut codegen = CodeGenerator::new().with_parallel(parallel);
+            results.push(codegen.compile(&program).unwrap().run().unwrap());
+        }
+        assert_eq!(results[0], results[1]);
+        
+        // Both ways produce the same machine code, byte for byte
+        let objects: Vec<Vec<u8>> = [false, true]
+            .into_iter()
+            .map(|parallel| {
+                let mut codegen = CodeGenerator::new_object_for("x86_64-unknown-linux-gnu")
+                    .unwrap()
+                    .with_parallel(parallel)
+                    .with_debug_info("chain.edust");
+                codegen.compile_object(&program).unwrap()
+            })
+            .collect();
+        assert!(objects[0] == objects[1]);
+    }
+    
+    #[test]
+    fn test_constant_if_drops_dead_branch() {
+        let program = parse(
+            r#"
+            func main() {
+                let x = 0;
+                if 2 * 3 == 7 {
+                    print(1);
+                } else {
+                    x = 5;
+                }
+                while 1 - 1 {
+                    print(2);
+                }
+                if x {
+                    x = x + 1;
+                }
+                return x;
+            }
+            "#,
+        );
+        
+        let ir = CodeGenerator::new().compile_to_ir(&program).unwrap();
+        assert!(!ir.contains("call"), "{}", ir);
+        
+        // Only the non-constant `if` needs a conditional branch
+        assert_eq!(ir.matches("brif").count(), 1, "{}", ir);
+        
+        assert_eq!(CodeGenerator::new().compile(&program).unwrap().run(), Ok(6));
+    }
+    
+    #[test]
+    fn test_conditions_branch_on_nonzero() {
+        let program = parse(
+            r#"
+            func pick(x) {
+                if x {
+                    return 1;
+                }
+                return 0;
+            }
+            
+            func main() {
+                return pick(7) * 10 + pick(-1) * 100 + pick(0);
+            }
+            "#,
+        );
+        
+        // The condition is tested against zero, not passed to brif as is
+        let ir = CodeGenerator::new().compile_to_ir(&program).unwrap();
+        let test = ir.find("icmp_imm ne").expect("condition compared to zero");
+        assert!(test < ir.find("brif").unwrap(), "{}", ir);
+        
+        assert_eq!(CodeGenerator::new().compile(&program).unwrap().run(), Ok(110));
+    }
+    
+    #[test]
+    fn test_ir_names() {
+        let source = r#"
+            func main() {
+                let i = 0;
+                let total = 0;
+                while i < 10 {
+                    if i % 2 == 0 {
+                        total = total + i;
+                    }
+                    i = i + 1;
+                }
+                return total;
+            }
+        "#;
+        let ir = CodeGenerator::new().compile_to_ir(&parse(source)).unwrap();
+        
+        for name in ["entry0", "loop_header1", "loop_body1", "loop_exit1", "then2", "else2", "merge2"] {
+            assert!(ir.contains(&format!("  ; {}", name)), "no {} in {}", name, ir);
+        }
+        assert!(ir.contains("; total = v1\n"), "{}", ir);
+        assert!(ir.contains("; v16 = 1, i = v17\n"), "{}", ir);
+        
+        // Names don't depend on anything but the program
+        let again = CodeGenerator::new().compile_to_ir(&parse(source)).unwrap();
+        assert_eq!(ir, again);
+    }
+    
+    #[test]
+    fn test_cfg_dot() {
+        let source = r#"
+            func main() {
+                let x = 1;
+                if x > 0 {
+                    x = 2;
+                } else {
+                    x = 3;
+                }
+                return x;
+            }
+        "#;
+        let dot = CodeGenerator::new().emit_cfg_dot(&parse(source)).unwrap();
+        
+        assert!(dot.starts_with("digraph cfg {\n"), "{}", dot);
+        assert!(dot.contains("subgraph \"cluster_main\""), "{}", dot);
+        assert!(dot.matches("[label=\"block").count() >= 4, "{}", dot);
+        for edge in [
+            "\"main.block0\" -> \"main.block1\" [label=\"true\"];",
+            "\"main.block0\" -> \"main.block2\" [label=\"false\"];",
+            "\"main.block1\" -> \"main.block3\";",
+            "\"main.block2\" -> \"main.block3\";",
+        ] {
+            assert!(dot.contains(edge), "no {} in {}", edge, dot);
+        }
+        assert!(dot.contains("\"main.block1\" [label=\"block1: then1\"];"), "{}", dot);
+    }
+    
+    #[test]
+    fn test_mutual_recursion() {
+        // is_odd is defined after is_even calls it, and the nested helper
+        // calls back into a top-level function
+        let program = parse(
+            r#"
+            func is_even(n) {
+                if n == 0 {
+                    return 1;
+                }
+                return is_odd(n - 1);
+            }
+            
+            func is_odd(n) {
+                func step(m) {
+                    return is_even(m - 1);
+                }
+                if n == 0 {
+                    return 0;
+                }
+                return step(n);
+            }
+            
+            func main() {
+                return is_even(10) * 10 + is_odd(7);
+            }
+            "#,
+        );
+        
+        let ir = CodeGenerator::new().compile_to_ir(&program).unwrap();
+        assert!(ir.contains("; is_odd"));
+        
+        assert_eq!(CodeGenerator::new().compile(&program).unwrap().run(), Ok(11));
+    }
+    
+    #[test]
+    fn test_inner_variable_shadows_outer() {
+        let program = parse(
+            r#"
+            func main(n) {
+                let x = 1;
+                let total = 0;
+                if n {
+                    let x = 10;
+                    total = total + x;
+                    x = x + 1;
+                    total = total + x;
+                }
+                total = total + x;
+                let i = 0;
+                while i < 2 {
+                    let x = x + i * 100;
+                    total = total + x;
+                    i = i + 1;
+                }
+                return total * 10 + x;
+            }
+            "#,
+        );
+        
+        let jit = CodeGenerator::new().compile(&program).unwrap();
+        let main_fn = |flag| jit.call("main", &[flag]).unwrap();
+        
+        // 10 + 11 inside the `if`, then the outer 1, then 1 and 101 from
+        // the loop; the outer x is still 1 at the end
+        assert_eq!(main_fn(1), (10 + 11 + 1 + 1 + 101) * 10 + 1);
+        assert_eq!(main_fn(0), (1 + 1 + 101) * 10 + 1);
+    }
+    
+    #[test]
+    fn test_opt_levels_agree() {
+        let program = parse(
+            r#"
+            func fib(n) {
+                if n <= 1 {
+                    return n;
+                }
+                return fib(n - 1) + fib(n - 2);
+            }
+            
+            func main() {
+                let i = 0;
+                let total = 0;
+                while i < 15 {
+                    total = total + fib(i) * (i % 3);
+                    i = i + 1;
+                }
+                return total;
+            }
+            "#,
+        );
+        
+        let mut results = Vec::new();
+        for level in ["none", "speed", "speed_and_size"] {
+            let mut codegen = CodeGenerator::with_opt_level(level).unwrap();
+            assert_eq!(codegen.opt_level(), level);
+            
+            results.push(codegen.compile(&program).unwrap().run().unwrap());
+        }
+        
+        assert_eq!(results, vec![results[0]; 3]);
+        assert!(CodeGenerator::with_opt_level("fastest").is_err());
+    }
+    
+    #[test]
+    fn test_object_for_other_targets() {
+        use object::{Architecture, BinaryFormat, Object, ObjectSymbol};
+        
+        let program = parse("func twice(n) { return n * 2; } func main() { print(twice(21)); return 0; }");
+        let targets = [
+            ("aarch64-apple-darwin", Architecture::Aarch64, BinaryFormat::MachO),
+            ("x86_64-unknown-linux-gnu", Architecture::X86_64, BinaryFormat::Elf),
+            ("riscv64gc-unknown-linux-gnu", Architecture::Riscv64, BinaryFormat::Elf),
+        ];
+        
+        for (triple, architecture, format) in targets {
+            let mut codegen = CodeGenerator::new_object_for(triple).unwrap();
+            let bytes = codegen.compile_object(&program).unwrap();
+            
+            let file = object::File::parse(&*bytes).unwrap();
+            assert_eq!(file.architecture(), architecture, "{}", triple);
+            assert_eq!(file.format(), format, "{}", triple);
+            
+            // Functions are defined, runtime calls are left to the linker
+            let symbols: Vec<_> = file.symbols().collect();
+            let named = |name: &str| {
+                symbols
+                    .iter()
+                    .find(|s| s.name().is_ok_and(|n| n.trim_start_matches('_') == name))
+                    .unwrap_or_else(|| panic!("{}: no symbol {}", triple, name))
+            };
+            assert!(named("main").is_definition());
+            assert!(named("twice").is_definition());
+            assert!(named("print_int").is_undefined());
+            
+            // The generator can be reused for another program
+            assert!(codegen.compile_object(&program).is_ok());
+        }
+    }
+    
+    #[test]
+    fn test_object_reuse_with_string_literals() {
+        // The second module needs its own data for the literal
+        let mut codegen = CodeGenerator::new_object_for("x86_64-unknown-linux-gnu").unwrap();
+        let first = parse(r#"func main() { print("hi"); return 0; }"#);
+        let second = parse(r#"func main() { let s = "hi"; print(s + "!"); return 1; }"#);
+        assert!(codegen.compile_object(&first).is_ok());
+        assert!(codegen.compile_object(&second).is_ok());
+    }
+    
+    #[test]
+    fn test_object_reuse_with_other_signatures() {
+        // `f` is variadic, then takes a default, then neither
+        let mut codegen = CodeGenerator::new_object_for("x86_64-unknown-linux-gnu").unwrap();
+        let programs = [
+            "func f(rest...) { return args_count(); } func main() { return f(1, 2); }",
+            "func f(a, b = 2) { return a + b; } func main() { return f(1); }",
+            "func f(a, b) { return a + b; } func main() { return f(1, 2); }",
+        ];
+        for source in programs {
+            assert!(codegen.compile_object(&parse(source)).is_ok(), "{}", source);
+        }
+    }
+    
+    #[test]
+    fn test_object_static_data() {
+        use object::{Object, ObjectSection, ObjectSymbol};
+        
+        let program = parse("func main() { static n = 258; n = n + 1; return n; }");
+        for (triple, bytes) in [
+            ("x86_64-unknown-linux-gnu", 258i64.to_le_bytes()),
+            ("s390x-unknown-linux-gnu", 258i64.to_be_bytes()),
+        ] {
+            let mut codegen = CodeGenerator::new_object_for(triple).unwrap();
+            let object_bytes = codegen.compile_object(&program).unwrap();
+            let file = object::File::parse(&*object_bytes).unwrap();
+            
+            // The initial value is written in the target's byte order
+            let symbol = file
+                .symbols()
+                .find(|s| s.name() == Ok("static.0.n"))
+                .unwrap_or_else(|| panic!("{}: no static symbol", triple));
+            let section = file.section_by_index(symbol.section_index().unwrap()).unwrap();
+            let start = symbol.address() - section.address();
+            let data = &section.data().unwrap()[start as usize..][..8];
+            assert_eq!(data, bytes, "{}", triple);
+        }
+    }
+    
+    #[test]
+    fn test_object_target_errors() {
+        let err = CodeGenerator::new_object_for("not a triple").err().unwrap();
+        assert!(err.starts_with("Invalid target triple not a triple"), "{}", err);
+        
+        let err = CodeGenerator::new_object_for("mips-unknown-linux-gnu").err().unwrap();
+        assert!(err.starts_with("Unsupported target triple mips-unknown-linux-gnu"), "{}", err);
+        
+        // Each kind of generator only does its own job
+        let program = parse("func main() { return 0; }");
+        assert!(CodeGenerator::new().compile_object(&program).is_err());
+        let mut codegen = CodeGenerator::new_object_for("x86_64-unknown-linux-gnu").unwrap();
+        assert!(codegen.compile(&program).is_err());
+    }
+    
+    #[test]
+    fn test_object_debug_line_info() {
+        use gimli::RunTimeEndian;
+        use object::{Object, ObjectSection};
+        
+        let program = parse(
+            "func twice(n) {
+    let m = n;
+    return m * 2;
+}
+
+func main() {
+    print(twice(21));
+    return 0;
+}",
+        );
+        
+        for triple in ["x86_64-unknown-linux-gnu", "aarch64-apple-darwin"] {
+            let mut codegen = CodeGenerator::new_object_for(triple)
+                .unwrap()
+                .with_debug_info("/src/twice.edust");
+            let bytes = codegen.compile_object(&program).unwrap();
+            let file = object::File::parse(&*bytes).unwrap();
+            
+            let debug_line = file.section_by_name(".debug_line").expect(triple);
+            let data = debug_line.uncompressed_data().unwrap();
+            assert!(!data.is_empty(), "{}", triple);
+            
+            // Every statement that produced code appears in the line
+            // program, plus function headers where there is a prologue.
+            // `let m = n;` only renames a value, so line 2 has none.
+            let debug_line = gimli::DebugLine::new(&data, RunTimeEndian::Little);
+            let program = debug_line
+                .program(gimli::DebugLineOffset(0), 8, None, None)
+                .unwrap();
+            let mut rows = program.rows();
+            let mut lines = Vec::new();
+            while let Some((_, row)) = rows.next_row().unwrap() {
+                if let Some(line) = row.line() {
+                    lines.push(line.get());
+                }
+            }
+            lines.sort();
+            lines.dedup();
+            assert!([3, 7, 8].iter().all(|l| lines.contains(l)), "{}: {:?}", triple, lines);
+            assert!(lines.iter().all(|l| [1, 3, 6, 7, 8].contains(l)), "{}: {:?}", triple, lines);
+        }
+        
+        // Without `with_debug_info` there are no debug sections
+        let mut codegen = CodeGenerator::new_object_for("x86_64-unknown-linux-gnu").unwrap();
+        let bytes = codegen.compile_object(&program).unwrap();
+        let file = object::File::parse(&*bytes).unwrap();
+        assert!(file.section_by_name(".debug_line").is_none());
+    }
+}
@@ -0,0 +1,254 @@
+//! DWARF line tables for object files.
+//!
+//! Only line numbers are described: each function gets a
+//! `DW_TAG_subprogram` and a line-program sequence mapping its code
+//! offsets to the source lines of the statements they came from. Variable
+//! locations are not emitted.
+
+use crate::ast;
+use cranelift::codegen::CompiledCode;
+use cranelift::codegen::ir::Endianness;
+use cranelift::codegen::isa::TargetIsa;
+use cranelift_module::FuncId;
+use cranelift_object::ObjectProduct;
+use gimli::write::{
+    Address, AttributeValue, DwarfUnit, EndianVec, LineProgram, LineString, Range, RangeList,
+    RelocateWriter, Relocation, RelocationTarget, Sections, Writer,
+};
+use gimli::{Encoding, Format, LineEncoding, RunTimeEndian, SectionId, constants};
+use object::write::{Object, SectionKind, StandardSegment};
+use object::{BinaryFormat, RelocationEncoding, RelocationFlags, RelocationKind};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The line table of one compiled function
+pub struct FunctionLines {
+    id: FuncId,
+    name: String,
+    line: u32,
+    size: u32,
+    
+    // `(code offset, source line)` at each change of line, by offset
+    rows: Vec<(u32, u32)>,
+}
+
+impl FunctionLines {
+    /// Collects the source lines recorded in `code`, the machine code
+    /// compiled for `func`
+    pub fn new(id: FuncId, func: &ast::Function, code: &CompiledCode) -> Self {
+        // The prologue belongs to the line the function is declared on
+        let line = func.span.line as u32;
+        let mut rows = vec![(0, line)];
+        for srcloc in code.buffer.get_srclocs_sorted() {
+            if srcloc.loc.is_default() || rows.last().is_some_and(|r| r.1 == srcloc.loc.bits()) {
+                continue;
+            }
+            if rows.last().is_some_and(|r| r.0 == srcloc.start) {
+                rows.pop();
+            }
+            rows.push((srcloc.start, srcloc.loc.bits()));
+        }
+        
+        FunctionLines {
+            id,
+            name: func.name.to_string(),
+            line,
+            size: code.code_info().total_size,
+            rows,
+        }
+    }
+}
+
+/// A section being written, with the relocations it needs
+#[derive(Clone)]
+struct SectionWriter {
+    data: EndianVec<RunTimeEndian>,
+    relocations: Vec<Relocation>,
+}
+
+impl RelocateWriter for SectionWriter {
+    type Writer = EndianVec<RunTimeEndian>;
+    
+    fn writer(&self) -> &Self::Writer {
+        &self.data
+    }
+    
+    fn writer_mut(&mut self) -> &mut Self::Writer {
+        &mut self.data
+    }
+    
+    fn relocate(&mut self, relocation: Relocation) {
+        self.relocations.push(relocation);
+    }
+}
+
+/// Adds `.debug_info`, `.debug_line` and the sections they refer to to
+/// `product`, describing `functions` as compiled from `source`
+pub fn write_line_info(
+    product: &mut ObjectProduct,
+    isa: &dyn TargetIsa,
+    source: &Path,
+    functions: &[FunctionLines],
+) -> Result<(), String> {
+    let format = product.object.format();
+    if !matches!(format, BinaryFormat::Elf | BinaryFormat::MachO) {
+        return Err(format!("Debug info is not supported for {:?} objects", format));
+    }
+    
+    let encoding = Encoding {
+        format: Format::Dwarf32,
+        version: 4,
+        address_size: isa.pointer_bytes(),
+    };
+    let mut dwarf = DwarfUnit::new(encoding);
+    
+    let dir = source.parent().unwrap_or(Path::new("")).to_string_lossy().into_owned();
+    let file_name = source.file_name().unwrap_or(source.as_os_str()).to_string_lossy().into_owned();
+    let mut line_program = LineProgram::new(
+        encoding,
+        LineEncoding::default(),
+        LineString::String(dir.clone().into_bytes()),
+        LineString::String(file_name.clone().into_bytes()),
+        None,
+    );
+    let file = line_program.add_file(
+        LineString::String(file_name.clone().into_bytes()),
+        line_program.default_directory(),
+        None,
+    );
+    
+    // Relocations name functions by their index in `functions`
+    let mut ranges = Vec::new();
+    for (i, func) in functions.iter().enumerate() {
+        let start = Address::Symbol { symbol: i, addend: 0 };
+        
+        line_program.begin_sequence(Some(start));
+        for &(offset, line) in &func.rows {
+            let row = line_program.row();
+            row.address_offset = offset as u64;
+            row.file = file;
+            row.line = line as u64;
+            line_program.generate_row();
+        }
+        line_program.end_sequence(func.size as u64);
+        
+        let root = dwarf.unit.root();
+        let subprogram = dwarf.unit.add(root, constants::DW_TAG_subprogram);
+        let name = dwarf.strings.add(func.name.as_str());
+        let entry = dwarf.unit.get_mut(subprogram);
+        entry.set(constants::DW_AT_name, AttributeValue::StringRef(name));
+        entry.set(constants::DW_AT_external, AttributeValue::Flag(true));
+        entry.set(constants::DW_AT_decl_file, AttributeValue::FileIndex(Some(file)));
+        entry.set(constants::DW_AT_decl_line, AttributeValue::Udata(func.line as u64));
+        entry.set(constants::DW_AT_low_pc, AttributeValue::Address(start));
+        entry.set(constants::DW_AT_high_pc, AttributeValue::Udata(func.size as u64));
+        
+        ranges.push(Range::StartLength {
+            begin: start,
+            length: func.size as u64,
+        });
+    }
+    dwarf.unit.line_program = line_program;
+    
+    let range_list = dwarf.unit.ranges.add(RangeList(ranges));
+    let name = dwarf.strings.add(file_name);
+    let comp_dir = dwarf.strings.add(dir);
+    let producer = dwarf.strings.add(format!("edust {}", env!("CARGO_PKG_VERSION")));
+    let root = dwarf.unit.root();
+    let entry = dwarf.unit.get_mut(root);
+    entry.set(constants::DW_AT_producer, AttributeValue::StringRef(producer));
+    entry.set(constants::DW_AT_name, AttributeValue::StringRef(name));
+    entry.set(constants::DW_AT_comp_dir, AttributeValue::StringRef(comp_dir));
+    entry.set(constants::DW_AT_low_pc, AttributeValue::Address(Address::Constant(0)));
+    entry.set(constants::DW_AT_ranges, AttributeValue::RangeListRef(range_list));
+    
+    let endian = match isa.endianness() {
+        Endianness::Little => RunTimeEndian::Little,
+        Endianness::Big => RunTimeEndian::Big,
+    };
+    let mut sections = Sections::new(SectionWriter {
+        data: EndianVec::new(endian),
+        relocations: Vec::new(),
+    });
+    dwarf.write(&mut sections).map_err(|e| e.to_string())?;
+    
+    add_sections(product, functions, &mut sections)
+}
+
+/// Copies the written DWARF sections into the object, along with their
+/// relocations
+fn add_sections(
+    product: &mut ObjectProduct,
+    functions: &[FunctionLines],
+    sections: &mut Sections<SectionWriter>,
+) -> Result<(), String> {
+    let format = product.object.format();
+    
+    // Mach-O debuggers read offsets between debug sections as written,
+    // without relocations
+    if format == BinaryFormat::MachO {
+        sections
+            .for_each_mut(|_, section| {
+                let SectionWriter { data, relocations } = section;
+                relocations.retain(|r| match r.target {
+                    RelocationTarget::Section(_) => {
+                        data.write_udata_at(r.offset, r.addend as u64, r.size).unwrap();
+                        false
+                    }
+                    RelocationTarget::Symbol(_) => true,
+                });
+                Ok::<(), ()>(())
+            })
+            .unwrap();
+    }
+    
+    // Create every section before adding relocations, which may refer to
+    // any of them
+    let mut ids = HashMap::new();
+    sections
+        .for_each(|id, section| {
+            if !section.data.slice().is_empty() {
+                let section_id = add_debug_section(&mut product.object, id);
+                product
+                    .object
+                    .section_mut(section_id)
+                    .set_data(section.data.slice().to_vec(), 1);
+                ids.insert(id, section_id);
+            }
+            Ok::<(), ()>(())
+        })
+        .unwrap();
+    
+    sections.for_each(|id, section| {
+        for relocation in &section.relocations {
+            let symbol = match relocation.target {
+                RelocationTarget::Symbol(i) => product.function_symbol(functions[i].id),
+                RelocationTarget::Section(target) => product.object.section_symbol(ids[&target]),
+            };
+            let relocation = object::write::Relocation {
+                offset: relocation.offset as u64,
+                symbol,
+                addend: relocation.addend,
+                flags: RelocationFlags::Generic {
+                    kind: RelocationKind::Absolute,
+                    encoding: RelocationEncoding::Generic,
+                    size: relocation.size * 8,
+                },
+            };
+            product
+                .object
+                .add_relocation(ids[&id], relocation)
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    })
+}
+
+fn add_debug_section(object: &mut Object, id: SectionId) -> object::write::SectionId {
+    let name = match object.format() {
+        BinaryFormat::MachO => id.name().replace('.', "__"),
+        _ => id.name().to_string(),
+    };
+    let segment = object.segment_name(StandardSegment::Debug).to_vec();
+    object.add_section(segment, name.into_bytes(), SectionKind::Debug)
+}
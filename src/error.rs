@@ -0,0 +1,82 @@
+use crate::ast::Span;
+
+/// A compilation error: its message, the source location it points at and
+/// the file it is in, when known. Displays in the `file:line:col: error:
+/// message` form editors use to jump to the error, leaving out whatever
+/// isn't known.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    pub message: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub file: Option<String>,
+}
+
+impl CompileError {
+    /// An error with no location
+    pub fn new(message: impl Into<String>) -> Self {
+        CompileError {
+            message: message.into(),
+            line: None,
+            column: None,
+            file: None,
+        }
+    }
+    
+    /// An error pointing at `span`
+    pub fn at(message: impl Into<String>, span: Span) -> Self {
+        CompileError {
+            line: Some(span.line),
+            column: Some(span.column),
+            ..CompileError::new(message)
+        }
+    }
+    
+    /// Names the file the error is in, unless it already names one
+    pub fn with_file(mut self, file: &str) -> Self {
+        self.file.get_or_insert_with(|| file.to_string());
+        self
+    }
+    
+    /// Prefixes the message with the phase that failed, e.g. `"Parser error"`
+    pub fn with_phase(mut self, phase: &str) -> Self {
+        self.message = format!("{}: {}", phase, self.message);
+        self
+    }
+    
+    /// The error as one sentence, `file: message at line L, column C`,
+    /// for the APIs that report errors as strings
+    pub fn describe(&self) -> String {
+        let mut text = String::new();
+        if let Some(file) = &self.file {
+            text.push_str(file);
+            text.push_str(": ");
+        }
+        text.push_str(&self.message);
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            text.push_str(&format!(" at line {}, column {}", line, column));
+        }
+        text
+    }
+}
+
+impl From<String> for CompileError {
+    fn from(message: String) -> Self {
+        CompileError::new(message)
+    }
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, "{}:{}:", line, column)?;
+        }
+        if self.file.is_some() || self.line.is_some() {
+            write!(f, " ")?;
+        }
+        write!(f, "error: {}", self.message)
+    }
+}
@@ -0,0 +1,723 @@
+//! Canonical source formatting for Edust programs
+
+use crate::ast::*;
+use crate::error::CompileError;
+use crate::lexer::Lexer;
+use std::collections::HashMap;
+
+/// Parse `source` and print it back in canonical form: 4-space indentation,
+/// one statement per line, spaces around binary operators, opening braces
+/// on the same line and a blank line between top-level functions.
+///
+/// Comments are not part of the AST, so formatting would delete them; a
+/// source with comments is refused instead.
+pub fn format_source(source: &str) -> Result<String, String> {
+    // Lexer errors are left for `parse` to report
+    let mut lexer = Lexer::new(source);
+    if lexer.tokenize().is_ok()
+        && let Some(span) = lexer.first_comment()
+    {
+        let error = CompileError::at("Cannot format a source with comments, as formatting would delete them", span);
+        return Err(error.describe());
+    }
+    let program = crate::parse(source)?;
+    Ok(format_program(&program))
+}
+
+/// Print `program` as canonical Edust source
+pub fn format_program(program: &Program) -> String {
+    let mut formatter = Formatter {
+        out: String::new(),
+        indent: 0,
+        exprs: &program.exprs,
+    };
+    
+    for import in &program.imports {
+        formatter.line(&format!("import \"{}\";", import.path));
+    }
+    
+    for (i, func) in program.functions.iter().enumerate() {
+        if i > 0 || !program.imports.is_empty() {
+            formatter.out.push('\n');
+        }
+        formatter.function(func);
+    }
+    
+    formatter.out
+}
+
+/// Binding strength of an expression, mirroring the parser's grammar
+/// levels; higher binds tighter
+fn precedence(exprs: &ExprArena, expr: &Expr) -> u8 {
+    match expr {
+        // A closure body and an assigned value extend as far as they can,
+        // and `..` sits below `||`
+        Expr::Closure { .. } | Expr::Assign { .. } | Expr::Range { .. } => 0,
+        // Printed back as the comparisons it came from
+        Expr::Loop { body, .. } if is_comparison_chain(body) => 3,
+        Expr::Loop { label: None, body, .. } if step(exprs, body).is_some() => 8,
+        Expr::Binary { op, .. } => binop_precedence(*op),
+        Expr::Cast { .. } => 7,
+        Expr::Unary { .. } => 8,
+        Expr::Number(n) if *n < 0 => 8,
+        Expr::Number(_) | Expr::Str(_) | Expr::Variable { .. } | Expr::Call { .. } | Expr::Array(_) => 10,
+        Expr::Loop { .. } | Expr::If { .. } => 10,
+    }
+}
+
+fn binop_precedence(op: BinOp) -> u8 {
+    match op {
+        BinOp::Or => 1,
+        BinOp::And => 2,
+        BinOp::Eq | BinOp::Ne => 3,
+        BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => 4,
+        BinOp::Add | BinOp::Sub => 5,
+        BinOp::Mul | BinOp::Div | BinOp::Mod => 6,
+        BinOp::Pow => 9,
+    }
+}
+
+pub(crate) fn binop_symbol(op: BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "+",
+        BinOp::Sub => "-",
+        BinOp::Mul => "*",
+        BinOp::Div => "/",
+        BinOp::Mod => "%",
+        BinOp::Pow => "**",
+        BinOp::Lt => "<",
+        BinOp::Le => "<=",
+        BinOp::Gt => ">",
+        BinOp::Ge => ">=",
+        BinOp::Eq => "==",
+        BinOp::Ne => "!=",
+        BinOp::And => "&&",
+        BinOp::Or => "||",
+    }
+}
+
+fn is_comparison(op: BinOp) -> bool {
+    matches!(op, BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge | BinOp::Eq | BinOp::Ne)
+}
+
+/// Whether `body` is a `loop` the parser made out of a comparison chain
+/// like `a < f() < c`, which starts by binding temporaries whose names
+/// can't come from source
+fn is_comparison_chain(body: &Block) -> bool {
+    matches!(
+        body.statements.first(),
+        Some(Statement::VarDecl { name, .. }) if name.as_str().starts_with(".chain")
+    )
+}
+
+/// The `++` or `--` and the variable of a `loop` the parser made out of
+/// `++i` or `--i`, which assigns the variable and breaks with it
+fn step(exprs: &ExprArena, body: &Block) -> Option<(&'static str, Symbol)> {
+    let [
+        Statement::Assignment { name, value, .. },
+        Statement::Break {
+            label: None,
+            value: Some(Expr::Variable { name: result, .. }),
+            ..
+        },
+    ] = body.statements.as_slice()
+    else {
+        return None;
+    };
+    let Expr::Binary { op, left, right } = value else {
+        return None;
+    };
+    let reads_name = matches!(exprs[*left], Expr::Variable { name: v, .. } if v == *name);
+    if result != name || !reads_name || !matches!(exprs[*right], Expr::Number(1)) {
+        return None;
+    }
+    match op {
+        BinOp::Add => Some(("++", *name)),
+        BinOp::Sub => Some(("--", *name)),
+        _ => None,
+    }
+}
+
+/// `label: ` before a labeled loop
+fn label_prefix(label: Option<Symbol>) -> String {
+    label.map_or(String::new(), |label| format!("{}: ", label))
+}
+
+/// ` label` after `break` or `continue`
+fn label_suffix(label: Option<Symbol>) -> String {
+    label.map_or(String::new(), |label| format!(" {}", label))
+}
+
+struct Formatter<'a> {
+    out: String,
+    indent: usize,
+    exprs: &'a ExprArena,
+}
+
+impl Formatter<'_> {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str("    ");
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+    
+    fn function(&mut self, func: &Function) {
+        let params: Vec<String> = func
+            .params
+            .iter()
+            .zip(&func.defaults)
+            .map(|(param, default)| match default {
+                Some(default) => format!("{} = {}", param, self.expr(default)),
+                None => param.to_string(),
+            })
+            .chain(func.variadic.map(|rest| format!("{}...", rest)))
+            .collect();
+        self.line(&format!("func {}({}) {{", func.name, params.join(", ")));
+        self.block_body(&func.body);
+        self.line("}");
+    }
+    
+    fn block_body(&mut self, block: &Block) {
+        self.indent += 1;
+        for stmt in &block.statements {
+            self.statement(stmt);
+        }
+        if let Some(tail) = &block.tail {
+            self.line(&self.expr(tail));
+        }
+        self.indent -= 1;
+    }
+    
+    fn statement(&mut self, stmt: &Statement) {
+        match stmt {
+            Statement::VarDecl { name, ty, value, .. } => {
+                let annotation = ty.map_or(String::new(), |ty| format!(": {}", ty.name()));
+                match value {
+                    Some(value) => self.line(&format!("let {}{} = {};", name, annotation, self.expr(value))),
+                    None => self.line(&format!("let {}{};", name, annotation)),
+                }
+            }
+            Statement::DestructureDecl { names, value, .. } => {
+                let names: Vec<&str> = names.iter().map(|n| n.as_str()).collect();
+                self.line(&format!("let ({}) = {};", names.join(", "), self.expr(value)))
+            }
+            Statement::StaticDecl { name, value, .. } => {
+                self.line(&format!("static {} = {};", name, self.expr(value)))
+            }
+            Statement::Assignment { name, value, .. } => {
+                self.line(&format!("{} = {};", name, self.expr(value)))
+            }
+            Statement::MultiAssign { names, values, .. } => {
+                let names: Vec<&str> = names.iter().map(|n| n.as_str()).collect();
+                let values: Vec<String> = values.iter().map(|v| self.expr(v)).collect();
+                self.line(&format!("{} = {};", names.join(", "), values.join(", ")))
+            }
+            Statement::If {
+                condition,
+                then_block,
+                else_block,
+                ..
+            } => {
+                self.line(&format!("if {} {{", self.expr(condition)));
+                self.block_body(then_block);
+                if let Some(else_blk) = else_block {
+                    self.line("} else {");
+                    self.block_body(else_blk);
+                }
+                self.line("}");
+            }
+            Statement::While {
+                label,
+                condition,
+                body,
+                ..
+            } => {
+                self.line(&format!("{}while {} {{", label_prefix(*label), self.expr(condition)));
+                self.block_body(body);
+                self.line("}");
+            }
+            Statement::ForIn {
+                label,
+                var,
+                iter,
+                body,
+                ..
+            } => {
+                self.line(&format!("{}for {} in {} {{", label_prefix(*label), var, self.expr(iter)));
+                self.block_body(body);
+                self.line("}");
+            }
+            Statement::DoWhile {
+                label,
+                body,
+                condition,
+                ..
+            } => {
+                self.line(&format!("{}do {{", label_prefix(*label)));
+                self.block_body(body);
+                self.line(&format!("}} while {};", self.expr(condition)));
+            }
+            Statement::Return { value, .. } => self.line(&format!("return {};", self.expr(value))),
+            Statement::Break { label, value, .. } => {
+                let value = value.as_ref().map_or(String::new(), |v| format!(" {}", self.expr(v)));
+                self.line(&format!("break{}{};", label_suffix(*label), value))
+            }
+            Statement::Continue { label, .. } => {
+                self.line(&format!("continue{};", label_suffix(*label)))
+            }
+            Statement::ExprStmt { expr: e, .. } => {
+                // A statement starting with `if` would parse as an if
+                // statement
+                let text = self.expr(e);
+                if text.starts_with("if ") {
+                    self.line(&format!("({});", text));
+                } else {
+                    self.line(&format!("{};", text));
+                }
+            }
+            Statement::FuncDecl { func, .. } => self.function(func),
+        }
+    }
+    
+    /// Format an expression, adding parentheses only where precedence needs them
+    fn expr(&self, e: &Expr) -> String {
+        match e {
+            Expr::Number(n) => n.to_string(),
+            Expr::Str(text) => format!("\"{}\"", text),
+            Expr::Variable { name, .. } => name.to_string(),
+            Expr::Assign { name, value, .. } => format!("{} = {}", name, self.operand(*value, 0)),
+            Expr::If { cond, then_val, else_val } => {
+                // `else if` chains stay flat
+                let else_text = match &self.exprs[*else_val] {
+                    nested @ Expr::If { .. } => self.expr(nested),
+                    value => format!("{{ {} }}", self.expr(value)),
+                };
+                format!(
+                    "if {} {{ {} }} else {}",
+                    self.expr(&self.exprs[*cond]),
+                    self.expr(&self.exprs[*then_val]),
+                    else_text
+                )
+            }
+            Expr::Range { start, end } => format!("{}..{}", self.operand(*start, 1), self.operand(*end, 1)),
+            // The operators down the left of `a + b + c + ...` are written
+            // out bottom-up in a loop rather than a level of recursion each
+            Expr::Binary { .. } | Expr::Cast { .. } => {
+                let (spine, bottom) = e.left_spine(self.exprs);
+                let mut text = self.expr(bottom);
+                let mut left = bottom;
+                for node in spine.into_iter().rev() {
+                    let (left_min, suffix) = match node {
+                        Expr::Binary { op, right, .. } => {
+                            let prec = binop_precedence(*op);
+                            
+                            // `**` is right-associative and takes a primary on
+                            // its left; comparisons chain, so a comparison
+                            // operand of one needs parentheses; everything
+                            // else is left-associative
+                            let (left_min, right_min) = if *op == BinOp::Pow {
+                                (10, 8)
+                            } else if is_comparison(*op) {
+                                (prec + 1, prec + 1)
+                            } else {
+                                (prec, prec + 1)
+                            };
+                            (left_min, format!(" {} {}", binop_symbol(*op), self.operand(*right, right_min)))
+                        }
+                        Expr::Cast { target, .. } => (7, format!(" as {}", target)),
+                        _ => unreachable!(),
+                    };
+                    if precedence(self.exprs, left) < left_min {
+                        text = format!("({})", text);
+                    }
+                    text.push_str(&suffix);
+                    left = node;
+                }
+                text
+            }
+            Expr::Unary { op, operand: inner } => {
+                let symbol = match op {
+                    UnaryOp::Neg => "-",
+                    UnaryOp::Not => "!",
+                };
+                // `- -x` must not run together into `--x`
+                let operand = self.operand(*inner, 8);
+                if operand.starts_with(symbol) && *op == UnaryOp::Neg {
+                    format!("{}({})", symbol, operand)
+                } else {
+                    format!("{}{}", symbol, operand)
+                }
+            }
+            Expr::Call { name, args, .. } => {
+                let args: Vec<String> = args.iter().map(|arg| self.expr(arg)).collect();
+                format!("{}({})", name, args.join(", "))
+            }
+            Expr::Array(elements) => {
+                let elements: Vec<String> = elements.iter().map(|e| self.expr(e)).collect();
+                format!("[{}]", elements.join(", "))
+            }
+            Expr::Closure { params, body, .. } => {
+                let params: Vec<&str> = params.iter().map(|p| p.as_str()).collect();
+                format!("|{}| {}", params.join(", "), self.expr(&self.exprs[*body]))
+            }
+            Expr::Loop { label: None, body, .. } if let Some((symbol, name)) = step(self.exprs, body) => {
+                format!("{}{}", symbol, name)
+            }
+            Expr::Loop { body, .. } if is_comparison_chain(body) => {
+                let mut temps = HashMap::new();
+                let mut operands = Vec::new();
+                let mut ops = Vec::new();
+                self.chain_links(e, &mut temps, &mut operands, &mut ops);
+                
+                // Operands bind tighter than the comparisons between them
+                let mut text = String::new();
+                for (i, operand) in operands.iter().enumerate() {
+                    if i > 0 {
+                        text.push_str(&format!(" {} ", binop_symbol(ops[i - 1])));
+                    }
+                    text.push_str(&self.operand_expr(operand, binop_precedence(ops[0]) + 1));
+                }
+                text
+            }
+            // The body goes on lines of its own, indented one level past
+            // the line the loop starts on
+            Expr::Loop { label, body, .. } => {
+                let mut inner = Formatter {
+                    out: String::new(),
+                    indent: self.indent,
+                    exprs: self.exprs,
+                };
+                inner.block_body(body);
+                format!("{}loop {{\n{}{}}}", label_prefix(*label), inner.out, "    ".repeat(self.indent))
+            }
+        }
+    }
+    
+    /// Collects the operands and operators of the comparison chain `e`
+    /// desugared into, reading temporaries back as the operands they hold
+    fn chain_links<'e>(
+        &'e self,
+        e: &'e Expr,
+        temps: &mut HashMap<Symbol, &'e Expr>,
+        operands: &mut Vec<&'e Expr>,
+        ops: &mut Vec<BinOp>,
+    ) {
+        match e {
+            Expr::Loop { body, .. } => {
+                for stmt in &body.statements {
+                    match stmt {
+                        Statement::VarDecl {
+                            name, value: Some(value), ..
+                        } => {
+                            temps.insert(*name, value);
+                        }
+                        Statement::Break { value: Some(value), .. } => {
+                            self.chain_links(value, temps, operands, ops)
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Expr::Binary {
+                op: BinOp::And,
+                left,
+                right,
+            } => {
+                self.chain_links(&self.exprs[*left], temps, operands, ops);
+                self.chain_links(&self.exprs[*right], temps, operands, ops);
+            }
+            Expr::Binary { op, left, right } => {
+                let operand = |id: &ExprId| match &self.exprs[*id] {
+                    Expr::Variable { name, .. } => temps.get(name).copied().unwrap_or(&self.exprs[*id]),
+                    operand => operand,
+                };
+                // Each comparison after the first starts where the one
+                // before it ended
+                if operands.is_empty() {
+                    operands.push(operand(left));
+                }
+                ops.push(*op);
+                operands.push(operand(right));
+            }
+            _ => {}
+        }
+    }
+    
+    /// Format the operand `id`, parenthesized if it binds more loosely
+    /// than `min_prec`
+    fn operand(&self, id: ExprId, min_prec: u8) -> String {
+        self.operand_expr(&self.exprs[id], min_prec)
+    }
+    
+    fn operand_expr(&self, e: &Expr, min_prec: u8) -> String {
+        if precedence(self.exprs, e) < min_prec {
+            format!("({})", self.expr(e))
+        } else {
+            self.expr(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_format_messy_source() {
+        let source = "func   add(a,b){return a+b;}
+func main( ) {
+  let x=add(1,2)*(3-1);   let y = -x ** 2;
+if x>2&&!(y==0) { print(x,y); } else {x=x as u8;}
+        while x<10{x=x+1; if x==7 {break;} else {continue;}}
+    do { x = x - 1 ; } while x > (0 - 2 as i8);
+  return (x - 1) - (2 - 3);
+}";
+
+        let expected = "func add(a, b) {
+    return a + b;
+}
+
+func main() {
+    let x = add(1, 2) * (3 - 1);
+    let y = -x ** 2;
+    if x > 2 && !(y == 0) {
+        print(x, y);
+    } else {
+        x = x as u8;
+    }
+    while x < 10 {
+        x = x + 1;
+        if x == 7 {
+            break;
+        } else {
+            continue;
+        }
+    }
+    do {
+        x = x - 1;
+    } while x > 0 - 2 as i8;
+    return x - 1 - (2 - 3);
+}
+";
+
+        assert_eq!(format_source(source).unwrap(), expected);
+    }
+    
+    #[test]
+    fn test_format_nested_function() {
+        let source = "func main() { func helper(n) { return n * 2; } return helper(21); }";
+        
+        let expected = "func main() {
+    func helper(n) {
+        return n * 2;
+    }
+    return helper(21);
+}
+";
+
+        assert_eq!(format_source(source).unwrap(), expected);
+    }
+    
+    #[test]
+    fn test_format_tail_expression() {
+        let source = "func f(x) { let y = x+1; y*2 }";
+        
+        let expected = "func f(x) {
+    let y = x + 1;
+    y * 2
+}
+";
+
+        assert_eq!(format_source(source).unwrap(), expected);
+    }
+    
+    #[test]
+    fn test_format_imports() {
+        let source = "import   \"lib/math.edust\" ;func main(){return 0;}";
+        
+        let expected = "import \"lib/math.edust\";
+
+func main() {
+    return 0;
+}
+";
+
+        assert_eq!(format_source(source).unwrap(), expected);
+    }
+    
+    #[test]
+    fn test_format_is_idempotent() {
+        let source = "func f(n){if n<=1{return 1;}return n*f(n-1);}
+func main(){let a=(2**3)**2;let b=2**3**2;let c=(a+b) as i16 as u8;
+print(-(a-b), !a || b && c);return f(5)+c%7/(1+1);}";
+
+        let once = format_source(source).unwrap();
+        let twice = format_source(&once).unwrap();
+        assert_eq!(once, twice);
+        
+        // Parentheses that change the meaning are kept
+        assert!(once.contains("let a = (2 ** 3) ** 2;"));
+        assert!(once.contains("let b = 2 ** 3 ** 2;"));
+        assert!(once.contains("let c = (a + b) as i16 as u8;"));
+        assert!(once.contains("print(-(a - b), !a || b && c);"));
+        assert_eq!(crate::interpret(source), crate::interpret(&once));
+    }
+    
+    #[test]
+    fn test_format_loop_labels() {
+        let source = "func main(){let n=0;outer:while n<9{n=n+1;inner:do{continue outer;}while 1;break;}return n;}";
+        let expected = "func main() {
+    let n = 0;
+    outer: while n < 9 {
+        n = n + 1;
+        inner: do {
+            continue outer;
+        } while 1;
+        break;
+    }
+    return n;
+}
+";
+        assert_eq!(format_source(source).unwrap(), expected);
+    }
+    
+    #[test]
+    fn test_format_for_in() {
+        let source = "func main(){let t=0;rows:for i in 0..t+3{for j in -1..(i||1){continue rows;}}return t;}";
+        let expected = "func main() {
+    let t = 0;
+    rows: for i in 0..t + 3 {
+        for j in -1..i || 1 {
+            continue rows;
+        }
+    }
+    return t;
+}
+";
+        assert_eq!(format_source(source).unwrap(), expected);
+        assert_eq!(format_source(expected).unwrap(), expected);
+    }
+    
+    #[test]
+    fn test_format_multi_assign() {
+        let source = "func main(){let a=1;let b=2;a,b=b,a+b;return a;}";
+        let expected = "func main() {
+    let a = 1;
+    let b = 2;
+    a, b = b, a + b;
+    return a;
+}
+";
+        assert_eq!(format_source(source).unwrap(), expected);
+    }
+    
+    #[test]
+    fn test_format_chained_assignment() {
+        let source = "func main(){let a=0;let b=0;a=b=(a=1)+2;return a;}";
+        let expected = "func main() {
+    let a = 0;
+    let b = 0;
+    a = b = (a = 1) + 2;
+    return a;
+}
+";
+        assert_eq!(format_source(source).unwrap(), expected);
+    }
+    
+    #[test]
+    fn test_format_if_expression() {
+        let source = "func main(){let a=if 1<2{3}else if 4{5}else{6};(if a{f()}else{f()});(if a{1}else{2})*3;return (if a{1}else{2})*3;}";
+        let expected = "func main() {
+    let a = if 1 < 2 { 3 } else if 4 { 5 } else { 6 };
+    (if a { f() } else { f() });
+    (if a { 1 } else { 2 } * 3);
+    return if a { 1 } else { 2 } * 3;
+}
+";
+        assert_eq!(format_source(source).unwrap(), expected);
+        assert_eq!(format_source(expected).unwrap(), expected);
+    }
+    
+    #[test]
+    fn test_format_increments() {
+        let source = "func main(){let i=0;++i;while ++i<5{--i;}return -(-i)+(++i)**2;}";
+        let expected = "func main() {
+    let i = 0;
+    i = i + 1;
+    while ++i < 5 {
+        i = i - 1;
+    }
+    return -(-i) + (++i) ** 2;
+}
+";
+        assert_eq!(format_source(source).unwrap(), expected);
+        assert_eq!(format_source(expected).unwrap(), expected);
+    }
+    
+    #[test]
+    fn test_format_comparison_chains() {
+        let source = "func main(){let x=f(1)<f(2)+1<=3;let y=(1<x)<2;let z=0<x<9==(x!=f(x)!=1);return 1<x<f(x)<y;}";
+        let expected = "func main() {
+    let x = f(1) < f(2) + 1 <= 3;
+    let y = (1 < x) < 2;
+    let z = (0 < x && x < 9) == (x != f(x) != 1);
+    return 1 < x && x < f(x) < y;
+}
+";
+        assert_eq!(format_source(source).unwrap(), expected);
+        assert_eq!(format_source(expected).unwrap(), expected);
+    }
+    
+    #[test]
+    fn test_format_default_parameters() {
+        let source = "func f(a,b=1,c=-2*3){return a+b+c;}func main(){return f(1);}";
+        let expected = "func f(a, b = 1, c = -2 * 3) {
+    return a + b + c;
+}
+
+func main() {
+    return f(1);
+}
+";
+        assert_eq!(format_source(source).unwrap(), expected);
+        assert_eq!(format_source(expected).unwrap(), expected);
+    }
+    
+    #[test]
+    fn test_format_loop_expression() {
+        let source = "func main(){let x=outer:loop{loop{break outer 1+2;}};loop{break;}loop{break x;}}";
+        let expected = "func main() {
+    let x = outer: loop {
+        loop {
+            break outer 1 + 2;
+        };
+    };
+    loop {
+        break;
+    };
+    loop {
+        break x;
+    }
+}
+";
+        assert_eq!(format_source(source).unwrap(), expected);
+        assert_eq!(format_source(expected).unwrap(), expected);
+    }
+    
+    #[test]
+    fn test_format_refuses_comments() {
+        let source = "func main() {\n    let s = \"// not a comment\";\n    return 0; // the status\n}\n";
+        assert_eq!(
+            format_source(source),
+            Err("Cannot format a source with comments, as formatting would delete them at line 3, column 15".to_string())
+        );
+        
+        let source = "/* no main yet */";
+        assert!(format_source(source).unwrap_err().starts_with("Cannot format a source with comments"));
+        
+        let source = "func main() { return \"/*; }";
+        assert_eq!(format_source(source), Err(crate::parse(source).unwrap_err()));
+    }
+}
@@ -0,0 +1,716 @@
+use crate::ast::*;
+use crate::runtime;
+use std::collections::{BTreeMap, HashMap};
+
+/// Tree-walking interpreter for Edust programs.
+///
+/// Runs a validated AST directly, without Cranelift, and follows the same
+/// semantics as the JIT backend: 64-bit wrapping arithmetic, both operands
+/// of `&&`/`||` are evaluated, and builtins call into the same runtime
+/// functions. Where the JIT would trap (division by zero, a failed
+/// `assert`), the interpreter returns an error instead.
+pub struct Interpreter<'a> {
+    functions: HashMap<Symbol, &'a Function>,
+    scopes: Vec<HashMap<Symbol, Slot>>,
+    
+    // Values of `static` variables, by the declaring statement's address,
+    // which is unique and stable for as long as the program is borrowed
+    statics: HashMap<*const Statement, i64>,
+    
+    // Closures not yet freed, by address. Each gets an `alloc` block, as
+    // its environment does in compiled code, so other values can't pass
+    // for one.
+    closures: HashMap<i64, Closure<'a>>,
+    
+    // Live `alloc` blocks by start address, as 8-byte words, and the
+    // address the next block starts at
+    memory: BTreeMap<i64, Vec<i64>>,
+    next_address: i64,
+    
+    // Function whose body is executing, used to spot self tail calls
+    current_function: Option<Symbol>,
+    
+    // Variadic arguments of the executing function
+    varargs: Vec<i64>,
+    
+    // The runtime string made for each distinct string literal
+    strings: HashMap<&'a str, i64>,
+    
+    // Expression nodes of the program being run
+    exprs: &'a ExprArena,
+}
+
+/// Where the value of a name in scope lives
+#[derive(Clone, Copy)]
+enum Slot {
+    Local(i64),
+    
+    // A local declared `u64`
+    Unsigned(i64),
+    
+    // A local holding a string
+    Str(i64),
+    
+    Static(*const Statement),
+}
+
+/// A closure's code and the variables it copied when it was created
+struct Closure<'a> {
+    params: &'a [Symbol],
+    body: &'a Expr,
+    captures: HashMap<Symbol, Slot>,
+}
+
+/// How control leaves a statement
+enum Flow {
+    Normal,
+    Return(i64),
+    /// `break`, with the label of the loop it leaves if it names one and
+    /// the value it gives a `loop` expression
+    Break(Option<Symbol>, Option<i64>),
+    Continue(Option<Symbol>),
+    /// `return f(args)` where `f` is the current function: restart it with
+    /// new arguments instead of recursing, like the JIT does
+    TailCall(Vec<i64>),
+}
+
+/// Why evaluating an expression stopped before giving a value
+enum EvalError {
+    /// The program failed with this message
+    Runtime(String),
+    /// A `loop` expression was left by `return` or by a `break` or
+    /// `continue` of an outer loop, so the statement around it is left
+    /// the same way
+    Flow(Flow),
+}
+
+impl From<String> for EvalError {
+    fn from(message: String) -> Self {
+        EvalError::Runtime(message)
+    }
+}
+
+impl Default for Interpreter<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new() -> Self {
+        Interpreter {
+            functions: HashMap::new(),
+            scopes: Vec::new(),
+            statics: HashMap::new(),
+            closures: HashMap::new(),
+            memory: BTreeMap::new(),
+            next_address: 0x1000,
+            current_function: None,
+            varargs: Vec::new(),
+            strings: HashMap::new(),
+            exprs: ExprArena::empty(),
+        }
+    }
+    
+    pub fn run(&mut self, program: &'a Program) -> Result<i64, String> {
+        self.exprs = &program.exprs;
+        for func in program.all_functions() {
+            self.functions.insert(func.name, func);
+        }
+        
+        let args = match self.functions.get(&Symbol::intern("main")) {
+            Some(main) if main.params.len() == 1 => vec![runtime::argc()],
+            Some(_) => Vec::new(),
+            None => return Err("No main function found".to_string()),
+        };
+        
+        self.call_function(Symbol::intern("main"), args).map_err(|e| match e {
+            EvalError::Runtime(message) => message,
+            EvalError::Flow(_) => unreachable!("control flow escaped main"),
+        })
+    }
+    
+    fn call_function(&mut self, name: Symbol, args: Vec<i64>) -> Result<i64, EvalError> {
+        let func = *self
+            .functions
+            .get(&name)
+            .ok_or_else(|| format!("Undefined function: {}", name))?;
+        
+        let saved_function = self.current_function.replace(func.name);
+        let saved_varargs = std::mem::take(&mut self.varargs);
+        let mut args = args;
+        
+        let result = loop {
+            if let Err(e) = self.push_defaults(func, &mut args) {
+                break Err(e);
+            }
+            if func.variadic.is_some() {
+                self.varargs = args.split_off(func.params.len());
+            }
+            
+            // Each call gets a fresh environment holding its parameters
+            let mut frame = HashMap::new();
+            for (param, value) in func.params.iter().zip(args) {
+                frame.insert(*param, Slot::Local(value));
+            }
+            let saved = std::mem::replace(&mut self.scopes, vec![frame]);
+            
+            let result = self.exec_block(&func.body);
+            
+            self.scopes = saved;
+            
+            match result {
+                Ok(Flow::TailCall(next_args)) => args = next_args,
+                Ok(Flow::Return(value)) => break Ok(value),
+                Ok(Flow::Normal) => break Ok(0),
+                Ok(Flow::Break(..) | Flow::Continue(_)) => unreachable!("loop control outside of a loop"),
+                Err(e) => break Err(e),
+            }
+        };
+        
+        self.current_function = saved_function;
+        self.varargs = saved_varargs;
+        result
+    }
+    
+    /// Appends the default values of the trailing parameters a call to
+    /// `func` left out
+    fn push_defaults(&mut self, func: &'a Function, args: &mut Vec<i64>) -> Result<(), EvalError> {
+        for default in func.defaults.iter().skip(args.len()) {
+            let default = default
+                .as_ref()
+                .ok_or_else(|| format!("Missing argument in call to {}", func.name))?;
+            args.push(self.eval_expr(default)?);
+        }
+        Ok(())
+    }
+    
+    fn exec_block(&mut self, block: &'a Block) -> Result<Flow, EvalError> {
+        self.scopes.push(HashMap::new());
+        
+        let mut flow = Ok(Flow::Normal);
+        for stmt in &block.statements {
+            flow = Self::catch_flow(self.exec_statement(stmt));
+            if !matches!(flow, Ok(Flow::Normal)) {
+                break;
+            }
+        }
+        
+        // A function body's tail expression is returned like `return`
+        if let (Ok(Flow::Normal), Some(tail)) = (&flow, &block.tail) {
+            flow = Self::catch_flow(self.exec_return(tail));
+        }
+        
+        self.scopes.pop();
+        flow
+    }
+    
+    fn exec_statement(&mut self, stmt: &'a Statement) -> Result<Flow, EvalError> {
+        match stmt {
+            Statement::VarDecl { name, ty, value, .. } => {
+                let val = match value {
+                    Some(value) => self.eval_expr(value)?,
+                    None => 0,
+                };
+                let slot = match (ty, value) {
+                    (Some(Type::U64), _) => Slot::Unsigned(val),
+                    (_, Some(value)) if value.is_string(self.exprs, &|name| self.is_string_var(name)) => Slot::Str(val),
+                    _ => Slot::Local(val),
+                };
+                self.scopes.last_mut().unwrap().insert(*name, slot);
+            }
+            
+            Statement::DestructureDecl { names, value, .. } => {
+                let Expr::Array(elements) = value else {
+                    return Err(EvalError::Runtime("Can only destructure an array literal".to_string()));
+                };
+                let mut values = Vec::new();
+                for element in elements {
+                    values.push(self.eval_expr(element)?);
+                }
+                let scope = self.scopes.last_mut().unwrap();
+                for (name, val) in names.iter().zip(values) {
+                    scope.insert(*name, Slot::Local(val));
+                }
+            }
+            
+            Statement::StaticDecl { name, value, .. } => {
+                let key = stmt as *const Statement;
+                if !self.statics.contains_key(&key) {
+                    let init = self.eval_expr(value)?;
+                    self.statics.insert(key, init);
+                }
+                self.scopes.last_mut().unwrap().insert(*name, Slot::Static(key));
+            }
+            
+            Statement::Assignment { name, value, .. } => {
+                let val = self.eval_expr(value)?;
+                *self.lookup(*name)? = val;
+            }
+            
+            Statement::MultiAssign { names, values, .. } => {
+                let mut vals = Vec::new();
+                for value in values {
+                    vals.push(self.eval_expr(value)?);
+                }
+                for (name, val) in names.iter().zip(vals) {
+                    *self.lookup(*name)? = val;
+                }
+            }
+            
+            Statement::If {
+                condition,
+                then_block,
+                else_block,
+                ..
+            } => {
+                if self.eval_expr(condition)? != 0 {
+                    return self.exec_block(then_block);
+                } else if let Some(else_blk) = else_block {
+                    return self.exec_block(else_blk);
+                }
+            }
+            
+            Statement::While {
+                label,
+                condition,
+                body,
+                ..
+            } => {
+                while self.eval_expr(condition)? != 0 {
+                    match self.exec_block(body)? {
+                        Flow::Normal => {}
+                        Flow::Continue(target) if target.is_none() || target == *label => {}
+                        Flow::Break(target, _) if target.is_none() || target == *label => break,
+                        flow => return Ok(flow),
+                    }
+                }
+            }
+            
+            Statement::ForIn {
+                label,
+                var,
+                iter,
+                body,
+                ..
+            } => {
+                let Expr::Range { start, end } = iter else {
+                    return Err(EvalError::Runtime("Can only iterate over a range".to_string()));
+                };
+                let first = self.eval_expr(&self.exprs[*start])?;
+                let end = self.eval_expr(&self.exprs[*end])?;
+                
+                // The loop variable lives in a scope of its own around the body
+                self.scopes.push(HashMap::from([(*var, Slot::Local(first))]));
+                let flow = self.exec_for_in(*label, *var, end, body);
+                self.scopes.pop();
+                return flow;
+            }
+            
+            Statement::DoWhile {
+                label,
+                body,
+                condition,
+                ..
+            } => loop {
+                match self.exec_block(body)? {
+                    Flow::Normal => {}
+                    Flow::Continue(target) if target.is_none() || target == *label => {}
+                    Flow::Break(target, _) if target.is_none() || target == *label => break,
+                    flow => return Ok(flow),
+                }
+                if self.eval_expr(condition)? == 0 {
+                    break;
+                }
+            },
+            
+            Statement::Return { value, .. } => return self.exec_return(value),
+            
+            Statement::Break { label, value, .. } => {
+                let value = match value {
+                    Some(value) => Some(self.eval_expr(value)?),
+                    None => None,
+                };
+                return Ok(Flow::Break(*label, value));
+            }
+            
+            Statement::Continue { label, .. } => return Ok(Flow::Continue(*label)),
+            
+            Statement::ExprStmt { expr, .. } => {
+                self.eval_expr(expr)?;
+            }
+            
+            // Registered in the function table up front
+            Statement::FuncDecl { .. } => {}
+        }
+        
+        Ok(Flow::Normal)
+    }
+    
+    /// Runs a `for` loop body until its variable reaches `end`, stepping
+    /// it after every pass including ones left by `continue`
+    fn exec_for_in(
+        &mut self,
+        label: Option<Symbol>,
+        var: Symbol,
+        end: i64,
+        body: &'a Block,
+    ) -> Result<Flow, EvalError> {
+        while *self.lookup(var)? < end {
+            match self.exec_block(body)? {
+                Flow::Normal => {}
+                Flow::Continue(target) if target.is_none() || target == label => {}
+                Flow::Break(target, _) if target.is_none() || target == label => break,
+                flow => return Ok(flow),
+            }
+            let i = self.lookup(var)?;
+            *i = i.wrapping_add(1);
+        }
+        Ok(Flow::Normal)
+    }
+    
+    /// Turns a `loop` expression left by `return` or by a `break` or
+    /// `continue` of an outer loop back into the flow of its statement
+    fn catch_flow(result: Result<Flow, EvalError>) -> Result<Flow, EvalError> {
+        match result {
+            Err(EvalError::Flow(flow)) => Ok(flow),
+            result => result,
+        }
+    }
+    
+    fn exec_return(&mut self, value: &'a Expr) -> Result<Flow, EvalError> {
+        if let Expr::Call { name, args, .. } = value
+            && self.current_function == Some(*name)
+        {
+            let mut arg_values = Vec::new();
+            for arg in args {
+                arg_values.push(self.eval_expr(arg)?);
+            }
+            return Ok(Flow::TailCall(arg_values));
+        }
+        
+        Ok(Flow::Return(self.eval_expr(value)?))
+    }
+    
+    fn eval_expr(&mut self, expr: &'a Expr) -> Result<i64, EvalError> {
+        match expr {
+            Expr::Number(n) => Ok(*n),
+            
+            Expr::Str(text) => Ok(*self
+                .strings
+                .entry(text)
+                .or_insert_with(|| runtime::new_str(text.as_bytes()))),
+            
+            Expr::Variable { name, .. } => Ok(*self.lookup(*name)?),
+            
+            Expr::Assign { name, value, .. } => {
+                let val = self.eval_expr(&self.exprs[*value])?;
+                *self.lookup(*name)? = val;
+                Ok(val)
+            }
+            
+            Expr::If { cond, then_val, else_val } => {
+                if self.eval_expr(&self.exprs[*cond])? != 0 {
+                    self.eval_expr(&self.exprs[*then_val])
+                } else {
+                    self.eval_expr(&self.exprs[*else_val])
+                }
+            }
+            
+            Expr::Binary { .. } | Expr::Cast { .. } => {
+                let (spine, bottom) = expr.left_spine(self.exprs);
+                let mut val = self.eval_expr(bottom)?;
+                let mut operand = LeftOperand::new(
+                    bottom,
+                    self.exprs,
+                    &|name| self.is_unsigned_var(name),
+                    &|name| self.is_string_var(name),
+                );
+                for node in spine.into_iter().rev() {
+                    let (unsigned, concat) = operand.climb(node, self.exprs, &|name| self.is_unsigned_var(name));
+                    val = match node {
+                        Expr::Binary { op, right, .. } => {
+                            let rhs = self.eval_expr(&self.exprs[*right])?;
+                            if concat {
+                                runtime::edust_str_concat(val, rhs)
+                            } else {
+                                let result = if unsigned { op.apply_unsigned(val, rhs) } else { op.apply(val, rhs) };
+                                result.ok_or_else(|| "integer division error".to_string())?
+                            }
+                        }
+                        Expr::Cast { target, .. } => target.wrap(val),
+                        _ => unreachable!(),
+                    };
+                }
+                Ok(val)
+            }
+            
+            Expr::Unary { op, operand } => {
+                let val = self.eval_expr(&self.exprs[*operand])?;
+                Ok(op.apply(val))
+            }
+            
+            Expr::Call { name, args, span } => {
+                // Arrays only exist as literals, whose length is known
+                if name == "len"
+                    && let [Expr::Array(elements)] = args.as_slice()
+                {
+                    for element in elements {
+                        self.eval_expr(element)?;
+                    }
+                    return Ok(elements.len() as i64);
+                }
+                
+                let mut arg_values = Vec::new();
+                for arg in args {
+                    arg_values.push(self.eval_expr(arg)?);
+                }
+                
+                if let Ok(closure) = self.lookup(*name).map(|value| *value) {
+                    return self.call_closure(closure, arg_values, *span);
+                }
+                
+                match name.as_str() {
+                    "print" | "write" => {
+                        if args.is_empty() {
+                            return Err(format!("{}() requires at least 1 argument", name).into());
+                        }
+                        let mut result = 0;
+                        for (i, (value, arg)) in arg_values.iter().zip(args).enumerate() {
+                            if i > 0 {
+                                runtime::print_sep();
+                            }
+                            result = if arg.is_string(self.exprs, &|name| self.is_string_var(name)) {
+                                runtime::print_str(*value)
+                            } else {
+                                runtime::print_int(*value)
+                            };
+                        }
+                        if name == "print" {
+                            runtime::print_newline();
+                        }
+                        Ok(result)
+                    }
+                    "print_hex" | "print_bin" => {
+                        if name == "print_hex" {
+                            runtime::print_hex(arg_values[0]);
+                        } else {
+                            runtime::print_bin(arg_values[0]);
+                        }
+                        runtime::print_newline();
+                        Ok(0)
+                    }
+                    "assert" => {
+                        if arg_values[0] == 0 {
+                            return Err(format!("assertion failed at line {}", span.line).into());
+                        }
+                        Ok(0)
+                    }
+                    "assert_eq" => {
+                        if arg_values[0] != arg_values[1] {
+                            return Err(format!(
+                                "assertion failed: {} != {} at line {}",
+                                arg_values[0], arg_values[1], span.line
+                            ).into());
+                        }
+                        Ok(0)
+                    }
+                    "exit" => runtime::edust_exit(arg_values[0]),
+                    "todo" | "unimplemented" => Err(format!("not yet implemented at line {}", span.line).into()),
+                    "panic" => {
+                        let message = unsafe { runtime::str_bytes(arg_values[0]) };
+                        Err(format!("panic: {}", String::from_utf8_lossy(message)).into())
+                    }
+                    "abs" => Ok(arg_values[0].wrapping_abs()),
+                    "popcount" => Ok(arg_values[0].count_ones() as i64),
+                    "clz" => Ok(arg_values[0].leading_zeros() as i64),
+                    "ctz" => Ok(arg_values[0].trailing_zeros() as i64),
+                    "read_int_or" => Ok(runtime::edust_read_int_or(arg_values[0])),
+                    "time_ms" => Ok(runtime::edust_time_ms()),
+                    "rand" => Ok(runtime::edust_rand()),
+                    "srand" => {
+                        runtime::edust_srand(arg_values[0]);
+                        Ok(0)
+                    }
+                    "to_str" => Ok(runtime::edust_int_to_str(arg_values[0])),
+                    "alloc" => Ok(self.alloc(arg_values[0], *span)?),
+                    "free" => {
+                        if arg_values[0] != 0 && self.memory.remove(&arg_values[0]).is_none() {
+                            return Err(format!("free of an invalid pointer at line {}", span.line).into());
+                        }
+                        self.closures.remove(&arg_values[0]);
+                        Ok(0)
+                    }
+                    "map_new" => Ok(runtime::edust_map_new()),
+                    "map_set" | "map_get" | "map_free" => {
+                        let handle = arg_values[0];
+                        if !(runtime::is_map(handle) || (name == "map_free" && handle == 0)) {
+                            return Err(format!("invalid map handle {} at line {}", handle, span.line).into());
+                        }
+                        match name.as_str() {
+                            "map_set" => runtime::edust_map_set(handle, arg_values[1], arg_values[2]),
+                            "map_get" => return Ok(runtime::edust_map_get(handle, arg_values[1])),
+                            _ => runtime::edust_map_free(handle),
+                        }
+                        Ok(0)
+                    }
+                    "args_count" => Ok(self.varargs.len() as i64),
+                    "args_get" => usize::try_from(arg_values[0])
+                        .ok()
+                        .and_then(|index| self.varargs.get(index).copied())
+                        .ok_or_else(|| {
+                            format!(
+                                "args_get index {} out of range for {} arguments at line {}",
+                                arg_values[0],
+                                self.varargs.len(),
+                                span.line
+                            )
+                            .into()
+                        }),
+                    "load" => Ok(*self.word(arg_values[0], *span)?),
+                    "store" => {
+                        *self.word(arg_values[0], *span)? = arg_values[1];
+                        Ok(0)
+                    }
+                    "sat_add" => Ok(arg_values[0].saturating_add(arg_values[1])),
+                    "sat_sub" => Ok(arg_values[0].saturating_sub(arg_values[1])),
+                    "sat_mul" => Ok(arg_values[0].saturating_mul(arg_values[1])),
+                    "min" => Ok(arg_values[0].min(arg_values[1])),
+                    "max" => Ok(arg_values[0].max(arg_values[1])),
+                    _ => self.call_function(*name, arg_values),
+                }
+            }
+            
+            Expr::Array(_) => Err(EvalError::Runtime("Array literals can only be destructured".to_string())),
+            Expr::Range { .. } => Err(EvalError::Runtime("Ranges can only be iterated over".to_string())),
+            
+            Expr::Closure { params, body, span, .. } => {
+                let mut captures = HashMap::new();
+                for name in expr.free_names(self.exprs) {
+                    let slot = self.scopes.iter().rev().find_map(|scope| scope.get(&name)).copied();
+                    let slot = match slot {
+                        // A static's value is copied like a local's
+                        Some(Slot::Static(key)) => Slot::Local(self.statics[&key]),
+                        Some(slot) => slot,
+                        None => continue,
+                    };
+                    captures.insert(name, slot);
+                }
+                
+                let address = self.alloc(16, *span)?;
+                let closure = Closure {
+                    params,
+                    body: &self.exprs[*body],
+                    captures,
+                };
+                self.closures.insert(address, closure);
+                Ok(address)
+            }
+            
+            Expr::Loop { label, body, .. } => loop {
+                match self.exec_block(body)? {
+                    Flow::Normal => {}
+                    Flow::Continue(target) if target.is_none() || target == *label => {}
+                    Flow::Break(target, value) if target.is_none() || target == *label => {
+                        return Ok(value.unwrap_or(0));
+                    }
+                    // Leaves the expressions around the loop too
+                    flow => return Err(EvalError::Flow(flow)),
+                }
+            },
+        }
+    }
+    
+    fn call_closure(&mut self, closure: i64, args: Vec<i64>, span: Span) -> Result<i64, EvalError> {
+        let closure = self
+            .closures
+            .get(&closure)
+            .ok_or_else(|| format!("called a value that is not a closure at line {}", span.line))?;
+        if closure.params.len() != args.len() {
+            return Err(format!(
+                "closure called with the wrong number of arguments at line {}",
+                span.line
+            ).into());
+        }
+        
+        // The body sees only its captures and parameters
+        let mut frame: HashMap<Symbol, Slot> = closure
+            .captures
+            .iter()
+            .map(|(name, slot)| (*name, *slot))
+            .collect();
+        frame.extend(closure.params.iter().copied().zip(args.into_iter().map(Slot::Local)));
+        let body = closure.body;
+        
+        // A `return` inside a `loop` in the body returns from the closure,
+        // which is never the current function of a tail call
+        let saved = std::mem::replace(&mut self.scopes, vec![frame]);
+        let saved_function = self.current_function.take();
+        let result = match self.eval_expr(body) {
+            Err(EvalError::Flow(Flow::Return(value))) => Ok(value),
+            result => result,
+        };
+        self.current_function = saved_function;
+        self.scopes = saved;
+        result
+    }
+    
+    /// Makes a zeroed block of at least `bytes` bytes and returns its
+    /// address. Blocks are spaced apart so running off the end of one
+    /// never reaches another.
+    fn alloc(&mut self, bytes: i64, span: Span) -> Result<i64, String> {
+        if bytes < 0 {
+            return Err(format!("invalid allocation size {} at line {}", bytes, span.line));
+        }
+        let words = (bytes as u64).div_ceil(8);
+        let address = self.next_address;
+        self.next_address = words
+            .checked_add(1)
+            .and_then(|n| i64::try_from(n * 8).ok())
+            .and_then(|size| address.checked_add(size))
+            .ok_or_else(|| format!("invalid allocation size {} at line {}", bytes, span.line))?;
+        self.memory.insert(address, vec![0; words as usize]);
+        Ok(address)
+    }
+    
+    /// The allocated word at `address`, which must be 8-byte aligned
+    /// within a live block
+    fn word(&mut self, address: i64, span: Span) -> Result<&mut i64, String> {
+        self.memory
+            .range_mut(..=address)
+            .next_back()
+            .and_then(|(start, words)| {
+                let offset = address - start;
+                if offset % 8 != 0 {
+                    return None;
+                }
+                words.get_mut((offset / 8) as usize)
+            })
+            .ok_or_else(|| format!("invalid memory access at line {}", span.line))
+    }
+    
+    fn lookup(&mut self, name: Symbol) -> Result<&mut i64, String> {
+        let slot = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(&name))
+            .ok_or_else(|| format!("Undefined variable: {}", name))?;
+        match slot {
+            Slot::Local(value) | Slot::Unsigned(value) | Slot::Str(value) => Ok(value),
+            Slot::Static(key) => Ok(self.statics.get_mut(key).unwrap()),
+        }
+    }
+    
+    /// Whether the innermost variable named `name` was declared `u64`
+    fn is_unsigned_var(&self, name: Symbol) -> bool {
+        let slot = self.scopes.iter().rev().find_map(|scope| scope.get(&name));
+        matches!(slot, Some(Slot::Unsigned(_)))
+    }
+    
+    /// Whether the innermost variable named `name` holds a string
+    fn is_string_var(&self, name: Symbol) -> bool {
+        let slot = self.scopes.iter().rev().find_map(|scope| scope.get(&name));
+        matches!(slot, Some(Slot::Str(_)))
+    }
+}
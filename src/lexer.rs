@@ -1,41 +1,66 @@
-use crate::token::{Token, TokenType};
+//! Lexical analysis.
+//!
+//! Identifiers follow Unicode's XID rules (UAX #31): they start with an
+//! `XID_Start` character or `_` and continue with `XID_Continue` characters,
+//! so `π` or `größe` are valid names. Identifiers are compared by code
+//! point without normalization, and columns count characters.
+
+use crate::ast::Span;
+use crate::error::CompileError;
+use crate::symbol::Symbol;
+use crate::token::{KEYWORDS, Token, TokenType};
+use std::num::IntErrorKind;
+use unicode_ident::{is_xid_continue, is_xid_start};
 
 pub struct Lexer {
     input: Vec<char>,
     position: usize,
     line: usize,
     column: usize,
+    
+    // Columns between tab stops
+    tab_width: usize,
+    
+    // Set once `Eof` or an error has been produced
+    finished: bool,
+    
+    // Where the first comment skipped so far starts
+    first_comment: Option<Span>,
 }
 
+/// Tab width used by `Lexer::new`
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+
 impl Lexer {
     pub fn new(input: &str) -> Self {
+        Self::with_tab_width(input, DEFAULT_TAB_WIDTH)
+    }
+    
+    /// Like `new`, but a tab moves the column to the next multiple of
+    /// `tab_width` (plus one, as columns start at 1)
+    pub fn with_tab_width(input: &str, tab_width: usize) -> Self {
         Lexer {
             input: input.chars().collect(),
             position: 0,
             line: 1,
             column: 1,
+            tab_width: tab_width.max(1),
+            finished: false,
+            first_comment: None,
         }
     }
     
-    pub fn tokenize(&mut self) -> Result<Vec<Token>, String> {
-        let mut tokens = Vec::new();
-        
-        loop {
-            self.skip_whitespace();
-            
-            if self.is_at_end() {
-                tokens.push(Token::new(TokenType::Eof, self.line, self.column));
-                break;
-            }
-            
-            let token = self.next_token()?;
-            tokens.push(token);
-        }
-        
-        Ok(tokens)
+    /// Collects the remaining tokens, ending with `Eof`
+    pub fn tokenize(&mut self) -> Result<Vec<Token>, CompileError> {
+        self.collect()
+    }
+    
+    /// Where the first comment read so far starts, if there was one
+    pub fn first_comment(&self) -> Option<Span> {
+        self.first_comment
     }
     
-    fn next_token(&mut self) -> Result<Token, String> {
+    fn next_token(&mut self) -> Result<Token, CompileError> {
         let start_line = self.line;
         let start_column = self.column;
         
@@ -59,25 +84,25 @@ impl Lexer {
                 self.advance();
                 return Ok(Token::new(TokenType::RBrace, start_line, start_column));
             }
-            ',' => {
+            '[' => {
                 self.advance();
-                return Ok(Token::new(TokenType::Comma, start_line, start_column));
+                return Ok(Token::new(TokenType::LBracket, start_line, start_column));
             }
-            ';' => {
+            ']' => {
                 self.advance();
-                return Ok(Token::new(TokenType::Semicolon, start_line, start_column));
+                return Ok(Token::new(TokenType::RBracket, start_line, start_column));
             }
-            '+' => {
+            ',' => {
                 self.advance();
-                return Ok(Token::new(TokenType::Plus, start_line, start_column));
+                return Ok(Token::new(TokenType::Comma, start_line, start_column));
             }
-            '-' => {
+            ':' => {
                 self.advance();
-                return Ok(Token::new(TokenType::Minus, start_line, start_column));
+                return Ok(Token::new(TokenType::Colon, start_line, start_column));
             }
-            '*' => {
+            ';' => {
                 self.advance();
-                return Ok(Token::new(TokenType::Star, start_line, start_column));
+                return Ok(Token::new(TokenType::Semicolon, start_line, start_column));
             }
             '/' => {
                 self.advance();
@@ -91,6 +116,33 @@ impl Lexer {
         }
         
         // Two-character operators
+        if ch == '+' {
+            self.advance();
+            if self.current_char() == '+' {
+                self.advance();
+                return Ok(Token::new(TokenType::PlusPlus, start_line, start_column));
+            }
+            return Ok(Token::new(TokenType::Plus, start_line, start_column));
+        }
+        
+        if ch == '-' {
+            self.advance();
+            if self.current_char() == '-' {
+                self.advance();
+                return Ok(Token::new(TokenType::MinusMinus, start_line, start_column));
+            }
+            return Ok(Token::new(TokenType::Minus, start_line, start_column));
+        }
+        
+        if ch == '*' {
+            self.advance();
+            if self.current_char() == '*' {
+                self.advance();
+                return Ok(Token::new(TokenType::StarStar, start_line, start_column));
+            }
+            return Ok(Token::new(TokenType::Star, start_line, start_column));
+        }
+        
         if ch == '=' {
             self.advance();
             if self.current_char() == '=' {
@@ -133,7 +185,8 @@ impl Lexer {
                 self.advance();
                 return Ok(Token::new(TokenType::And, start_line, start_column));
             }
-            return Err(format!("Unexpected character '&' at line {}, column {}", start_line, start_column));
+            let span = Span { line: start_line, column: start_column };
+            return Err(CompileError::at("Unexpected character '&'", span));
         }
         
         if ch == '|' {
@@ -142,42 +195,150 @@ impl Lexer {
                 self.advance();
                 return Ok(Token::new(TokenType::Or, start_line, start_column));
             }
-            return Err(format!("Unexpected character '|' at line {}, column {}", start_line, start_column));
+            return Ok(Token::new(TokenType::Pipe, start_line, start_column));
         }
         
-        // Numbers
-        if ch.is_ascii_digit() {
+        if ch == '.' && self.input[self.position..].starts_with(&['.', '.', '.']) {
+            for _ in 0..3 {
+                self.advance();
+            }
+            return Ok(Token::new(TokenType::Ellipsis, start_line, start_column));
+        }
+        
+        if ch == '.' && self.input[self.position..].starts_with(&['.', '.']) {
+            self.advance();
+            self.advance();
+            return Ok(Token::new(TokenType::DotDot, start_line, start_column));
+        }
+        
+        // Numbers, including floats written `.5`
+        let next_is_digit = self.input.get(self.position + 1).is_some_and(|c| c.is_ascii_digit());
+        if ch.is_ascii_digit() || (ch == '.' && next_is_digit) {
             return self.read_number(start_line, start_column);
         }
         
+        // String literals
+        if ch == '"' {
+            return self.read_string(start_line, start_column);
+        }
+        
         // Identifiers and keywords
-        if ch.is_alphabetic() || ch == '_' {
+        if is_xid_start(ch) || ch == '_' {
             return self.read_identifier(start_line, start_column);
         }
         
-        Err(format!("Unexpected character '{}' at line {}, column {}", ch, start_line, start_column))
+        // Combining marks and other continue-only characters must follow a
+        // letter, e.g. U+0301 cannot begin a name on its own
+        if is_xid_continue(ch) && !ch.is_ascii_digit() {
+            return Err(CompileError::at(
+                format!("Identifier cannot start with '{}' (U+{:04X})", ch, ch as u32),
+                Span { line: start_line, column: start_column },
+            ));
+        }
+        
+        Err(CompileError::at(
+            format!("Unexpected character '{}'", ch),
+            Span { line: start_line, column: start_column },
+        ))
     }
     
-    fn read_number(&mut self, line: usize, column: usize) -> Result<Token, String> {
+    fn read_number(&mut self, line: usize, column: usize) -> Result<Token, CompileError> {
+        let span = Span { line, column };
         let mut num_str = String::new();
+        self.read_digits(&mut num_str);
         
-        while !self.is_at_end() && self.current_char().is_ascii_digit() {
+        // A fraction or an exponent makes a float. A `.` followed by
+        // another starts a range instead, as in `0..3`.
+        let mut is_float = false;
+        if self.current_char() == '.' && self.input.get(self.position + 1) != Some(&'.') {
+            is_float = true;
+            num_str.push('.');
+            self.advance();
+            self.read_digits(&mut num_str);
+        }
+        if matches!(self.current_char(), 'e' | 'E') {
+            is_float = true;
             num_str.push(self.current_char());
             self.advance();
+            if matches!(self.current_char(), '+' | '-') {
+                num_str.push(self.current_char());
+                self.advance();
+            }
+            if !self.current_char().is_ascii_digit() {
+                return Err(CompileError::at(
+                    format!("malformed float literal '{}': the exponent has no digits", num_str),
+                    span,
+                ));
+            }
+            self.read_digits(&mut num_str);
+        }
+        if self.current_char() == '.' && self.input.get(self.position + 1).is_some_and(|c| c.is_ascii_digit()) {
+            while self.current_char() == '.' || self.current_char().is_ascii_digit() {
+                num_str.push(self.current_char());
+                self.advance();
+            }
+            return Err(CompileError::at(
+                format!("malformed float literal '{}': more than one decimal point", num_str),
+                span,
+            ));
         }
         
-        let value = num_str.parse::<i64>()
-            .map_err(|_| format!("Invalid number at line {}, column {}", line, column))?;
+        if is_float {
+            let value = num_str
+                .parse::<f64>()
+                .map_err(|_| CompileError::at("Invalid number", span))?;
+            return Ok(Token::new(TokenType::Float(value), line, column));
+        }
+        
+        // `i64::MIN`'s magnitude is only valid after a unary minus, which the
+        // parser checks; it is passed on as `Number(i64::MIN)`
+        let value = match num_str.parse::<i64>() {
+            Ok(value) => value,
+            Err(e) if *e.kind() == IntErrorKind::PosOverflow => {
+                if num_str.parse::<u64>() != Ok(i64::MIN.unsigned_abs()) {
+                    return Err(CompileError::at("integer literal too large for i64", span));
+                }
+                i64::MIN
+            }
+            Err(_) => return Err(CompileError::at("Invalid number", span)),
+        };
         
         Ok(Token::new(TokenType::Number(value), line, column))
     }
     
-    fn read_identifier(&mut self, line: usize, column: usize) -> Result<Token, String> {
+    /// Appends the decimal digits at the current position to `out`
+    fn read_digits(&mut self, out: &mut String) {
+        while !self.is_at_end() && self.current_char().is_ascii_digit() {
+            out.push(self.current_char());
+            self.advance();
+        }
+    }
+    
+    /// Reads a `"..."` literal. There are no escapes, and a string cannot
+    /// span lines.
+    fn read_string(&mut self, line: usize, column: usize) -> Result<Token, CompileError> {
+        self.advance();
+        
+        let mut text = String::new();
+        while !self.is_at_end() && !matches!(self.current_char(), '"' | '\n' | '\r') {
+            text.push(self.current_char());
+            self.advance();
+        }
+        
+        if self.current_char() != '"' {
+            return Err(CompileError::at("Unterminated string", Span { line, column }));
+        }
+        self.advance();
+        
+        Ok(Token::new(TokenType::Str(text), line, column))
+    }
+    
+    fn read_identifier(&mut self, line: usize, column: usize) -> Result<Token, CompileError> {
         let mut ident = String::new();
         
         while !self.is_at_end() {
             let ch = self.current_char();
-            if ch.is_alphanumeric() || ch == '_' {
+            if is_xid_continue(ch) {
                 ident.push(ch);
                 self.advance();
             } else {
@@ -185,32 +346,75 @@ impl Lexer {
             }
         }
         
-        let token_type = match ident.as_str() {
-            "func" => TokenType::Func,
-            "let" => TokenType::Let,
-            "if" => TokenType::If,
-            "else" => TokenType::Else,
-            "while" => TokenType::While,
-            "return" => TokenType::Return,
-            _ => TokenType::Ident(ident),
+        let token_type = match KEYWORDS.iter().find(|(word, _)| *word == ident) {
+            Some((_, typ)) => typ.clone(),
+            None => TokenType::Ident(Symbol::intern(&ident)),
         };
         
         Ok(Token::new(token_type, line, column))
     }
     
-    fn skip_whitespace(&mut self) {
+    /// Skips blanks, line breaks and comments: `//` runs to the end of the
+    /// line, `/* ... */` to the first `*/` (they don't nest)
+    fn skip_whitespace(&mut self) -> Result<(), CompileError> {
         while !self.is_at_end() {
             let ch = self.current_char();
-            if ch == ' ' || ch == '\t' || ch == '\r' {
+            let next = self.input.get(self.position + 1).copied();
+            if ch == ' ' || ch == '\t' {
                 self.advance();
-            } else if ch == '\n' {
-                self.line += 1;
-                self.column = 1;
-                self.position += 1;
+            } else if ch == '\n' || ch == '\r' {
+                self.newline();
+            } else if ch == '/' && next == Some('/') {
+                self.note_comment();
+                while !self.is_at_end() && !matches!(self.current_char(), '\n' | '\r') {
+                    self.advance();
+                }
+            } else if ch == '/' && next == Some('*') {
+                self.note_comment();
+                self.skip_block_comment()?;
             } else {
                 break;
             }
         }
+        Ok(())
+    }
+    
+    fn note_comment(&mut self) {
+        let span = Span { line: self.line, column: self.column };
+        self.first_comment.get_or_insert(span);
+    }
+    
+    fn skip_block_comment(&mut self) -> Result<(), CompileError> {
+        let (line, column) = (self.line, self.column);
+        self.advance();
+        self.advance();
+        
+        loop {
+            match self.current_char() {
+                _ if self.is_at_end() => {
+                    return Err(CompileError::at("Unterminated comment", Span { line, column }));
+                }
+                '*' if self.input.get(self.position + 1) == Some(&'/') => {
+                    self.advance();
+                    self.advance();
+                    return Ok(());
+                }
+                '\n' | '\r' => self.newline(),
+                _ => self.advance(),
+            }
+        }
+    }
+    
+    /// Consume one line break: `\n`, `\r\n` or a lone `\r`
+    fn newline(&mut self) {
+        if self.current_char() == '\r' {
+            self.position += 1;
+        }
+        if self.current_char() == '\n' {
+            self.position += 1;
+        }
+        self.line += 1;
+        self.column = 1;
     }
     
     fn current_char(&self) -> char {
@@ -223,8 +427,12 @@ impl Lexer {
     
     fn advance(&mut self) {
         if !self.is_at_end() {
+            if self.current_char() == '\t' {
+                self.column += self.tab_width - (self.column - 1) % self.tab_width;
+            } else {
+                self.column += 1;
+            }
             self.position += 1;
-            self.column += 1;
         }
     }
     
@@ -233,6 +441,34 @@ impl Lexer {
     }
 }
 
+/// Yields tokens lazily, ending with `Eof`. Iteration stops after the
+/// first error.
+impl Iterator for Lexer {
+    type Item = Result<Token, CompileError>;
+    
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        
+        if let Err(e) = self.skip_whitespace() {
+            self.finished = true;
+            return Some(Err(e));
+        }
+        
+        if self.is_at_end() {
+            self.finished = true;
+            return Some(Ok(Token::new(TokenType::Eof, self.line, self.column)));
+        }
+        
+        let token = self.next_token();
+        if token.is_err() {
+            self.finished = true;
+        }
+        Some(token)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -248,6 +484,19 @@ mod tests {
         assert!(matches!(tokens[2].typ, TokenType::LParen));
     }
     
+    #[test]
+    fn test_keywords() {
+        for (word, typ) in KEYWORDS {
+            let tokens = Lexer::new(word).tokenize().unwrap();
+            assert_eq!(&tokens[0].typ, typ);
+            assert_eq!(typ.keyword(), Some(*word));
+        }
+        
+        let tokens = Lexer::new("lets").tokenize().unwrap();
+        assert_eq!(tokens[0].typ, TokenType::Ident(Symbol::intern("lets")));
+        assert_eq!(tokens[0].typ.keyword(), None);
+    }
+    
     #[test]
     fn test_operators() {
         let input = "+ - * / % < <= > >= == != && || !";
@@ -258,4 +507,207 @@ mod tests {
         assert!(matches!(tokens[1].typ, TokenType::Minus));
         assert!(matches!(tokens[2].typ, TokenType::Star));
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_star_star() {
+        let input = "2 ** 3 * 4";
+        let mut lexer = Lexer::new(input);
+        let tokens = lexer.tokenize().unwrap();
+        
+        assert!(matches!(tokens[1].typ, TokenType::StarStar));
+        assert!(matches!(tokens[3].typ, TokenType::Star));
+    }
+    
+    #[test]
+    fn test_increment_tokens() {
+        let tokens = Lexer::new("++i - --j + +k").tokenize().unwrap();
+        let types: Vec<TokenType> = tokens.into_iter().map(|t| t.typ).collect();
+        
+        assert!(matches!(
+            types[..],
+            [
+                TokenType::PlusPlus,
+                TokenType::Ident(_),
+                TokenType::Minus,
+                TokenType::MinusMinus,
+                TokenType::Ident(_),
+                TokenType::Plus,
+                TokenType::Plus,
+                TokenType::Ident(_),
+                TokenType::Eof
+            ]
+        ));
+    }
+    
+    #[test]
+    fn test_iterator_matches_tokenize() {
+        let input = "func main() {\n    let x = 2 ** 3;\n    return x;\n}";
+        let collected = Lexer::new(input).tokenize().unwrap();
+        let iterated: Vec<Token> = Lexer::new(input).map(|t| t.unwrap()).collect();
+        
+        assert_eq!(collected.len(), iterated.len());
+        for (a, b) in collected.iter().zip(&iterated) {
+            assert_eq!((&a.typ, a.line, a.column), (&b.typ, b.line, b.column));
+        }
+        assert!(matches!(iterated.last().unwrap().typ, TokenType::Eof));
+    }
+    
+    #[test]
+    fn test_iterator_stops_after_error() {
+        let mut lexer = Lexer::new("let @ x");
+        
+        assert!(matches!(lexer.next(), Some(Ok(_))));
+        assert!(matches!(lexer.next(), Some(Err(_))));
+        assert!(lexer.next().is_none());
+    }
+    
+    #[test]
+    fn test_line_comments() {
+        let tokens = Lexer::new("x // y = 1;\r\n// z\n/ w").tokenize().unwrap();
+        let kinds: Vec<_> = tokens.iter().map(|t| (t.typ.clone(), t.line, t.column)).collect();
+        
+        assert_eq!(
+            kinds,
+            vec![
+                (TokenType::Ident(Symbol::intern("x")), 1, 1),
+                (TokenType::Slash, 3, 1),
+                (TokenType::Ident(Symbol::intern("w")), 3, 3),
+                (TokenType::Eof, 3, 4),
+            ]
+        );
+    }
+    
+    #[test]
+    fn test_block_comments() {
+        let tokens = Lexer::new("x /* y\r\n * z */ w /**/ /* /* */ v").tokenize().unwrap();
+        let kinds: Vec<_> = tokens.iter().map(|t| (t.typ.clone(), t.line, t.column)).collect();
+        
+        assert_eq!(
+            kinds,
+            vec![
+                (TokenType::Ident(Symbol::intern("x")), 1, 1),
+                (TokenType::Ident(Symbol::intern("w")), 2, 9),
+                (TokenType::Ident(Symbol::intern("v")), 2, 25),
+                (TokenType::Eof, 2, 26),
+            ]
+        );
+        
+        let err = Lexer::new("x\n  /* never closed").tokenize().unwrap_err().describe();
+        assert_eq!(err, "Unterminated comment at line 2, column 3");
+    }
+    
+    #[test]
+    fn test_crlf_positions_match_lf() {
+        let lf = "func main() {\n    let x = 1;\n\n    return x;\n}\n";
+        let crlf = lf.replace('\n', "\r\n");
+        let mixed = "func main() {\r\n    let x = 1;\r\r    return x;\n}\r\n";
+        
+        let positions = |input: &str| -> Vec<(usize, usize)> {
+            Lexer::new(input)
+                .tokenize()
+                .unwrap()
+                .iter()
+                .map(|t| (t.line, t.column))
+                .collect()
+        };
+        
+        let expected = positions(lf);
+        assert_eq!(positions(&crlf), expected);
+        assert_eq!(positions(mixed), expected);
+        
+        // `return` starts line 4, `}` line 5
+        assert_eq!(expected[10], (4, 5));
+        assert_eq!(expected[13], (5, 1));
+    }
+    
+    #[test]
+    fn test_tab_advances_to_next_stop() {
+        let column_of = |lexer: Lexer, index: usize| {
+            let tokens: Vec<Token> = lexer.map(|t| t.unwrap()).collect();
+            tokens[index].column
+        };
+        
+        assert_eq!(column_of(Lexer::new("\tlet"), 0), 5);
+        assert_eq!(column_of(Lexer::with_tab_width("\tlet", 8), 0), 9);
+        
+        // A tab after text only moves to the next stop
+        assert_eq!(column_of(Lexer::new("ab\tx"), 1), 5);
+        assert_eq!(column_of(Lexer::new("abcd\tx"), 1), 9);
+        assert_eq!(column_of(Lexer::with_tab_width("x\t\ty", 2), 1), 5);
+    }
+    
+    #[test]
+    fn test_unicode_identifiers() {
+        let tokens = Lexer::new("let π = 3; let Δx_2 = π; let größe\u{0301} = 1;").tokenize().unwrap();
+        
+        assert!(matches!(tokens[1].typ, TokenType::Ident(name) if name == "π"));
+        assert!(matches!(tokens[6].typ, TokenType::Ident(name) if name == "Δx_2"));
+        assert!(matches!(tokens[11].typ, TokenType::Ident(name) if name == "größe\u{0301}"));
+        assert_eq!(tokens[8].column, 23);
+    }
+    
+    #[test]
+    fn test_identifier_cannot_start_with_combining_mark() {
+        let err = Lexer::new("let \u{0301}x = 1;").tokenize().unwrap_err().describe();
+        assert_eq!(err, "Identifier cannot start with '\u{0301}' (U+0301) at line 1, column 5");
+        
+        // Symbols that are not identifier characters at all are still unexpected
+        let err = Lexer::new("let € = 1;").tokenize().unwrap_err().describe();
+        assert_eq!(err, "Unexpected character '€' at line 1, column 5");
+    }
+    
+    #[test]
+    fn test_number_literals() {
+        let tokens = Lexer::new("0 42 007 9223372036854775807").tokenize().unwrap();
+        let values: Vec<i64> = tokens
+            .iter()
+            .filter_map(|t| match t.typ {
+                TokenType::Number(n) => Some(n),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(values, vec![0, 42, 7, i64::MAX]);
+        
+        // One past `i64::MAX` is left for the parser to accept after `-`
+        let tokens = Lexer::new("9223372036854775808").tokenize().unwrap();
+        assert_eq!(tokens[0].typ, TokenType::Number(i64::MIN));
+    }
+    
+    #[test]
+    fn test_number_literal_overflow() {
+        let err = Lexer::new("return 9223372036854775809;").tokenize().unwrap_err().describe();
+        assert_eq!(err, "integer literal too large for i64 at line 1, column 8");
+        
+        let err = Lexer::new("\n  99999999999999999999").tokenize().unwrap_err().describe();
+        assert_eq!(err, "integer literal too large for i64 at line 2, column 3");
+    }
+    
+    #[test]
+    fn test_float_literals() {
+        let tokens = Lexer::new("1e9 2.5e-3 .5 5. 3E+2 0..3").tokenize().unwrap();
+        let types: Vec<TokenType> = tokens.into_iter().map(|t| t.typ).collect();
+        assert_eq!(
+            types,
+            vec![
+                TokenType::Float(1e9),
+                TokenType::Float(2.5e-3),
+                TokenType::Float(0.5),
+                TokenType::Float(5.0),
+                TokenType::Float(300.0),
+                TokenType::Number(0),
+                TokenType::DotDot,
+                TokenType::Number(3),
+                TokenType::Eof,
+            ]
+        );
+        
+        let err = Lexer::new("let x = 1e;").tokenize().unwrap_err().describe();
+        assert_eq!(err, "malformed float literal '1e': the exponent has no digits at line 1, column 9");
+        
+        let err = Lexer::new("2.5e-").tokenize().unwrap_err().describe();
+        assert_eq!(err, "malformed float literal '2.5e-': the exponent has no digits at line 1, column 1");
+        
+        let err = Lexer::new("x = 1.2.3;").tokenize().unwrap_err().describe();
+        assert_eq!(err, "malformed float literal '1.2.3': more than one decimal point at line 1, column 5");
+    }
+}
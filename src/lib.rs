@@ -1,54 +1,475 @@
 pub mod ast;
 pub mod codegen;
+pub mod debuginfo;
+pub mod error;
+pub mod format;
+pub mod interp;
 pub mod lexer;
+pub mod optimize;
 pub mod parser;
+pub mod prelude;
 pub mod runtime;
 pub mod semantic;
+pub mod symbol;
 pub mod token;
 
 use codegen::CodeGenerator;
+use interp::Interpreter;
 use lexer::Lexer;
 use parser::Parser;
-use semantic::SemanticAnalyzer;
+use semantic::{SemanticAnalyzer, Warning};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use symbol::Symbol;
+use token::Token;
 
-/// Complete compilation pipeline for Edust
-pub fn compile_and_run(source: &str) -> Result<i64, String> {
-    // 1. Lexical analysis
+pub use error::CompileError;
+pub use runtime::{BufferIo, EdustIo, set_io};
+
+/// Time spent in each compilation phase. Imported files add to `lex` and
+/// `parse`; `codegen` includes the AST optimizations of optimized builds.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PhaseTimings {
+    pub lex: Duration,
+    pub parse: Duration,
+    pub analyze: Duration,
+    pub codegen: Duration,
+}
+
+impl std::fmt::Display for PhaseTimings {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "lex:      {:?}", self.lex)?;
+        writeln!(f, "parse:    {:?}", self.parse)?;
+        writeln!(f, "analyze:  {:?}", self.analyze)?;
+        write!(f, "codegen:  {:?}", self.codegen)
+    }
+}
+
+/// How serious a `Diagnostic` is
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// An error or warning about a program, with the location it points at
+/// when known. Serializes as `{"severity", "message", "line", "column"}`
+/// for editors and other tools, adding `"file"` for errors in a file
+/// that was loaded, such as an import.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+}
+
+impl From<CompileError> for Diagnostic {
+    fn from(error: CompileError) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            message: error.message,
+            line: error.line,
+            column: error.column,
+            file: error.file,
+        }
+    }
+}
+
+impl From<&Warning> for Diagnostic {
+    fn from(warning: &Warning) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            message: warning.message.clone(),
+            line: warning.span.map(|span| span.line),
+            column: warning.span.map(|span| span.column),
+            file: None,
+        }
+    }
+}
+
+/// Lex `source` into tokens, ending with `Eof`
+pub fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    lex(source).map_err(|e| e.describe())
+}
+
+fn lex(source: &str) -> Result<Vec<Token>, CompileError> {
     let mut lexer = Lexer::new(source);
-    let tokens = lexer.tokenize().map_err(|e| format!("Lexer error: {}", e))?;
+    lexer.tokenize().map_err(|e| e.with_phase("Lexer error"))
+}
+
+/// Lex and parse `source` into an AST, without semantic checks
+pub fn parse(source: &str) -> Result<ast::Program, String> {
+    parse_timed(source, ast::ExprArena::new(), &mut PhaseTimings::default()).map_err(|e| e.describe())
+}
+
+/// Like `parse`, adding the time taken to `timings`. The program's
+/// expressions are added to `exprs`, which becomes its arena.
+fn parse_timed(
+    source: &str,
+    exprs: ast::ExprArena,
+    timings: &mut PhaseTimings,
+) -> Result<ast::Program, CompileError> {
+    let start = Instant::now();
+    let tokens = lex(source)?;
+    timings.lex += start.elapsed();
+    
+    let start = Instant::now();
+    let mut parser = Parser::with_exprs(tokens, exprs);
+    let program = parser.parse().map_err(|e| e.with_phase("Parser error"))?;
+    timings.parse += start.elapsed();
+    
+    Ok(program)
+}
+
+/// Parse `source` as a complete program. Imports can only be resolved
+/// relative to a file, so they are rejected here; see `load_program`.
+fn parse_single_file(source: &str) -> Result<ast::Program, CompileError> {
+    parse_single_file_timed(source, &mut PhaseTimings::default())
+}
+
+fn parse_single_file_timed(source: &str, timings: &mut PhaseTimings) -> Result<ast::Program, CompileError> {
+    let ast = parse_timed(source, ast::ExprArena::new(), timings)?;
+    
+    if let Some(import) = ast.imports.first() {
+        return Err(CompileError::at(
+            format!("Import of \"{}\" needs a source file; use compile_files", import.path),
+            import.span,
+        ));
+    }
+    
+    Ok(ast)
+}
+
+/// Parse the program in `root` together with every file it imports,
+/// directly or indirectly, merging all their functions into one `Program`.
+///
+/// Import paths are relative to the importing file. A file imported from
+/// several places is loaded once; an import cycle is an error, as is the
+/// same function name defined in two different files.
+pub fn load_program(root: &Path) -> Result<ast::Program, String> {
+    load_files(root).map_err(|e| e.describe())
+}
+
+fn load_files(root: &Path) -> Result<ast::Program, CompileError> {
+    let mut loader = Loader::default();
+    loader.load(root)?;
+    Ok(loader.program)
+}
+
+#[derive(Default)]
+struct Loader {
+    program: ast::Program,
+    
+    // Canonical paths of the files already merged
+    loaded: HashSet<PathBuf>,
+    
+    // Files whose imports are being loaded, outermost first
+    stack: Vec<PathBuf>,
+    
+    // The file each function was defined in
+    origins: HashMap<Symbol, PathBuf>,
+    
+    // Time spent lexing and parsing the files loaded so far
+    timings: PhaseTimings,
+}
+
+impl Loader {
+    fn load(&mut self, path: &Path) -> Result<(), CompileError> {
+        let read_error =
+            |e: std::io::Error| CompileError::new(format!("Error reading file {}: {}", path.display(), e));
+        let canonical = fs::canonicalize(path).map_err(read_error)?;
+        
+        if let Some(start) = self.stack.iter().position(|p| *p == canonical) {
+            let cycle: Vec<String> = self.stack[start..]
+                .iter()
+                .chain([&canonical])
+                .map(|p| p.display().to_string())
+                .collect();
+            return Err(CompileError::new(format!("Cyclic import: {}", cycle.join(" -> "))));
+        }
+        if self.loaded.contains(&canonical) {
+            return Ok(());
+        }
+        
+        let source = fs::read_to_string(&canonical).map_err(read_error)?;
+        
+        // Every file's expressions go into the merged program's arena, so
+        // the functions moved there below keep valid ids
+        let exprs = std::mem::take(Arc::make_mut(&mut self.program.exprs));
+        let mut program = parse_timed(&source, exprs, &mut self.timings)
+            .map_err(|e| e.with_file(&path.display().to_string()))?;
+        self.program.exprs = std::mem::take(&mut program.exprs);
+        
+        // Imported functions come before the importing file's own
+        let dir = canonical.parent().unwrap_or(Path::new("")).to_path_buf();
+        self.stack.push(canonical.clone());
+        for import in std::mem::take(&mut program.imports) {
+            self.load(&dir.join(&import.path))?;
+        }
+        self.stack.pop();
+        self.loaded.insert(canonical.clone());
+        
+        for func in program.all_functions() {
+            match self.origins.get(&func.name) {
+                Some(first) if *first != canonical => {
+                    return Err(CompileError::new(format!(
+                        "Duplicate function definition: {} (in {} and {})",
+                        func.name,
+                        first.display(),
+                        canonical.display()
+                    )));
+                }
+                _ => {
+                    self.origins.insert(func.name, canonical.clone());
+                }
+            }
+        }
+        self.program.functions.extend(program.functions);
+        
+        Ok(())
+    }
+}
+
+/// Largest function body (in statements) that optimized builds inline
+const INLINE_MAX_STMTS: usize = 4;
+
+/// Settings for `compile_and_run_with_options`
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Cranelift optimization level: `"none"`, `"speed"` or `"speed_and_size"`.
+    /// Any level other than `"none"` also inlines small functions.
+    pub opt_level: String,
     
-    // 2. Parsing
-    let mut parser = Parser::new(tokens);
-    let ast = parser.parse().map_err(|e| format!("Parser error: {}", e))?;
+    /// Abort with "maximum recursion depth exceeded" once more than this
+    /// many calls are active
+    pub recursion_limit: Option<u64>,
     
+    /// Value passed to `main(argc)`
+    pub argc: i64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            opt_level: "none".to_string(),
+            recursion_limit: None,
+            argc: runtime::argc(),
+        }
+    }
+}
+
+/// Complete compilation pipeline for Edust
+pub fn compile_and_run(source: &str) -> Result<i64, String> {
+    compile_and_run_with_options(source, &Options::default())
+}
+
+/// Like `compile_and_run`, generating code at the given Cranelift
+/// optimization level (`"none"`, `"speed"` or `"speed_and_size"`)
+pub fn compile_and_run_with_opt_level(source: &str, opt_level: &str) -> Result<i64, String> {
+    let options = Options {
+        opt_level: opt_level.to_string(),
+        ..Options::default()
+    };
+    compile_and_run_with_options(source, &options)
+}
+
+/// Like `compile_and_run`, with explicit settings
+pub fn compile_and_run_with_options(source: &str, options: &Options) -> Result<i64, String> {
+    compile_and_run_timed(source, options, |_| {})
+        .map(|(exit_code, _)| exit_code)
+        .map_err(|e| e.describe())
+}
+
+/// Like `compile_and_run_with_options`, also measuring how long each
+/// compilation phase takes. `report` is called with the timings once the
+/// code is generated, before the program runs.
+pub fn compile_and_run_timed(
+    source: &str,
+    options: &Options,
+    report: impl FnOnce(&PhaseTimings),
+) -> Result<(i64, PhaseTimings), CompileError> {
+    // 1-2. Lexical analysis and parsing
+    let mut timings = PhaseTimings::default();
+    let ast = parse_single_file_timed(source, &mut timings)?;
+    run_program(ast, options, timings, report)
+}
+
+/// Like `compile_and_run`, with the functions of the prelude (`gcd`,
+/// `pow`, `factorial`, ...) available to the program. Functions the
+/// program defines itself take precedence over the prelude's.
+pub fn compile_and_run_with_prelude(source: &str) -> Result<i64, String> {
+    let mut timings = PhaseTimings::default();
+    let mut ast = parse_single_file_timed(source, &mut timings).map_err(|e| e.describe())?;
+    prelude::add_to(&mut ast);
+    run_program(ast, &Options::default(), timings, |_| {})
+        .map(|(exit_code, _)| exit_code)
+        .map_err(|e| e.describe())
+}
+
+/// Compile and run the program in `root`, resolving its imports
+pub fn compile_files(root: &Path) -> Result<i64, String> {
+    compile_files_with_options(root, &Options::default())
+}
+
+/// Like `compile_files`, with explicit settings
+pub fn compile_files_with_options(root: &Path, options: &Options) -> Result<i64, String> {
+    compile_files_timed(root, options, |_| {})
+        .map(|(exit_code, _)| exit_code)
+        .map_err(|e| e.describe())
+}
+
+/// Like `compile_files_with_options`, measuring each compilation phase as
+/// `compile_and_run_timed` does
+pub fn compile_files_timed(
+    root: &Path,
+    options: &Options,
+    report: impl FnOnce(&PhaseTimings),
+) -> Result<(i64, PhaseTimings), CompileError> {
+    let mut loader = Loader::default();
+    loader.load(root)?;
+    run_program(loader.program, options, loader.timings, report)
+}
+
+/// Analyze, compile and run a parsed program, adding the time taken by
+/// each phase to `timings` and passing them to `report` before running
+fn run_program(
+    mut ast: ast::Program,
+    options: &Options,
+    mut timings: PhaseTimings,
+    report: impl FnOnce(&PhaseTimings),
+) -> Result<(i64, PhaseTimings), CompileError> {
     // 3. Semantic analysis
-    let mut analyzer = SemanticAnalyzer::new();
-    analyzer.analyze(&ast).map_err(|e| format!("Semantic error: {}", e))?;
+    let start = Instant::now();
+    analyze(&ast)?;
+    timings.analyze = start.elapsed();
+    
+    let start = Instant::now();
+    if options.opt_level != "none" {
+        optimize::inline_small_functions(&mut ast, INLINE_MAX_STMTS);
+        optimize::propagate_constants(&mut ast);
+        optimize::simplify_expr(&mut ast);
+        optimize::prune_dead_branches(&mut ast);
+        optimize::eliminate_dead_functions(&mut ast);
+    }
     
     // 4. Code generation
+    let mut codegen = CodeGenerator::with_opt_level(&options.opt_level).map_err(codegen_error)?;
+    if let Some(limit) = options.recursion_limit {
+        codegen = codegen.with_recursion_limit(limit);
+    }
+    let jit = codegen.compile_all(&ast).map_err(codegen_error)?;
+    timings.codegen = start.elapsed();
+    report(&timings);
+    
+    // 5. Execute, passing argc if main asks for it
+    let result = jit.run_with_argc(options.argc).map_err(codegen_error)?;
+    
+    Ok((result, timings))
+}
+
+fn codegen_error(message: String) -> CompileError {
+    CompileError::new(message).with_phase("Codegen error")
+}
+
+/// Run the front end only and return any warnings it produced
+pub fn check(source: &str) -> Result<Vec<Warning>, CompileError> {
+    let ast = parse_single_file(source)?;
+    analyze(&ast)
+}
+
+/// Like `check`, for the program in `root` and the files it imports
+pub fn check_files(root: &Path) -> Result<Vec<Warning>, CompileError> {
+    let ast = load_files(root)?;
+    analyze(&ast)
+}
+
+/// Run the front end on `source` and return the warnings it produced
+/// followed by the error that stopped it, if any
+pub fn diagnostics(source: &str) -> Vec<Diagnostic> {
+    collect_diagnostics(parse_single_file(source))
+}
+
+/// Like `diagnostics`, for the program in `root` and the files it imports
+pub fn diagnostics_files(root: &Path) -> Vec<Diagnostic> {
+    collect_diagnostics(load_files(root))
+}
+
+fn collect_diagnostics(ast: Result<ast::Program, CompileError>) -> Vec<Diagnostic> {
+    let ast = match ast {
+        Ok(ast) => ast,
+        Err(e) => return vec![e.into()],
+    };
+    
+    // Warnings found before an error are still reported
+    let mut analyzer = SemanticAnalyzer::new();
+    let result = analyzer.analyze(&ast);
+    let mut diagnostics: Vec<Diagnostic> = analyzer.warnings().iter().map(Diagnostic::from).collect();
+    if let Err(e) = result {
+        diagnostics.push(e.with_phase("Semantic error").into());
+    }
+    diagnostics
+}
+
+/// Check `source` and return the control-flow graph of each of its
+/// functions as Graphviz DOT; see `CodeGenerator::emit_cfg_dot`
+pub fn cfg_dot(source: &str) -> Result<String, CompileError> {
+    let ast = parse_single_file(source)?;
+    emit_cfg_dot(&ast)
+}
+
+/// Like `cfg_dot`, for the program in `root` and the files it imports
+pub fn cfg_dot_files(root: &Path) -> Result<String, CompileError> {
+    let ast = load_files(root)?;
+    emit_cfg_dot(&ast)
+}
+
+fn emit_cfg_dot(ast: &ast::Program) -> Result<String, CompileError> {
+    analyze(ast)?;
     let mut codegen = CodeGenerator::new();
-    let code_ptr = codegen.compile(&ast).map_err(|e| format!("Codegen error: {}", e))?;
+    codegen.emit_cfg_dot(ast).map_err(codegen_error)
+}
+
+fn analyze(ast: &ast::Program) -> Result<Vec<Warning>, CompileError> {
+    let mut analyzer = SemanticAnalyzer::new();
+    analyzer.analyze(ast).map_err(|e| e.with_phase("Semantic error"))?;
     
-    // 5. Execute
-    let main_fn: fn() -> i64 = unsafe { std::mem::transmute(code_ptr) };
-    let result = main_fn();
+    Ok(analyzer.warnings().to_vec())
+}
+
+/// Like `compile_and_run`, but also returns everything the program printed
+/// instead of writing it to stdout
+pub fn compile_and_run_capturing(source: &str) -> Result<(i64, String), String> {
+    runtime::start_capture();
+    let result = compile_and_run(source);
+    let output = runtime::finish_capture();
     
-    Ok(result)
+    result.map(|exit_code| (exit_code, output))
 }
 
 /// Compile without running (for testing/debugging)
 pub fn compile_only(source: &str) -> Result<(), String> {
-    let mut lexer = Lexer::new(source);
-    let tokens = lexer.tokenize().map_err(|e| format!("Lexer error: {}", e))?;
-    
-    let mut parser = Parser::new(tokens);
-    let ast = parser.parse().map_err(|e| format!("Parser error: {}", e))?;
-    
-    let mut analyzer = SemanticAnalyzer::new();
-    analyzer.analyze(&ast).map_err(|e| format!("Semantic error: {}", e))?;
+    let ast = parse_single_file(source).map_err(|e| e.describe())?;
+    analyze(&ast).map_err(|e| e.describe())?;
     
     let mut codegen = CodeGenerator::new();
-    let _code_ptr = codegen.compile(&ast).map_err(|e| format!("Codegen error: {}", e))?;
+    codegen.compile(&ast).map_err(|e| format!("Codegen error: {}", e))?;
     
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Run a program with the tree-walking interpreter instead of the JIT
+pub fn interpret(source: &str) -> Result<i64, String> {
+    let ast = parse_single_file(source).map_err(|e| e.describe())?;
+    analyze(&ast).map_err(|e| e.describe())?;
+    
+    let mut interpreter = Interpreter::new();
+    interpreter.run(&ast).map_err(|e| format!("Runtime error: {}", e))
+}
@@ -1,39 +1,155 @@
-use edust::compile_and_run;
+use edust::{CompileError, Options, PhaseTimings, Severity, cfg_dot, cfg_dot_files, check, check_files};
+use edust::{compile_and_run_timed, compile_files_timed, diagnostics, diagnostics_files};
 use std::env;
-use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+const USAGE: &str =
+    "Usage: edustc [--recursion-limit N] [--time] [--emit-cfg] [--diagnostics-json] <source-file | -> [args...]";
 
 fn main() {
     let args: Vec<String> = env::args().collect();
+    let mut options = Options::default();
+    let mut time = false;
+    let mut emit_cfg = false;
+    let mut diagnostics_json = false;
+    
+    // Compiler options come before the source file
+    let mut rest = &args[1..];
+    while let Some(option) = rest.first().filter(|arg| arg.starts_with("--")) {
+        match option.as_str() {
+            "--recursion-limit" => {
+                let limit = rest.get(1).and_then(|n| n.parse().ok()).unwrap_or_else(|| {
+                    eprintln!("--recursion-limit expects a number");
+                    std::process::exit(1);
+                });
+                options.recursion_limit = Some(limit);
+                rest = &rest[2..];
+            }
+            "--time" => {
+                time = true;
+                rest = &rest[1..];
+            }
+            "--emit-cfg" => {
+                emit_cfg = true;
+                rest = &rest[1..];
+            }
+            "--diagnostics-json" => {
+                diagnostics_json = true;
+                rest = &rest[1..];
+            }
+            _ => {
+                eprintln!("Unknown option: {}\n{}", option, USAGE);
+                std::process::exit(1);
+            }
+        }
+    }
     
-    if args.len() < 2 {
-        eprintln!("Usage: edustc <source-file>");
+    if rest.is_empty() {
+        eprintln!("{}", USAGE);
         std::process::exit(1);
     }
     
-    let filename = &args[1];
+    // Like C's argc, the count includes the source file
+    let filename = &rest[0];
+    options.argc = rest.len() as i64;
+    let path = Path::new(filename);
     
-    // Read source file
-    let source = fs::read_to_string(filename)
-        .unwrap_or_else(|e| {
-            eprintln!("Error reading file {}: {}", filename, e);
+    // `-` reads the program from stdin. Such a program can't import files,
+    // which are resolved relative to the importing file.
+    let stdin_source = (filename == "-").then(|| {
+        let mut source = String::new();
+        io::stdin().read_to_string(&mut source).unwrap_or_else(|e| {
+            eprintln!("Error reading stdin: {}", e);
             std::process::exit(1);
         });
+        source
+    });
     
-    // Compile and run
-    match compile_and_run(&source) {
-        Ok(exit_code) => {
-            println!("\nProgram exited with code: {}", exit_code);
+    // `--diagnostics-json` prints the errors and warnings as JSON for tools
+    // instead of running the program, failing if there are errors
+    if diagnostics_json {
+        let diagnostics = match &stdin_source {
+            Some(source) => diagnostics(source),
+            None => diagnostics_files(path),
+        };
+        println!("{}", serde_json::to_string_pretty(&diagnostics).expect("diagnostics serialize"));
+        let failed = diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error);
+        std::process::exit(failed as i32);
+    }
+    
+    // Report warnings; errors are reported by the compile step below
+    let warnings = match &stdin_source {
+        Some(source) => check(source),
+        None => check_files(path),
+    };
+    for warning in warnings.into_iter().flatten() {
+        eprintln!("Warning: {}", warning);
+    }
+    
+    // `--emit-cfg` prints the functions' control-flow graphs instead of
+    // running the program
+    if emit_cfg {
+        let dot = match &stdin_source {
+            Some(source) => cfg_dot(source),
+            None => cfg_dot_files(path),
+        };
+        match dot {
+            Ok(dot) => print!("{}", dot),
+            Err(e) => report_error(e, filename, stdin_source.is_some()),
         }
-        Err(e) => {
-            eprintln!("Compilation error: {}", e);
-            std::process::exit(1);
+        return;
+    }
+    
+    // Compile and run, showing the phase timings before the program's output
+    let report = |timings: &PhaseTimings| {
+        if time {
+            eprintln!("{}", timings);
+        }
+    };
+    let result = match &stdin_source {
+        Some(source) => compile_and_run_timed(source, &options, report),
+        None => compile_files_timed(path, &options, report),
+    };
+    match result {
+        Ok((exit_code, _)) => {
+            println!("\nProgram exited with code: {}", exit_code);
+            
+            // The process status keeps the low 8 bits, as shells do
+            std::process::exit(exit_code as u8 as i32);
         }
+        Err(e) => report_error(e, filename, stdin_source.is_some()),
     }
 }
 
+/// Prints a compile error in the program read from `filename`, or from
+/// stdin, and exits
+fn report_error(e: CompileError, filename: &str, from_stdin: bool) -> ! {
+    // Errors in an imported file already name it; programs read from stdin
+    // have no name
+    let file = if from_stdin { "<stdin>" } else { filename };
+    eprintln!("{}", e.with_file(file));
+    std::process::exit(1);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use edust::ast::{BinOp, Expr, Statement};
+    use edust::codegen::CodeGenerator;
+    use edust::interp::Interpreter;
+    use edust::parser::Parser;
+    use edust::token::TokenType;
+    use edust::{BufferIo, EdustIo, set_io};
+    use edust::{compile_and_run, compile_and_run_capturing, interpret, parse, runtime, tokenize};
+    
+    /// Runs `source` on both backends and checks that they agree
+    fn run(source: &str) -> Result<i64, String> {
+        let jit = compile_and_run(source);
+        let interpreted = interpret(source);
+        assert_eq!(jit, interpreted, "JIT and interpreter disagree");
+        jit
+    }
     
     #[test]
     fn test_basic_program() {
@@ -44,7 +160,7 @@ mod tests {
             }
         "#;
         
-        let result = compile_and_run(source);
+        let result = run(source);
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), 42);
     }
@@ -60,7 +176,7 @@ mod tests {
             }
         "#;
         
-        let result = compile_and_run(source);
+        let result = run(source);
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), 50);
     }
@@ -78,11 +194,473 @@ mod tests {
             }
         "#;
         
-        let result = compile_and_run(source);
+        let result = run(source);
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), 1);
     }
     
+    #[test]
+    fn test_labeled_loops() {
+        let source = r#"
+            func main() {
+                let found = 0;
+                let i = 0;
+                outer: while i < 10 {
+                    i = i + 1;
+                    let j = 0;
+                    while j < 10 {
+                        j = j + 1;
+                        if i * j == 42 {
+                            found = i * 100 + j;
+                            break outer;
+                        }
+                    }
+                }
+                
+                let skipped = 0;
+                let n = 0;
+                rows: do {
+                    n = n + 1;
+                    let m = 0;
+                    while m < 5 {
+                        m = m + 1;
+                        if m == n {
+                            continue rows;
+                        }
+                        skipped = skipped + 1;
+                    }
+                } while n < 4;
+                
+                let total = 0;
+                let a = 0;
+                grid: while a < 3 {
+                    a = a + 1;
+                    let b = 0;
+                    do {
+                        b = b + 1;
+                        if b > a {
+                            continue grid;
+                        }
+                        total = total + b;
+                    } while 1;
+                }
+                
+                return found * 1000 + i * 100 + skipped * 10 + total;
+            }
+        "#;
+        
+        assert_eq!(run(source), Ok(607670));
+        
+        let err = parse("func main() { outer: return 1; }").unwrap_err();
+        assert_eq!(err, "Parser error: Expected a loop after label outer at line 1, column 22");
+    }
+    
+    #[test]
+    fn test_parallel_assignment() {
+        let source = r#"
+            func main() {
+                let a = 1;
+                let b = 2;
+                a, b = b, a;
+                assert_eq(a * 10 + b, 21);
+                
+                let x = 1;
+                let y = 2;
+                let z = 3;
+                x, y, z = y, z, x;
+                assert_eq(x * 100 + y * 10 + z, 231);
+                
+                // Fibonacci, with each step reading the previous pair
+                let prev = 0;
+                let cur = 1;
+                for i in 0..10 {
+                    prev, cur = cur, prev + cur;
+                }
+                return cur;
+            }
+        "#;
+        
+        assert_eq!(run(source), Ok(89));
+    }
+    
+    #[test]
+    fn test_chained_assignment() {
+        let source = r#"
+            func main() {
+                let a = 0;
+                let b = 0;
+                a = b = 7;
+                assert_eq(a, 7);
+                assert_eq(b, 7);
+                
+                // An assignment's value is the value assigned
+                let x = 0;
+                let y = (x = 2) + 1;
+                assert_eq(x * 10 + y, 23);
+                
+                let n = 5;
+                let steps = 0;
+                while (n = n - 1) > 0 {
+                    steps = steps + 1;
+                }
+                return a + b + steps * 100;
+            }
+        "#;
+        
+        assert_eq!(run(source), Ok(414));
+    }
+    
+    #[test]
+    fn test_if_expression() {
+        let source = r#"
+            func larger(a, b) {
+                return if a > b { a } else { b };
+            }
+            
+            func sign(n) {
+                return if n < 0 { -1 } else if n == 0 { 0 } else { 1 };
+            }
+            
+            func main() {
+                let m = larger(3, 9) * 100 + larger(42, 7);
+                assert_eq(sign(-5) * 100 + sign(0) * 10 + sign(7), -99);
+                
+                // Only the branch taken is evaluated
+                let calls = 0;
+                let x = if m > 0 { calls = calls + 1 } else { calls = calls + 100 };
+                
+                let word = if x == 1 { "one" } else { "other" };
+                print(word);
+                return m + (if calls == 1 { 10000 } else { 0 });
+            }
+        "#;
+        
+        assert_eq!(run(source), Ok(10942));
+        
+        let error = parse("func main() { return if 1 { 2 }; }").unwrap_err();
+        assert_eq!(
+            error,
+            "Parser error: Expected else: an if expression needs a value for both branches at line 1, column 32"
+        );
+    }
+    
+    #[test]
+    fn test_increment_and_decrement() {
+        let source = r#"
+            func main() {
+                let i = 0;
+                let passes = 0;
+                while ++i < 5 {
+                    ++passes;
+                }
+                let n = 10;
+                --n;
+                let doubled = ++n * 2 + --i;
+                return passes * 1000 + doubled * 10 + i;
+            }
+        "#;
+        
+        // 4 passes, i ends at 5 and is 4 after `--i`, n is back to 10
+        assert_eq!(run(source), Ok(4244));
+        
+        let err = parse("func main() { ++5; return 0; }").unwrap_err();
+        assert_eq!(err, "Parser error: ++ can only be applied to a variable at line 1, column 17");
+        let err = parse("func main() { return --f(); }").unwrap_err();
+        assert_eq!(err, "Parser error: -- can only be applied to a variable at line 1, column 25");
+        let err = compile_and_run("func main() { ++main; return 0; }").unwrap_err();
+        assert_eq!(err, "Semantic error: Undefined variable: main at line 1, column 15");
+    }
+    
+    #[test]
+    fn test_loop_expression() {
+        let source = r#"
+            func main() {
+                let x = loop { break 42; };
+                return x;
+            }
+        "#;
+        
+        assert_eq!(run(source), Ok(42));
+        
+        let source = r#"
+            func first_square_above(n) {
+                let i = 0;
+                loop {
+                    i = i + 1;
+                    if i * i > n {
+                        return i;
+                    }
+                }
+            }
+            
+            func main() {
+                // Labeled breaks give the value to the loop they name
+                let i = 0;
+                let pair = outer: loop {
+                    i = i + 1;
+                    let j = 0;
+                    while j < 10 {
+                        j = j + 1;
+                        if i * j == 42 {
+                            break outer i * 100 + j;
+                        }
+                    }
+                };
+                
+                // `continue` starts the body over
+                let odd_sum = 0;
+                let n = 0;
+                let count = loop {
+                    n = n + 1;
+                    if n > 9 {
+                        break n;
+                    }
+                    if n % 2 == 0 {
+                        continue;
+                    }
+                    odd_sum = odd_sum + n;
+                };
+                
+                let plain = loop { break; };
+                let sum = 1 + loop { break 2; } * 3;
+                let f = |k| loop { if k > 0 { return k * 2; } break 5; };
+                
+                // A `return` inside a loop expression leaves the function
+                let early = first_square_above(50);
+                return pair * 1000000 + odd_sum * 10000 + count * 1000 + plain + sum * 100
+                    + f(3) + f(0) + early;
+            }
+        "#;
+        
+        assert_eq!(run(source), Ok(607260719));
+    }
+    
+    #[test]
+    fn test_comparison_chains() {
+        let source = r#"
+            func main() {
+                let x = 5;
+                let a = 0 < x < 10;
+                let b = 0 < x < 3;
+                let c = 1 <= 1 < 2 <= 2;
+                let d = 5 > x > 1;
+                let e = x == 5 == 1;
+                
+                // Every operand is evaluated once, in order
+                let f = 0 < print(x) <= print(7);
+                let g = 9 < print(x) < print(8);
+                let h = print(1) < print(2) < print(3) < print(4);
+                return a * 10000000 + b * 1000000 + c * 100000 + d * 10000 + e * 1000 + f * 100 + g * 10 + h;
+            }
+        "#;
+        
+        runtime::start_capture();
+        let result = run(source);
+        let output = runtime::finish_capture();
+        
+        assert_eq!(result, Ok(10100101));
+        assert_eq!(output, "5\n7\n5\n8\n1\n2\n3\n4\n".repeat(2));
+    }
+    
+    #[test]
+    fn test_default_parameters() {
+        let source = r#"
+            func greet(n, times = 1, base = 10 * 10) {
+                let i = 0;
+                while i < times {
+                    print(n);
+                    i = i + 1;
+                }
+                return base + n * times;
+            }
+            
+            func main() {
+                let a = greet(5);
+                let b = greet(6, 2);
+                let c = greet(7, 1, 0);
+                return a * 1000000 + b * 1000 + c;
+            }
+        "#;
+        
+        runtime::start_capture();
+        let result = run(source);
+        let output = runtime::finish_capture();
+        
+        assert_eq!(result, Ok(105112007));
+        assert_eq!(output, "5\n6\n6\n7\n".repeat(2));
+    }
+    
+    #[test]
+    fn test_variadic_functions() {
+        let source = r#"
+            func sum(args...) {
+                let total = 0;
+                let i = 0;
+                while i < args_count() {
+                    total = total + args_get(i);
+                    i = i + 1;
+                }
+                return total;
+            }
+            
+            func describe(label, scale = 1, rest...) {
+                print(label, scale, args_count());
+                if args_count() > 0 {
+                    return label + scale * sum(args_get(0), args_get(args_count() - 1));
+                }
+                return label;
+            }
+            
+            func main() {
+                let a = sum();
+                let b = sum(5);
+                let c = sum(1, 2, 3, 4, 5, 6, 7, 8, 9, 10);
+                let d = sum(sum(1, 2), sum(3, 4, 5), 6);
+                let e = describe(1) + describe(2, 3) + describe(3, 10, 4, 5, 6);
+                return a * 1000000 + b * 100000 + c * 1000 + d * 10 + e;
+            }
+        "#;
+        
+        runtime::start_capture();
+        let result = run(source);
+        let output = runtime::finish_capture();
+        
+        // e = 1 + 2 + (3 + 10 * (4 + 6))
+        assert_eq!(result, Ok(555316));
+        assert_eq!(output, "1 1 0\n2 3 0\n3 10 3\n".repeat(2));
+    }
+    
+    #[test]
+    fn test_unsigned_division() {
+        let source = r#"
+            func half(a) {
+                return a / 2;
+            }
+            
+            func main() {
+                let s = -7;
+                let u: u64 = -7;
+                
+                // Same bits, different division
+                assert_eq(s / 2, -3);
+                assert_eq(s % 2, -1);
+                assert_eq(u / 2, 9223372036854775804);
+                assert_eq(u % 2, 1);
+                assert_eq((-7 as u64) / 2, 9223372036854775804);
+                
+                // Unsigned -7 is huge
+                assert_eq(s < 7, 1);
+                assert_eq(u < 7, 0);
+                assert_eq(u >= 9223372036854775807, 1);
+                
+                // Arithmetic on unsigned operands stays unsigned, and a
+                // closure's copy of `u` is unsigned too
+                assert_eq((u + 1) / 2, 9223372036854775805);
+                let by = |d| u / (d as u64);
+                assert_eq(by(4), 4611686018427387902);
+                
+                // A parameter is signed whatever the argument was
+                assert_eq(half(u), -3);
+                
+                let big: u64 = 0;
+                big = u;
+                return big / 1000000000000000000 + (u as i64) / 2;
+            }
+        "#;
+        
+        assert_eq!(run(source), Ok(18 - 3));
+    }
+    
+    #[test]
+    fn test_min_remainder_minus_one() {
+        // The remainder can't overflow, so both backends give 0 where the
+        // quotient would trap, whether folded or computed at run time
+        let source = r#"
+            func rem(a, b) {
+                return a % b;
+            }
+            
+            func main() {
+                let min = -9223372036854775807 - 1;
+                assert_eq(min % -1, 0);
+                return rem(min, -1) + rem(min, 0 - 1) + 7 % -1;
+            }
+        "#;
+        
+        assert_eq!(run(source), Ok(0));
+        assert_eq!(edust::compile_and_run_with_opt_level(source, "speed"), Ok(0));
+    }
+    
+    #[test]
+    fn test_prelude_functions() {
+        let source = r#"
+            func main() {
+                assert_eq(gcd(48, 36), 12);
+                assert_eq(gcd(-48, 36), 12);
+                assert_eq(gcd(0, 0), 0);
+                assert_eq(lcm(4, 6), 12);
+                assert_eq(pow(3, 4), 81);
+                assert_eq(factorial(10), 3628800);
+                return gcd(48, 36) + abs(-1);
+            }
+        "#;
+        assert_eq!(edust::compile_and_run_with_prelude(source), Ok(13));
+        
+        // Without the prelude the functions don't exist
+        assert_eq!(
+            compile_and_run(source),
+            Err("Semantic error: Undefined function: gcd at line 3, column 27".to_string())
+        );
+    }
+    
+    #[test]
+    fn test_prelude_functions_can_be_overridden() {
+        // The user's gcd replaces the prelude's, including for the
+        // prelude's own lcm, and nested functions count too
+        let source = r#"
+            func gcd(a, b) {
+                return 1;
+            }
+            
+            func main() {
+                func factorial(n) {
+                    return -n;
+                }
+                return lcm(4, 6) * 100 + factorial(5);
+            }
+        "#;
+        assert_eq!(edust::compile_and_run_with_prelude(source), Ok(2395));
+    }
+    
+    #[test]
+    fn test_compile_error_locations() {
+        let error = check("func main() {\n    let x = ;\n}").unwrap_err();
+        assert_eq!(error.message, "Parser error: Expected expression");
+        assert_eq!((error.line, error.column), (Some(2), Some(13)));
+        assert_eq!(error.to_string(), "2:13: error: Parser error: Expected expression");
+        assert_eq!(error.describe(), "Parser error: Expected expression at line 2, column 13");
+        assert_eq!(
+            error.with_file("prog.ed").to_string(),
+            "prog.ed:2:13: error: Parser error: Expected expression"
+        );
+        
+        // Locations of other code stay in the message
+        let error = check("func f() { return 1; }\nfunc main() { return 0; }\nfunc f() { return 2; }").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "3:1: error: Semantic error: Duplicate function definition: f (first defined at line 1, column 1)"
+        );
+        
+        let error = check("func main() {\n    let x = 1;\n    x = \"a\";\n}").unwrap_err();
+        assert_eq!(error.message, "Semantic error: Type mismatch: cannot assign string to x, which holds int");
+        assert_eq!((error.line, error.column), (Some(3), Some(5)));
+        
+        // A file named already is kept
+        let error = check("").unwrap_err().with_file("prog.ed");
+        assert_eq!(error.to_string(), "prog.ed:1:1: error: Semantic error: No main function found");
+        assert_eq!(error.with_file("other.ed").file.as_deref(), Some("prog.ed"));
+    }
+    
     #[test]
     fn test_while_loop() {
         let source = r#"
@@ -97,7 +675,7 @@ mod tests {
             }
         "#;
         
-        let result = compile_and_run(source);
+        let result = run(source);
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), 10);
     }
@@ -115,7 +693,7 @@ mod tests {
             }
         "#;
         
-        let result = compile_and_run(source);
+        let result = run(source);
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), 30);
     }
@@ -143,11 +721,134 @@ mod tests {
             }
         "#;
         
-        let result = compile_and_run(source);
+        let result = run(source);
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), 1);
     }
     
+    #[test]
+    fn test_comparison_results_are_zero_or_one() {
+        // Comparison, logical and `!` results widen to exactly 0 or 1, so
+        // they can be counted
+        let source = r#"
+            func count(a, b, c, d) {
+                return (a < b) + (c < d);
+            }
+            
+            func truth(x, y) {
+                return (x && y) * 100 + (x || y) * 10 + !x;
+            }
+            
+            func main() {
+                assert_eq(count(1, 2, 3, 4), 2);
+                assert_eq(count(2, 1, 3, 4), 1);
+                assert_eq(count(2, 1, 4, 3), 0);
+                assert_eq(count(-5, 5, 5, -5), 1);
+                assert_eq(truth(7, -3), 110);
+                assert_eq(truth(0, 9), 11);
+                assert_eq(truth(0, 0), 1);
+                return count(1, 2, 3, 4) * 1000 + truth(7, -3) + (5 == 5) + (5 != 5);
+            }
+        "#;
+        
+        assert_eq!(run(source), Ok(2111));
+    }
+    
+    #[test]
+    fn test_nonzero_conditions_are_true() {
+        let source = r#"
+            func main() {
+                let taken = 0;
+                let n = 7;
+                if n {
+                    taken = taken + 1;
+                }
+                while n - 7 {
+                    taken = 100;
+                }
+                let i = -3;
+                while i {
+                    i = i + 1;
+                    taken = taken + 10;
+                }
+                assert(n);
+                if 7 {
+                    return taken;
+                }
+                return -1;
+            }
+        "#;
+        
+        assert_eq!(run(source), Ok(31));
+    }
+    
+    #[test]
+    fn test_declaration_without_value() {
+        let source = r#"
+            func main() {
+                let sign;
+                let n: u64;
+                n = -1 as u64;
+                if n > 0 {
+                    sign = 1;
+                } else {
+                    if n == 0 {
+                        sign = 0;
+                    } else {
+                        return -1;
+                    }
+                }
+                return sign * 10 + n % 7;
+            }
+        "#;
+        
+        assert_eq!(run(source), Ok(10 + (u64::MAX % 7) as i64));
+    }
+    
+    #[test]
+    fn test_for_in_range() {
+        let sum = r#"
+            func main() {
+                let total = 0;
+                for i in 0..5 {
+                    total = total + i;
+                }
+                return total;
+            }
+        "#;
+        assert_eq!(run(sum), Ok(10));
+        
+        // The end is read once, `continue` still steps, labels reach outer
+        // loops, and the loop variable shadows an outer `i`
+        let control = r#"
+            func main() {
+                let i = 100;
+                let n = 4;
+                let total = 0;
+                outer: for a in 1..n {
+                    n = n + 1;
+                    for i in 0..10 {
+                        if i == 1 {
+                            continue;
+                        }
+                        if i == 3 {
+                            continue outer;
+                        }
+                        if a == 3 {
+                            break outer;
+                        }
+                        total = total + a * 10 + i;
+                    }
+                }
+                for empty in 5..2 {
+                    total = total + 1000;
+                }
+                return total + i;
+            }
+        "#;
+        assert_eq!(run(control), Ok(164));
+    }
+    
     #[test]
     fn test_logical_operators() {
         let source = r#"
@@ -163,8 +864,1247 @@ mod tests {
             }
         "#;
         
-        let result = compile_and_run(source);
+        let result = run(source);
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), 1);
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_narrowing_casts() {
+        let source = r#"
+            func main() {
+                let x = 300;
+                let y = -1;
+                if x as i8 == 44 {
+                    if y as u8 == 255 {
+                        if 70000 as i16 == 4464 {
+                            return x as u8;
+                        }
+                    }
+                }
+                return 0;
+            }
+        "#;
+        
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 44);
+    }
+    
+    #[test]
+    fn test_widening_casts() {
+        let source = r#"
+            func main() {
+                let small = -5 as i8;
+                let wide = small as i64;
+                let same = wide as i64;
+                return same * 2 as i32;
+            }
+        "#;
+        
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), -10);
+    }
+    
+    #[test]
+    fn test_power_operator() {
+        let source = r#"
+            func main() {
+                if 2 ** 10 == 1024 {
+                    if 5 ** 0 == 1 {
+                        if 2 ** 3 ** 2 == 512 {
+                            if -2 ** 2 == -4 {
+                                return 2 ** -1;
+                            }
+                        }
+                    }
+                }
+                return 1;
+            }
+        "#;
+        
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+    }
+    
+    #[test]
+    fn test_passing_assert() {
+        let source = r#"
+            func main() {
+                let x = 5;
+                assert(x == 5);
+                assert(x > 0 && x < 10);
+                assert_eq(x, 5);
+                assert_eq(x * 2 - 10, 0);
+                return x;
+            }
+        "#;
+        
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 5);
+    }
+    
+    #[test]
+    fn test_recursion() {
+        let source = r#"
+            func factorial(n) {
+                if n <= 1 {
+                    return 1;
+                }
+                return n * factorial(n - 1);
+            }
+            
+            func main() {
+                return factorial(10);
+            }
+        "#;
+        
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 3628800);
+    }
+    
+    #[test]
+    fn test_captured_output() {
+        let source = r#"
+            func main() {
+                print(1);
+                print(2);
+                return 3;
+            }
+        "#;
+        
+        let result = compile_and_run_capturing(source);
+        assert_eq!(result, Ok((3, "1\n2\n".to_string())));
+    }
+    
+    #[test]
+    fn test_installed_io() {
+        let source = r#"
+            func main() {
+                print(1, "two", -3);
+                write(to_str(4) + "5");
+                print_hex(255);
+                return 0;
+            }
+        "#;
+        
+        // Counts the integers separately from the rest of the output
+        struct CountingIo(BufferIo, std::rc::Rc<std::cell::Cell<usize>>);
+        impl EdustIo for CountingIo {
+            fn write_int(&mut self, value: i64) {
+                self.1.set(self.1.get() + 1);
+                self.0.write_int(value);
+            }
+            fn write_str(&mut self, text: &str) {
+                self.0.write_str(text);
+            }
+        }
+        
+        let buffer = BufferIo::new();
+        let ints = std::rc::Rc::default();
+        let stdout = set_io(Box::new(CountingIo(buffer.clone(), std::rc::Rc::clone(&ints))));
+        let jit = compile_and_run(source);
+        let jit_output = buffer.contents();
+        let interpreted = interpret(source);
+        set_io(stdout);
+        
+        assert_eq!((jit, interpreted), (Ok(0), Ok(0)));
+        assert_eq!(jit_output, "1 two -3\n450xff\n");
+        assert_eq!(buffer.contents(), jit_output.repeat(2));
+        assert_eq!(ints.get(), 4);
+    }
+    
+    #[test]
+    fn test_trailing_expression_returns() {
+        let source = r#"
+            func three() {
+                1 + 2
+            }
+            
+            func classify(n) {
+                if n < 0 {
+                    return 0 - 1;
+                }
+                let doubled = n * 2;
+                doubled + three()
+            }
+            
+            func count_down(n, acc) {
+                if n == 0 {
+                    return acc;
+                }
+                count_down(n - 1, acc + 1)
+            }
+            
+            func main() {
+                assert(three() == 3);
+                assert(classify(0 - 5) == 0 - 1);
+                assert(classify(4) == 11);
+                count_down(1000000, 0)
+            }
+        "#;
+        
+        let result = run(source);
+        assert_eq!(result, Ok(1000000));
+    }
+    
+    #[test]
+    fn test_trailing_expression_only_ends_function_bodies() {
+        let source = r#"
+            func main() {
+                if 1 {
+                    2
+                }
+                return 0;
+            }
+        "#;
+        
+        let result = run(source);
+        assert!(result.unwrap_err().starts_with("Parser error:"));
+        
+        // A missing `;` before other statements is still an error
+        let source = "func main() { print(1) return 0; }";
+        assert!(run(source).unwrap_err().starts_with("Parser error:"));
+    }
+    
+    #[test]
+    fn test_break_and_continue() {
+        let source = r#"
+            func main() {
+                let i = 0;
+                let odd_sum = 0;
+                while 1 {
+                    i = i + 1;
+                    if i > 10 {
+                        break;
+                    }
+                    if i % 2 == 0 {
+                        continue;
+                    }
+                    odd_sum = odd_sum + i;
+                }
+                
+                let j = 0;
+                let pairs = 0;
+                do {
+                    j = j + 1;
+                    let k = 0;
+                    while k < 10 {
+                        k = k + 1;
+                        if k > j {
+                            break;
+                        }
+                        pairs = pairs + 1;
+                    }
+                    if j < 5 {
+                        continue;
+                    }
+                    break;
+                } while 1;
+                
+                do {
+                    break;
+                } while 1;
+                
+                return odd_sum * 100 + pairs;
+            }
+        "#;
+        
+        let result = run(source);
+        assert_eq!(result, Ok(2515));
+    }
+    
+    #[test]
+    fn test_mutual_recursion() {
+        let source = r#"
+            func main() {
+                return is_even(10);
+            }
+            
+            func is_even(n) {
+                if n == 0 {
+                    return 1;
+                }
+                return is_odd(n - 1);
+            }
+            
+            func is_odd(n) {
+                if n == 0 {
+                    return 0;
+                }
+                return is_even(n - 1);
+            }
+        "#;
+        
+        let result = run(source);
+        assert_eq!(result, Ok(1));
+    }
+    
+    #[test]
+    fn test_unicode_identifiers() {
+        let source = r#"
+            func площадь(ширина, высота) {
+                return ширина * высота;
+            }
+            
+            func main() {
+                let π = 3;
+                let Δx = π * 2;
+                return площадь(Δx, π);
+            }
+        "#;
+        
+        let result = run(source);
+        assert_eq!(result, Ok(18));
+    }
+    
+    #[test]
+    fn test_shadowing_in_nested_blocks() {
+        let source = r#"
+            func main() {
+                let x = 1;
+                let seen = 0;
+                if x == 1 {
+                    let x = 2;
+                    if x == 2 {
+                        let x = 3;
+                        seen = seen * 10 + x;
+                    }
+                    seen = seen * 10 + x;
+                    x = 5;
+                }
+                return seen * 10 + x;
+            }
+        "#;
+        
+        let result = run(source);
+        assert_eq!(result, Ok(321));
+    }
+    
+    #[test]
+    fn test_name_resolution() {
+        let source = r#"
+            func value(value) {
+                let x = value * 2;
+                return x + 1;
+            }
+            
+            func scale(x, value) {
+                return x * value;
+            }
+            
+            func main() {
+                let x = 3;
+                let value = value(x);
+                let total = 0;
+                let i = 0;
+                while i < 3 {
+                    let step = scale(i, value);
+                    total = total + step;
+                    i = i + 1;
+                }
+                return total + x;
+            }
+        "#;
+        
+        let result = run(source);
+        assert_eq!(result, Ok(24));
+    }
+    
+    #[test]
+    fn test_min_max_abs() {
+        let source = r#"
+            func main() {
+                assert(max(3, 7) == 7);
+                assert(min(3, 7) == 3);
+                assert(abs(-4) == 4);
+                assert(abs(4) == 4);
+                assert(min(-2, max(-9, -5)) == -5);
+                return max(min(10, 20), abs(0 - 15));
+            }
+        "#;
+        
+        let result = run(source);
+        assert_eq!(result, Ok(15));
+    }
+    
+    #[test]
+    fn test_print_multiple_arguments() {
+        let source = r#"
+            func main() {
+                print(1, 2, 3);
+                write(4, 5);
+                write(6);
+                print(7);
+                return print(8, 9);
+            }
+        "#;
+        let expected = "1 2 3\n4 567\n8 9\n".to_string();
+        
+        let result = compile_and_run_capturing(source);
+        assert_eq!(result, Ok((9, expected.clone())));
+        
+        runtime::start_capture();
+        let result = interpret(source);
+        assert_eq!((result, runtime::finish_capture()), (Ok(9), expected));
+    }
+    
+    #[test]
+    fn test_saturating_arithmetic() {
+        let source = r#"
+            func main() {
+                let max = 9223372036854775807;
+                let min = -max - 1;
+                
+                assert(sat_add(max, 1) == max);
+                assert(sat_add(min, -1) == min);
+                assert(sat_sub(min, 1) == min);
+                assert(sat_sub(max, -1) == max);
+                assert(sat_sub(0, min) == max);
+                assert(sat_mul(max, 2) == max);
+                assert(sat_mul(min, 2) == min);
+                assert(sat_mul(-max, 2) == min);
+                assert(sat_mul(min, -1) == max);
+                
+                return sat_add(40, 2) + sat_sub(10, 20) + sat_mul(-3, 7);
+            }
+        "#;
+        
+        let result = run(source);
+        assert_eq!(result, Ok(42 - 10 - 21));
+    }
+    
+    #[test]
+    fn test_bit_counting() {
+        let source = r#"
+            func main() {
+                assert(popcount(7) == 3);
+                assert(popcount(-1) == 64);
+                assert(clz(1) == 63);
+                assert(clz(-1) == 0);
+                assert(ctz(8) == 3);
+                assert(clz(0) == 64);
+                assert(ctz(0) == 64);
+                return popcount(255) + clz(256) + ctz(1024);
+            }
+        "#;
+        
+        let result = run(source);
+        assert_eq!(result, Ok(8 + 55 + 10));
+    }
+    
+    #[test]
+    fn test_print_hex_and_bin() {
+        let source = r#"
+            func main() {
+                print_hex(255);
+                print_bin(5);
+                print_hex(-1);
+                print_bin(0);
+                return print_hex(16);
+            }
+        "#;
+        let expected = "0xff\n0b101\n0xffffffffffffffff\n0b0\n0x10\n".to_string();
+        
+        let result = compile_and_run_capturing(source);
+        assert_eq!(result, Ok((0, expected.clone())));
+        
+        runtime::start_capture();
+        let result = interpret(source);
+        assert_eq!((result, runtime::finish_capture()), (Ok(0), expected));
+    }
+    
+    #[test]
+    fn test_string_concatenation() {
+        let source = r#"
+            func main() {
+                let score = 42;
+                let msg = "Score: " + to_str(score);
+                print(msg);
+                
+                // Strings are copied into closures like other values
+                let show = |n| print(msg + " of " + to_str(n), n);
+                show(-50);
+                
+                let digits = "";
+                let i = 0;
+                while i < 3 {
+                    digits = digits + to_str(i);
+                    i = i + 1;
+                }
+                write(digits, "");
+                return print("!");
+            }
+        "#;
+        let expected = "Score: 42\nScore: 42 of -50 -50\n012 !\n".to_string();
+        
+        let result = compile_and_run_capturing(source);
+        assert_eq!(result, Ok((0, expected.clone())));
+        
+        runtime::start_capture();
+        let result = interpret(source);
+        assert_eq!((result, runtime::finish_capture()), (Ok(0), expected));
+    }
+    
+    #[test]
+    fn test_time_ms() {
+        let source = r#"
+            func main() {
+                let start = time_ms();
+                let i = 0;
+                let sum = 0;
+                while i < 100000 {
+                    sum = sum + i % 7;
+                    i = i + 1;
+                }
+                let elapsed = time_ms() - start;
+                assert(start >= 0);
+                return elapsed >= 0 && sum > 0;
+            }
+        "#;
+        
+        assert_eq!(run(source), Ok(1));
+    }
+    
+    #[test]
+    fn test_main_with_argc() {
+        let source = r#"
+            func main(argc) {
+                return argc;
+            }
+        "#;
+        
+        let result = run(source);
+        assert_eq!(result, Ok(env::args().count() as i64 - 1));
+    }
+    
+    #[test]
+    fn test_nested_function() {
+        let source = r#"
+            func main() {
+                func helper(a) {
+                    return a * 3;
+                }
+                let x = helper(5);
+                return x + twice(1);
+            }
+            
+            func twice(n) {
+                return helper(n) - n;
+            }
+        "#;
+        
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 17);
+    }
+    
+    #[test]
+    fn test_do_while_runs_body_once() {
+        let source = r#"
+            func main() {
+                let count = 0;
+                do {
+                    count = count + 1;
+                } while 0;
+                
+                let i = 0;
+                do {
+                    i = i + 1;
+                } while i < 5;
+                
+                return count * 10 + i;
+            }
+        "#;
+        
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 15);
+    }
+    
+    #[test]
+    fn test_tokenize_api() {
+        let tokens = tokenize("func main() { return 1; }").unwrap();
+        
+        assert!(matches!(tokens[0].typ, TokenType::Func));
+        assert!(matches!(tokens.last().unwrap().typ, TokenType::Eof));
+        assert_eq!(tokens.len(), 10);
+        
+        let err = tokenize("func main() { return @; }").unwrap_err();
+        assert!(err.starts_with("Lexer error: Unexpected character '@'"));
+    }
+    
+    #[test]
+    fn test_parse_api() {
+        let program = parse("func main() { let x = 1; return x; }").unwrap();
+        
+        assert_eq!(program.functions.len(), 1);
+        assert_eq!(program.functions[0].name, "main");
+        assert!(matches!(program.functions[0].body.statements[0], Statement::VarDecl { .. }));
+        
+        // No semantic checks: an undefined variable still parses
+        assert!(parse("func main() { return y; }").is_ok());
+        assert!(parse("func main() { return 1 }").unwrap_err().starts_with("Parser error:"));
+    }
+    
+    /// Writes `files` (name, source) into a fresh temporary directory
+    fn write_files(test: &str, files: &[(&str, &str)]) -> std::path::PathBuf {
+        let dir = env::temp_dir().join(format!("edust_{}_{}", test, std::process::id()));
+        for (name, source) in files {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, source).unwrap();
+        }
+        dir
+    }
+    
+    #[test]
+    fn test_import_functions_from_another_file() {
+        let dir = write_files(
+            "import",
+            &[
+                (
+                    "main.edust",
+                    r#"
+                    import "lib/math.edust";
+                    import "lib/util.edust";
+                    
+                    func main() {
+                        return square(add(2, 3)) + twice(1);
+                    }
+                    "#,
+                ),
+                (
+                    "lib/math.edust",
+                    r#"
+                    import "util.edust";
+                    
+                    func square(x) {
+                        return x * x;
+                    }
+                    
+                    func add(a, b) {
+                        return a + b;
+                    }
+                    "#,
+                ),
+                ("lib/util.edust", "func twice(x) { return add(x, x); }"),
+            ],
+        );
+        
+        // util.edust is imported twice but loaded once
+        let program = edust::load_program(&dir.join("main.edust")).unwrap();
+        assert_eq!(program.functions.len(), 4);
+        assert!(program.imports.is_empty());
+        
+        assert_eq!(edust::compile_files(&dir.join("main.edust")), Ok(27));
+        
+        // A single source string has no directory to import from
+        let err = compile_and_run("import \"lib/math.edust\"; func main() { return 0; }").unwrap_err();
+        assert_eq!(err, "Import of \"lib/math.edust\" needs a source file; use compile_files at line 1, column 1");
+        
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[test]
+    fn test_import_errors() {
+        let dir = write_files(
+            "import_errors",
+            &[
+                ("a.edust", "import \"b.edust\"; func main() { return 0; }"),
+                ("b.edust", "import \"c.edust\"; func b() { return 1; }"),
+                ("c.edust", "import \"b.edust\"; func c() { return 2; }"),
+                ("dup.edust", "import \"other.edust\"; func helper() { return 1; } func main() { return 0; }"),
+                ("other.edust", "func helper() { return 2; }"),
+                ("missing.edust", "import \"nowhere.edust\"; func main() { return 0; }"),
+                ("bad_import.edust", "import \"bad.edust\"; func main() { return 0; }"),
+                ("bad.edust", "func bad() {\n    return ;\n}"),
+            ],
+        );
+        
+        let err = edust::compile_files(&dir.join("a.edust")).unwrap_err();
+        let canonical = |name: &str| dir.canonicalize().unwrap().join(name).display().to_string();
+        let cycle = [canonical("b.edust"), canonical("c.edust"), canonical("b.edust")].join(" -> ");
+        assert_eq!(err, format!("Cyclic import: {}", cycle));
+        
+        let err = edust::compile_files(&dir.join("dup.edust")).unwrap_err();
+        assert!(err.starts_with("Duplicate function definition: helper (in "), "{}", err);
+        assert!(err.contains("other.edust and ") && err.ends_with("dup.edust)"), "{}", err);
+        
+        let err = edust::compile_files(&dir.join("missing.edust")).unwrap_err();
+        assert!(err.starts_with("Error reading file ") && err.contains("nowhere.edust"), "{}", err);
+        
+        // Errors in an imported file name it apart from the message
+        let diagnostics = edust::diagnostics_files(&dir.join("bad_import.edust"));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "Parser error: Expected expression");
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (Some(2), Some(12)));
+        assert!(diagnostics[0].file.as_ref().is_some_and(|file| file.ends_with("bad.edust")));
+        
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[test]
+    fn test_parse_truncated_token_stream() {
+        // Drop the trailing Eof and cut the program off mid-statement
+        let mut tokens = tokenize("func main() { return 1 + 2; }").unwrap();
+        tokens.pop();
+        tokens.truncate(8);
+        
+        let result = Parser::new(tokens).parse();
+        assert_eq!(result.unwrap_err().describe(), "Expected expression at line 1, column 25");
+        
+        // Without Eof, a complete program still parses
+        let mut tokens = tokenize("func main() { return 1; }").unwrap();
+        tokens.pop();
+        assert!(Parser::new(tokens).parse().is_ok());
+        
+        assert!(Parser::new(Vec::new()).parse().unwrap().functions.is_empty());
+    }
+    
+    #[test]
+    fn test_example_program() {
+        let source = include_str!("../examples/test.edust");
+        let (result, output) = compile_and_run_capturing(source).unwrap();
+        assert_eq!(result, 0);
+        assert_eq!(output, "0\n1\n2\n3\n4\n");
+        assert_eq!(interpret(source), Ok(0));
+    }
+    
+    #[test]
+    fn test_interpreted_panic() {
+        // The JIT aborts the process, which tests/cli.rs checks
+        let source = r#"func main() { panic("bad " + to_str(7)); }"#;
+        assert_eq!(interpret(source), Err("Runtime error: panic: bad 7".to_string()));
+        
+        let source = "func main() {\n    print(1);\n    return 2 * todo();\n}";
+        assert_eq!(interpret(source), Err("Runtime error: not yet implemented at line 3".to_string()));
+        
+        // A parameter can hold anything, so calling it is checked as it
+        // runs, even when the number happens to match another closure's
+        let source = "func apply(g) {\n    return g(1);\n}\nfunc main() {\n    let f = |a| a;\n    return apply(0);\n}";
+        assert_eq!(
+            interpret(source),
+            Err("Runtime error: called a value that is not a closure at line 2".to_string())
+        );
+    }
+    
+    #[test]
+    fn test_programs_without_main() {
+        let sources = ["", "  \n\t\r\n ", "// nothing to run\n  // at all", "func helper() { return 1; }"];
+        for source in sources {
+            let expected = "Semantic error: No main function found at line 1, column 1";
+            assert_eq!(compile_and_run(source).unwrap_err(), expected, "{:?}", source);
+            assert_eq!(interpret(source).unwrap_err(), expected, "{:?}", source);
+            assert_eq!(check(source).unwrap_err().describe(), expected, "{:?}", source);
+            
+            // Skipping analysis still gives the same error, not a panic
+            let program = parse(source).unwrap();
+            let expected = "No main function found";
+            assert_eq!(CodeGenerator::new().compile(&program).unwrap_err(), expected);
+            assert_eq!(Interpreter::new().run(&program).unwrap_err(), expected);
+        }
+    }
+    
+    #[test]
+    fn test_empty_parameter_and_argument_lists() {
+        let source = r#"
+            func seven() {
+                return 7;
+            }
+            
+            func nothing() {}
+            
+            func main() {
+                nothing();
+                let f = || seven() * 2;
+                return seven() + f();
+            }
+        "#;
+        assert_eq!(run(source), Ok(21));
+        
+        let program = parse(source).unwrap();
+        assert!(program.functions[0].params.is_empty());
+        let Some(Statement::ExprStmt { expr: Expr::Call { args, .. }, .. }) =
+            program.functions[2].body.statements.first()
+        else {
+            panic!("expected a call");
+        };
+        assert!(args.is_empty());
+        
+        let source = "func seven() { return 7; } func main() { return seven(1); }";
+        let expected = "Semantic error: Function seven expects 0 arguments, got 1 at line 1, column 49";
+        assert_eq!(compile_and_run(source).unwrap_err(), expected);
+        
+        // print needs something to print, whether or not the program was
+        // analyzed first
+        for name in ["print", "write"] {
+            let source = format!("func main() {{ {}(); return 0; }}", name);
+            let expected = format!("{}() requires at least 1 argument", name);
+            let located = format!("Semantic error: {} at line 1, column 15", expected);
+            assert_eq!(compile_and_run(&source).unwrap_err(), located);
+            assert_eq!(interpret(&source).unwrap_err(), located);
+            
+            let program = parse(&source).unwrap();
+            assert_eq!(CodeGenerator::new().compile(&program).unwrap_err(), expected);
+            assert_eq!(Interpreter::new().run(&program).unwrap_err(), expected);
+        }
+    }
+    
+    #[test]
+    fn test_trailing_commas() {
+        let source = r#"
+            func add(
+                a,
+                b,
+            ) {
+                return a + b;
+            }
+            
+            func sum(first, rest...,) {
+                let total = first;
+                for i in 0..args_count() {
+                    total = total + args_get(i);
+                }
+                return total;
+            }
+            
+            func main() {
+                let x = add(
+                    1,
+                    2,
+                );
+                return x * 100 + sum(1, 2, 3,) + add(4, 5);
+            }
+        "#;
+        assert_eq!(run(source), Ok(315));
+        
+        let program = parse("func add(a, b,) { return a + b; } func main() { return add(1, 2,); }").unwrap();
+        assert_eq!(program.functions[0].params.len(), 2);
+        
+        // A comma needs something before it
+        let source = "func f(a) { return a; } func main() { return f(,); }";
+        assert_eq!(parse(source).unwrap_err(), "Parser error: Expected expression at line 1, column 48");
+        let source = "func f(a) { return a; } func main() { return f(1,,); }";
+        assert_eq!(parse(source).unwrap_err(), "Parser error: Expected expression at line 1, column 50");
+        assert!(parse("func f(,) { return 1; } func main() { return 0; }").is_err());
+        assert!(parse("func f(a,,) { return a; } func main() { return 0; }").is_err());
+        
+        // Closure parameters may end with one too
+        let source = "func main() { let add = |a, b,| a + b; let one = |n,| n; return add(1, 2) + one(4); }";
+        assert_eq!(run(source), Ok(7));
+        assert!(parse("func main() { let f = |,| 1; return 0; }").is_err());
+        assert!(parse("func main() { let f = |a,,| a; return 0; }").is_err());
+    }
+    
+    #[test]
+    fn test_keywords_as_names() {
+        let cases = [
+            (
+                "func main() { let while = 1; return 0; }",
+                "'while' is a reserved keyword and cannot be used as a variable name at line 1, column 19",
+            ),
+            (
+                "func return() {} func main() { return 0; }",
+                "'return' is a reserved keyword and cannot be used as a function name at line 1, column 6",
+            ),
+            (
+                "func f(a, if) { return a; } func main() { return 0; }",
+                "'if' is a reserved keyword and cannot be used as a parameter name at line 1, column 11",
+            ),
+            (
+                "func main() { static loop = 0; return 0; }",
+                "'loop' is a reserved keyword and cannot be used as a variable name at line 1, column 22",
+            ),
+            (
+                "func main() { for in in 0..3 {} return 0; }",
+                "'in' is a reserved keyword and cannot be used as a loop variable name at line 1, column 19",
+            ),
+            ("func main() { let = 1; return 0; }", "Expected variable name at line 1, column 19"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(parse(source).unwrap_err(), format!("Parser error: {}", expected));
+        }
+    }
+    
+    #[test]
+    fn test_float_literals_rejected() {
+        let error = parse("func main() { return 2.5e-3; }").unwrap_err();
+        assert_eq!(
+            error,
+            "Parser error: Float literals are not supported: Edust values are 64-bit integers at line 1, column 22"
+        );
+    }
+    
+    #[test]
+    fn test_expression_nesting_limit() {
+        let parens = format!("func main() {{ return {}1{}; }}", "(".repeat(10_000), ")".repeat(10_000));
+        let error = parse(&parens).unwrap_err();
+        assert_eq!(error, "Parser error: expression nesting too deep at line 1, column 148");
+        
+        let negations = format!("func main() {{ return {}1; }}", "- ".repeat(10_000));
+        assert!(parse(&negations).unwrap_err().contains("expression nesting too deep"));
+        
+        let powers = format!("func main() {{ return {}1; }}", "2 ** ".repeat(10_000));
+        assert!(parse(&powers).unwrap_err().contains("expression nesting too deep"));
+        
+        let arms = "else if 1 { 1 } ".repeat(10_000);
+        let else_ifs = format!("func main() {{ return if 1 {{ 1 }} {}else {{ 0 }}; }}", arms);
+        assert!(parse(&else_ifs).unwrap_err().contains("expression nesting too deep"));
+        
+        let ifs = format!("func main() {{ {}{}return 0; }}", "if 1 { ".repeat(20_000), "} ".repeat(20_000));
+        assert!(parse(&ifs).unwrap_err().contains("nesting too deep"));
+        
+        let (opens, closes) = ("if 1 { ".repeat(20_000), " } else { 0 }".repeat(20_000));
+        let if_exprs = format!("func main() {{ return {}1{}; }}", opens, closes);
+        assert!(parse(&if_exprs).unwrap_err().contains("expression nesting too deep"));
+        
+        let chain = format!("func main() {{ return {}2; }}", "1 < ".repeat(3000));
+        assert!(parse(&chain).unwrap_err().contains("expression nesting too deep"));
+        let chain = format!("func main() {{ return {}0; }}", "f() == ".repeat(3000));
+        assert!(parse(&chain).unwrap_err().contains("expression nesting too deep"));
+        
+        // Nesting up to the limit still parses
+        let parens = format!("func main() {{ return {}1{}; }}", "(".repeat(100), ")".repeat(100));
+        assert_eq!(run(&parens), Ok(1));
+        
+        let arms = "else if 0 { 1 } ".repeat(100);
+        let else_ifs = format!("func main() {{ return if 0 {{ 1 }} {}else {{ 2 }}; }}", arms);
+        assert_eq!(run(&else_ifs), Ok(2));
+        
+        // A block or braced branch counts as two levels
+        let ifs = format!("func main() {{ {}return 3;{} return 0; }}", "if 1 { ".repeat(60), " }".repeat(60));
+        assert_eq!(run(&ifs), Ok(3));
+        
+        let chain = format!("func main() {{ return {}2; }}", "1 <= ".repeat(100));
+        assert_eq!(run(&chain), Ok(1));
+        
+        let tokens = edust::tokenize("func main() { return ((1)); }").unwrap();
+        let error = Parser::new(tokens).with_max_depth(4).parse().unwrap_err();
+        assert_eq!(error.describe(), "expression nesting too deep at line 1, column 24");
+        let tokens = edust::tokenize("func main() { if 1 { return 0; } }").unwrap();
+        let error = Parser::new(tokens).with_max_depth(3).parse().unwrap_err();
+        assert_eq!(error.describe(), "block nesting too deep at line 1, column 20");
+    }
+    
+    #[test]
+    fn test_long_operator_chains() {
+        // Left-associative operators nest a level each without counting
+        // against the limit, so every pass walks them without recursing
+        let sum = format!("func f(x) {{ return x{}; }} func main() {{ return f(1); }}", " + x".repeat(5000));
+        assert_eq!(run(&sum), Ok(5001));
+        assert_eq!(edust::compile_and_run_with_opt_level(&sum, "speed"), Ok(5001));
+        let formatted = edust::format::format_source(&sum).unwrap();
+        assert_eq!(run(&formatted), Ok(5001));
+        
+        let casts = format!("func main() {{ let x = 300; return x{}; }}", " as u8 as i64".repeat(3000));
+        assert_eq!(run(&casts), Ok(44));
+        
+        // The sum stays unsigned all the way up, so the division is too
+        let zeros = " + 0".repeat(3000);
+        let unsigned = format!("func main() {{ let u: u64 = -1; return (u{}) / 4611686018427387904; }}", zeros);
+        assert_eq!(run(&unsigned), Ok(3));
+    }
+    
+    #[test]
+    fn test_expression_arena() {
+        let program = parse("func main() { let x = 1 + 2 * 3; x * -x }").unwrap();
+        let exprs = &program.exprs;
+        let body = &program.functions[0].body;
+        
+        let Statement::VarDecl { value: Some(value), .. } = &body.statements[0] else {
+            panic!("expected a let");
+        };
+        let Expr::Binary { op: BinOp::Add, left, right } = value else {
+            panic!("expected an addition, got {:?}", value);
+        };
+        assert!(matches!(exprs[*left], Expr::Number(1)));
+        let Expr::Binary { op: BinOp::Mul, left, right } = &exprs[*right] else {
+            panic!("expected a multiplication, got {:?}", exprs[*right]);
+        };
+        assert!(matches!((&exprs[*left], &exprs[*right]), (Expr::Number(2), Expr::Number(3))));
+        
+        // The tail is first tried as a statement; the nodes of that attempt
+        // are dropped, leaving 1, 2, 3, 2 * 3, x, x and -x
+        assert_eq!(exprs.len(), 7);
+        let Some(Expr::Binary { right, .. }) = &body.tail else {
+            panic!("expected a binary tail, got {:?}", body.tail);
+        };
+        assert!(matches!(exprs[*right], Expr::Unary { .. }));
+    }
+    
+    #[test]
+    fn test_deep_tail_recursion() {
+        let source = r#"
+            func sum(n, acc) {
+                if n == 0 {
+                    return acc;
+                }
+                return sum(n - 1, acc + n);
+            }
+            
+            func main() {
+                return sum(1000000, 0);
+            }
+        "#;
+        
+        let result = run(source);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 500000500000);
+    }
+    
+    #[test]
+    fn test_i64_min_literal() {
+        let source = r#"
+            func main() {
+                let x = -9223372036854775808;
+                print(x, x + 1 == -9223372036854775807);
+                return x;
+            }
+        "#;
+        
+        let (result, output) = compile_and_run_capturing(source).unwrap();
+        assert_eq!(result, i64::MIN);
+        assert_eq!(output, "-9223372036854775808 1\n");
+        assert_eq!(interpret(source), Ok(i64::MIN));
+        
+        // Without the minus the literal overflows
+        let result = parse("func main() { return 9223372036854775808; }");
+        assert_eq!(
+            result.unwrap_err(),
+            "Parser error: integer literal too large for i64 at line 1, column 22"
+        );
+        let result = parse("func main() { return -(9223372036854775808); }");
+        assert!(result.unwrap_err().contains("integer literal too large for i64"));
+    }
+    
+    #[test]
+    fn test_destructure_array_literal() {
+        let source = r#"
+            func main() {
+                let x = 3;
+                let (a, b) = [x * 2, x + 1];
+                if a > b {
+                    let (a, b) = [b, a];
+                    print(a, b);
+                }
+                return a * 10 + b;
+            }
+        "#;
+        
+        assert_eq!(run(source), Ok(64));
+        let (_, output) = compile_and_run_capturing(source).unwrap();
+        assert_eq!(output, "4 6\n");
+    }
+    
+    #[test]
+    fn test_closures() {
+        let source = r#"
+            func main() {
+                let x = 10;
+                let adder = |y| x + y;
+                x = 100;
+                let a = adder(1);
+                let b = adder(5);
+                let twice = |f, v| f(f(v));
+                let make = |n| |m| n * m;
+                let triple = make(3);
+                print(a, b, twice(adder, 0), triple(7));
+                return a + b;
+            }
+        "#;
+        
+        assert_eq!(run(source), Ok(26));
+        let (_, output) = compile_and_run_capturing(source).unwrap();
+        assert_eq!(output, "11 15 20 21\n");
+    }
+    
+    #[test]
+    fn test_heap_linked_list() {
+        let source = r#"
+            func push(head, value) {
+                let node = alloc(16);
+                store(node, value);
+                store(node + 8, head);
+                return node;
+            }
+            
+            func main() {
+                let head = 0;
+                let i = 1;
+                while i <= 4 {
+                    head = push(head, i * i);
+                    i = i + 1;
+                }
+                
+                let sum = 0;
+                while head != 0 {
+                    sum = sum * 100 + load(head);
+                    let next = load(head + 8);
+                    free(head);
+                    head = next;
+                }
+                return sum;
+            }
+        "#;
+        
+        assert_eq!(run(source), Ok(16090401));
+    }
+    
+    #[test]
+    fn test_len_of_array_literal() {
+        let source = r#"
+            func main() {
+                // There is no indexing, so the elements are summed through
+                // the destructured values, and len() bounds a loop that
+                // counts them
+                let (a, b, c, d) = [3, 1, 4, 1];
+                let sum = a + b + c + d;
+                let count = 0;
+                for i in 0..len([3, 1, 4, 1]) {
+                    count = count + 1;
+                }
+                assert_eq(count, 4);
+                
+                // The elements are still evaluated, once each
+                let calls = 0;
+                let n = len([calls = calls + 1, calls = calls + 1]);
+                return sum * 100 + count * 10 + n + calls;
+            }
+        "#;
+        
+        assert_eq!(run(source), Ok(944));
+    }
+    
+    #[test]
+    fn test_seeded_rand() {
+        let source = r#"
+            func main() {
+                srand(42);
+                assert_eq(rand(), 295345038901016077);
+                assert_eq(rand(), 269997436565281638);
+                assert_eq(rand(), 443328343917320271);
+                
+                // Seeding again restarts the sequence
+                srand(42);
+                assert_eq(rand(), 295345038901016077);
+                
+                srand(0);
+                let rolls = 0;
+                for i in 0..3 {
+                    rolls = rolls * 100 + rand() % 100;
+                }
+                return rolls;
+            }
+        "#;
+        
+        assert_eq!(run(source), Ok(54356));
+    }
+    
+    #[test]
+    fn test_map_builtins() {
+        let source = r#"
+            func main() {
+                // How often each digit of 3141592653589 occurs
+                let counts = map_new();
+                let n = 3141592653589;
+                while n > 0 {
+                    let digit = n % 10;
+                    map_set(counts, digit, map_get(counts, digit) + 1);
+                    n = n / 10;
+                }
+                assert_eq(map_get(counts, 5), 3);
+                assert_eq(map_get(counts, 1), 2);
+                
+                // Keys that were never set read as 0
+                assert_eq(map_get(counts, 7), 0);
+                assert_eq(map_get(counts, -1000000), 0);
+                
+                // Setting a key again replaces its value, and maps are separate
+                let other = map_new();
+                map_set(other, 5, 50);
+                map_set(other, 5, 55);
+                let result = map_get(counts, 9) * 100 + map_get(other, 5);
+                map_free(counts);
+                map_free(other);
+                map_free(0);
+                return result;
+            }
+        "#;
+        
+        assert_eq!(run(source), Ok(255));
+        
+        let source = "func main() { let m = map_new(); map_free(m); return map_get(m, 1); }";
+        assert!(interpret(source).unwrap_err().starts_with("Runtime error: invalid map handle"));
+    }
+    
+    #[test]
+    fn test_static_locals() {
+        let source = r#"
+            func next() {
+                static n = 0;
+                n = n + 1;
+                return n;
+            }
+            
+            func main() {
+                let a = next();
+                let b = next();
+                let c = next();
+                let i = 0;
+                while i < 3 {
+                    static seen = 10 * 10;
+                    seen = seen + i;
+                    i = i + 1;
+                }
+                print(a, b, c);
+                return next() * 1000 + a * 100 + b * 10 + c;
+            }
+        "#;
+        
+        assert_eq!(run(source), Ok(4123));
+        let (_, output) = compile_and_run_capturing(source).unwrap();
+        assert_eq!(output, "1 2 3\n");
+    }
+    
+    #[test]
+    fn test_phase_timings() {
+        let source = r#"
+            func fib(n) {
+                if n < 2 {
+                    return n;
+                }
+                return fib(n - 1) + fib(n - 2);
+            }
+            
+            func main() {
+                let total = 0;
+                let i = 0;
+                while i < 10 {
+                    total = total + fib(i) * (i % 3 + 1);
+                    i = i + 1;
+                }
+                return total;
+            }
+        "#;
+        
+        let mut reported = None;
+        let (exit_code, timings) =
+            compile_and_run_timed(source, &Options::default(), |t| reported = Some(*t)).unwrap();
+        
+        assert_eq!(Ok(exit_code), compile_and_run(source));
+        assert_eq!(reported, Some(timings));
+        for (phase, duration) in [
+            ("lex", timings.lex),
+            ("parse", timings.parse),
+            ("analyze", timings.analyze),
+            ("codegen", timings.codegen),
+        ] {
+            assert!(!duration.is_zero(), "{} took no time", phase);
+        }
+    }
+}
@@ -0,0 +1,1614 @@
+//! AST-level optimization passes, run after semantic analysis and before
+//! code generation
+
+use crate::ast::*;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Evaluate `expr` at compile time.
+///
+/// Folds number literals, names bound in `consts`, and arithmetic,
+/// comparison, logical and cast operators over them, with unsigned
+/// semantics where the operands are cast to `u64`. Returns `None` if the
+/// expression reads a variable or calls a function, or if it would trap at
+/// run time (division by zero).
+pub fn eval_const_expr(exprs: &ExprArena, expr: &Expr, consts: &HashMap<Symbol, i64>) -> Option<i64> {
+    let eval = |id: &ExprId| eval_const_expr(exprs, &exprs[*id], consts);
+    match expr {
+        Expr::Number(n) => Some(*n),
+        Expr::Str(_) => None,
+        Expr::Variable { name, .. } => consts.get(name).copied(),
+        Expr::Unary { op, operand } => Some(op.apply(eval(operand)?)),
+        Expr::Binary { .. } | Expr::Cast { .. } => {
+            let (spine, bottom) = expr.left_spine(exprs);
+            let mut value = eval_const_expr(exprs, bottom, consts)?;
+            let mut operand = LeftOperand::new(bottom, exprs, &|_| false, &|_| false);
+            for node in spine.into_iter().rev() {
+                let (unsigned, _) = operand.climb(node, exprs, &|_| false);
+                value = match node {
+                    Expr::Binary { op, right, .. } => {
+                        let rhs = eval(right)?;
+                        if unsigned { op.apply_unsigned(value, rhs)? } else { op.apply(value, rhs)? }
+                    }
+                    Expr::Cast { target, .. } => target.wrap(value),
+                    _ => unreachable!(),
+                };
+            }
+            Some(value)
+        }
+        Expr::If { cond, then_val, else_val } => {
+            if eval(cond)? != 0 {
+                eval(then_val)
+            } else {
+                eval(else_val)
+            }
+        }
+        Expr::Call { .. }
+        | Expr::Array(_)
+        | Expr::Range { .. }
+        | Expr::Closure { .. }
+        | Expr::Loop { .. }
+        | Expr::Assign { .. } => None,
+    }
+}
+
+/// Remove branches that can never run.
+///
+/// An `if` whose condition is a compile-time constant is replaced by the
+/// branch it selects, and a `while` whose condition is constant false is
+/// removed. A taken branch that declares variables stays wrapped in
+/// `if 1 { ... }` so its declarations keep their own scope. Nested
+/// functions declared inside a dropped branch are kept, since they are
+/// visible to the whole program.
+pub fn prune_dead_branches(program: &mut Program) {
+    for func in &mut program.functions {
+        prune_block(&program.exprs, &mut func.body);
+    }
+}
+
+fn prune_block(exprs: &ExprArena, block: &mut Block) {
+    let statements = std::mem::take(&mut block.statements);
+    
+    for stmt in statements {
+        match stmt {
+            Statement::If {
+                condition,
+                mut then_block,
+                else_block,
+                span,
+            } => {
+                let mut else_block = else_block.map(|mut else_blk| {
+                    prune_block(exprs, &mut else_blk);
+                    else_blk
+                });
+                prune_block(exprs, &mut then_block);
+                
+                let Some(cond) = eval_const_expr(exprs, &condition, &HashMap::new()) else {
+                    block.statements.push(Statement::If {
+                        condition,
+                        then_block,
+                        else_block,
+                        span,
+                    });
+                    continue;
+                };
+                
+                let (taken, dropped) = if cond != 0 {
+                    (Some(then_block), else_block)
+                } else {
+                    (else_block.take(), Some(then_block))
+                };
+                if let Some(dropped) = dropped {
+                    keep_functions(&dropped, &mut block.statements);
+                }
+                
+                match taken {
+                    Some(taken) if declares_variables(&taken) => {
+                        block.statements.push(Statement::If {
+                            condition: Expr::Number(1),
+                            then_block: taken,
+                            else_block: None,
+                            span,
+                        });
+                    }
+                    Some(taken) => block.statements.extend(taken.statements),
+                    None => {}
+                }
+            }
+            Statement::While {
+                label,
+                condition,
+                mut body,
+                span,
+            } => {
+                prune_block(exprs, &mut body);
+                if eval_const_expr(exprs, &condition, &HashMap::new()) == Some(0) {
+                    keep_functions(&body, &mut block.statements);
+                } else {
+                    block.statements.push(Statement::While {
+                        label,
+                        condition,
+                        body,
+                        span,
+                    });
+                }
+            }
+            Statement::ForIn {
+                label,
+                var,
+                iter,
+                mut body,
+                span,
+            } => {
+                prune_block(exprs, &mut body);
+                block.statements.push(Statement::ForIn {
+                    label,
+                    var,
+                    iter,
+                    body,
+                    span,
+                });
+            }
+            Statement::DoWhile {
+                label,
+                mut body,
+                condition,
+                span,
+            } => {
+                prune_block(exprs, &mut body);
+                block.statements.push(Statement::DoWhile {
+                    label,
+                    body,
+                    condition,
+                    span,
+                });
+            }
+            Statement::FuncDecl { mut func, span } => {
+                prune_block(exprs, &mut func.body);
+                block.statements.push(Statement::FuncDecl { func, span });
+            }
+            stmt => block.statements.push(stmt),
+        }
+    }
+}
+
+fn declares_variables(block: &Block) -> bool {
+    block.statements.iter().any(|stmt| {
+        matches!(
+            stmt,
+            Statement::VarDecl { .. } | Statement::DestructureDecl { .. } | Statement::StaticDecl { .. }
+        )
+    })
+}
+
+/// Move the nested function declarations found anywhere in `block` to `out`
+fn keep_functions(block: &Block, out: &mut Vec<Statement>) {
+    for stmt in &block.statements {
+        match stmt {
+            Statement::FuncDecl { .. } => out.push(stmt.clone()),
+            Statement::If {
+                then_block,
+                else_block,
+                ..
+            } => {
+                keep_functions(then_block, out);
+                if let Some(else_blk) = else_block {
+                    keep_functions(else_blk, out);
+                }
+            }
+            Statement::While { body, .. }
+            | Statement::DoWhile { body, .. }
+            | Statement::ForIn { body, .. } => keep_functions(body, out),
+            _ => {}
+        }
+    }
+}
+
+/// Remove the functions `main` can never reach.
+///
+/// Starting from `main`, follows every name a reachable function calls or
+/// reads, so a function whose name is used as a value is kept as well.
+/// Closure and `loop` bodies count as part of the function they are
+/// written in. A nested function that is reachable while the function
+/// around it is not moves to the top level, since nested functions can't
+/// capture anything from around them. Programs without `main` are left
+/// alone.
+pub fn eliminate_dead_functions(program: &mut Program) {
+    let main = Symbol::intern("main");
+    let functions: HashMap<Symbol, &Function> =
+        program.all_functions().into_iter().map(|func| (func.name, func)).collect();
+    if !functions.contains_key(&main) {
+        return;
+    }
+    
+    let mut live = HashSet::new();
+    let mut pending = vec![main];
+    while let Some(name) = pending.pop() {
+        if let Some(func) = functions.get(&name)
+            && live.insert(name)
+        {
+            block_references(&program.exprs, &func.body, &mut pending);
+            // Defaults are compiled into the callers, but are still code
+            // that runs whenever the function is called
+            for default in func.defaults.iter().flatten() {
+                expr_references(&program.exprs, default, &mut pending);
+            }
+        }
+    }
+    
+    for func in std::mem::take(&mut program.functions) {
+        keep_live(func, &live, &mut program.functions);
+    }
+}
+
+/// Adds `func` to `out` if it is live, with its dead nested functions
+/// removed; otherwise adds just the live functions nested inside it
+fn keep_live(mut func: Function, live: &HashSet<Symbol>, out: &mut Vec<Function>) {
+    if live.contains(&func.name) {
+        let mut hoisted = Vec::new();
+        remove_dead_nested(&mut func.body, live, &mut hoisted);
+        out.push(func);
+        out.append(&mut hoisted);
+    } else {
+        let mut nested = Vec::new();
+        keep_functions(&func.body, &mut nested);
+        for stmt in nested {
+            if let Statement::FuncDecl { func, .. } = stmt {
+                keep_live(func, live, out);
+            }
+        }
+    }
+}
+
+/// Removes the declarations of dead functions from `block`, moving the
+/// live functions nested in them to `hoisted`
+fn remove_dead_nested(block: &mut Block, live: &HashSet<Symbol>, hoisted: &mut Vec<Function>) {
+    for stmt in std::mem::take(&mut block.statements) {
+        match stmt {
+            Statement::FuncDecl { mut func, span } if live.contains(&func.name) => {
+                remove_dead_nested(&mut func.body, live, hoisted);
+                block.statements.push(Statement::FuncDecl { func, span });
+            }
+            Statement::FuncDecl { func, .. } => keep_live(func, live, hoisted),
+            Statement::If {
+                condition,
+                mut then_block,
+                mut else_block,
+                span,
+            } => {
+                remove_dead_nested(&mut then_block, live, hoisted);
+                if let Some(else_blk) = &mut else_block {
+                    remove_dead_nested(else_blk, live, hoisted);
+                }
+                block.statements.push(Statement::If {
+                    condition,
+                    then_block,
+                    else_block,
+                    span,
+                });
+            }
+            Statement::While {
+                label,
+                condition,
+                mut body,
+                span,
+            } => {
+                remove_dead_nested(&mut body, live, hoisted);
+                block.statements.push(Statement::While {
+                    label,
+                    condition,
+                    body,
+                    span,
+                });
+            }
+            Statement::ForIn {
+                label,
+                var,
+                iter,
+                mut body,
+                span,
+            } => {
+                remove_dead_nested(&mut body, live, hoisted);
+                block.statements.push(Statement::ForIn {
+                    label,
+                    var,
+                    iter,
+                    body,
+                    span,
+                });
+            }
+            Statement::DoWhile {
+                label,
+                mut body,
+                condition,
+                span,
+            } => {
+                remove_dead_nested(&mut body, live, hoisted);
+                block.statements.push(Statement::DoWhile {
+                    label,
+                    body,
+                    condition,
+                    span,
+                });
+            }
+            stmt => block.statements.push(stmt),
+        }
+    }
+}
+
+/// Adds the names `block` calls or reads to `out`, leaving out the bodies
+/// of the functions declared in it
+fn block_references(exprs: &ExprArena, block: &Block, out: &mut Vec<Symbol>) {
+    for stmt in &block.statements {
+        match stmt {
+            Statement::VarDecl { value: Some(value), .. }
+            | Statement::DestructureDecl { value, .. }
+            | Statement::StaticDecl { value, .. }
+            | Statement::Assignment { value, .. }
+            | Statement::Return { value, .. }
+            | Statement::Break { value: Some(value), .. }
+            | Statement::ExprStmt { expr: value, .. } => expr_references(exprs, value, out),
+            Statement::MultiAssign { values, .. } => {
+                for value in values {
+                    expr_references(exprs, value, out);
+                }
+            }
+            Statement::If {
+                condition,
+                then_block,
+                else_block,
+                ..
+            } => {
+                expr_references(exprs, condition, out);
+                block_references(exprs, then_block, out);
+                if let Some(else_blk) = else_block {
+                    block_references(exprs, else_blk, out);
+                }
+            }
+            Statement::While { condition, body, .. }
+            | Statement::DoWhile { condition, body, .. }
+            | Statement::ForIn { iter: condition, body, .. } => {
+                expr_references(exprs, condition, out);
+                block_references(exprs, body, out);
+            }
+            Statement::VarDecl { value: None, .. }
+            | Statement::Break { value: None, .. }
+            | Statement::Continue { .. }
+            | Statement::FuncDecl { .. } => {}
+        }
+    }
+    if let Some(tail) = &block.tail {
+        expr_references(exprs, tail, out);
+    }
+}
+
+fn expr_references(exprs: &ExprArena, expr: &Expr, out: &mut Vec<Symbol>) {
+    match expr {
+        Expr::Number(_) | Expr::Str(_) => {}
+        Expr::Variable { name, .. } => out.push(*name),
+        Expr::Binary { .. } | Expr::Cast { .. } => {
+            for operand in expr.spine_operands(exprs) {
+                expr_references(exprs, operand, out);
+            }
+        }
+        Expr::Range { start, end } => {
+            expr_references(exprs, &exprs[*start], out);
+            expr_references(exprs, &exprs[*end], out);
+        }
+        Expr::Unary { operand, .. } | Expr::Assign { value: operand, .. } => {
+            expr_references(exprs, &exprs[*operand], out)
+        }
+        Expr::If { cond, then_val, else_val } => {
+            for id in [cond, then_val, else_val] {
+                expr_references(exprs, &exprs[*id], out);
+            }
+        }
+        Expr::Call { name, args, .. } => {
+            out.push(*name);
+            for arg in args {
+                expr_references(exprs, arg, out);
+            }
+        }
+        Expr::Array(elements) => {
+            for element in elements {
+                expr_references(exprs, element, out);
+            }
+        }
+        Expr::Closure { body, .. } => expr_references(exprs, &exprs[*body], out),
+        Expr::Loop { body, .. } => block_references(exprs, body, out),
+    }
+}
+
+/// Replace reads of variables known to hold a constant with the constant.
+///
+/// After `let x = <const>;`, reads of `x` become the value, and any
+/// expression that is then constant is folded to a number, so
+/// `let c = 5; return c + c;` returns `10`. Propagation stops at the first
+/// assignment to the variable, and a loop that assigns it anywhere stops
+/// it before the loop starts. Closure bodies are left alone, since they
+/// run with their own copies of the variables. The declarations stay.
+pub fn propagate_constants(program: &mut Program) {
+    let exprs = Arc::make_mut(&mut program.exprs);
+    for func in &mut program.functions {
+        propagate_function(exprs, func);
+    }
+}
+
+fn propagate_function(exprs: &mut ExprArena, func: &mut Function) {
+    let mut propagator = Propagator {
+        exprs,
+        consts: HashMap::new(),
+    };
+    propagator.block(&mut func.body);
+}
+
+struct Propagator<'a> {
+    exprs: &'a mut ExprArena,
+    
+    // Variables holding a known value at the statement being rewritten
+    consts: HashMap<Symbol, i64>,
+}
+
+impl Propagator<'_> {
+    fn block(&mut self, block: &mut Block) {
+        let outer = self.consts.clone();
+        for stmt in &mut block.statements {
+            self.statement(stmt);
+        }
+        if let Some(tail) = &mut block.tail {
+            self.expr(tail);
+        }
+        
+        // Variables declared in the block go out of scope. An outer
+        // variable is still known only if the block left it alone, which
+        // is the case when it still has its value.
+        let inner = std::mem::replace(&mut self.consts, outer);
+        self.consts.retain(|name, value| inner.get(name) == Some(value));
+    }
+    
+    fn statement(&mut self, stmt: &mut Statement) {
+        match stmt {
+            // A `u64` variable's value would become a signed number
+            Statement::VarDecl { name, ty, value, .. } => {
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+                match value {
+                    Some(Expr::Number(n)) if *ty != Some(Type::U64) => self.consts.insert(*name, *n),
+                    _ => self.consts.remove(name),
+                };
+            }
+            Statement::DestructureDecl { names, value, .. } => {
+                self.expr(value);
+                for name in names {
+                    self.consts.remove(name);
+                }
+            }
+            // A static keeps its value between calls, so it isn't constant
+            Statement::StaticDecl { name, .. } => {
+                self.consts.remove(name);
+            }
+            Statement::Assignment { name, value, .. } => {
+                self.expr(value);
+                self.consts.remove(name);
+            }
+            Statement::MultiAssign { names, values, .. } => {
+                for value in values {
+                    self.expr(value);
+                }
+                for name in names {
+                    self.consts.remove(name);
+                }
+            }
+            Statement::If {
+                condition,
+                then_block,
+                else_block,
+                ..
+            } => {
+                self.expr(condition);
+                self.block(then_block);
+                if let Some(else_blk) = else_block {
+                    self.block(else_blk);
+                }
+            }
+            Statement::While { condition, body, .. } => {
+                self.forget([expr_assigned(self.exprs, condition), block_assigned(self.exprs, body)].concat());
+                self.expr(condition);
+                self.block(body);
+            }
+            Statement::DoWhile { body, condition, .. } => {
+                self.forget([expr_assigned(self.exprs, condition), block_assigned(self.exprs, body)].concat());
+                self.block(body);
+                self.expr(condition);
+            }
+            // The loop variable shadows any outer variable of its name,
+            // which the body therefore can't assign
+            Statement::ForIn { var, iter, body, .. } => {
+                self.expr(iter);
+                let outer = self.consts.remove(var);
+                self.forget(block_assigned(self.exprs, body));
+                self.block(body);
+                if let Some(value) = outer {
+                    self.consts.insert(*var, value);
+                }
+            }
+            Statement::Return { value, .. } | Statement::Break { value: Some(value), .. } => self.expr(value),
+            Statement::ExprStmt { expr, .. } => self.expr(expr),
+            Statement::FuncDecl { func, .. } => propagate_function(self.exprs, func),
+            Statement::Break { value: None, .. } | Statement::Continue { .. } => {}
+        }
+    }
+    
+    fn expr(&mut self, expr: &mut Expr) {
+        if self.fold(expr) {
+            return;
+        }
+        
+        match expr {
+            Expr::Number(_) | Expr::Str(_) | Expr::Variable { .. } | Expr::Closure { .. } => {}
+            // The operators down the left of `a + b + c + ...` are visited
+            // bottom-up in a loop. Once its operands are visited, an
+            // operator is constant only if they became constants.
+            Expr::Binary { .. } | Expr::Cast { .. } => {
+                let mut spine = Vec::new();
+                let mut next = expr.left_operand();
+                while let Some(id) = next {
+                    spine.push(id);
+                    next = self.exprs[id].left_operand();
+                }
+                let bottom = spine.pop().unwrap();
+                self.operand(bottom);
+                for id in spine.into_iter().rev() {
+                    let mut node = self.exprs[id].clone();
+                    self.right_operand(&mut node);
+                    self.exprs[id] = node;
+                }
+                self.right_operand(expr);
+            }
+            Expr::Range { start, end } => {
+                self.operand(*start);
+                self.operand(*end);
+            }
+            Expr::Unary { operand: inner, .. } => self.operand(*inner),
+            Expr::Call { args, .. } | Expr::Array(args) => {
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            Expr::Loop { body, .. } => {
+                self.forget(block_assigned(self.exprs, body));
+                self.block(body);
+            }
+            Expr::Assign { name, value, .. } => {
+                self.operand(*value);
+                self.consts.remove(name);
+            }
+            // Like the blocks of an `if` statement, a branch leaves only
+            // what it didn't change known
+            Expr::If { cond, then_val, else_val } => {
+                self.operand(*cond);
+                for branch in [*then_val, *else_val] {
+                    let outer = self.consts.clone();
+                    self.operand(branch);
+                    let inner = std::mem::replace(&mut self.consts, outer);
+                    self.consts.retain(|name, value| inner.get(name) == Some(value));
+                }
+            }
+        }
+    }
+    
+    fn operand(&mut self, id: ExprId) {
+        let mut operand = self.exprs[id].clone();
+        self.expr(&mut operand);
+        self.exprs[id] = operand;
+    }
+    
+    /// Visits the right operand of `node`, a binary operator or cast whose
+    /// left operand has been visited, and folds `node` if both are now
+    /// constants
+    fn right_operand(&mut self, node: &mut Expr) {
+        if let Expr::Binary { right, .. } = node {
+            self.operand(*right);
+        }
+        let left = node.left_operand().unwrap();
+        let constant = |expr: &Expr| match expr {
+            Expr::Number(_) => true,
+            Expr::Cast { expr, .. } => matches!(self.exprs[*expr], Expr::Number(_)),
+            _ => false,
+        };
+        let folds = match node {
+            Expr::Binary { right, .. } => constant(&self.exprs[left]) && constant(&self.exprs[*right]),
+            _ => constant(&self.exprs[left]),
+        };
+        if folds {
+            self.fold(node);
+        }
+    }
+    
+    /// Replaces `expr` with its value if it is a constant
+    fn fold(&mut self, expr: &mut Expr) -> bool {
+        let Some(value) = eval_const_expr(self.exprs, expr, &self.consts) else {
+            return false;
+        };
+        // An unsigned constant stays cast to `u64`, so that the operators
+        // it is an operand of stay unsigned too
+        *expr = if expr.is_unsigned(self.exprs, &|_| false) {
+            Expr::Cast {
+                expr: self.exprs.alloc(Expr::Number(value)),
+                target: Type::U64,
+            }
+        } else {
+            Expr::Number(value)
+        };
+        true
+    }
+    
+    /// Stops propagating `names`, the variables a loop assigns, which may
+    /// change before any iteration after the first
+    fn forget(&mut self, names: Vec<Symbol>) {
+        for name in names {
+            self.consts.remove(&name);
+        }
+    }
+}
+
+/// Variables assigned anywhere in `block`, including inside the bodies of
+/// nested loops and `loop` expressions
+fn block_assigned(exprs: &ExprArena, block: &Block) -> Vec<Symbol> {
+    let mut names = Vec::new();
+    for stmt in &block.statements {
+        match stmt {
+            Statement::Assignment { name, value, .. } => {
+                names.push(*name);
+                names.extend(expr_assigned(exprs, value));
+            }
+            Statement::MultiAssign { names: targets, values, .. } => {
+                names.extend(targets);
+                for value in values {
+                    names.extend(expr_assigned(exprs, value));
+                }
+            }
+            Statement::VarDecl { value: Some(value), .. }
+            | Statement::DestructureDecl { value, .. }
+            | Statement::Return { value, .. }
+            | Statement::Break { value: Some(value), .. }
+            | Statement::ExprStmt { expr: value, .. } => names.extend(expr_assigned(exprs, value)),
+            Statement::If {
+                condition,
+                then_block,
+                else_block,
+                ..
+            } => {
+                names.extend(expr_assigned(exprs, condition));
+                names.extend(block_assigned(exprs, then_block));
+                if let Some(else_blk) = else_block {
+                    names.extend(block_assigned(exprs, else_blk));
+                }
+            }
+            Statement::While { condition, body, .. }
+            | Statement::DoWhile { condition, body, .. }
+            | Statement::ForIn { iter: condition, body, .. } => {
+                names.extend(expr_assigned(exprs, condition));
+                names.extend(block_assigned(exprs, body));
+            }
+            Statement::VarDecl { value: None, .. }
+            | Statement::StaticDecl { .. }
+            | Statement::Break { value: None, .. }
+            | Statement::Continue { .. }
+            | Statement::FuncDecl { .. } => {}
+        }
+    }
+    if let Some(tail) = &block.tail {
+        names.extend(expr_assigned(exprs, tail));
+    }
+    names
+}
+
+/// Variables `expr` assigns, itself or in the statements of its `loop`
+/// expressions
+fn expr_assigned(exprs: &ExprArena, expr: &Expr) -> Vec<Symbol> {
+    let child = |id: &ExprId| expr_assigned(exprs, &exprs[*id]);
+    match expr {
+        Expr::Number(_) | Expr::Str(_) | Expr::Variable { .. } | Expr::Closure { .. } => Vec::new(),
+        Expr::Binary { .. } | Expr::Cast { .. } => {
+            expr.spine_operands(exprs).into_iter().flat_map(|operand| expr_assigned(exprs, operand)).collect()
+        }
+        Expr::Range { start, end } => [child(start), child(end)].concat(),
+        Expr::Unary { operand, .. } => child(operand),
+        Expr::Call { args, .. } | Expr::Array(args) => {
+            args.iter().flat_map(|arg| expr_assigned(exprs, arg)).collect()
+        }
+        Expr::Loop { body, .. } => block_assigned(exprs, body),
+        Expr::Assign { name, value, .. } => [vec![*name], child(value)].concat(),
+        Expr::If { cond, then_val, else_val } => [child(cond), child(then_val), child(else_val)].concat(),
+    }
+}
+
+/// Rewrite algebraic identities: `x * 1`, `x / 1`, `x + 0` and `x - 0`
+/// become `x`, and `x - x` and `x * 0` become `0`.
+///
+/// An operand is only dropped if evaluating it has no effect: it makes no
+/// calls, creates no closures and cannot trap by dividing by zero. For the
+/// same reason `x * 2` becomes `x + x` only when `x` is such an
+/// expression. Cranelift already turns that multiplication into a shift
+/// itself, which the AST has no operator for.
+pub fn simplify_expr(program: &mut Program) {
+    let exprs = Arc::make_mut(&mut program.exprs);
+    for func in &mut program.functions {
+        simplify_block(exprs, &mut func.body);
+    }
+}
+
+fn simplify_block(exprs: &mut ExprArena, block: &mut Block) {
+    for stmt in &mut block.statements {
+        match stmt {
+            Statement::VarDecl { value: Some(value), .. }
+            | Statement::DestructureDecl { value, .. }
+            | Statement::Assignment { value, .. }
+            | Statement::Return { value, .. }
+            | Statement::Break { value: Some(value), .. }
+            | Statement::ExprStmt { expr: value, .. } => simplify(exprs, value),
+            Statement::MultiAssign { values, .. } => {
+                for value in values {
+                    simplify(exprs, value);
+                }
+            }
+            Statement::If {
+                condition,
+                then_block,
+                else_block,
+                ..
+            } => {
+                simplify(exprs, condition);
+                simplify_block(exprs, then_block);
+                if let Some(else_blk) = else_block {
+                    simplify_block(exprs, else_blk);
+                }
+            }
+            Statement::While { condition, body, .. }
+            | Statement::DoWhile { condition, body, .. }
+            | Statement::ForIn { iter: condition, body, .. } => {
+                simplify(exprs, condition);
+                simplify_block(exprs, body);
+            }
+            Statement::FuncDecl { func, .. } => simplify_block(exprs, &mut func.body),
+            Statement::VarDecl { value: None, .. }
+            | Statement::StaticDecl { .. }
+            | Statement::Break { value: None, .. }
+            | Statement::Continue { .. } => {}
+        }
+    }
+    if let Some(tail) = &mut block.tail {
+        simplify(exprs, tail);
+    }
+}
+
+/// Simplifies `expr` bottom-up, so `x * 1 + 0` becomes `x`
+fn simplify(exprs: &mut ExprArena, expr: &mut Expr) {
+    let child = |exprs: &mut ExprArena, id: ExprId| {
+        let mut operand = exprs[id].clone();
+        simplify(exprs, &mut operand);
+        exprs[id] = operand;
+    };
+    match expr {
+        Expr::Number(_) | Expr::Str(_) | Expr::Variable { .. } => {}
+        // The operators down the left of `a + b + c + ...` are simplified
+        // bottom-up in a loop rather than a level of recursion each
+        Expr::Binary { .. } | Expr::Cast { .. } => {
+            let mut spine = Vec::new();
+            let mut next = expr.left_operand();
+            while let Some(id) = next {
+                spine.push(id);
+                next = exprs[id].left_operand();
+            }
+            child(exprs, spine.pop().unwrap());
+            for id in spine.into_iter().rev() {
+                let mut node = exprs[id].clone();
+                if let Expr::Binary { right, .. } = node {
+                    child(exprs, right);
+                }
+                simplify_operator(exprs, &mut node);
+                exprs[id] = node;
+            }
+            if let Expr::Binary { right, .. } = *expr {
+                child(exprs, right);
+            }
+        }
+        Expr::Range { start, end } => {
+            child(exprs, *start);
+            child(exprs, *end);
+        }
+        Expr::Unary { operand, .. } => child(exprs, *operand),
+        Expr::Call { args, .. } | Expr::Array(args) => {
+            for arg in args {
+                simplify(exprs, arg);
+            }
+        }
+        Expr::Closure { body, .. } | Expr::Assign { value: body, .. } => child(exprs, *body),
+        Expr::Loop { body, .. } => simplify_block(exprs, body),
+        Expr::If { cond, then_val, else_val } => {
+            for id in [*cond, *then_val, *else_val] {
+                child(exprs, id);
+            }
+        }
+    }
+    simplify_operator(exprs, expr);
+}
+
+/// Rewrites `expr` if it is a binary operator with an identity that
+/// applies, once its operands are simplified
+fn simplify_operator(exprs: &mut ExprArena, expr: &mut Expr) {
+    let Expr::Binary { op, left, right } = *expr else {
+        return;
+    };
+    let number = |id: ExprId| match exprs[id] {
+        Expr::Number(n) => Some(n),
+        _ => None,
+    };
+    let simplified = match (op, number(left), number(right)) {
+        (BinOp::Mul, _, Some(1)) | (BinOp::Div, _, Some(1)) => exprs[left].clone(),
+        (BinOp::Add, _, Some(0)) | (BinOp::Sub, _, Some(0)) => exprs[left].clone(),
+        (BinOp::Mul, Some(1), _) | (BinOp::Add, Some(0), _) => exprs[right].clone(),
+        (BinOp::Mul, _, Some(0)) if is_pure(exprs, &exprs[left]) => Expr::Number(0),
+        (BinOp::Mul, Some(0), _) if is_pure(exprs, &exprs[right]) => Expr::Number(0),
+        (BinOp::Sub, _, _) if is_pure(exprs, &exprs[left]) && same_value(exprs, &exprs[left], &exprs[right]) => {
+            Expr::Number(0)
+        }
+        (BinOp::Mul, _, Some(2)) if is_pure(exprs, &exprs[left]) => Expr::Binary {
+            op: BinOp::Add,
+            left,
+            right: exprs.alloc(exprs[left].clone()),
+        },
+        _ => return,
+    };
+    *expr = simplified;
+}
+
+/// Whether evaluating `expr` does nothing but produce its value: no
+/// calls, no closures, no `loop`s and no division that could trap
+fn is_pure(exprs: &ExprArena, expr: &Expr) -> bool {
+    match expr {
+        Expr::Number(_) | Expr::Str(_) | Expr::Variable { .. } => true,
+        Expr::Binary { .. } | Expr::Cast { .. } => {
+            let (spine, _) = expr.left_spine(exprs);
+            let may_trap = |node: &&Expr| match node {
+                Expr::Binary {
+                    op: BinOp::Div | BinOp::Mod,
+                    right,
+                    ..
+                } => !matches!(exprs[*right], Expr::Number(n) if n != 0 && n != -1),
+                _ => false,
+            };
+            let operands = expr.spine_operands(exprs);
+            !spine.iter().any(may_trap) && operands.into_iter().all(|operand| is_pure(exprs, operand))
+        }
+        Expr::Range { start, end } => is_pure(exprs, &exprs[*start]) && is_pure(exprs, &exprs[*end]),
+        Expr::Unary { operand, .. } => is_pure(exprs, &exprs[*operand]),
+        Expr::If { cond, then_val, else_val } => {
+            [cond, then_val, else_val].iter().all(|id| is_pure(exprs, &exprs[**id]))
+        }
+        Expr::Call { .. } | Expr::Array(_) | Expr::Closure { .. } | Expr::Loop { .. } | Expr::Assign { .. } => false,
+    }
+}
+
+/// Whether `a` and `b` are the same pure expression, so they evaluate to
+/// the same value
+fn same_value(exprs: &ExprArena, a: &Expr, b: &Expr) -> bool {
+    let same = |a: &ExprId, b: &ExprId| same_value(exprs, &exprs[*a], &exprs[*b]);
+    match (a, b) {
+        (Expr::Number(a), Expr::Number(b)) => a == b,
+        (Expr::Variable { name: a, .. }, Expr::Variable { name: b, .. }) => a == b,
+        (Expr::Binary { .. } | Expr::Cast { .. }, Expr::Binary { .. } | Expr::Cast { .. }) => {
+            let ((spine, bottom), (spine2, bottom2)) = (a.left_spine(exprs), b.left_spine(exprs));
+            let same_node = |(node, node2): (&Expr, &Expr)| match (node, node2) {
+                (Expr::Binary { op, right, .. }, Expr::Binary { op: op2, right: right2, .. }) => {
+                    op == op2 && same(right, right2)
+                }
+                (Expr::Cast { target, .. }, Expr::Cast { target: target2, .. }) => target == target2,
+                _ => false,
+            };
+            spine.len() == spine2.len()
+                && spine.into_iter().zip(spine2).all(same_node)
+                && same_value(exprs, bottom, bottom2)
+        }
+        (Expr::Unary { op, operand }, Expr::Unary { op: op2, operand: operand2 }) => {
+            op == op2 && same(operand, operand2)
+        }
+        _ => false,
+    }
+}
+
+/// Inline calls to small leaf functions.
+///
+/// A function is inlined when its body has at most `max_stmts` statements,
+/// consisting of `let`s and expression statements followed by a single
+/// `return` or tail expression, and it never calls itself. Only calls that make up the whole
+/// value of a `let`, assignment, `return` or expression statement are
+/// replaced; calls to the function being optimized are left alone.
+///
+/// Arguments that are plain numbers or variables are substituted for the
+/// parameters directly. Any other argument is bound to a temporary first,
+/// so its side effects happen exactly once and in the original order.
+pub fn inline_small_functions(program: &mut Program, max_stmts: usize) {
+    let candidates: HashMap<Symbol, Function> = program
+        .all_functions()
+        .into_iter()
+        .filter(|func| is_inlinable(&program.exprs, func, max_stmts))
+        .map(|func| (func.name, func.clone()))
+        .collect();
+    
+    if candidates.is_empty() {
+        return;
+    }
+    
+    let mut inliner = Inliner {
+        candidates,
+        counter: 0,
+        exprs: Arc::make_mut(&mut program.exprs),
+    };
+    for func in &mut program.functions {
+        inliner.inline_function(func);
+    }
+}
+
+fn is_inlinable(exprs: &ExprArena, func: &Function, max_stmts: usize) -> bool {
+    let body = &func.body;
+    let rest = match (body.statements.split_last(), &body.tail) {
+        (_, Some(_)) => &body.statements[..],
+        (Some((Statement::Return { .. }, rest)), None) => rest,
+        _ => return false,
+    };
+    
+    body.statements.len() + body.tail.iter().count() <= max_stmts
+        && rest
+            .iter()
+            .all(|stmt| matches!(stmt, Statement::VarDecl { .. } | Statement::ExprStmt { .. }))
+        && !body.statements.iter().any(|stmt| statement_calls(exprs, stmt, func.name))
+        && !body.tail.as_ref().is_some_and(|tail| expr_calls(exprs, tail, func.name))
+        && !body.statements.iter().any(|stmt| statement_has_closure(exprs, stmt))
+        && !body.tail.as_ref().is_some_and(|tail| has_closure(exprs, tail))
+}
+
+// Substituting arguments into a closure could capture the wrong variable
+// when an argument's name matches a closure parameter, so functions that
+// create closures are not inlined. Neither are functions with a `loop`
+// expression, whose body may declare variables or `return`, or with an
+// assignment expression, which may assign a parameter.
+fn statement_has_closure(exprs: &ExprArena, stmt: &Statement) -> bool {
+    match stmt {
+        Statement::VarDecl { value: Some(value), .. } | Statement::Return { value, .. } => {
+            has_closure(exprs, value)
+        }
+        Statement::ExprStmt { expr, .. } => has_closure(exprs, expr),
+        _ => false,
+    }
+}
+
+fn has_closure(exprs: &ExprArena, expr: &Expr) -> bool {
+    let child = |id: &ExprId| has_closure(exprs, &exprs[*id]);
+    match expr {
+        Expr::Number(_) | Expr::Str(_) | Expr::Variable { .. } => false,
+        Expr::Binary { .. } | Expr::Cast { .. } => {
+            expr.spine_operands(exprs).into_iter().any(|operand| has_closure(exprs, operand))
+        }
+        Expr::Range { start, end } => child(start) || child(end),
+        Expr::Unary { operand, .. } => child(operand),
+        Expr::Call { args, .. } | Expr::Array(args) => args.iter().any(|arg| has_closure(exprs, arg)),
+        Expr::If { cond, then_val, else_val } => child(cond) || child(then_val) || child(else_val),
+        Expr::Closure { .. } | Expr::Loop { .. } | Expr::Assign { .. } => true,
+    }
+}
+
+fn statement_calls(exprs: &ExprArena, stmt: &Statement, name: Symbol) -> bool {
+    match stmt {
+        Statement::VarDecl { value: Some(value), .. }
+        | Statement::Assignment { value, .. }
+        | Statement::Return { value, .. } => expr_calls(exprs, value, name),
+        Statement::ExprStmt { expr, .. } => expr_calls(exprs, expr, name),
+        _ => false,
+    }
+}
+
+fn expr_calls(exprs: &ExprArena, expr: &Expr, name: Symbol) -> bool {
+    let child = |id: &ExprId| expr_calls(exprs, &exprs[*id], name);
+    match expr {
+        Expr::Number(_) | Expr::Str(_) | Expr::Variable { .. } => false,
+        Expr::Binary { .. } | Expr::Cast { .. } => {
+            expr.spine_operands(exprs).into_iter().any(|operand| expr_calls(exprs, operand, name))
+        }
+        Expr::Range { start, end } => child(start) || child(end),
+        Expr::Unary { operand, .. } => child(operand),
+        Expr::Call {
+            name: callee, args, ..
+        } => *callee == name || args.iter().any(|arg| expr_calls(exprs, arg, name)),
+        Expr::Array(elements) => elements.iter().any(|e| expr_calls(exprs, e, name)),
+        Expr::Closure { body, .. } | Expr::Assign { value: body, .. } => child(body),
+        Expr::Loop { body, .. } => body.statements.iter().any(|stmt| statement_calls(exprs, stmt, name)),
+        Expr::If { cond, then_val, else_val } => child(cond) || child(then_val) || child(else_val),
+    }
+}
+
+struct Inliner<'a> {
+    candidates: HashMap<Symbol, Function>,
+    
+    // Numbers each inlined call so its temporaries get unique names
+    counter: usize,
+    
+    // Arena the substituted copies of inlined bodies are added to
+    exprs: &'a mut ExprArena,
+}
+
+impl Inliner<'_> {
+    fn inline_function(&mut self, func: &mut Function) {
+        self.inline_block(&mut func.body, func.name);
+    }
+    
+    fn inline_block(&mut self, block: &mut Block, current: Symbol) {
+        let statements = std::mem::take(&mut block.statements);
+        
+        for mut stmt in statements {
+            match &mut stmt {
+                Statement::If {
+                    then_block,
+                    else_block,
+                    ..
+                } => {
+                    self.inline_block(then_block, current);
+                    if let Some(else_blk) = else_block {
+                        self.inline_block(else_blk, current);
+                    }
+                }
+                Statement::While { body, .. }
+                | Statement::DoWhile { body, .. }
+                | Statement::ForIn { body, .. } => self.inline_block(body, current),
+                Statement::FuncDecl { func, .. } => self.inline_function(func),
+                Statement::Break { .. }
+                | Statement::Continue { .. }
+                | Statement::DestructureDecl { .. }
+                | Statement::StaticDecl { .. }
+                | Statement::MultiAssign { .. }
+                | Statement::VarDecl { value: None, .. } => {}
+                
+                Statement::VarDecl {
+                    value: Some(value), span, ..
+                }
+                | Statement::Assignment { value, span, .. }
+                | Statement::Return { value, span } => {
+                    if let Some((prelude, result)) = self.expand_call(value, current, *span) {
+                        block.statements.extend(prelude);
+                        *value = result;
+                    }
+                }
+                Statement::ExprStmt { expr, span } => {
+                    if let Some((prelude, result)) = self.expand_call(expr, current, *span) {
+                        block.statements.extend(prelude);
+                        *expr = result;
+                    }
+                }
+            }
+            
+            block.statements.push(stmt);
+        }
+        
+        if let Some(tail) = &mut block.tail
+            && let Some((prelude, result)) = self.expand_call(tail, current, Span::default())
+        {
+            block.statements.extend(prelude);
+            *tail = result;
+        }
+    }
+    
+    /// If `expr` is a call to an inlinable function, return the statements
+    /// that must run first and the expression that replaces the call
+    fn expand_call(
+        &mut self,
+        expr: &Expr,
+        current: Symbol,
+        span: Span,
+    ) -> Option<(Vec<Statement>, Expr)> {
+        let Expr::Call { name, args, .. } = expr else {
+            return None;
+        };
+        if *name == current {
+            return None;
+        }
+        let callee = self.candidates.get(name)?.clone();
+        if callee.params.len() != args.len() || callee.variadic.is_some() {
+            return None;
+        }
+        
+        let id = self.counter;
+        self.counter += 1;
+        
+        // Temporaries use `.`, which can't appear in source identifiers
+        let temp = |local: Symbol| Symbol::intern(&format!("{}.{}.{}", callee.name, id, local));
+        
+        let mut prelude = Vec::new();
+        let mut bindings = HashMap::new();
+        
+        // A variable argument is copied too: if it is `u64`, reading it
+        // directly would make the callee's arithmetic on it unsigned
+        for (param, arg) in callee.params.iter().zip(args) {
+            let value = match arg {
+                Expr::Number(_) => arg.clone(),
+                _ => {
+                    // The argument is caller code, so it may be inlined too
+                    let value = match self.expand_call(arg, current, span) {
+                        Some((arg_prelude, value)) => {
+                            prelude.extend(arg_prelude);
+                            value
+                        }
+                        None => arg.clone(),
+                    };
+                    let name = temp(*param);
+                    prelude.push(Statement::VarDecl {
+                        name,
+                        ty: None,
+                        value: Some(value),
+                        span,
+                    });
+                    Expr::Variable { name, span }
+                }
+            };
+            bindings.insert(*param, value);
+        }
+        
+        let mut result = Expr::Number(0);
+        for stmt in &callee.body.statements {
+            match stmt {
+                Statement::VarDecl { name, ty, value, .. } => {
+                    let value = value.as_ref().map(|value| substitute(self.exprs, value, &bindings));
+                    let local = temp(*name);
+                    prelude.push(Statement::VarDecl {
+                        name: local,
+                        ty: *ty,
+                        value,
+                        span,
+                    });
+                    bindings.insert(*name, Expr::Variable { name: local, span });
+                }
+                Statement::ExprStmt { expr, .. } => prelude.push(Statement::ExprStmt {
+                    expr: substitute(self.exprs, expr, &bindings),
+                    span,
+                }),
+                Statement::Return { value, .. } => {
+                    result = substitute(self.exprs, value, &bindings)
+                }
+                _ => unreachable!("not an inlinable function"),
+            }
+        }
+        if let Some(tail) = &callee.body.tail {
+            result = substitute(self.exprs, tail, &bindings);
+        }
+        
+        Some((prelude, result))
+    }
+}
+
+/// Replace variables named in `bindings` with their bound expressions.
+/// Operands are copied into new nodes in `exprs`; the originals still
+/// belong to the function being inlined.
+fn substitute(exprs: &mut ExprArena, expr: &Expr, bindings: &HashMap<Symbol, Expr>) -> Expr {
+    let child = |exprs: &mut ExprArena, id: ExprId, bindings: &HashMap<Symbol, Expr>| {
+        let operand = exprs[id].clone();
+        let operand = substitute(exprs, &operand, bindings);
+        exprs.alloc(operand)
+    };
+    match expr {
+        Expr::Number(n) => Expr::Number(*n),
+        Expr::Str(text) => Expr::Str(text.clone()),
+        Expr::Variable { name, .. } => bindings.get(name).cloned().unwrap_or_else(|| expr.clone()),
+        // Copies the operators down the left of `a + b + c + ...` bottom-up
+        // in a loop rather than a level of recursion each
+        Expr::Binary { .. } | Expr::Cast { .. } => {
+            let (spine, bottom) = expr.left_spine(exprs);
+            let spine: Vec<Expr> = spine.into_iter().cloned().collect();
+            let bottom = bottom.clone();
+            let mut copy = substitute(exprs, &bottom, bindings);
+            for node in spine.into_iter().rev() {
+                let left = exprs.alloc(copy);
+                copy = match node {
+                    Expr::Binary { op, right, .. } => Expr::Binary {
+                        op,
+                        left,
+                        right: child(exprs, right, bindings),
+                    },
+                    Expr::Cast { target, .. } => Expr::Cast { expr: left, target },
+                    _ => unreachable!(),
+                };
+            }
+            copy
+        }
+        Expr::Unary { op, operand } => Expr::Unary {
+            op: *op,
+            operand: child(exprs, *operand, bindings),
+        },
+        Expr::Range { start, end } => Expr::Range {
+            start: child(exprs, *start, bindings),
+            end: child(exprs, *end, bindings),
+        },
+        Expr::Call { name, args, span } => Expr::Call {
+            name: *name,
+            args: args.iter().map(|arg| substitute(exprs, arg, bindings)).collect(),
+            span: *span,
+        },
+        Expr::Array(elements) => {
+            Expr::Array(elements.iter().map(|e| substitute(exprs, e, bindings)).collect())
+        }
+        Expr::Closure {
+            params,
+            param_spans,
+            body,
+            span,
+        } => {
+            // Parameters shadow any binding of the same name
+            let mut inner = bindings.clone();
+            for param in params {
+                inner.remove(param);
+            }
+            Expr::Closure {
+                params: params.clone(),
+                param_spans: param_spans.clone(),
+                body: child(exprs, *body, &inner),
+                span: *span,
+            }
+        }
+        Expr::If { cond, then_val, else_val } => Expr::If {
+            cond: child(exprs, *cond, bindings),
+            then_val: child(exprs, *then_val, bindings),
+            else_val: child(exprs, *else_val, bindings),
+        },
+        Expr::Loop { .. } | Expr::Assign { .. } => {
+            unreachable!("functions with a loop or assignment expression are not inlined")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::CodeGenerator;
+    use crate::interp::Interpreter;
+    use crate::semantic::SemanticAnalyzer;
+    
+    fn parse(source: &str) -> Program {
+        let program = crate::parse(source).unwrap();
+        SemanticAnalyzer::new().analyze(&program).unwrap();
+        program
+    }
+    
+    fn run_jit(program: &Program) -> i64 {
+        CodeGenerator::new().compile(program).unwrap().run().unwrap()
+    }
+    
+    const SOURCE: &str = r#"
+        func square(x) {
+            return x * x;
+        }
+        
+        func scaled(a, b) {
+            let sum = a + b;
+            return sum * 3 - a;
+        }
+        
+        func count(n) {
+            if n == 0 {
+                return 0;
+            }
+            return count(n - 1) + 1;
+        }
+        
+        func main() {
+            let total = square(7);
+            let i = 0;
+            while i < 5 {
+                let step = scaled(i, square(i + 1));
+                total = total + step;
+                i = i + 1;
+            }
+            return total + count(4);
+        }
+    "#;
+    
+    #[test]
+    fn test_eval_const_expr() {
+        let mut consts = HashMap::new();
+        consts.insert(Symbol::intern("SIZE"), 8);
+        let eval = |source: &str| {
+            let program = crate::parse(&format!("func main() {{ return {}; }}", source)).unwrap();
+            match &program.functions[0].body.statements[0] {
+                Statement::Return { value, .. } => eval_const_expr(&program.exprs, value, &consts),
+                _ => unreachable!(),
+            }
+        };
+        
+        assert_eq!(eval("2 + 3 * 4"), Some(14));
+        assert_eq!(eval("SIZE * 2 - 1"), Some(15));
+        assert_eq!(eval("SIZE > 4 && !(1 == 2)"), Some(1));
+        assert_eq!(eval("(300 as u8) ** 2"), Some(1936));
+        assert_eq!(eval("-SIZE % 3"), Some(-2));
+        
+        // Variables, calls and trapping operations are left to run time
+        assert_eq!(eval("SIZE + other"), None);
+        assert_eq!(eval("abs(1)"), None);
+        assert_eq!(eval("1 / (SIZE - 8)"), None);
+    }
+    
+    #[test]
+    fn test_pruning_preserves_results() {
+        let source = r#"
+            func main() {
+                let x = 1;
+                if 2 > 1 {
+                    x = x + 10;
+                } else {
+                    x = x + 100;
+                }
+                if 0 {
+                    func unused(n) {
+                        return n;
+                    }
+                    x = 0;
+                }
+                if 1 == 1 {
+                    let x = 5;
+                    print(x);
+                }
+                while 3 - 3 {
+                    x = x * 2;
+                }
+                while x < 20 {
+                    if 1 {
+                        x = x + 1;
+                    }
+                }
+                return x;
+            }
+        "#;
+        let original = parse(source);
+        let mut pruned = original.clone();
+        prune_dead_branches(&mut pruned);
+        
+        let body = &pruned.functions[0].body.statements;
+        assert!(matches!(body[1], Statement::Assignment { .. }));
+        assert!(matches!(body[2], Statement::FuncDecl { .. }));
+        assert!(matches!(
+            body[3],
+            Statement::If {
+                condition: Expr::Number(1),
+                else_block: None,
+                ..
+            }
+        ));
+        assert!(matches!(body[4], Statement::While { .. }));
+        assert_eq!(body.len(), 6);
+        
+        crate::runtime::start_capture();
+        let expected = Interpreter::new().run(&original).unwrap();
+        assert_eq!(Interpreter::new().run(&pruned).unwrap(), expected);
+        assert_eq!(run_jit(&pruned), expected);
+        let output = crate::runtime::finish_capture();
+        
+        assert_eq!(expected, 20);
+        assert_eq!(output, "5\n5\n5\n");
+    }
+    
+    #[test]
+    fn test_pruned_branch_is_absent_from_ir() {
+        let mut program = parse(
+            r#"
+            func main() {
+                if 0 {
+                    print(1);
+                }
+                return 0;
+            }
+            "#,
+        );
+        prune_dead_branches(&mut program);
+        
+        assert_eq!(program.functions[0].body.statements.len(), 1);
+        let ir = CodeGenerator::new().compile_to_ir(&program).unwrap();
+        assert!(!ir.contains("call"), "{}", ir);
+    }
+    
+    #[test]
+    fn test_dead_functions_are_removed() {
+        let source = r#"
+            func unused_helper(x) {
+                return x * 3;
+            }
+            
+            func dead(n) {
+                func still_called(k) {
+                    return k + 1;
+                }
+                func also_dead() {
+                    return 0;
+                }
+                return unused_helper(n);
+            }
+            
+            func twice(n) {
+                return n * 2;
+            }
+            
+            func main() {
+                let f = |x| twice(x);
+                return f(still_called(20));
+            }
+        "#;
+        let mut program = parse(source);
+        eliminate_dead_functions(&mut program);
+        
+        let names: Vec<&str> = program.all_functions().iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["still_called", "twice", "main"]);
+        
+        let ir = CodeGenerator::new().compile_to_ir(&program).unwrap();
+        assert!(!ir.contains("unused_helper"), "{}", ir);
+        assert_eq!(Interpreter::new().run(&program).unwrap(), 42);
+        assert_eq!(run_jit(&program), 42);
+    }
+    
+    #[test]
+    fn test_simplify_identities() {
+        let simplified = |source: &str| {
+            let mut program = parse(&format!("func main() {{ let x = 6; return {}; }}", source));
+            simplify_expr(&mut program);
+            crate::format::format_program(&program)
+                .lines()
+                .nth(2)
+                .unwrap()
+                .trim()
+                .to_string()
+        };
+        
+        assert_eq!(simplified("x * 1"), "return x;");
+        assert_eq!(simplified("1 * x"), "return x;");
+        assert_eq!(simplified("x / 1"), "return x;");
+        assert_eq!(simplified("x + 0"), "return x;");
+        assert_eq!(simplified("0 + x"), "return x;");
+        assert_eq!(simplified("x - 0"), "return x;");
+        assert_eq!(simplified("x - x"), "return 0;");
+        assert_eq!(simplified("(x + 1) * 3 - (x + 1) * 3"), "return 0;");
+        assert_eq!(simplified("x * 0"), "return 0;");
+        assert_eq!(simplified("x * 2"), "return x + x;");
+        assert_eq!(simplified("(x * 1 + 0) * 2"), "return x + x;");
+        assert_eq!(simplified("abs(x * 1)"), "return abs(x);");
+        
+        // Operands with side effects, or that might trap, stay
+        assert_eq!(simplified("print(x) - print(x)"), "return print(x) - print(x);");
+        assert_eq!(simplified("print(x) * 0"), "return print(x) * 0;");
+        assert_eq!(simplified("print(x) * 2"), "return print(x) * 2;");
+        assert_eq!(simplified("1 / x - 1 / x"), "return 1 / x - 1 / x;");
+        assert_eq!(simplified("x - (x + 0)"), "return 0;");
+        
+        let mut program = parse("func main() { let x = 5; return print(x) - print(x) + x * 2 * 1; }");
+        simplify_expr(&mut program);
+        crate::runtime::start_capture();
+        assert_eq!(run_jit(&program), 10);
+        assert_eq!(crate::runtime::finish_capture(), "5\n5\n");
+    }
+    
+    /// The value of the statement at `index` of `main`'s body
+    fn statement_value(program: &Program, index: usize) -> &Expr {
+        match &program.functions.last().unwrap().body.statements[index] {
+            Statement::VarDecl { value: Some(value), .. }
+            | Statement::Assignment { value, .. }
+            | Statement::Return { value, .. } => value,
+            stmt => panic!("no value in {:?}", stmt),
+        }
+    }
+    
+    #[test]
+    fn test_constant_propagation() {
+        let mut program = parse("func main() { let c = 5; return c + c; }");
+        propagate_constants(&mut program);
+        assert!(matches!(statement_value(&program, 1), Expr::Number(10)));
+        
+        // Propagation stops at an assignment
+        let mut program = parse("func main() { let x = 1; let y = x * 2; x = 3; return x + y; }");
+        propagate_constants(&mut program);
+        assert!(matches!(statement_value(&program, 1), Expr::Number(2)));
+        assert!(matches!(statement_value(&program, 2), Expr::Number(3)));
+        assert!(matches!(statement_value(&program, 3), Expr::Binary { .. }));
+        
+        // A variable a loop assigns isn't known even before the assignment
+        let mut program = parse("func main() { let i = 0; while i < 3 { print(i); i = i + 1; } return i; }");
+        propagate_constants(&mut program);
+        let Statement::While { condition, .. } = &program.functions[0].body.statements[1] else {
+            unreachable!()
+        };
+        assert!(matches!(condition, Expr::Binary { .. }));
+        assert!(matches!(statement_value(&program, 2), Expr::Variable { .. }));
+    }
+    
+    #[test]
+    fn test_constant_propagation_preserves_results() {
+        let source = r#"
+            func main() {
+                let a = 4;
+                let b = a * a;
+                let c = 1;
+                if a > 2 {
+                    let a = 100;
+                    c = a + b;
+                } else {
+                    c = 0;
+                }
+                let n = 0;
+                let total = loop {
+                    n = n + 1;
+                    if n > b {
+                        break n + a;
+                    }
+                };
+                let f = |x| x + a;
+                static s = 7;
+                s = s + a;
+                do {
+                    b = b - 1;
+                } while b > a;
+                return a + b * 10 + c * 100 + total * 100000 + f(1) * 10000000 + s;
+            }
+        "#;
+        let original = parse(source);
+        let mut propagated = original.clone();
+        propagate_constants(&mut propagated);
+        
+        let expected = Interpreter::new().run(&original).unwrap();
+        assert_eq!(expected, 4 + 40 + 11600 + 2100000 + 50000000 + 11);
+        assert_eq!(Interpreter::new().run(&propagated).unwrap(), expected);
+        assert_eq!(run_jit(&propagated), expected);
+    }
+    
+    #[test]
+    fn test_inlining_preserves_results() {
+        let original = parse(SOURCE);
+        let mut inlined = original.clone();
+        inline_small_functions(&mut inlined, 4);
+        
+        let expected = Interpreter::new().run(&original).unwrap();
+        assert_eq!(Interpreter::new().run(&inlined).unwrap(), expected);
+        assert_eq!(run_jit(&inlined), expected);
+        assert_eq!(run_jit(&original), expected);
+    }
+    
+    #[test]
+    fn test_inlined_body_appears_in_ir() {
+        let mut program = parse(SOURCE);
+        inline_small_functions(&mut program, 4);
+        
+        let ir = CodeGenerator::new().compile_to_ir(&program).unwrap();
+        let main_ir = &ir[ir.find("; main").unwrap()..];
+        
+        // square and scaled are gone from main; the recursive count stays
+        let calls = main_ir.matches(" call ").count();
+        assert_eq!(calls, 1, "{}", main_ir);
+        assert!(main_ir.contains("imul"));
+    }
+    
+    #[test]
+    fn test_side_effecting_arguments_run_once() {
+        let mut program = parse(
+            r#"
+            func double(x) {
+                return x + x;
+            }
+            
+            func main() {
+                return double(print(21));
+            }
+            "#,
+        );
+        inline_small_functions(&mut program, 4);
+        
+        crate::runtime::start_capture();
+        let result = run_jit(&program);
+        let output = crate::runtime::finish_capture();
+        
+        assert_eq!(result, 42);
+        assert_eq!(output, "21\n");
+    }
+}
@@ -1,79 +1,289 @@
 use crate::ast::*;
+use crate::error::CompileError;
 use crate::token::{Token, TokenType};
 
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    exprs: ExprArena,
+    /// Labels of the loops enclosing the current position, which tell
+    /// `break label;` apart from `break value;`
+    labels: Vec<Symbol>,
+    /// Comparison chains desugared so far, numbering their temporaries
+    chains: usize,
+    /// Expressions, unary operators, exponents and blocks the current
+    /// position is nested in, and how deep that may get before parsing
+    /// fails
+    depth: usize,
+    max_depth: usize,
+}
+
+/// Errors are boxed while parsing: every recursive call returns a result,
+/// and a full `CompileError` in each would deepen the stack used per
+/// level of nesting
+type ParseResult<T> = Result<T, Box<CompileError>>;
+
+/// The parameters of a function declaration
+struct ParamList {
+    params: Vec<Symbol>,
+    param_spans: Vec<Span>,
+    defaults: Vec<Option<Expr>>,
+    variadic: Option<Symbol>,
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens, current: 0 }
+    /// How deeply expressions may nest unless `with_max_depth` says
+    /// otherwise. Each level takes around a dozen stack frames, over 10 KiB
+    /// in a debug build, so this stays well within a 2 MiB thread stack.
+    /// A block or the braced branch of an `if` expression counts as two
+    /// levels, as parsing what is inside takes even more.
+    pub const DEFAULT_MAX_DEPTH: usize = 128;
+    
+    /// Creates a parser over `tokens`. An `Eof` is appended if the stream
+    /// doesn't already end with one, so the parser never reads past the end.
+    pub fn new(mut tokens: Vec<Token>) -> Self {
+        if !matches!(tokens.last(), Some(Token { typ: TokenType::Eof, .. })) {
+            let (line, column) = tokens.last().map_or((1, 1), |t| (t.line, t.column + 1));
+            tokens.push(Token::new(TokenType::Eof, line, column));
+        }
+        Parser {
+            tokens,
+            current: 0,
+            exprs: ExprArena::new(),
+            labels: Vec::new(),
+            chains: 0,
+            depth: 0,
+            max_depth: Self::DEFAULT_MAX_DEPTH,
+        }
+    }
+    
+    /// Fails with "expression nesting too deep" or "block nesting too deep"
+    /// once parentheses, unary operators, blocks and the like nest more
+    /// than `max_depth` levels, rather than recursing until the stack
+    /// overflows
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+    
+    /// Creates a parser that adds its expressions to `exprs`, so several
+    /// files parsed in turn can share one arena
+    pub fn with_exprs(tokens: Vec<Token>, exprs: ExprArena) -> Self {
+        Parser { exprs, ..Parser::new(tokens) }
+    }
+    
+    /// Parses the whole token stream. The returned program owns the
+    /// parser's arena, which is left empty.
+    pub fn parse(&mut self) -> Result<Program, CompileError> {
+        self.parse_program().map_err(|e| *e)
     }
     
-    pub fn parse(&mut self) -> Result<Program, String> {
+    fn parse_program(&mut self) -> ParseResult<Program> {
         let mut program = Program::new();
         
         while !self.is_at_end() {
-            let func = self.parse_function()?;
-            program.add_function(func);
+            if self.check(&TokenType::Import) {
+                let import = self.parse_import()?;
+                program.imports.push(import);
+            } else {
+                let func = self.parse_function()?;
+                program.add_function(func);
+            }
         }
         
+        program.exprs = std::sync::Arc::new(std::mem::take(&mut self.exprs));
         Ok(program)
     }
     
-    // Function = "func" Ident "(" [ ParamList ] ")" Block
-    fn parse_function(&mut self) -> Result<Function, String> {
-        self.expect(TokenType::Func)?;
+    // Import = "import" String ";"
+    fn parse_import(&mut self) -> ParseResult<Import> {
+        let span = self.span();
+        self.expect(TokenType::Import)?;
         
-        let name = match &self.current_token().typ {
-            TokenType::Ident(s) => s.clone(),
-            _ => return Err(self.error("Expected function name")),
+        let path = match &self.current_token().typ {
+            TokenType::Str(path) => path.clone(),
+            _ => return Err(self.error("Expected file path after import")),
         };
         self.advance();
+        self.expect(TokenType::Semicolon)?;
+        
+        Ok(Import { path, span })
+    }
+    
+    // Function = "func" Ident "(" [ ParamList ] ")" Block
+    fn parse_function(&mut self) -> ParseResult<Function> {
+        let span = self.span();
+        self.expect(TokenType::Func)?;
+        
+        let name = self.expect_name("function name")?;
         
         self.expect(TokenType::LParen)?;
         
-        let params = self.parse_param_list()?;
+        let ParamList {
+            params,
+            param_spans,
+            defaults,
+            variadic,
+        } = self.parse_param_list()?;
         
         self.expect(TokenType::RParen)?;
         
-        let body = self.parse_block()?;
+        // Loops around a nested function don't enclose its body
+        let labels = std::mem::take(&mut self.labels);
+        let body = self.parse_body();
+        self.labels = labels;
+        let body = body?;
         
-        Ok(Function { name, params, body })
+        Ok(Function {
+            name,
+            params,
+            param_spans,
+            defaults,
+            variadic,
+            body,
+            span,
+        })
     }
     
-    // ParamList = Ident { "," Ident }
-    fn parse_param_list(&mut self) -> Result<Vec<String>, String> {
-        let mut params = Vec::new();
+    // ParamList = Param { "," Param } [ "," ]
+    // Param = Ident [ "=" Expr | "..." ], where only the last may use "..."
+    fn parse_param_list(&mut self) -> ParseResult<ParamList> {
+        let mut list = ParamList {
+            params: Vec::new(),
+            param_spans: Vec::new(),
+            defaults: Vec::new(),
+            variadic: None,
+        };
         
-        if let TokenType::Ident(name) = &self.current_token().typ {
-            params.push(name.clone());
-            self.advance();
+        if self.check(&TokenType::RParen) {
+            return Ok(list);
+        }
+        
+        loop {
+            let span = self.span();
+            let name = self.expect_name("parameter name")?;
             
-            while self.check(&TokenType::Comma) {
-                self.advance(); // consume comma
-                
-                if let TokenType::Ident(name) = &self.current_token().typ {
-                    params.push(name.clone());
+            if self.check(&TokenType::Ellipsis) {
+                self.advance();
+                list.variadic = Some(name);
+                if self.check(&TokenType::Comma) {
                     self.advance();
-                } else {
-                    return Err(self.error("Expected parameter name"));
+                    if !self.check(&TokenType::RParen) {
+                        return Err(self.error("Variadic parameter must be the last parameter"));
+                    }
                 }
+                return Ok(list);
+            }
+            list.params.push(name);
+            list.param_spans.push(span);
+            list.defaults.push(self.parse_param_default()?);
+            
+            if !self.check(&TokenType::Comma) {
+                return Ok(list);
+            }
+            self.advance(); // consume comma
+            if self.check(&TokenType::RParen) {
+                return Ok(list);
             }
         }
+    }
+    
+    fn parse_param_default(&mut self) -> ParseResult<Option<Expr>> {
+        if !self.check(&TokenType::Assign) {
+            return Ok(None);
+        }
+        self.advance();
+        Ok(Some(self.parse_expr()?))
+    }
+    
+    // VarType = "i64" | "u64"
+    fn parse_var_type(&mut self) -> ParseResult<Type> {
+        let ty = match &self.current_token().typ {
+            TokenType::Ident(name) => match Type::from_name(name.as_str()) {
+                Some(ty @ (Type::I64 | Type::U64)) => ty,
+                Some(_) => return Err(self.error(&format!("Variables can only be declared i64 or u64, not {}", name))),
+                None => return Err(self.error(&format!("Unknown type '{}'", name))),
+            },
+            _ => return Err(self.error("Expected type name after ':'")),
+        };
+        self.advance();
+        Ok(ty)
+    }
+    
+    // NameList = Ident { "," Ident }
+    fn parse_name_list(&mut self) -> ParseResult<Vec<Symbol>> {
+        self.parse_name_list_with_spans(None).map(|(names, _)| names)
+    }
+    
+    /// Like `parse_name_list`, also returning where each name is. With
+    /// `closing`, the list may end with a comma before that token.
+    fn parse_name_list_with_spans(&mut self, closing: Option<&TokenType>) -> ParseResult<(Vec<Symbol>, Vec<Span>)> {
+        let mut names = Vec::new();
+        let mut spans = Vec::new();
         
-        Ok(params)
+        loop {
+            spans.push(self.span());
+            names.push(self.expect_name("variable name")?);
+            
+            if !self.check(&TokenType::Comma) {
+                return Ok((names, spans));
+            }
+            self.advance();
+            if closing.is_some_and(|closing| self.check(closing)) {
+                return Ok((names, spans));
+            }
+        }
     }
     
     // Block = "{" { Statement } "}"
-    fn parse_block(&mut self) -> Result<Block, String> {
+    fn parse_block(&mut self) -> ParseResult<Block> {
+        self.parse_block_with_tail(false)
+    }
+    
+    // Body = "{" { Statement } [ Expr ] "}"
+    fn parse_body(&mut self) -> ParseResult<Block> {
+        self.parse_block_with_tail(true)
+    }
+    
+    fn parse_block_with_tail(&mut self, allow_tail: bool) -> ParseResult<Block> {
+        self.nested_by(2, "block nesting too deep", |parser| parser.parse_block_items(allow_tail))
+    }
+    
+    fn parse_block_items(&mut self, allow_tail: bool) -> ParseResult<Block> {
         self.expect(TokenType::LBrace)?;
         
         let mut block = Block::new();
         
         while !self.check(&TokenType::RBrace) && !self.is_at_end() {
-            let stmt = self.parse_statement()?;
+            let start = self.current;
+            let nodes = self.exprs.len();
+            let stmt = match self.parse_statement() {
+                Ok(stmt) => stmt,
+                Err(e) if allow_tail => {
+                    // An expression right before the closing brace, with no
+                    // `;`, is the block's value
+                    self.current = start;
+                    self.exprs.truncate(nodes);
+                    match self.parse_expr() {
+                        Ok(expr) if self.check(&TokenType::RBrace) => {
+                            block.tail = Some(expr);
+                            break;
+                        }
+                        _ => return Err(e),
+                    }
+                }
+                Err(e) => return Err(e),
+            };
+            // A `loop` without `;` right before the closing brace is the
+            // block's value
+            if allow_tail
+                && self.check(&TokenType::RBrace)
+                && self.tokens[self.current - 1].typ != TokenType::Semicolon
+                && let Statement::ExprStmt { expr: expr @ Expr::Loop { .. }, .. } = stmt
+            {
+                block.tail = Some(expr);
+                break;
+            }
             block.add_statement(stmt);
         }
         
@@ -82,25 +292,66 @@ impl Parser {
         Ok(block)
     }
     
-    // Statement = VarDecl | Assignment | If | While | Return | Expr ";"
-    fn parse_statement(&mut self) -> Result<Statement, String> {
-        // VarDecl: "let" Ident "=" Expr ";"
+    // Statement = VarDecl | Function | Assignment | If | [ Ident ":" ] Loop
+    //           | Return | Break | Continue | Expr ";"
+    fn parse_statement(&mut self) -> ParseResult<Statement> {
+        let span = self.span();
+        
+        // VarDecl: "let" Ident [ ":" Type ] [ "=" Expr ] ";"
+        //         | "let" "(" Ident { "," Ident } ")" "=" Expr ";"
         if self.check(&TokenType::Let) {
             self.advance();
             
-            let name = match &self.current_token().typ {
-                TokenType::Ident(s) => s.clone(),
-                _ => return Err(self.error("Expected variable name")),
+            if self.check(&TokenType::LParen) {
+                self.advance();
+                let names = self.parse_name_list()?;
+                self.expect(TokenType::RParen)?;
+                self.expect(TokenType::Assign)?;
+                let value = self.parse_expr()?;
+                self.expect(TokenType::Semicolon)?;
+                
+                return Ok(Statement::DestructureDecl { names, value, span });
+            }
+            
+            let name = self.expect_name("variable name")?;
+            
+            let ty = if self.check(&TokenType::Colon) {
+                self.advance();
+                Some(self.parse_var_type()?)
+            } else {
+                None
             };
+            
+            // Without a value, the variable is assigned later
+            let value = if self.check(&TokenType::Semicolon) {
+                None
+            } else {
+                self.expect(TokenType::Assign)?;
+                Some(self.parse_expr()?)
+            };
+            
+            self.expect(TokenType::Semicolon)?;
+            
+            return Ok(Statement::VarDecl { name, ty, value, span });
+        }
+        
+        // StaticDecl: "static" Ident "=" Expr ";"
+        if self.check(&TokenType::Static) {
             self.advance();
             
-            self.expect(TokenType::Assign)?;
+            let name = self.expect_name("variable name")?;
             
+            self.expect(TokenType::Assign)?;
             let value = self.parse_expr()?;
-            
             self.expect(TokenType::Semicolon)?;
             
-            return Ok(Statement::VarDecl { name, value });
+            return Ok(Statement::StaticDecl { name, value, span });
+        }
+        
+        // Nested function declaration
+        if self.check(&TokenType::Func) {
+            let func = self.parse_function()?;
+            return Ok(Statement::FuncDecl { func, span });
         }
         
         // If: "if" Expr Block [ "else" Block ]
@@ -121,17 +372,12 @@ impl Parser {
                 condition,
                 then_block,
                 else_block,
+                span,
             });
         }
         
-        // While: "while" Expr Block
-        if self.check(&TokenType::While) {
-            self.advance();
-            
-            let condition = self.parse_expr()?;
-            let body = self.parse_block()?;
-            
-            return Ok(Statement::While { condition, body });
+        if self.starts_loop() {
+            return self.parse_loop(None, span);
         }
         
         // Return: "return" Expr ";"
@@ -142,15 +388,68 @@ impl Parser {
             
             self.expect(TokenType::Semicolon)?;
             
-            return Ok(Statement::Return { value });
+            return Ok(Statement::Return { value, span });
+        }
+        
+        // Break: "break" [ Ident ] [ Expr ] ";"
+        if self.check(&TokenType::Break) {
+            self.advance();
+            let label = match self.current_token().typ {
+                TokenType::Ident(label) if self.labels.contains(&label) => {
+                    self.advance();
+                    Some(label)
+                }
+                _ => None,
+            };
+            let value = if self.check(&TokenType::Semicolon) {
+                None
+            } else {
+                Some(self.parse_expr()?)
+            };
+            self.expect(TokenType::Semicolon)?;
+            return Ok(Statement::Break { label, value, span });
+        }
+        
+        // Continue: "continue" [ Ident ] ";"
+        if self.check(&TokenType::Continue) {
+            self.advance();
+            let label = self.parse_label_ref();
+            self.expect(TokenType::Semicolon)?;
+            return Ok(Statement::Continue { label, span });
+        }
+        
+        // Increment: ( "++" | "--" ) Ident ";" is an assignment; followed
+        // by anything else, the increment is part of an expression
+        if self.check(&TokenType::PlusPlus) || self.check(&TokenType::MinusMinus) {
+            let start = self.current;
+            let step = self.parse_step(span)?;
+            if self.check(&TokenType::Semicolon) {
+                self.advance();
+                return Ok(step);
+            }
+            self.current = start;
         }
         
         // Assignment or ExprStmt
         // Look ahead to distinguish assignment from expression statement
         if let TokenType::Ident(name) = &self.current_token().typ {
-            let name_clone = name.clone();
+            let name_clone = *name;
+            let start = self.current;
             self.advance();
             
+            // Labeled loop: Ident ":" Loop; a labeled `loop` is an
+            // expression statement
+            if self.check(&TokenType::Colon) {
+                self.advance();
+                if self.check(&TokenType::Loop) {
+                    self.current = start;
+                } else if self.starts_loop() {
+                    return self.parse_loop(Some(name_clone), span);
+                } else {
+                    return Err(self.error(&format!("Expected a loop after label {}", name_clone)));
+                }
+            }
+            
             if self.check(&TokenType::Assign) {
                 self.advance();
                 let value = self.parse_expr()?;
@@ -159,29 +458,221 @@ impl Parser {
                 return Ok(Statement::Assignment {
                     name: name_clone,
                     value,
+                    span,
                 });
+            } else if self.check(&TokenType::Comma) {
+                // MultiAssign: Ident { "," Ident } "=" Expr { "," Expr } ";"
+                self.current = start;
+                let names = self.parse_name_list()?;
+                self.expect(TokenType::Assign)?;
+                let mut values = vec![self.parse_expr()?];
+                while self.check(&TokenType::Comma) {
+                    self.advance();
+                    values.push(self.parse_expr()?);
+                }
+                self.expect(TokenType::Semicolon)?;
+                
+                return Ok(Statement::MultiAssign { names, values, span });
             } else {
                 // Backtrack - it's an expression statement
-                self.current -= 1;
+                self.current = start;
             }
         }
         
-        // ExprStmt: Expr ";"
+        // ExprStmt: Expr ";", where a `loop` may leave out the ";"
         let expr = self.parse_expr()?;
+        if !matches!(expr, Expr::Loop { .. }) || self.check(&TokenType::Semicolon) {
+            self.expect(TokenType::Semicolon)?;
+        }
+        
+        Ok(Statement::ExprStmt { expr, span })
+    }
+    
+    /// Whether the current token begins a `while`, `do` or `for` loop
+    fn starts_loop(&self) -> bool {
+        self.check(&TokenType::While) || self.check(&TokenType::Do) || self.check(&TokenType::For)
+    }
+    
+    // Loop = "while" Expr Block | "do" Block "while" Expr ";"
+    //      | "for" Ident "in" Expr ".." Expr Block
+    fn parse_loop(&mut self, label: Option<Symbol>, span: Span) -> ParseResult<Statement> {
+        if self.check(&TokenType::For) {
+            self.advance();
+            
+            let var = self.expect_name("loop variable name")?;
+            self.expect(TokenType::In)?;
+            
+            let iter = self.parse_expr()?;
+            if !matches!(iter, Expr::Range { .. }) {
+                return Err(self.error("Expected a range like 0..n after 'in'"));
+            }
+            let body = self.parse_loop_body(label)?;
+            
+            return Ok(Statement::ForIn {
+                label,
+                var,
+                iter,
+                body,
+                span,
+            });
+        }
+        
+        if self.check(&TokenType::While) {
+            self.advance();
+            
+            let condition = self.parse_expr()?;
+            let body = self.parse_loop_body(label)?;
+            
+            return Ok(Statement::While {
+                label,
+                condition,
+                body,
+                span,
+            });
+        }
+        
+        // Runs the body once before testing the condition
+        self.expect(TokenType::Do)?;
+        let body = self.parse_loop_body(label)?;
+        self.expect(TokenType::While)?;
+        let condition = self.parse_expr()?;
         self.expect(TokenType::Semicolon)?;
         
-        Ok(Statement::ExprStmt { expr })
+        Ok(Statement::DoWhile {
+            label,
+            body,
+            condition,
+            span,
+        })
+    }
+    
+    /// Parses a loop's body, inside which `label` can be broken out of
+    fn parse_loop_body(&mut self, label: Option<Symbol>) -> ParseResult<Block> {
+        let outer = self.labels.len();
+        self.labels.extend(label);
+        let body = self.parse_block();
+        self.labels.truncate(outer);
+        body
+    }
+    
+    // LoopExpr = "loop" Block
+    fn parse_loop_expr(&mut self, label: Option<Symbol>, span: Span) -> ParseResult<Expr> {
+        self.expect(TokenType::Loop)?;
+        let body = self.parse_loop_body(label)?;
+        Ok(Expr::Loop {
+            label,
+            body: Box::new(body),
+            span,
+        })
+    }
+    
+    // IfExpr = "if" Expr "{" Expr "}" "else" ( IfExpr | "{" Expr "}" )
+    fn parse_if_expr(&mut self) -> ParseResult<Expr> {
+        self.expect(TokenType::If)?;
+        let cond = self.parse_expr()?;
+        let then_val = self.parse_branch_value()?;
+        
+        // Without an `else` there would be no value when `cond` is false
+        if !self.check(&TokenType::Else) {
+            return Err(self.error("Expected else: an if expression needs a value for both branches"));
+        }
+        self.advance();
+        // Each `else if` nests the rest of the chain one level deeper
+        let else_val = if self.check(&TokenType::If) {
+            self.nested(Self::parse_if_expr)?
+        } else {
+            self.parse_branch_value()?
+        };
+        
+        Ok(Expr::If {
+            cond: self.exprs.alloc(cond),
+            then_val: self.exprs.alloc(then_val),
+            else_val: self.exprs.alloc(else_val),
+        })
+    }
+    
+    // BranchValue = "{" Expr "}"
+    fn parse_branch_value(&mut self) -> ParseResult<Expr> {
+        // Braced like a block, and as costly to nest
+        self.nested_by(2, "expression nesting too deep", |parser| {
+            parser.expect(TokenType::LBrace)?;
+            let value = parser.parse_expr()?;
+            parser.expect(TokenType::RBrace)?;
+            Ok(value)
+        })
+    }
+    
+    /// The loop label after `continue`, if there is one
+    fn parse_label_ref(&mut self) -> Option<Symbol> {
+        match self.current_token().typ {
+            TokenType::Ident(label) => {
+                self.advance();
+                Some(label)
+            }
+            _ => None,
+        }
     }
     
     // Expression parsing using precedence climbing
     
-    // Expr = LogicOr
-    fn parse_expr(&mut self) -> Result<Expr, String> {
-        self.parse_logic_or()
+    /// Runs `parse` one nesting level deeper
+    fn nested(&mut self, parse: impl FnOnce(&mut Self) -> ParseResult<Expr>) -> ParseResult<Expr> {
+        self.nested_by(1, "expression nesting too deep", parse)
+    }
+    
+    /// Runs `parse` `levels` nesting levels deeper, failing with `message`
+    /// if that would pass `max_depth`
+    fn nested_by<T>(
+        &mut self,
+        levels: usize,
+        message: &str,
+        parse: impl FnOnce(&mut Self) -> ParseResult<T>,
+    ) -> ParseResult<T> {
+        if self.depth + levels > self.max_depth {
+            return Err(self.error(message));
+        }
+        self.depth += levels;
+        let result = parse(self);
+        self.depth -= levels;
+        result
+    }
+    
+    fn parse_expr(&mut self) -> ParseResult<Expr> {
+        self.nested(Self::parse_assign_or_range)
+    }
+    
+    // Expr = Ident "=" Expr | LogicOr [ ".." LogicOr ]
+    fn parse_assign_or_range(&mut self) -> ParseResult<Expr> {
+        // Assignment is right-associative: `a = b = 0` assigns `b` first
+        if let TokenType::Ident(name) = self.current_token().typ
+            && self.tokens.get(self.current + 1).is_some_and(|next| next.typ == TokenType::Assign)
+        {
+            let span = self.span();
+            self.advance();
+            self.advance();
+            let value = self.parse_expr()?;
+            return Ok(Expr::Assign {
+                name,
+                value: self.exprs.alloc(value),
+                span,
+            });
+        }
+        
+        let start = self.parse_logic_or()?;
+        
+        if !self.check(&TokenType::DotDot) {
+            return Ok(start);
+        }
+        self.advance();
+        let end = self.parse_logic_or()?;
+        Ok(Expr::Range {
+            start: self.exprs.alloc(start),
+            end: self.exprs.alloc(end),
+        })
     }
     
     // LogicOr = LogicAnd { "||" LogicAnd }
-    fn parse_logic_or(&mut self) -> Result<Expr, String> {
+    fn parse_logic_or(&mut self) -> ParseResult<Expr> {
         let mut left = self.parse_logic_and()?;
         
         while self.check(&TokenType::Or) {
@@ -189,8 +680,8 @@ impl Parser {
             let right = self.parse_logic_and()?;
             left = Expr::Binary {
                 op: BinOp::Or,
-                left: Box::new(left),
-                right: Box::new(right),
+                left: self.exprs.alloc(left),
+                right: self.exprs.alloc(right),
             };
         }
         
@@ -198,7 +689,7 @@ impl Parser {
     }
     
     // LogicAnd = Equality { "&&" Equality }
-    fn parse_logic_and(&mut self) -> Result<Expr, String> {
+    fn parse_logic_and(&mut self) -> ParseResult<Expr> {
         let mut left = self.parse_equality()?;
         
         while self.check(&TokenType::And) {
@@ -206,8 +697,8 @@ impl Parser {
             let right = self.parse_equality()?;
             left = Expr::Binary {
                 op: BinOp::And,
-                left: Box::new(left),
-                right: Box::new(right),
+                left: self.exprs.alloc(left),
+                right: self.exprs.alloc(right),
             };
         }
         
@@ -215,32 +706,32 @@ impl Parser {
     }
     
     // Equality = Relational { ("==" | "!=") Relational }
-    fn parse_equality(&mut self) -> Result<Expr, String> {
-        let mut left = self.parse_relational()?;
+    fn parse_equality(&mut self) -> ParseResult<Expr> {
+        let span = self.span();
+        let first = self.parse_relational()?;
         
+        let mut rest = Vec::new();
         while self.check(&TokenType::Eq) || self.check(&TokenType::Ne) {
             let op = if self.check(&TokenType::Eq) {
                 BinOp::Eq
             } else {
                 BinOp::Ne
             };
+            self.check_chain_depth(rest.len())?;
             self.advance();
             
-            let right = self.parse_relational()?;
-            left = Expr::Binary {
-                op,
-                left: Box::new(left),
-                right: Box::new(right),
-            };
+            rest.push((op, self.parse_relational()?));
         }
         
-        Ok(left)
+        Ok(self.comparison_chain(first, rest, span))
     }
     
     // Relational = Add { ("<" | "<=" | ">" | ">=") Add }
-    fn parse_relational(&mut self) -> Result<Expr, String> {
-        let mut left = self.parse_add()?;
+    fn parse_relational(&mut self) -> ParseResult<Expr> {
+        let span = self.span();
+        let first = self.parse_add()?;
         
+        let mut rest = Vec::new();
         while self.check(&TokenType::Lt)
             || self.check(&TokenType::Le)
             || self.check(&TokenType::Gt)
@@ -253,21 +744,102 @@ impl Parser {
                 TokenType::Ge => BinOp::Ge,
                 _ => unreachable!(),
             };
+            self.check_chain_depth(rest.len())?;
             self.advance();
             
-            let right = self.parse_add()?;
-            left = Expr::Binary {
-                op,
-                left: Box::new(left),
-                right: Box::new(right),
+            rest.push((op, self.parse_add()?));
+        }
+        
+        Ok(self.comparison_chain(first, rest, span))
+    }
+    
+    /// Each link of a comparison chain nests the `&&` it desugars to one
+    /// level deeper, so a chain counts its links against `max_depth`
+    fn check_chain_depth(&self, links: usize) -> ParseResult<()> {
+        if self.depth + links > self.max_depth {
+            return Err(self.error("expression nesting too deep"));
+        }
+        Ok(())
+    }
+    
+    /// Desugars `a < b < c` into `a < b && b < c`, evaluating `b` once. A
+    /// middle operand that isn't a variable or number is bound to a
+    /// temporary in a `loop` expression, along with the operand before it
+    /// so the operands still run in order:
+    /// `loop { let .chain0.left = a; let .chain0.mid = b; break .chain0.left < .chain0.mid && ...; }`.
+    fn comparison_chain(&mut self, left: Expr, rest: Vec<(BinOp, Expr)>, span: Span) -> Expr {
+        let last = rest.len().saturating_sub(1);
+        // Walk the links forwards to bind temporaries in source order, then
+        // fold them from the back into `first && (second && ...)`
+        let mut links = Vec::with_capacity(rest.len());
+        let mut left = left;
+        for (i, (op, right)) in rest.into_iter().enumerate() {
+            if i == last || matches!(right, Expr::Number(_) | Expr::Variable { .. }) {
+                let next = right.clone();
+                links.push((op, std::mem::replace(&mut left, next), right, None));
+                continue;
+            }
+            
+            let chain = self.chains;
+            self.chains += 1;
+            let mut statements = Vec::new();
+            let mut temp = |suffix: &str, value: Expr| {
+                let name = Symbol::intern(&format!(".chain{}.{}", chain, suffix));
+                statements.push(Statement::VarDecl {
+                    name,
+                    ty: None,
+                    value: Some(value),
+                    span,
+                });
+                Expr::Variable { name, span }
+            };
+            // Numbers and earlier temporaries read the same whenever they run
+            let link_left = match left {
+                Expr::Number(_) => left,
+                Expr::Variable { name, .. } if name.as_str().starts_with('.') => left,
+                _ => temp("left", left),
             };
+            let mid = temp("mid", right);
+            left = mid.clone();
+            links.push((op, link_left, mid, Some(statements)));
         }
         
-        Ok(left)
+        let mut chain: Option<Expr> = None;
+        for (op, link_left, right, statements) in links.into_iter().rev() {
+            let comparison = Expr::Binary {
+                op,
+                left: self.exprs.alloc(link_left),
+                right: self.exprs.alloc(right),
+            };
+            let value = match chain {
+                Some(others) => Expr::Binary {
+                    op: BinOp::And,
+                    left: self.exprs.alloc(comparison),
+                    right: self.exprs.alloc(others),
+                },
+                None => comparison,
+            };
+            chain = Some(match statements {
+                Some(mut statements) => {
+                    statements.push(Statement::Break {
+                        label: None,
+                        value: Some(value),
+                        span,
+                    });
+                    Expr::Loop {
+                        label: None,
+                        body: Box::new(Block { statements, tail: None }),
+                        span,
+                    }
+                }
+                None => value,
+            });
+        }
+        chain.unwrap_or(left)
     }
     
     // Add = Mul { ("+" | "-") Mul }
-    fn parse_add(&mut self) -> Result<Expr, String> {
+    fn parse_add(&mut self) -> ParseResult<Expr> {
         let mut left = self.parse_mul()?;
         
         while self.check(&TokenType::Plus) || self.check(&TokenType::Minus) {
@@ -281,17 +853,17 @@ impl Parser {
             let right = self.parse_mul()?;
             left = Expr::Binary {
                 op,
-                left: Box::new(left),
-                right: Box::new(right),
+                left: self.exprs.alloc(left),
+                right: self.exprs.alloc(right),
             };
         }
         
         Ok(left)
     }
     
-    // Mul = Unary { ("*" | "/" | "%") Unary }
-    fn parse_mul(&mut self) -> Result<Expr, String> {
-        let mut left = self.parse_unary()?;
+    // Mul = Cast { ("*" | "/" | "%") Cast }
+    fn parse_mul(&mut self) -> ParseResult<Expr> {
+        let mut left = self.parse_cast()?;
         
         while self.check(&TokenType::Star)
             || self.check(&TokenType::Slash)
@@ -305,19 +877,65 @@ impl Parser {
             };
             self.advance();
             
-            let right = self.parse_unary()?;
+            let right = self.parse_cast()?;
             left = Expr::Binary {
                 op,
-                left: Box::new(left),
-                right: Box::new(right),
+                left: self.exprs.alloc(left),
+                right: self.exprs.alloc(right),
             };
         }
         
         Ok(left)
     }
     
-    // Unary = ("!" | "-") Unary | Primary
-    fn parse_unary(&mut self) -> Result<Expr, String> {
+    // Cast = Unary { "as" Type }
+    fn parse_cast(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.parse_unary()?;
+        
+        while self.check(&TokenType::As) {
+            self.advance();
+            
+            let target = match &self.current_token().typ {
+                TokenType::Ident(name) => Type::from_name(name.as_str())
+                    .ok_or_else(|| self.error(&format!("Unknown type '{}'", name)))?,
+                _ => return Err(self.error("Expected type name after 'as'")),
+            };
+            self.advance();
+            
+            expr = Expr::Cast {
+                expr: self.exprs.alloc(expr),
+                target,
+            };
+        }
+        
+        Ok(expr)
+    }
+    
+    // Unary = ("!" | "-") Unary | Power
+    fn parse_unary(&mut self) -> ParseResult<Expr> {
+        // `++i` is a `loop` expression that assigns `i` and breaks with
+        // its new value
+        if self.check(&TokenType::PlusPlus) || self.check(&TokenType::MinusMinus) {
+            let span = self.span();
+            let step = self.parse_step(span)?;
+            let Statement::Assignment { name, .. } = step else {
+                unreachable!("parse_step returns an assignment");
+            };
+            let statements = vec![
+                step,
+                Statement::Break {
+                    label: None,
+                    value: Some(Expr::Variable { name, span }),
+                    span,
+                },
+            ];
+            return Ok(Expr::Loop {
+                label: None,
+                body: Box::new(Block { statements, tail: None }),
+                span,
+            });
+        }
+        
         if self.check(&TokenType::Bang) || self.check(&TokenType::Minus) {
             let op = if self.check(&TokenType::Bang) {
                 UnaryOp::Not
@@ -326,27 +944,94 @@ impl Parser {
             };
             self.advance();
             
-            let operand = self.parse_unary()?;
+            // `-9223372036854775808` is the only way to write `i64::MIN`, as
+            // its magnitude alone overflows
+            if op == UnaryOp::Neg && self.current_token().typ == TokenType::Number(i64::MIN) {
+                self.advance();
+                return Ok(Expr::Number(i64::MIN));
+            }
+            
+            let operand = self.nested(Self::parse_unary)?;
             return Ok(Expr::Unary {
                 op,
-                operand: Box::new(operand),
+                operand: self.exprs.alloc(operand),
             });
         }
         
-        self.parse_primary()
+        self.parse_power()
     }
     
-    // Primary = Number | Ident | "(" Expr ")" | FunctionCall
-    fn parse_primary(&mut self) -> Result<Expr, String> {
+    // Step = ( "++" | "--" ) Ident
+    /// Parses `++i` or `--i` into the assignment `i = i + 1` or `i = i - 1`
+    fn parse_step(&mut self, span: Span) -> ParseResult<Statement> {
+        let (op, symbol) = if self.check(&TokenType::PlusPlus) {
+            (BinOp::Add, "++")
+        } else {
+            (BinOp::Sub, "--")
+        };
+        self.advance();
+        
+        let not_variable = format!("{} can only be applied to a variable", symbol);
+        let TokenType::Ident(name) = self.current_token().typ else {
+            return Err(self.error(&not_variable));
+        };
+        self.advance();
+        if self.check(&TokenType::LParen) {
+            return Err(self.error(&not_variable));
+        }
+        
+        let value = Expr::Binary {
+            op,
+            left: self.exprs.alloc(Expr::Variable { name, span }),
+            right: self.exprs.alloc(Expr::Number(1)),
+        };
+        Ok(Statement::Assignment { name, value, span })
+    }
+    
+    // Power = Primary [ "**" Unary ]   (right-associative)
+    fn parse_power(&mut self) -> ParseResult<Expr> {
+        let base = self.parse_primary()?;
+        
+        if self.check(&TokenType::StarStar) {
+            self.advance();
+            let exponent = self.nested(Self::parse_unary)?;
+            return Ok(Expr::Binary {
+                op: BinOp::Pow,
+                left: self.exprs.alloc(base),
+                right: self.exprs.alloc(exponent),
+            });
+        }
+        
+        Ok(base)
+    }
+    
+    // Primary = Number | String | Ident | "(" Expr ")" | FunctionCall | "[" ArgList "]"
+    //         | "|" [ NameList [ "," ] ] "|" Expr | [ Ident ":" ] LoopExpr | IfExpr
+    fn parse_primary(&mut self) -> ParseResult<Expr> {
         // Number
         if let TokenType::Number(n) = self.current_token().typ {
+            if n == i64::MIN {
+                return Err(self.error("integer literal too large for i64"));
+            }
             self.advance();
             return Ok(Expr::Number(n));
         }
         
+        if let TokenType::Float(_) = self.current_token().typ {
+            return Err(self.error("Float literals are not supported: Edust values are 64-bit integers"));
+        }
+        
+        // String
+        if let TokenType::Str(text) = &self.current_token().typ {
+            let text = text.clone();
+            self.advance();
+            return Ok(Expr::Str(text));
+        }
+        
         // Identifier or FunctionCall
         if let TokenType::Ident(name) = &self.current_token().typ {
-            let name_clone = name.clone();
+            let name_clone = *name;
+            let span = self.span();
             self.advance();
             
             // Check for function call
@@ -360,10 +1045,66 @@ impl Parser {
                 return Ok(Expr::Call {
                     name: name_clone,
                     args,
+                    span,
                 });
             }
             
-            return Ok(Expr::Variable(name_clone));
+            if self.check(&TokenType::Colon) {
+                self.advance();
+                if !self.check(&TokenType::Loop) {
+                    return Err(self.error(&format!("Expected a loop after label {}", name_clone)));
+                }
+                return self.parse_loop_expr(Some(name_clone), span);
+            }
+            
+            return Ok(Expr::Variable { name: name_clone, span });
+        }
+        
+        // Closure; `||` is lexed as one token and starts one without
+        // parameters. The body extends as far as possible.
+        if self.check(&TokenType::Pipe) || self.check(&TokenType::Or) {
+            let span = self.span();
+            let (mut params, mut param_spans) = (Vec::new(), Vec::new());
+            if self.check(&TokenType::Pipe) {
+                self.advance();
+                if !self.check(&TokenType::Pipe) {
+                    (params, param_spans) = self.parse_name_list_with_spans(Some(&TokenType::Pipe))?;
+                }
+                self.expect(TokenType::Pipe)?;
+            } else {
+                self.advance();
+            }
+            
+            let labels = std::mem::take(&mut self.labels);
+            let body = self.parse_expr();
+            self.labels = labels;
+            let body = body?;
+            return Ok(Expr::Closure {
+                params,
+                param_spans,
+                body: self.exprs.alloc(body),
+                span,
+            });
+        }
+        
+        if self.check(&TokenType::Loop) {
+            let span = self.span();
+            return self.parse_loop_expr(None, span);
+        }
+        
+        if self.check(&TokenType::If) {
+            return self.parse_if_expr();
+        }
+        
+        // Array literal
+        if self.check(&TokenType::LBracket) {
+            self.advance();
+            let mut elements = Vec::new();
+            if !self.check(&TokenType::RBracket) {
+                elements = self.parse_arg_list()?;
+            }
+            self.expect(TokenType::RBracket)?;
+            return Ok(Expr::Array(elements));
         }
         
         // Parenthesized expression
@@ -377,8 +1118,8 @@ impl Parser {
         Err(self.error("Expected expression"))
     }
     
-    // ArgList = Expr { "," Expr }
-    fn parse_arg_list(&mut self) -> Result<Vec<Expr>, String> {
+    // ArgList = Expr { "," Expr } [ "," ]
+    fn parse_arg_list(&mut self) -> ParseResult<Vec<Expr>> {
         let mut args = Vec::new();
         
         if !self.check(&TokenType::RParen) {
@@ -386,6 +1127,9 @@ impl Parser {
             
             while self.check(&TokenType::Comma) {
                 self.advance();
+                if self.check(&TokenType::RParen) {
+                    break;
+                }
                 args.push(self.parse_expr()?);
             }
         }
@@ -416,7 +1160,7 @@ impl Parser {
         matches!(self.current_token().typ, TokenType::Eof)
     }
     
-    fn expect(&mut self, typ: TokenType) -> Result<(), String> {
+    fn expect(&mut self, typ: TokenType) -> ParseResult<()> {
         if self.check(&typ) {
             self.advance();
             Ok(())
@@ -425,11 +1169,31 @@ impl Parser {
         }
     }
     
-    fn error(&self, msg: &str) -> String {
+    fn span(&self) -> Span {
+        let token = self.current_token();
+        Span {
+            line: token.line,
+            column: token.column,
+        }
+    }
+    
+    /// Consumes the identifier naming a `what`, such as a "variable name".
+    /// A keyword there gets its own message, since it looks like a name.
+    fn expect_name(&mut self, what: &str) -> ParseResult<Symbol> {
+        let typ = &self.current_token().typ;
+        if let TokenType::Ident(name) = *typ {
+            self.advance();
+            return Ok(name);
+        }
+        let message = match typ.keyword() {
+            Some(word) => format!("'{}' is a reserved keyword and cannot be used as a {}", word, what),
+            None => format!("Expected {}", what),
+        };
+        Err(self.error(&message))
+    }
+    
+    fn error(&self, msg: &str) -> Box<CompileError> {
         let token = self.current_token();
-        format!(
-            "{} at line {}, column {}",
-            msg, token.line, token.column
-        )
+        Box::new(CompileError::at(msg, Span { line: token.line, column: token.column }))
     }
 }
\ No newline at end of file
@@ -0,0 +1,68 @@
+//! Functions every program can call without defining them, for use with
+//! `compile_and_run_with_prelude`.
+//!
+//! Most are plain Edust. Where the runtime already implements something in
+//! Rust, the prelude function is a thin wrapper that reaches it: `pow` is
+//! the `**` operator, which calls `edust_ipow`. `abs`, `min` and `max` are
+//! builtins and need no prelude entry at all.
+
+use crate::ast::Program;
+use crate::parser::Parser;
+use crate::symbol::Symbol;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Source of the prelude functions
+pub const SOURCE: &str = r#"
+// Greatest common divisor, never negative; gcd(0, 0) is 0
+func gcd(a, b) {
+    a = abs(a);
+    b = abs(b);
+    while b != 0 {
+        let rest = a % b;
+        a = b;
+        b = rest;
+    }
+    return a;
+}
+
+// Least common multiple, never negative; 0 if either argument is
+func lcm(a, b) {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    return abs(a / gcd(a, b) * b);
+}
+
+// `base` to the power `exp`, wrapping on overflow like `**`
+func pow(base, exp) {
+    return base ** exp;
+}
+
+// n!, or 1 for n < 2
+func factorial(n) {
+    let result = 1;
+    while n > 1 {
+        result = result * n;
+        n = n - 1;
+    }
+    return result;
+}
+"#;
+
+/// Adds the prelude's functions in front of `program`'s own. A function
+/// the program defines itself, at the top level or nested, replaces the
+/// prelude function of the same name, which is then left out entirely.
+pub fn add_to(program: &mut Program) {
+    // The prelude's expressions join the program's arena, after the ones
+    // its functions already refer to
+    let exprs = std::mem::take(Arc::make_mut(&mut program.exprs));
+    let tokens = crate::tokenize(SOURCE).expect("the prelude lexes");
+    let mut prelude = Parser::with_exprs(tokens, exprs).parse().expect("the prelude parses");
+    program.exprs = std::mem::take(&mut prelude.exprs);
+    
+    let defined: HashSet<Symbol> = program.all_functions().iter().map(|func| func.name).collect();
+    prelude.functions.retain(|func| !defined.contains(&func.name));
+    prelude.functions.append(&mut program.functions);
+    program.functions = prelude.functions;
+}
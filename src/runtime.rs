@@ -1,8 +1,469 @@
-/// Runtime support functions for Edust programs
+//! Runtime support functions for Edust programs
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::LazyLock;
+use std::time::Instant;
+
+/// Where program output goes, for embedding Edust in a program that shows
+/// it somewhere other than stdout. `print` writes each integer argument
+/// with `write_int` and everything else (strings, the spaces between
+/// arguments, newlines, `print_hex` output) with `write_str`.
+pub trait EdustIo {
+    fn write_int(&mut self, value: i64);
+    fn write_str(&mut self, text: &str);
+}
+
+/// Writes program output to stdout; the default
+pub struct StdoutIo;
+
+impl EdustIo for StdoutIo {
+    fn write_int(&mut self, value: i64) {
+        print!("{}", value);
+    }
+    
+    fn write_str(&mut self, text: &str) {
+        print!("{}", text);
+    }
+}
+
+/// Collects program output in memory. Clones share the buffer, so one can
+/// be installed with `set_io` while another reads back what was written.
+#[derive(Debug, Clone, Default)]
+pub struct BufferIo {
+    buffer: Rc<RefCell<String>>,
+}
+
+impl BufferIo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Everything written so far
+    pub fn contents(&self) -> String {
+        self.buffer.borrow().clone()
+    }
+}
+
+impl EdustIo for BufferIo {
+    fn write_int(&mut self, value: i64) {
+        self.buffer.borrow_mut().push_str(&value.to_string());
+    }
+    
+    fn write_str(&mut self, text: &str) {
+        self.buffer.borrow_mut().push_str(text);
+    }
+}
+
+thread_local! {
+    /// When set, program output is appended here instead of going to `IO`
+    static CAPTURE: RefCell<Option<String>> = const { RefCell::new(None) };
+    
+    /// Where programs running on this thread write their output
+    static IO: RefCell<Box<dyn EdustIo>> = RefCell::new(Box::new(StdoutIo));
+    
+    /// Number of active calls, tracked when a recursion limit is set
+    static DEPTH: Cell<u64> = const { Cell::new(0) };
+    
+    /// Maps created with `map_new` and not yet freed, by handle
+    static MAPS: RefCell<HashMap<i64, HashMap<i64, i64>>> = RefCell::new(HashMap::new());
+    
+    /// Closure environments created and not yet freed, by address
+    static CLOSURES: RefCell<HashSet<i64>> = RefCell::new(HashSet::new());
+    
+    /// Handle of the next map; 0 is never a handle
+    static NEXT_MAP: Cell<i64> = const { Cell::new(1) };
+    
+    /// State of the `rand` generator, never 0; as if seeded with `srand(0)`
+    /// until a program seeds it
+    static RAND_STATE: Cell<u64> = const { Cell::new(rand_state(0)) };
+}
+
+/// Send the output of programs run on the current thread to `io`, and
+/// return the writer it replaces
+pub fn set_io(io: Box<dyn EdustIo>) -> Box<dyn EdustIo> {
+    IO.with(|current| std::mem::replace(&mut *current.borrow_mut(), io))
+}
+
+/// Start buffering program output on the current thread
+pub fn start_capture() {
+    CAPTURE.with(|c| *c.borrow_mut() = Some(String::new()));
+}
+
+/// Stop buffering and return everything printed since `start_capture`
+pub fn finish_capture() -> String {
+    CAPTURE.with(|c| c.borrow_mut().take()).unwrap_or_default()
+}
+
+/// Value passed to `main(argc)`: the number of command-line arguments
+/// after the executable name, counting the source file like C's `argv[0]`
+pub fn argc() -> i64 {
+    std::env::args().count() as i64 - 1
+}
+
+/// Append `text` to the capture buffer, returning false when output isn't
+/// being captured
+fn capture(text: &str) -> bool {
+    CAPTURE.with(|c| match c.borrow_mut().as_mut() {
+        Some(buf) => {
+            buf.push_str(text);
+            true
+        }
+        None => false,
+    })
+}
+
+/// Write program output to the capture buffer, or to the installed
+/// `EdustIo` when output isn't being captured
+fn emit(text: &str) {
+    if !capture(text) {
+        IO.with(|io| io.borrow_mut().write_str(text));
+    }
+}
 
 /// Print an integer value (called from generated code)
 #[unsafe(no_mangle)]
 pub extern "C" fn print_int(value: i64) -> i64 {
-    println!("{}", value);
+    if !capture(&value.to_string()) {
+        IO.with(|io| io.borrow_mut().write_int(value));
+    }
     value
-}
\ No newline at end of file
+}
+
+/// Print a string (called from generated code for `print` arguments that
+/// are strings)
+#[unsafe(no_mangle)]
+pub extern "C" fn print_str(s: i64) -> i64 {
+    emit(&String::from_utf8_lossy(unsafe { str_bytes(s) }));
+    0
+}
+
+/// Print a value in hexadecimal with a `0x` prefix. Negative values show
+/// their two's complement bits.
+#[unsafe(no_mangle)]
+pub extern "C" fn print_hex(value: i64) {
+    emit(&format!("{:#x}", value));
+}
+
+/// Print a value in binary with a `0b` prefix
+#[unsafe(no_mangle)]
+pub extern "C" fn print_bin(value: i64) {
+    emit(&format!("{:#b}", value));
+}
+
+/// Print the space between two `print` arguments
+#[unsafe(no_mangle)]
+pub extern "C" fn print_sep() {
+    emit(" ");
+}
+
+/// End a line of `print` output
+#[unsafe(no_mangle)]
+pub extern "C" fn print_newline() {
+    emit("\n");
+}
+
+/// Report a failed `assert` and abort the process
+#[unsafe(no_mangle)]
+pub extern "C" fn edust_assert_failed(line: i64) -> ! {
+    let _ = std::io::Write::flush(&mut std::io::stdout());
+    eprintln!("assertion failed at line {}", line);
+    std::process::abort();
+}
+
+/// Report a failed `assert_eq` with both values and abort the process
+#[unsafe(no_mangle)]
+pub extern "C" fn edust_assert_eq_failed(lhs: i64, rhs: i64, line: i64) -> ! {
+    let _ = std::io::Write::flush(&mut std::io::stdout());
+    eprintln!("assertion failed: {} != {} at line {}", lhs, rhs, line);
+    std::process::abort();
+}
+
+/// Print the message of a `panic` and abort the process
+#[unsafe(no_mangle)]
+pub extern "C" fn edust_panic(message: i64) -> ! {
+    let _ = std::io::Write::flush(&mut std::io::stdout());
+    eprintln!("panic: {}", String::from_utf8_lossy(unsafe { str_bytes(message) }));
+    std::process::abort();
+}
+
+/// Report a `todo()` or `unimplemented()` that was reached and abort the
+/// process
+#[unsafe(no_mangle)]
+pub extern "C" fn edust_todo(line: i64) -> ! {
+    let _ = std::io::Write::flush(&mut std::io::stdout());
+    eprintln!("not yet implemented at line {}", line);
+    std::process::abort();
+}
+
+/// Terminate the process with the given status (the `exit` builtin)
+#[unsafe(no_mangle)]
+pub extern "C" fn edust_exit(code: i64) -> ! {
+    // process::exit skips flushing, and `write` output may be pending
+    let _ = std::io::Write::flush(&mut std::io::stdout());
+    std::process::exit(code as i32);
+}
+
+/// Report a closure called with the wrong number of arguments and abort
+#[unsafe(no_mangle)]
+pub extern "C" fn edust_bad_closure_call(line: i64) -> ! {
+    let _ = std::io::Write::flush(&mut std::io::stdout());
+    eprintln!("closure called with the wrong number of arguments at line {}", line);
+    std::process::abort();
+}
+
+/// Report an `args_get` index outside the variadic arguments and abort
+#[unsafe(no_mangle)]
+pub extern "C" fn edust_bad_vararg(index: i64, count: i64, line: i64) -> ! {
+    let _ = std::io::Write::flush(&mut std::io::stdout());
+    eprintln!("args_get index {} out of range for {} arguments at line {}", index, count, line);
+    std::process::abort();
+}
+
+/// Allocate `bytes` zeroed bytes aligned to 8 and return the address as an
+/// integer (the `alloc` builtin, also used for closure environments).
+/// Aborts if `bytes` is negative or too large.
+#[unsafe(no_mangle)]
+pub extern "C" fn edust_alloc(bytes: i64) -> i64 {
+    let Some(layout) = alloc_layout(bytes) else {
+        let _ = std::io::Write::flush(&mut std::io::stdout());
+        eprintln!("invalid allocation size {}", bytes);
+        std::process::abort();
+    };
+    let base = unsafe { std::alloc::alloc_zeroed(layout) };
+    if base.is_null() {
+        std::alloc::handle_alloc_error(layout);
+    }
+    
+    // The size goes in a header just before the returned address, so
+    // `edust_free` can rebuild the layout
+    unsafe {
+        (base as *mut i64).write(bytes);
+        base.add(8) as i64
+    }
+}
+
+/// Release memory returned by `edust_alloc` (the `free` builtin). Freeing
+/// 0 does nothing.
+#[unsafe(no_mangle)]
+pub extern "C" fn edust_free(ptr: i64) {
+    if ptr == 0 {
+        return;
+    }
+    CLOSURES.with(|closures| closures.borrow_mut().remove(&ptr));
+    unsafe {
+        let base = (ptr as *mut u8).sub(8);
+        let bytes = (base as *const i64).read();
+        std::alloc::dealloc(base, alloc_layout(bytes).unwrap());
+    }
+}
+
+/// Allocate a closure environment of `bytes` bytes, recorded so
+/// `edust_check_closure` can tell it apart from other values
+#[unsafe(no_mangle)]
+pub extern "C" fn edust_alloc_closure(bytes: i64) -> i64 {
+    let env = edust_alloc(bytes);
+    CLOSURES.with(|closures| closures.borrow_mut().insert(env));
+    env
+}
+
+/// Abort unless `env` is a closure environment that hasn't been freed.
+/// Any value can reach a call through a parameter, so it is looked up
+/// rather than read through.
+#[unsafe(no_mangle)]
+pub extern "C" fn edust_check_closure(env: i64, line: i64) {
+    if !CLOSURES.with(|closures| closures.borrow().contains(&env)) {
+        let _ = std::io::Write::flush(&mut std::io::stdout());
+        eprintln!("called a value that is not a closure at line {}", line);
+        std::process::abort();
+    }
+}
+
+/// Layout of an `edust_alloc` block of `bytes` bytes plus its size header
+fn alloc_layout(bytes: i64) -> Option<std::alloc::Layout> {
+    let size = usize::try_from(bytes).ok()?.checked_add(8)?;
+    std::alloc::Layout::from_size_align(size, 8).ok()
+}
+
+/// Create an empty integer-keyed map and return its handle (the `map_new`
+/// builtin)
+#[unsafe(no_mangle)]
+pub extern "C" fn edust_map_new() -> i64 {
+    let handle = NEXT_MAP.with(|next| next.replace(next.get() + 1));
+    MAPS.with(|maps| maps.borrow_mut().insert(handle, HashMap::new()));
+    handle
+}
+
+/// Set `key` to `value` in map `handle` (the `map_set` builtin)
+#[unsafe(no_mangle)]
+pub extern "C" fn edust_map_set(handle: i64, key: i64, value: i64) {
+    with_map(handle, |map| map.insert(key, value));
+}
+
+/// The value of `key` in map `handle`, or 0 if it has none (the `map_get`
+/// builtin), so counting needs no check for a first occurrence
+#[unsafe(no_mangle)]
+pub extern "C" fn edust_map_get(handle: i64, key: i64) -> i64 {
+    with_map(handle, |map| map.get(&key).copied().unwrap_or(0))
+}
+
+/// Release map `handle` (the `map_free` builtin). Freeing 0 does nothing.
+#[unsafe(no_mangle)]
+pub extern "C" fn edust_map_free(handle: i64) {
+    if handle != 0 {
+        with_map(handle, |_| ());
+        MAPS.with(|maps| maps.borrow_mut().remove(&handle));
+    }
+}
+
+/// Whether `handle` is a map that hasn't been freed
+pub fn is_map(handle: i64) -> bool {
+    MAPS.with(|maps| maps.borrow().contains_key(&handle))
+}
+
+/// Runs `f` on map `handle`, aborting if there is no such map
+fn with_map<T>(handle: i64, f: impl FnOnce(&mut HashMap<i64, i64>) -> T) -> T {
+    MAPS.with(|maps| match maps.borrow_mut().get_mut(&handle) {
+        Some(map) => f(map),
+        None => {
+            let _ = std::io::Write::flush(&mut std::io::stdout());
+            eprintln!("invalid map handle {}", handle);
+            std::process::abort();
+        }
+    })
+}
+
+/// Allocate a string holding `bytes`. A string value is the address of its
+/// length, an 8-byte integer, followed by its bytes. Strings are never
+/// freed.
+pub fn new_str(bytes: &[u8]) -> i64 {
+    let s = edust_alloc(8 + bytes.len() as i64);
+    unsafe {
+        (s as *mut i64).write(bytes.len() as i64);
+        std::ptr::copy_nonoverlapping(bytes.as_ptr(), (s + 8) as *mut u8, bytes.len());
+    }
+    s
+}
+
+/// The bytes of the string `s`, which must come from `new_str` or be laid
+/// out the same way
+///
+/// # Safety
+///
+/// `s` must be the address of a string that lives for `'a`
+pub unsafe fn str_bytes<'a>(s: i64) -> &'a [u8] {
+    unsafe {
+        let len = (s as *const i64).read();
+        std::slice::from_raw_parts((s + 8) as *const u8, len as usize)
+    }
+}
+
+/// Join two strings into a new one (`+` on strings)
+#[unsafe(no_mangle)]
+pub extern "C" fn edust_str_concat(a: i64, b: i64) -> i64 {
+    let mut bytes = unsafe { str_bytes(a) }.to_vec();
+    bytes.extend_from_slice(unsafe { str_bytes(b) });
+    new_str(&bytes)
+}
+
+/// Format an integer in decimal as a new string (the `to_str` builtin)
+#[unsafe(no_mangle)]
+pub extern "C" fn edust_int_to_str(value: i64) -> i64 {
+    new_str(value.to_string().as_bytes())
+}
+
+/// Read a line from stdin and parse it as an integer (the `read_int_or`
+/// builtin). Returns `default` at end of input or if the line isn't a number.
+#[unsafe(no_mangle)]
+pub extern "C" fn edust_read_int_or(default: i64) -> i64 {
+    // Show any prompt written with `write` before blocking
+    let _ = std::io::Write::flush(&mut std::io::stdout());
+    
+    let mut line = String::new();
+    match std::io::stdin().read_line(&mut line) {
+        Ok(0) | Err(_) => default,
+        Ok(_) => line.trim().parse().unwrap_or(default),
+    }
+}
+
+/// When the clock read by `time_ms` started: the first time the runtime
+/// needs it in this process
+static START: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// Milliseconds since the process's clock started (the `time_ms` builtin).
+/// The clock is monotonic, so it never goes backwards, but it is not
+/// wall-clock time.
+#[unsafe(no_mangle)]
+pub extern "C" fn edust_time_ms() -> i64 {
+    START.elapsed().as_millis() as i64
+}
+
+/// Seed the generator behind `rand` (the `srand` builtin). The same seed
+/// always gives the same sequence of numbers.
+#[unsafe(no_mangle)]
+pub extern "C" fn edust_srand(seed: i64) {
+    RAND_STATE.with(|state| state.set(rand_state(seed)));
+}
+
+/// The next pseudo-random number from 0 to `i64::MAX` (the `rand` builtin),
+/// from a xorshift64* generator
+#[unsafe(no_mangle)]
+pub extern "C" fn edust_rand() -> i64 {
+    RAND_STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        (x.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 1) as i64
+    })
+}
+
+/// Generator state for `seed`. Xorshift stays at 0 forever, so the seed is
+/// mixed with a constant no seed can cancel out to 0.
+const fn rand_state(seed: i64) -> u64 {
+    match seed as u64 ^ 0x9e37_79b9_7f4a_7c15 {
+        0 => 0x9e37_79b9_7f4a_7c15,
+        state => state,
+    }
+}
+
+/// Integer exponentiation backing the `**` operator.
+/// Overflow wraps; a negative exponent yields 0.
+#[unsafe(no_mangle)]
+pub extern "C" fn edust_ipow(base: i64, exp: i64) -> i64 {
+    if exp < 0 {
+        return 0;
+    }
+    
+    let mut result: i64 = 1;
+    let mut base = base;
+    let mut exp = exp;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = result.wrapping_mul(base);
+        }
+        base = base.wrapping_mul(base);
+        exp >>= 1;
+    }
+    result
+}
+
+/// Count a function call, aborting once more than `limit` are active
+#[unsafe(no_mangle)]
+pub extern "C" fn edust_enter(limit: i64) {
+    let depth = DEPTH.get() + 1;
+    if depth > limit as u64 {
+        let _ = std::io::Write::flush(&mut std::io::stdout());
+        eprintln!("maximum recursion depth exceeded");
+        std::process::abort();
+    }
+    DEPTH.set(depth);
+}
+
+/// Undo `edust_enter` when a function returns
+#[unsafe(no_mangle)]
+pub extern "C" fn edust_leave() {
+    DEPTH.set(DEPTH.get().saturating_sub(1));
+}
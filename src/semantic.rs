@@ -1,24 +1,151 @@
 use crate::ast::*;
+use crate::error::CompileError;
+use crate::format::binop_symbol;
+use crate::optimize::eval_const_expr;
 use std::collections::HashMap;
+use std::sync::Arc;
 
 /// Semantic analyzer performs:
 /// - Function signature collection
 /// - Variable scope checking
-/// - Type checking (basic - all integers for MVP)
+/// - Type inference, keeping closures and arrays out of arithmetic
+/// - Return path checking
 pub struct SemanticAnalyzer {
-    functions: HashMap<String, FunctionSignature>,
-    scopes: Vec<HashMap<String, VarInfo>>,
+    functions: HashMap<Symbol, FunctionSignature>,
+    scopes: Vec<HashMap<Symbol, VarInfo>>,
+    warnings: Vec<Warning>,
+    
+    // Loops enclosing the statement being analyzed, innermost last
+    loops: Vec<LoopScope>,
+    
+    // Report functions that can fall off the end as errors instead of warnings
+    missing_return_is_error: bool,
+    
+    // Expression nodes of the program being analyzed
+    exprs: Arc<ExprArena>,
+    
+    // Whether the code being analyzed can read variadic arguments
+    in_variadic: bool,
+    
+    // The parameter whose default value is being analyzed
+    default_of: Option<Symbol>,
+}
+
+/// A loop enclosing the statement being analyzed
+#[derive(Debug, Clone)]
+struct LoopScope {
+    label: Option<Symbol>,
+    // A `loop` expression, which `break` can give a value
+    is_expr: bool,
+    // Variables not yet assigned at some `break` out of the loop, as
+    // returned by `unassigned`, or `None` if nothing breaks out of it
+    unassigned_at_break: Option<Vec<(usize, Symbol)>>,
+}
+
+/// What a value stands for. Every value is an `i64` at run time, so this
+/// only exists during analysis, to reject operations that make no sense for
+/// the value, like adding a closure to a number. Values are integers unless
+/// known otherwise: parameters and call results are `Int` even when they
+/// hold closures.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueType {
+    Int,
+    Closure,
+    Str,
+    
+    // An array literal, which only `let (...)` can take apart
+    Array,
+    
+    // A range, which only `for ... in` can iterate over
+    Range,
+    
+    // The result of `todo()` or `unimplemented()`, which never return, so
+    // it can stand in for a value of any type
+    Never,
+}
+
+impl ValueType {
+    /// Whether a value of this type can be used where one of `expected` is
+    fn fits(self, expected: ValueType) -> bool {
+        self == expected || self == ValueType::Never
+    }
+}
+
+impl std::fmt::Display for ValueType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ValueType::Int => write!(f, "int"),
+            ValueType::Closure => write!(f, "closure"),
+            ValueType::Str => write!(f, "string"),
+            ValueType::Array => write!(f, "array"),
+            ValueType::Range => write!(f, "range"),
+            ValueType::Never => write!(f, "never"),
+        }
+    }
+}
+
+/// A non-fatal diagnostic produced during analysis
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "{} at line {}, column {}", self.message, span.line, span.column),
+            None => write!(f, "{}", self.message),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct FunctionSignature {
-    pub name: String,
+    pub name: Symbol,
     pub param_count: usize,
+    /// Number of leading parameters without a default value
+    pub required: usize,
+    /// Whether calls can pass any number of further arguments
+    pub variadic: bool,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 struct VarInfo {
-    name: String,
+    name: Symbol,
+    
+    // Where the variable was declared
+    span: Span,
+    
+    // Type of the values the variable holds
+    ty: ValueType,
+    
+    // Declared `u64`, so its division, remainder and comparisons are
+    // unsigned
+    unsigned: bool,
+    
+    // Whether any expression has used the variable's value yet
+    read: bool,
+    
+    // Whether the variable holds a value on every path to the code being
+    // analyzed; only `let x;` declares one that doesn't
+    assigned: bool,
+    
+    // Whether the variable may hold a closure. Parameters may, as may any
+    // variable given a value whose type isn't known here.
+    may_hold_closure: bool,
+    
+    // Where the variable is first called. Checked against
+    // `may_hold_closure` when its scope ends, since an assignment later in
+    // a loop can still give it a closure before the call runs.
+    called_at: Option<Span>,
+}
+
+impl Default for SemanticAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SemanticAnalyzer {
@@ -26,87 +153,367 @@ impl SemanticAnalyzer {
         SemanticAnalyzer {
             functions: HashMap::new(),
             scopes: vec![HashMap::new()],
+            warnings: Vec::new(),
+            loops: Vec::new(),
+            missing_return_is_error: false,
+            exprs: Arc::default(),
+            in_variadic: false,
+            default_of: None,
         }
     }
     
-    pub fn analyze(&mut self, program: &Program) -> Result<(), String> {
-        // First pass: collect all function signatures
-        for func in &program.functions {
-            if self.functions.contains_key(&func.name) {
-                return Err(format!("Duplicate function definition: {}", func.name));
+    /// Treat functions that may fall off the end without returning a value
+    /// as errors rather than warnings
+    pub fn set_missing_return_error(&mut self, enabled: bool) {
+        self.missing_return_is_error = enabled;
+    }
+    
+    /// Warnings collected by the last call to `analyze`
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+    
+    pub fn analyze(&mut self, program: &Program) -> Result<(), CompileError> {
+        self.exprs = Arc::clone(&program.exprs);
+        
+        // First pass: collect all function signatures, hoisting nested ones
+        for func in program.all_functions() {
+            // Calls to builtin names always reach the builtin, so a function
+            // with the same name could never be called
+            if Self::is_builtin(func.name.as_str()) {
+                return Err(CompileError::at(
+                    format!("Function name {} is reserved for a builtin", func.name),
+                    func.span,
+                ));
+            }
+            
+            if let Some(first) = self.functions.get(&func.name) {
+                return Err(CompileError::at(
+                    format!(
+                        "Duplicate function definition: {} (first defined at line {}, column {})",
+                        func.name, first.span.line, first.span.column
+                    ),
+                    func.span,
+                ));
+            }
+            
+            // Calls can only leave out trailing arguments, so every
+            // parameter after one with a default needs a default too
+            let required = func.defaults.iter().take_while(|d| d.is_none()).count();
+            if let Some(index) = func.defaults[required..].iter().position(|d| d.is_none()) {
+                return Err(CompileError::at(
+                    format!(
+                        "Parameter {} of function {} needs a default value because an earlier parameter has one",
+                        func.params[required + index], func.name
+                    ),
+                    func.span,
+                ));
             }
             
             self.functions.insert(
-                func.name.clone(),
+                func.name,
                 FunctionSignature {
-                    name: func.name.clone(),
+                    name: func.name,
                     param_count: func.params.len(),
+                    required,
+                    variadic: func.variadic.is_some(),
+                    span: func.span,
                 },
             );
         }
         
         // Check for main function
-        if !self.functions.contains_key("main") {
-            return Err("No main function found".to_string());
+        if !self.functions.contains_key(&Symbol::intern("main")) {
+            // There is nothing to point at, so the error points at the
+            // start of the program
+            return Err(CompileError::at("No main function found", Span { line: 1, column: 1 }));
         }
         
-        if self.functions.get("main").unwrap().param_count != 0 {
-            return Err("main function must have no parameters".to_string());
+        // main may optionally take the argument count
+        let main = &self.functions[&Symbol::intern("main")];
+        if main.param_count > 1 || main.variadic {
+            return Err(CompileError::at(
+                "main function must have no parameters or a single argc parameter",
+                main.span,
+            ));
         }
         
-        // Second pass: analyze each function body
-        for func in &program.functions {
+        // Second pass: analyze each function body. Nested functions are
+        // analyzed on their own, so they cannot see the enclosing locals.
+        for func in program.all_functions() {
             self.analyze_function(func)?;
         }
         
         Ok(())
     }
     
-    fn analyze_function(&mut self, func: &Function) -> Result<(), String> {
+    fn analyze_function(&mut self, func: &Function) -> Result<(), CompileError> {
+        // Defaults are evaluated at the call site before the function's
+        // scope exists, so they cannot refer to any variables
+        self.loops.clear();
+        self.in_variadic = false;
+        for (param, default) in func.params.iter().zip(&func.defaults) {
+            if let Some(default) = default {
+                self.default_of = Some(*param);
+                let result = self.analyze_int_expr(default, func.span);
+                self.default_of = None;
+                result?;
+            }
+        }
+        
         // Create new scope for function
         self.enter_scope();
+        self.loops.clear();
         
         // Add parameters to scope
-        for param in &func.params {
+        for (param, span) in func.params.iter().zip(&func.param_spans) {
             if self.current_scope().contains_key(param) {
-                return Err(format!("Duplicate parameter name: {}", param));
+                return Err(CompileError::at(format!("Duplicate parameter name: {}", param), *span));
+            }
+            self.declare_variable(*param, *span, ValueType::Int);
+            
+            if self.functions.contains_key(param) {
+                self.warnings.push(Warning {
+                    message: format!("parameter {} of function {} shadows the function {}", param, func.name, param),
+                    span: Some(*span),
+                });
             }
-            self.declare_variable(param.clone());
         }
+        if let Some(rest) = func.variadic
+            && func.params.contains(&rest)
+        {
+            return Err(CompileError::at(format!("Duplicate parameter name: {}", rest), func.span));
+        }
+        self.in_variadic = func.variadic.is_some();
         
         // Analyze function body
-        self.analyze_block(&func.body)?;
+        self.analyze_block(&func.body, func.span)?;
+        
+        for (param, span) in func.params.iter().zip(&func.param_spans) {
+            if !param.as_str().starts_with('_') && !self.current_scope()[param].read {
+                self.warnings.push(Warning {
+                    message: format!("parameter {} of function {} is never read", param, func.name),
+                    span: Some(*span),
+                });
+            }
+        }
+        
+        // A function that returns a value somewhere must do so on every path;
+        // functions without any `return` implicitly return 0
+        if Self::block_contains_return(&func.body) && !Self::block_always_returns(&func.body) {
+            let message = format!(
+                "Function {} may reach the end without returning a value",
+                func.name
+            );
+            if self.missing_return_is_error {
+                return Err(CompileError::at(message, func.span));
+            }
+            self.warnings.push(Warning { message, span: None });
+        }
         
         // Exit function scope
-        self.exit_scope();
+        self.exit_scope()?;
         
         Ok(())
     }
     
-    fn analyze_block(&mut self, block: &Block) -> Result<(), String> {
+    /// `span` locates the construct the block belongs to, which errors in
+    /// its tail expression point at
+    fn analyze_block(&mut self, block: &Block, span: Span) -> Result<(), CompileError> {
+        let mut terminated = None;
+        
         for stmt in &block.statements {
+            // Only the first unreachable statement of a block is reported
+            if let Some(keyword) = terminated.take() {
+                self.warnings.push(Warning {
+                    message: format!("unreachable code after {}", keyword),
+                    span: Some(stmt.span()),
+                });
+            }
+            
             self.analyze_statement(stmt)?;
+            
+            terminated = Self::terminator(stmt);
+        }
+        
+        if let Some(tail) = &block.tail {
+            if let Some(keyword) = terminated {
+                self.warnings.push(Warning {
+                    message: format!("unreachable code after {}", keyword),
+                    span: None,
+                });
+            }
+            self.analyze_int_expr(tail, span)?;
         }
         Ok(())
     }
     
-    fn analyze_statement(&mut self, stmt: &Statement) -> Result<(), String> {
+    /// If control never continues past `stmt`, what ends it: a keyword or
+    /// a call to a builtin that never returns
+    fn terminator(stmt: &Statement) -> Option<&'static str> {
+        match stmt {
+            Statement::Break { .. } => Some("break"),
+            Statement::Continue { .. } => Some("continue"),
+            Statement::Return { .. } => Some("return"),
+            Statement::ExprStmt {
+                expr: Expr::Call { name, .. },
+                ..
+            } => match name.as_str() {
+                "exit" => Some("call to exit"),
+                "panic" => Some("call to panic"),
+                "todo" => Some("call to todo"),
+                "unimplemented" => Some("call to unimplemented"),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+    
+    /// Whether `stmt` leaves the function: a `return` or a call to a
+    /// builtin that never returns, like `exit` or `todo`
+    fn statement_returns(stmt: &Statement) -> bool {
+        match stmt {
+            Statement::Return { .. } => true,
+            Statement::ExprStmt {
+                expr: Expr::Call { name, .. },
+                ..
+            } => matches!(name.as_str(), "exit" | "panic" | "todo" | "unimplemented"),
+            _ => false,
+        }
+    }
+    
+    fn analyze_statement(&mut self, stmt: &Statement) -> Result<(), CompileError> {
         match stmt {
-            Statement::VarDecl { name, value } => {
-                self.analyze_expr(value)?;
+            Statement::VarDecl { name, ty: annotation, value, span } => {
+                // A variable declared without a value holds an integer, as
+                // does one initialized with `todo()`
+                let ty = match value {
+                    Some(value) => match self.analyze_expr(value, *span)? {
+                        ValueType::Never => ValueType::Int,
+                        ty => ty,
+                    },
+                    None => ValueType::Int,
+                };
+                if let Some(annotation) = annotation
+                    && ty != ValueType::Int
+                {
+                    return Err(CompileError::at(
+                        format!("Type mismatch: cannot declare {} as {}, it holds {}", name, annotation.name(), ty),
+                        *span,
+                    ));
+                }
                 
-                if self.current_scope().contains_key(name) {
-                    return Err(format!("Variable already declared in this scope: {}", name));
+                if let Some(first) = self.current_scope().get(name) {
+                    return Err(Self::already_declared(*name, *span, first));
                 }
                 
-                self.declare_variable(name.clone());
+                let may_hold_closure = value.as_ref().is_some_and(|value| self.may_hold_closure(value));
+                self.declare_variable(*name, *span, ty);
+                let info = self.current_scope().get_mut(name).unwrap();
+                info.unsigned = *annotation == Some(Type::U64);
+                info.assigned = value.is_some();
+                info.may_hold_closure = may_hold_closure;
+            }
+            
+            Statement::DestructureDecl { names, value, span } => {
+                let Expr::Array(elements) = value else {
+                    return Err(CompileError::at("Can only destructure an array literal", *span));
+                };
+                if elements.len() != names.len() {
+                    return Err(CompileError::at(
+                        format!(
+                            "Cannot destructure {} element{} into {} name{}",
+                            elements.len(),
+                            if elements.len() == 1 { "" } else { "s" },
+                            names.len(),
+                            if names.len() == 1 { "" } else { "s" }
+                        ),
+                        *span,
+                    ));
+                }
+                let mut types = Vec::new();
+                for element in elements {
+                    types.push(self.analyze_int_expr(element, *span)?);
+                }
+                
+                for (i, name) in names.iter().enumerate() {
+                    if names[..i].contains(name) {
+                        return Err(CompileError::at(
+                            format!("Variable bound twice in destructuring: {}", name),
+                            *span,
+                        ));
+                    }
+                    if let Some(first) = self.current_scope().get(name) {
+                        return Err(Self::already_declared(*name, *span, first));
+                    }
+                    self.declare_variable(*name, *span, types[i]);
+                    let may_hold_closure = self.may_hold_closure(&elements[i]);
+                    self.current_scope().get_mut(name).unwrap().may_hold_closure = may_hold_closure;
+                }
+            }
+            
+            Statement::StaticDecl { name, value, span } => {
+                if eval_const_expr(&self.exprs, value, &HashMap::new()).is_none() {
+                    return Err(CompileError::at("Static initializer must be a constant expression", *span));
+                }
+                
+                if let Some(first) = self.current_scope().get(name) {
+                    return Err(Self::already_declared(*name, *span, first));
+                }
+                
+                let info = VarInfo {
+                    name: *name,
+                    span: *span,
+                    ty: ValueType::Int,
+                    unsigned: false,
+                    read: false,
+                    assigned: true,
+                    may_hold_closure: false,
+                    called_at: None,
+                };
+                self.current_scope().insert(*name, info);
+            }
+            
+            Statement::Assignment { name, value, span } => {
+                let ty = self.analyze_expr(value, *span)?;
+                self.assign_variable(*name, ty, self.may_hold_closure(value), *span)?;
+                
+                if self.is_self_assignment(*name, value) {
+                    self.warnings.push(Warning {
+                        message: "self-assignment has no effect".to_string(),
+                        span: Some(*span),
+                    });
+                }
             }
             
-            Statement::Assignment { name, value } => {
-                self.analyze_expr(value)?;
+            Statement::MultiAssign { names, values, span } => {
+                if names.len() != values.len() {
+                    return Err(CompileError::at(
+                        format!(
+                            "Cannot assign {} value{} to {} name{}",
+                            values.len(),
+                            if values.len() == 1 { "" } else { "s" },
+                            names.len(),
+                            if names.len() == 1 { "" } else { "s" }
+                        ),
+                        *span,
+                    ));
+                }
                 
-                if !self.is_variable_declared(name) {
-                    return Err(format!("Undefined variable: {}", name));
+                // Every value is read before any name is assigned
+                let mut types = Vec::new();
+                for value in values {
+                    types.push(self.analyze_expr(value, *span)?);
+                }
+                let closures: Vec<bool> = values.iter().map(|value| self.may_hold_closure(value)).collect();
+                for (i, name) in names.iter().enumerate() {
+                    if names[..i].contains(name) {
+                        return Err(CompileError::at(
+                            format!("Variable assigned twice in one assignment: {}", name),
+                            *span,
+                        ));
+                    }
+                    self.assign_variable(*name, types[i], closures[i], *span)?;
                 }
             }
             
@@ -114,120 +521,1772 @@ impl SemanticAnalyzer {
                 condition,
                 then_block,
                 else_block,
+                span,
             } => {
-                self.analyze_expr(condition)?;
+                self.analyze_int_expr(condition, *span)?;
+                let before = self.unassigned();
                 
                 self.enter_scope();
-                self.analyze_block(then_block)?;
-                self.exit_scope();
+                self.analyze_block(then_block, *span)?;
+                self.exit_scope()?;
+                let after_then = self.unassigned();
                 
+                self.set_unassigned(&before);
                 if let Some(else_blk) = else_block {
                     self.enter_scope();
-                    self.analyze_block(else_blk)?;
-                    self.exit_scope();
+                    self.analyze_block(else_blk, *span)?;
+                    self.exit_scope()?;
                 }
+                
+                let after_else = self.unassigned();
+                
+                // After the `if`, a variable is assigned if every branch
+                // that gets there assigned it
+                let then_diverges = Self::block_diverges(then_block);
+                let else_diverges = else_block.as_ref().is_some_and(Self::block_diverges);
+                let after = match (then_diverges, else_diverges) {
+                    (true, false) => after_else,
+                    (false, true) => after_then,
+                    _ => [after_then, after_else].concat(),
+                };
+                self.set_unassigned(&after);
+            }
+            
+            Statement::While {
+                label,
+                condition,
+                body,
+                span,
+            } => {
+                self.analyze_int_expr(condition, *span)?;
+                let before = self.unassigned();
+                self.analyze_loop_body(*label, false, body, *span)?;
+                self.check_infinite_loop(*label, condition, body, *span);
+                
+                // The body may not run at all
+                self.set_unassigned(&before);
             }
             
-            Statement::While { condition, body } => {
-                self.analyze_expr(condition)?;
+            Statement::ForIn {
+                label,
+                var,
+                iter,
+                body,
+                span,
+            } => {
+                self.infer_expr_type(iter, *span)?;
+                let before = self.unassigned();
                 
+                // The loop variable is only in scope in the body
                 self.enter_scope();
-                self.analyze_block(body)?;
-                self.exit_scope();
+                self.declare_variable(*var, *span, ValueType::Int);
+                self.current_scope().get_mut(var).unwrap().may_hold_closure = false;
+                let result = self.analyze_loop_body(*label, false, body, *span);
+                let exited = self.exit_scope();
+                result?;
+                exited?;
+                
+                // The range may be empty
+                self.set_unassigned(&before);
             }
             
-            Statement::Return { value } => {
-                self.analyze_expr(value)?;
+            Statement::DoWhile {
+                label,
+                body,
+                condition,
+                span,
+            } => {
+                let before = self.unassigned();
+                self.analyze_loop_body(*label, false, body, *span)?;
+                
+                // The body runs at least once, but a `break` or `continue`
+                // can skip the assignments in the rest of it
+                if Self::block_jumps(body) {
+                    self.set_unassigned(&before);
+                }
+                self.analyze_int_expr(condition, *span)?;
+                self.check_infinite_loop(*label, condition, body, *span);
+            }
+            
+            Statement::Return { value, span } => {
+                self.analyze_int_expr(value, *span)?;
             }
             
-            Statement::ExprStmt { expr } => {
-                self.analyze_expr(expr)?;
+            Statement::ExprStmt { expr, span } => {
+                self.analyze_expr(expr, *span)?;
+            }
+            
+            Statement::Break { label, value, span } => {
+                self.check_loop_control("break", *label, *span)?;
+                if let Some(value) = value {
+                    self.check_break_value(*label, value, *span)?;
+                }
+                
+                let unassigned = self.unassigned();
+                let target = match label {
+                    Some(label) => self.loops.iter_mut().rev().find(|l| l.label == Some(*label)),
+                    None => self.loops.last_mut(),
+                };
+                if let Some(target) = target {
+                    target.unassigned_at_break.get_or_insert_with(Vec::new).extend(unassigned);
+                }
+            }
+            
+            Statement::Continue { label, span } => self.check_loop_control("continue", *label, *span)?,
+            
+            // Analyzed separately from the enclosing function, which only
+            // finds it outside of expressions
+            Statement::FuncDecl { span, .. } => {
+                if self.loops.iter().any(|l| l.is_expr) {
+                    return Err(CompileError::at("Functions cannot be declared inside a loop expression", *span));
+                }
             }
         }
         
         Ok(())
     }
     
-    fn analyze_expr(&self, expr: &Expr) -> Result<(), String> {
+    /// Analyzes an expression whose value is used, which arrays and ranges
+    /// cannot be. `span` locates the innermost statement or expression
+    /// around `expr` that has a location, for errors about it.
+    fn analyze_expr(&mut self, expr: &Expr, span: Span) -> Result<ValueType, CompileError> {
+        let ty = self.infer_expr_type(expr, span)?;
+        if ty == ValueType::Array {
+            return Err(CompileError::at("Array literals can only be destructured with let (...)", span));
+        }
+        if ty == ValueType::Range {
+            return Err(CompileError::at("Ranges can only be iterated over with for ... in", span));
+        }
+        Ok(ty)
+    }
+    
+    /// Analyzes an expression whose value is passed on as a plain integer:
+    /// an argument, a result or a condition. Strings can only be stored in
+    /// variables, joined with `+` and printed.
+    fn analyze_int_expr(&mut self, expr: &Expr, span: Span) -> Result<ValueType, CompileError> {
+        let ty = self.analyze_expr(expr, span)?;
+        if ty == ValueType::Str {
+            return Err(CompileError::at(
+                "Type mismatch: strings can only be stored in variables, joined with + and printed",
+                span,
+            ));
+        }
+        Ok(ty)
+    }
+    
+    /// Checks `expr` bottom-up and returns the type of its value. Operators
+    /// only take integers; anything else is a type mismatch.
+    fn infer_expr_type(&mut self, expr: &Expr, span: Span) -> Result<ValueType, CompileError> {
         match expr {
-            Expr::Number(_) => Ok(()),
+            Expr::Number(_) => Ok(ValueType::Int),
+            Expr::Str(_) => Ok(ValueType::Str),
             
-            Expr::Variable(name) => {
-                if !self.is_variable_declared(name) {
-                    return Err(format!("Undefined variable: {}", name));
+            Expr::Variable { name, span } => {
+                let Some(info) = self.read_variable(*name) else {
+                    return Err(self.undefined_variable(*name, *span));
+                };
+                if !info.assigned {
+                    return Err(CompileError::at(
+                        format!("Variable {} may be read before it is assigned", name),
+                        *span,
+                    ));
                 }
-                Ok(())
+                Ok(info.ty)
             }
             
-            Expr::Binary { left, right, .. } => {
-                self.analyze_expr(left)?;
-                self.analyze_expr(right)?;
-                Ok(())
+            Expr::Binary { .. } | Expr::Cast { .. } => {
+                let exprs = Arc::clone(&self.exprs);
+                let (spine, bottom) = expr.left_spine(&exprs);
+                let mut ty = self.infer_expr_type(bottom, span)?;
+                for node in spine.into_iter().rev() {
+                    ty = match node {
+                        Expr::Binary { op, left, right } => {
+                            let rhs = self.infer_expr_type(&exprs[*right], span)?;
+                            self.binary_type(*op, &exprs[*left], &exprs[*right], ty, rhs, span)?
+                        }
+                        Expr::Cast { target, .. } => {
+                            if !ty.fits(ValueType::Int) {
+                                return Err(CompileError::at(
+                                    format!("Type mismatch: cannot cast {} to {}", ty, target),
+                                    span,
+                                ));
+                            }
+                            ValueType::Int
+                        }
+                        _ => unreachable!(),
+                    };
+                }
+                Ok(ty)
             }
             
-            Expr::Unary { operand, .. } => {
-                self.analyze_expr(operand)?;
-                Ok(())
+            Expr::Unary { op, operand } => {
+                let exprs = Arc::clone(&self.exprs);
+                let ty = self.infer_expr_type(&exprs[*operand], span)?;
+                if !ty.fits(ValueType::Int) {
+                    let symbol = match op {
+                        UnaryOp::Neg => "-",
+                        UnaryOp::Not => "!",
+                    };
+                    return Err(CompileError::at(format!("Type mismatch: cannot apply {} to {}", symbol, ty), span));
+                }
+                Ok(ValueType::Int)
             }
             
-            Expr::Call { name, args } => {
-                // Check if it's the builtin print function
-                if name == "print" {
+            Expr::Call { name, args, span } => {
+                // Calling a variable calls the closure it holds. The
+                // argument count is checked when the call runs.
+                if let Some(info) = self.read_variable(*name) {
+                    if !info.assigned {
+                        return Err(CompileError::at(
+                            format!("Variable {} may be read before it is assigned", name),
+                            *span,
+                        ));
+                    }
+                    if self.functions.contains_key(name) || Self::is_builtin(name.as_str()) {
+                        return Err(CompileError::at(
+                            format!("Call to {} is ambiguous: it names both a variable and a function", name),
+                            *span,
+                        ));
+                    }
+                    for arg in args {
+                        self.analyze_int_expr(arg, *span)?;
+                    }
+                    let info = self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(name)).unwrap();
+                    info.called_at.get_or_insert(*span);
+                    return Ok(ValueType::Int);
+                }
+                
+                // Check if it's the builtin print or write function
+                if name == "print" || name == "write" {
+                    if args.is_empty() {
+                        return Err(CompileError::at(format!("{}() requires at least 1 argument", name), *span));
+                    }
+                    for arg in args {
+                        self.analyze_expr(arg, *span)?;
+                    }
+                    return Ok(ValueType::Int);
+                }
+                
+                // The length of an array is known when it is compiled
+                if name == "len" {
+                    if args.len() != 1 {
+                        return Err(CompileError::at("len() requires exactly 1 argument", *span));
+                    }
+                    let ty = match &args[0] {
+                        // The elements are still evaluated, and must be
+                        // integers like those of any other array
+                        Expr::Array(elements) => {
+                            for element in elements {
+                                let ty = self.analyze_expr(element, *span)?;
+                                if !ty.fits(ValueType::Int) {
+                                    let message =
+                                        format!("Type mismatch: len() takes an array of integers, not one holding {}", ty);
+                                    return Err(CompileError::at(message, *span));
+                                }
+                            }
+                            ValueType::Array
+                        }
+                        arg => self.infer_expr_type(arg, *span)?,
+                    };
+                    if ty != ValueType::Array {
+                        let message = format!("Type mismatch: len() takes an array literal, not {}", ty);
+                        return Err(CompileError::at(message, *span));
+                    }
+                    return Ok(ValueType::Int);
+                }
+                
+                // panic takes a string message, and never returns
+                if name == "panic" {
                     if args.len() != 1 {
-                        return Err("print() requires exactly 1 argument".to_string());
+                        return Err(CompileError::at("panic() requires exactly 1 argument", *span));
+                    }
+                    let ty = self.analyze_expr(&args[0], *span)?;
+                    if !ty.fits(ValueType::Str) {
+                        let message = format!("Type mismatch: panic() takes a string message, not {}", ty);
+                        return Err(CompileError::at(message, *span));
+                    }
+                    return Ok(ValueType::Int);
+                }
+                
+                if (name == "args_count" || name == "args_get") && !self.in_variadic {
+                    return Err(CompileError::at(
+                        format!("{}() can only be used in a function with a variadic parameter", name),
+                        *span,
+                    ));
+                }
+                
+                // Builtins taking a fixed number of arguments
+                if let Some(arity) = Self::builtin_arity(name.as_str()) {
+                    if args.len() != arity {
+                        let plural = if arity == 1 { "" } else { "s" };
+                        let message = format!("{}() requires exactly {} argument{}", name, arity, plural);
+                        return Err(CompileError::at(message, *span));
+                    }
+                    for arg in args {
+                        self.analyze_int_expr(arg, *span)?;
+                    }
+                    if name == "to_str" {
+                        return Ok(ValueType::Str);
                     }
-                    self.analyze_expr(&args[0])?;
-                    return Ok(());
+                    if name == "todo" || name == "unimplemented" {
+                        return Ok(ValueType::Never);
+                    }
+                    return Ok(ValueType::Int);
                 }
                 
                 // Check if function exists
                 let sig = self
                     .functions
                     .get(name)
-                    .ok_or_else(|| format!("Undefined function: {}", name))?;
+                    .ok_or_else(|| CompileError::at(format!("Undefined function: {}", name), *span))?;
                 
                 // Check argument count
-                if args.len() != sig.param_count {
-                    return Err(format!(
-                        "Function {} expects {} arguments, got {}",
+                if sig.variadic {
+                    if args.len() < sig.required {
+                        let message = format!(
+                            "Function {} expects at least {} arguments, got {}",
+                            name,
+                            sig.required,
+                            args.len()
+                        );
+                        return Err(CompileError::at(message, *span));
+                    }
+                } else if sig.required == sig.param_count && args.len() != sig.param_count {
+                    let message =
+                        format!("Function {} expects {} arguments, got {}", name, sig.param_count, args.len());
+                    return Err(CompileError::at(message, *span));
+                } else if args.len() < sig.required || args.len() > sig.param_count {
+                    let message = format!(
+                        "Function {} expects {} to {} arguments, got {}",
                         name,
+                        sig.required,
                         sig.param_count,
                         args.len()
-                    ));
+                    );
+                    return Err(CompileError::at(message, *span));
                 }
                 
                 // Analyze all arguments
                 for arg in args {
-                    self.analyze_expr(arg)?;
+                    self.analyze_int_expr(arg, *span)?;
                 }
                 
-                Ok(())
+                Ok(ValueType::Int)
+            }
+            
+            Expr::Array(elements) => {
+                for element in elements {
+                    self.analyze_expr(element, span)?;
+                }
+                Ok(ValueType::Array)
+            }
+            
+            Expr::Range { start, end } => {
+                let exprs = Arc::clone(&self.exprs);
+                self.analyze_int_expr(&exprs[*start], span)?;
+                self.analyze_int_expr(&exprs[*end], span)?;
+                Ok(ValueType::Range)
+            }
+            
+            // The body sees the parameters and, through captures, every
+            // variable in scope where the closure is written
+            Expr::Closure {
+                params,
+                param_spans,
+                body,
+                span,
+            } => {
+                // Loops around the closure don't enclose its body, and its
+                // body is compiled as a function of its own
+                let loops = std::mem::take(&mut self.loops);
+                let in_variadic = std::mem::replace(&mut self.in_variadic, false);
+                let unassigned = self.unassigned();
+                self.enter_scope();
+                for (param, param_span) in params.iter().zip(param_spans) {
+                    if self.current_scope().contains_key(param) {
+                        return Err(CompileError::at(format!("Duplicate parameter name: {}", param), *param_span));
+                    }
+                    self.declare_variable(*param, *param_span, ValueType::Int);
+                }
+                let exprs = Arc::clone(&self.exprs);
+                let result = self.analyze_int_expr(&exprs[*body], *span);
+                let exited = self.exit_scope();
+                self.loops = loops;
+                self.in_variadic = in_variadic;
+                self.set_unassigned(&unassigned);
+                result?;
+                exited?;
+                Ok(ValueType::Closure)
+            }
+            
+            // Only what was assigned before the loop is known to be
+            // assigned at every `break` out of it
+            // Control only leaves the loop through a `break`, so a variable
+            // is assigned after it if it was at every `break`
+            Expr::Loop { label, body, span } => {
+                let before = self.unassigned();
+                let at_break = self.analyze_loop_body(*label, true, body, *span)?;
+                self.set_unassigned(&at_break.unwrap_or(before));
+                Ok(ValueType::Int)
+            }
+            
+            Expr::Assign { name, value, span } => {
+                let exprs = Arc::clone(&self.exprs);
+                let ty = self.analyze_expr(&exprs[*value], *span)?;
+                self.assign_variable(*name, ty, self.may_hold_closure(&exprs[*value]), *span)?;
+                Ok(ty)
+            }
+            
+            // A variable is assigned after the `if` only if both branches
+            // assigned it
+            Expr::If { cond, then_val, else_val } => {
+                let exprs = Arc::clone(&self.exprs);
+                self.analyze_int_expr(&exprs[*cond], span)?;
+                let before = self.unassigned();
+                let then_ty = self.analyze_expr(&exprs[*then_val], span)?;
+                let after_then = self.unassigned();
+                self.set_unassigned(&before);
+                let else_ty = self.analyze_expr(&exprs[*else_val], span)?;
+                let after_else = self.unassigned();
+                self.set_unassigned(&[after_then, after_else].concat());
+                
+                match (then_ty, else_ty) {
+                    (ValueType::Never, ty) | (ty, ValueType::Never) => Ok(ty),
+                    _ if then_ty == else_ty => Ok(then_ty),
+                    _ => Err(CompileError::at(
+                        format!("Type mismatch: if branches have different types, {} and {}", then_ty, else_ty),
+                        span,
+                    )),
+                }
             }
         }
     }
     
+    /// The type of `left op right`, given the types of its operands
+    fn binary_type(
+        &mut self,
+        op: BinOp,
+        left: &Expr,
+        right: &Expr,
+        lhs: ValueType,
+        rhs: ValueType,
+        span: Span,
+    ) -> Result<ValueType, CompileError> {
+        // `todo()` takes the type of the other operand
+        let (lhs, rhs) = match (lhs, rhs) {
+            (ValueType::Never, ValueType::Never) => (ValueType::Int, ValueType::Int),
+            (ValueType::Never, ty) | (ty, ValueType::Never) => (ty, ty),
+            types => types,
+        };
+        if op == BinOp::Add && lhs == ValueType::Str && rhs == ValueType::Str {
+            return Ok(ValueType::Str);
+        }
+        if lhs != ValueType::Int || rhs != ValueType::Int {
+            return Err(CompileError::at(
+                format!("Type mismatch: cannot apply {} to {} and {}", binop_symbol(op), lhs, rhs),
+                span,
+            ));
+        }
+        
+        if self.mixes_signedness(op, left, right) {
+            self.warnings.push(Warning {
+                message: format!(
+                    "{} of a u64 and a signed value is signed; cast the signed operand with `as u64` for an unsigned {}",
+                    binop_symbol(op),
+                    binop_symbol(op)
+                ),
+                span: None,
+            });
+        }
+        Ok(ValueType::Int)
+    }
+    
+    fn is_builtin(name: &str) -> bool {
+        matches!(name, "print" | "write" | "panic" | "len") || Self::builtin_arity(name).is_some()
+    }
+    
+    /// Number of arguments taken by builtins with a fixed arity
+    fn builtin_arity(name: &str) -> Option<usize> {
+        match name {
+            "assert" | "exit" | "abs" | "read_int_or" | "print_hex" | "print_bin" => Some(1),
+            "to_str" => Some(1),
+            "popcount" | "clz" | "ctz" => Some(1),
+            "alloc" | "free" | "load" => Some(1),
+            "map_new" => Some(0),
+            "map_free" => Some(1),
+            "map_get" => Some(2),
+            "map_set" => Some(3),
+            "args_count" | "time_ms" | "rand" => Some(0),
+            "todo" | "unimplemented" => Some(0),
+            "srand" => Some(1),
+            "args_get" => Some(1),
+            "min" | "max" | "sat_add" | "sat_sub" | "sat_mul" | "store" => Some(2),
+            "assert_eq" => Some(2),
+            _ => None,
+        }
+    }
+    
+    /// Whether every path through `block` ends in a `return` (or a call
+    /// that never returns, like `exit`)
+    fn block_always_returns(block: &Block) -> bool {
+        // A function body's tail expression is its return value
+        if block.tail.is_some() {
+            return true;
+        }
+        
+        block.statements.iter().any(|stmt| match stmt {
+            Statement::If {
+                then_block,
+                else_block: Some(else_blk),
+                ..
+            } => Self::block_always_returns(then_block) && Self::block_always_returns(else_blk),
+            // The loop condition may be false on entry, or the range empty
+            Statement::While { .. } | Statement::ForIn { .. } => false,
+            // The body always runs at least once
+            Statement::DoWhile { body, .. } => Self::block_always_returns(body),
+            _ => Self::statement_returns(stmt),
+        })
+    }
+    
+    /// Whether control never reaches the end of `block`, because it returns
+    /// or leaves with a `break` or `continue`
+    fn block_diverges(block: &Block) -> bool {
+        Self::block_always_returns(block) || block.statements.iter().any(|stmt| Self::terminator(stmt).is_some())
+    }
+    
+    /// Whether `block` contains a `break` or `continue` anywhere, including
+    /// in the loops nested in it
+    fn block_jumps(block: &Block) -> bool {
+        block.statements.iter().any(|stmt| match stmt {
+            Statement::Break { .. } | Statement::Continue { .. } => true,
+            Statement::If {
+                then_block,
+                else_block,
+                ..
+            } => Self::block_jumps(then_block) || else_block.as_ref().is_some_and(Self::block_jumps),
+            Statement::While { body, .. }
+            | Statement::DoWhile { body, .. }
+            | Statement::ForIn { body, .. } => Self::block_jumps(body),
+            _ => false,
+        })
+    }
+    
+    /// Whether `block`, the body of a loop, contains a `break` of that loop
+    /// or anything that leaves the function. A `break` naming one of
+    /// `labels`, the loop's own label and those of the loops around it,
+    /// leaves the loop too.
+    fn loop_has_exit(block: &Block, labels: &[Symbol]) -> bool {
+        block.statements.iter().any(|stmt| match stmt {
+            Statement::Break { label: None, .. } => true,
+            Statement::Break { label: Some(label), .. } => labels.contains(label),
+            Statement::If {
+                then_block,
+                else_block,
+                ..
+            } => {
+                Self::loop_has_exit(then_block, labels)
+                    || else_block.as_ref().is_some_and(|b| Self::loop_has_exit(b, labels))
+            }
+            // An unlabeled `break` in an inner loop only leaves that loop
+            Statement::While { body, .. }
+            | Statement::DoWhile { body, .. }
+            | Statement::ForIn { body, .. } => {
+                Self::block_leaves_loops(body, labels)
+            }
+            _ => Self::statement_returns(stmt),
+        })
+    }
+    
+    /// Whether `block` returns or breaks out of a loop named in `labels`
+    fn block_leaves_loops(block: &Block, labels: &[Symbol]) -> bool {
+        block.statements.iter().any(|stmt| match stmt {
+            Statement::Break { label: Some(label), .. } => labels.contains(label),
+            Statement::If {
+                then_block,
+                else_block,
+                ..
+            } => {
+                Self::block_leaves_loops(then_block, labels)
+                    || else_block.as_ref().is_some_and(|b| Self::block_leaves_loops(b, labels))
+            }
+            Statement::While { body, .. }
+            | Statement::DoWhile { body, .. }
+            | Statement::ForIn { body, .. } => {
+                Self::block_leaves_loops(body, labels)
+            }
+            _ => Self::statement_returns(stmt),
+        })
+    }
+    
+    fn block_contains_return(block: &Block) -> bool {
+        block.statements.iter().any(|stmt| match stmt {
+            Statement::Return { .. } => true,
+            Statement::If {
+                then_block,
+                else_block,
+                ..
+            } => {
+                Self::block_contains_return(then_block)
+                    || else_block.as_ref().is_some_and(Self::block_contains_return)
+            }
+            Statement::While { body, .. }
+            | Statement::DoWhile { body, .. }
+            | Statement::ForIn { body, .. } => {
+                Self::block_contains_return(body)
+            }
+            _ => false,
+        })
+    }
+    
+    /// Analyzes the body of a loop, and returns the variables that were not
+    /// yet assigned at some `break` out of it, if anything breaks out
+    fn analyze_loop_body(
+        &mut self,
+        label: Option<Symbol>,
+        is_expr: bool,
+        body: &Block,
+        span: Span,
+    ) -> Result<Option<Vec<(usize, Symbol)>>, CompileError> {
+        if let Some(label) = label
+            && self.loops.iter().any(|l| l.label == Some(label))
+        {
+            return Err(CompileError::at(
+                format!("Loop label {} is already used by an enclosing loop", label),
+                span,
+            ));
+        }
+        
+        self.enter_scope();
+        self.loops.push(LoopScope {
+            label,
+            is_expr,
+            unassigned_at_break: None,
+        });
+        let result = self.analyze_block(body, span);
+        let scope = self.loops.pop().unwrap();
+        let exited = self.exit_scope();
+        result.and(exited)?;
+        Ok(scope.unassigned_at_break)
+    }
+    
+    /// Checks that a `break` or `continue` is inside a loop, and inside
+    /// the loop it names if it has a label
+    fn check_loop_control(&self, keyword: &str, label: Option<Symbol>, span: Span) -> Result<(), CompileError> {
+        if self.loops.is_empty() {
+            return Err(CompileError::at(format!("{} outside of a loop", keyword), span));
+        }
+        if let Some(label) = label
+            && !self.loops.iter().any(|l| l.label == Some(label))
+        {
+            return Err(CompileError::at(format!("Unknown loop label: {}", label), span));
+        }
+        Ok(())
+    }
+    
+    /// Checks the value of a `break`, which only a `loop` expression can
+    /// take. The value must be an integer, like every loop's value.
+    fn check_break_value(&mut self, label: Option<Symbol>, value: &Expr, span: Span) -> Result<(), CompileError> {
+        // The parser only reads a label it knows, so `break outer;` with no
+        // enclosing loop `outer` comes here as a value
+        if let (None, Expr::Variable { name, .. }) = (label, value)
+            && !self.is_variable_declared(*name)
+        {
+            return Err(CompileError::at(format!("Unknown loop label: {}", name), span));
+        }
+        
+        let target = match label {
+            Some(label) => self.loops.iter().rev().find(|l| l.label == Some(label)),
+            None => self.loops.last(),
+        };
+        if !target.is_some_and(|l| l.is_expr) {
+            return Err(CompileError::at("break with a value is only allowed inside loop", span));
+        }
+        
+        let ty = self.analyze_int_expr(value, span)?;
+        if !ty.fits(ValueType::Int) {
+            return Err(CompileError::at(format!("Type mismatch: break value must be int, found {}", ty), span));
+        }
+        Ok(())
+    }
+    
+    /// Warn about a loop whose condition is a constant true value and whose
+    /// body has no `break` or `return`, since it can never finish
+    /// Whether assigning `value` to `name` leaves it unchanged: `x = x`, or
+    /// `x` with 0 added or subtracted or multiplied or divided by 1
+    fn is_self_assignment(&self, name: Symbol, value: &Expr) -> bool {
+        let is_name = |id: ExprId| matches!(self.exprs[id], Expr::Variable { name: n, .. } if n == name);
+        let is_number = |id: ExprId, value: i64| matches!(self.exprs[id], Expr::Number(n) if n == value);
+        match value {
+            Expr::Variable { name: n, .. } => *n == name,
+            Expr::Binary { op, left, right } => match op {
+                BinOp::Add => is_name(*left) && is_number(*right, 0) || is_number(*left, 0) && is_name(*right),
+                BinOp::Mul => is_name(*left) && is_number(*right, 1) || is_number(*left, 1) && is_name(*right),
+                BinOp::Sub => is_name(*left) && is_number(*right, 0),
+                BinOp::Div => is_name(*left) && is_number(*right, 1),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+    
+    fn check_infinite_loop(&mut self, label: Option<Symbol>, condition: &Expr, body: &Block, span: Span) {
+        let always_true = eval_const_expr(&self.exprs, condition, &HashMap::new()).is_some_and(|v| v != 0);
+        
+        // Called once the loop is done, so `self.loops` holds the loops
+        // around it
+        let labels: Vec<Symbol> = label.into_iter().chain(self.loops.iter().filter_map(|l| l.label)).collect();
+        if always_true && !Self::loop_has_exit(body, &labels) {
+            self.warnings.push(Warning {
+                message: "infinite loop: condition is always true and the body never breaks or returns"
+                    .to_string(),
+                span: Some(span),
+            });
+        }
+    }
+    
     fn enter_scope(&mut self) {
         self.scopes.push(HashMap::new());
     }
     
-    fn exit_scope(&mut self) {
-        self.scopes.pop();
+    /// Leaves the innermost scope, rejecting calls through its variables
+    /// that can only hold numbers or strings
+    fn exit_scope(&mut self) -> Result<(), CompileError> {
+        let scope = self.scopes.pop().unwrap();
+        let bad_call = scope
+            .values()
+            .filter(|info| !info.may_hold_closure)
+            .filter_map(|info| Some((info.called_at?, info)))
+            .min_by_key(|(span, _)| (span.line, span.column));
+        if let Some((span, info)) = bad_call {
+            return Err(CompileError::at(
+                format!("Cannot call {}: it holds {}, not a closure", info.name, info.ty),
+                span,
+            ));
+        }
+        Ok(())
     }
     
-    fn current_scope(&mut self) -> &mut HashMap<String, VarInfo> {
+    fn current_scope(&mut self) -> &mut HashMap<Symbol, VarInfo> {
         self.scopes.last_mut().unwrap()
     }
     
-    fn declare_variable(&mut self, name: String) {
-        self.current_scope().insert(name.clone(), VarInfo { name });
+    /// The error for declaring `name` at `span` in a scope that already has
+    /// the variable `first`
+    fn already_declared(name: Symbol, span: Span, first: &VarInfo) -> CompileError {
+        CompileError::at(
+            format!(
+                "Variable already declared in this scope: {} (first declared at line {}, column {})",
+                name, first.span.line, first.span.column
+            ),
+            span,
+        )
+    }
+    
+    fn declare_variable(&mut self, name: Symbol, span: Span, ty: ValueType) {
+        let info = VarInfo {
+            name,
+            span,
+            ty,
+            unsigned: false,
+            read: false,
+            assigned: true,
+            may_hold_closure: true,
+            called_at: None,
+        };
+        self.current_scope().insert(name, info);
+    }
+    
+    /// Whether `expr` may evaluate to a closure. Builtins return numbers,
+    /// apart from those that hand back a value stored earlier; any other
+    /// call or loop could return a closure.
+    fn may_hold_closure(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Variable { name, .. } => self.lookup_variable(*name).is_none_or(|info| info.may_hold_closure),
+            Expr::Call { name, .. } => {
+                self.lookup_variable(*name).is_some()
+                    || !Self::is_builtin(name.as_str())
+                    || matches!(name.as_str(), "load" | "map_get" | "args_get")
+            }
+            Expr::Closure { .. } | Expr::Loop { .. } => true,
+            Expr::Assign { value, .. } => self.may_hold_closure(&self.exprs[*value]),
+            Expr::If { then_val, else_val, .. } => {
+                self.may_hold_closure(&self.exprs[*then_val]) || self.may_hold_closure(&self.exprs[*else_val])
+            }
+            _ => false,
+        }
+    }
+    
+    /// Checks that `name` is a variable that can hold a value of type `ty`,
+    /// and marks it assigned, and able to hold a closure if the value may
+    /// be one
+    fn assign_variable(
+        &mut self,
+        name: Symbol,
+        ty: ValueType,
+        may_hold_closure: bool,
+        span: Span,
+    ) -> Result<(), CompileError> {
+        let Some(info) = self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(&name)) else {
+            return Err(self.undefined_variable(name, span));
+        };
+        if !ty.fits(info.ty) {
+            return Err(CompileError::at(
+                format!("Type mismatch: cannot assign {} to {}, which holds {}", ty, name, info.ty),
+                span,
+            ));
+        }
+        info.assigned = true;
+        info.may_hold_closure |= may_hold_closure;
+        Ok(())
+    }
+    
+    /// The variables declared without a value that some path to the code
+    /// being analyzed has not assigned yet, with the depth of their scope
+    fn unassigned(&self) -> Vec<(usize, Symbol)> {
+        let mut vars = Vec::new();
+        for (depth, scope) in self.scopes.iter().enumerate() {
+            vars.extend(scope.values().filter(|info| !info.assigned).map(|info| (depth, info.name)));
+        }
+        vars
+    }
+    
+    /// Marks exactly the variables in `vars`, as returned by `unassigned`,
+    /// as not yet assigned
+    fn set_unassigned(&mut self, vars: &[(usize, Symbol)]) {
+        for (depth, scope) in self.scopes.iter_mut().enumerate() {
+            for info in scope.values_mut() {
+                info.assigned = !vars.contains(&(depth, info.name));
+            }
+        }
+    }
+    
+    /// Whether `left op right` has a `u64` operand but is still signed,
+    /// because the other operand may be negative. Only division, remainder
+    /// and ordering comparisons give a different result.
+    fn mixes_signedness(&self, op: BinOp, left: &Expr, right: &Expr) -> bool {
+        if !matches!(op, BinOp::Div | BinOp::Mod | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge) {
+            return false;
+        }
+        let is_unsigned_var = |name| self.lookup_variable(name).is_some_and(|info| info.unsigned);
+        let unsigned = |expr: &Expr| expr.is_unsigned(&self.exprs, &is_unsigned_var);
+        (unsigned(left) || unsigned(right)) && !unsigned_operands(&self.exprs, left, right, &is_unsigned_var)
+    }
+    
+    /// The innermost variable named `name`
+    fn lookup_variable(&self, name: Symbol) -> Option<&VarInfo> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(&name))
+    }
+    
+    /// The innermost variable named `name`, marked as read
+    fn read_variable(&mut self, name: Symbol) -> Option<&VarInfo> {
+        let info = self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(&name))?;
+        info.read = true;
+        Some(info)
     }
     
-    fn is_variable_declared(&self, name: &str) -> bool {
-        for scope in self.scopes.iter().rev() {
-            if scope.contains_key(name) {
-                return true;
+    fn is_variable_declared(&self, name: Symbol) -> bool {
+        self.lookup_variable(name).is_some()
+    }
+    
+    /// The error for using `name` where no such variable is in scope,
+    /// which inside a default value is any variable at all
+    fn undefined_variable(&self, name: Symbol, span: Span) -> CompileError {
+        match self.default_of {
+            Some(param) => CompileError::at(
+                format!("Default value of parameter {} cannot refer to variables, found {}", param, name),
+                span,
+            ),
+            None => CompileError::at(format!("Undefined variable: {}", name), span),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    
+    fn analyze(source: &str) -> (Result<(), String>, Vec<Warning>) {
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+        let mut analyzer = SemanticAnalyzer::new();
+        let result = analyzer.analyze(&program).map_err(|e| e.describe());
+        (result, analyzer.warnings().to_vec())
+    }
+    
+    #[test]
+    fn test_all_paths_return() {
+        let source = r#"
+            func sign(n) {
+                if n < 0 {
+                    return -1;
+                } else {
+                    if n == 0 {
+                        return 0;
+                    } else {
+                        return 1;
+                    }
+                }
+            }
+            
+            func main() {
+                print(sign(3));
             }
+        "#;
+        
+        let (result, warnings) = analyze(source);
+        assert!(result.is_ok());
+        assert!(warnings.is_empty(), "unexpected warnings: {:?}", warnings);
+    }
+    
+    #[test]
+    fn test_missing_return_on_some_path() {
+        let source = r#"
+            func f(n) {
+                if n > 0 {
+                    return 1;
+                }
+            }
+            
+            func main() {
+                return f(1);
+            }
+        "#;
+        
+        let (result, warnings) = analyze(source);
+        assert!(result.is_ok());
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].message.contains("Function f may reach the end"));
+        
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+        let mut analyzer = SemanticAnalyzer::new();
+        analyzer.set_missing_return_error(true);
+        assert!(analyzer.analyze(&program).is_err());
+    }
+    
+    #[test]
+    fn test_unreachable_after_return() {
+        let source = "func main() {\n    return 1;\n    print(2);\n    print(3);\n}";
+        
+        let (result, warnings) = analyze(source);
+        assert!(result.is_ok());
+        assert_eq!(
+            warnings,
+            vec![Warning {
+                message: "unreachable code after return".to_string(),
+                span: Some(Span { line: 3, column: 5 }),
+            }]
+        );
+        
+        // A builtin that never returns is named as what ends the block
+        for builtin in ["exit(1)", "panic(\"stop\")", "todo()"] {
+            let source = format!("func main() {{\n    {};\n    return 2;\n}}", builtin);
+            let name = &builtin[..builtin.find('(').unwrap()];
+            
+            let (result, warnings) = analyze(&source);
+            assert!(result.is_ok());
+            assert_eq!(
+                warnings,
+                vec![Warning {
+                    message: format!("unreachable code after call to {}", name),
+                    span: Some(Span { line: 3, column: 5 }),
+                }]
+            );
         }
-        false
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_panic_message() {
+        // Like exit, panic ends the function
+        let (result, _) = analyze(r#"func main() { let x = 1; if x > 0 { return x; } panic("bad state"); }"#);
+        assert_eq!(result, Ok(()));
+        
+        let (result, _) = analyze("func main() { panic(1); }");
+        let expected = "Type mismatch: panic() takes a string message, not int at line 1, column 15";
+        assert_eq!(result, Err(expected.to_string()));
+        
+        let (result, _) = analyze(r#"func main() { panic("a", "b"); }"#);
+        assert_eq!(result, Err("panic() requires exactly 1 argument at line 1, column 15".to_string()));
+    }
+    
+    #[test]
+    fn test_todo_fits_anywhere() {
+        let source = r#"
+            func later() {
+                todo();
+            }
+            
+            func main() {
+                let s = "a";
+                s = s + unimplemented();
+                let n = -todo() + 1;
+                let m = todo();
+                m = todo() as i8;
+                print(todo(), later());
+                return n + m;
+            }
+        "#;
+        let (result, _) = analyze(source);
+        assert_eq!(result, Ok(()));
+        
+        let (result, _) = analyze("func main() { let n = todo(1); return n; }");
+        assert_eq!(result, Err("todo() requires exactly 0 arguments at line 1, column 23".to_string()));
+    }
+    
+    #[test]
+    fn test_self_assignment_warning() {
+        let source = "func main() {\n    let x = 1;\n    let y = 2;\n    x = x;\n    x = y;\n    y = 0 + y;\n    y = y * 2;\n    return x + y;\n}";
+        
+        let (result, warnings) = analyze(source);
+        assert!(result.is_ok());
+        assert_eq!(
+            warnings,
+            vec![
+                Warning {
+                    message: "self-assignment has no effect".to_string(),
+                    span: Some(Span { line: 4, column: 5 }),
+                },
+                Warning {
+                    message: "self-assignment has no effect".to_string(),
+                    span: Some(Span { line: 6, column: 5 }),
+                },
+            ]
+        );
+    }
+    
+    #[test]
+    fn test_mixed_signedness_warning() {
+        let source = "func main() {\n    let u: u64 = 7;\n    let s = -1;\n    let a = u / 2 + u % (s as u64);\n    let b = u < s;\n    return a + b + u * s;\n}";
+        
+        let (result, warnings) = analyze(source);
+        assert!(result.is_ok());
+        assert_eq!(
+            warnings,
+            vec![Warning {
+                message: "< of a u64 and a signed value is signed; cast the signed operand with `as u64` for an unsigned <"
+                    .to_string(),
+                span: None,
+            }]
+        );
+        
+        let (result, _) = analyze("func main() {\n    let f: u64 = || 1;\n    return 0;\n}");
+        assert_eq!(
+            result,
+            Err("Type mismatch: cannot declare f as u64, it holds closure at line 2, column 5".to_string())
+        );
+    }
+    
+    #[test]
+    fn test_unused_parameter_warning() {
+        let (result, warnings) = analyze("func f(x) { return 0; }\nfunc main() { return f(1); }");
+        assert!(result.is_ok());
+        assert_eq!(
+            warnings,
+            vec![Warning {
+                message: "parameter x of function f is never read".to_string(),
+                span: Some(Span { line: 1, column: 8 }),
+            }]
+        );
+        
+        // Reads anywhere count, including through a closure or by calling
+        // the closure a parameter holds; `_` marks a deliberately unused one
+        let source = r#"
+            func f(x) { return x; }
+            func g(a, b, _c) {
+                let h = || a;
+                return h() + b(1);
+            }
+            func main() { return f(1) + g(1, |n| n, 3); }
+        "#;
+        let (result, warnings) = analyze(source);
+        assert!(result.is_ok());
+        assert_eq!(warnings, vec![]);
+    }
+    
+    #[test]
+    fn test_parameter_shadowing_function_warning() {
+        let source = "func step(n) { return n + 1; }\nfunc apply(step, n) {\n    return step + n;\n}\nfunc main() { return apply(1, 2); }";
+        let (result, warnings) = analyze(source);
+        assert!(result.is_ok());
+        assert_eq!(
+            warnings,
+            vec![Warning {
+                message: "parameter step of function apply shadows the function step".to_string(),
+                span: Some(Span { line: 2, column: 12 }),
+            }]
+        );
+    }
+    
+    #[test]
+    fn test_string_type_errors() {
+        let (result, _) = analyze(r#"func main() { let s = "a" + to_str(1); print(s); return 0; }"#);
+        assert_eq!(result, Ok(()));
+        
+        let (result, _) = analyze(r#"func main() { let s = "a" + 1; return 0; }"#);
+        assert_eq!(result, Err("Type mismatch: cannot apply + to string and int at line 1, column 15".to_string()));
+        
+        // Strings don't pass through anything that takes integers. The
+        // error points at the statement or call they are passed to.
+        let not_int = "Type mismatch: strings can only be stored in variables, joined with + and printed";
+        let bodies = [
+            (r#"return "a";"#, 15),
+            (r#"if "a" { return 1; }"#, 15),
+            (r#"return abs("a");"#, 22),
+            (r#"return f("a");"#, 22),
+        ];
+        for (body, column) in bodies {
+            let source = format!("func f(x) {{ return x; }}\nfunc main() {{ {} return 0; }}", body);
+            let (result, _) = analyze(&source);
+            assert_eq!(result, Err(format!("{} at line 2, column {}", not_int, column)), "{}", body);
+        }
+    }
+    
+    #[test]
+    fn test_definite_assignment() {
+        let both = r#"
+            func main(argc) {
+                let x;
+                if argc > 1 {
+                    x = 1;
+                } else {
+                    x = 2;
+                }
+                return x;
+            }
+        "#;
+        assert_eq!(analyze(both).0, Ok(()));
+        
+        let one = r#"
+            func main(argc) {
+                let x;
+                if argc > 1 {
+                    x = 1;
+                }
+                return x;
+            }
+        "#;
+        assert_eq!(
+            analyze(one).0,
+            Err("Variable x may be read before it is assigned at line 7, column 24".to_string())
+        );
+        
+        // Calling the closure a variable will hold reads it too
+        let (result, _) = analyze("func main() { let f; return f(1); }");
+        assert_eq!(result, Err("Variable f may be read before it is assigned at line 1, column 29".to_string()));
+        
+        // A branch that returns doesn't reach the read, and a loop body may
+        // not run, except a `do` body without `break` or `continue`
+        let cases = [
+            ("if argc { x = 1; } else { return 0; } return x;", true),
+            ("while argc < 3 { x = 1; argc = argc + 1; } return x;", false),
+            ("for i in 0..argc { x = i; } return x;", false),
+            ("do { x = argc; } while x > 5; return x;", true),
+            ("do { if argc { continue; } x = 1; } while x; return 0;", false),
+            ("let f = || x; x = 1; return 0;", false),
+            // A `loop` expression is only left through a `break`
+            ("loop { x = 3; break; } return x;", true),
+            ("let y = loop { if argc { break 0; } x = 1; break 1; }; return x;", false),
+            ("outer: loop { loop { x = 1; break outer; } } return x;", true),
+            ("loop { if argc { x = 1; break; } x = 2; break; } return x;", true),
+            ("x = x + 1; return 0;", false),
+        ];
+        for (body, ok) in cases {
+            let source = format!("func main(argc) {{ let x; {} }}", body);
+            assert_eq!(analyze(&source).0.is_ok(), ok, "{}", body);
+        }
+    }
+    
+    #[test]
+    fn test_multi_assign_errors() {
+        let (result, _) = analyze("func main() { let a = 1; let b = 2; a, b = b, a; return a; }");
+        assert_eq!(result, Ok(()));
+        
+        let (result, _) = analyze("func main() { let a = 1; let b = 2; a, b = 1, 2, 3; return a; }");
+        assert_eq!(result, Err("Cannot assign 3 values to 2 names at line 1, column 37".to_string()));
+        
+        let (result, _) = analyze("func main() { let a = 1; a, c = 2, 3; return a; }");
+        assert_eq!(result, Err("Undefined variable: c at line 1, column 26".to_string()));
+        
+        let (result, _) = analyze("func main() { let a = 1; a, a = 2, 3; return a; }");
+        assert_eq!(
+            result,
+            Err("Variable assigned twice in one assignment: a at line 1, column 26".to_string())
+        );
+    }
+    
+    #[test]
+    fn test_chained_assignment_targets() {
+        let (result, _) = analyze("func main() { let a; let b; a = b = 7; return a + b; }");
+        assert_eq!(result, Ok(()));
+        
+        let (result, _) = analyze("func main() { let a = 0; a = c = 7; return a; }");
+        assert_eq!(result, Err("Undefined variable: c at line 1, column 30".to_string()));
+        
+        let (result, _) = analyze(r#"func main() { let a = 0; let s = ""; a = s = "x"; return a; }"#);
+        assert_eq!(
+            result,
+            Err("Type mismatch: cannot assign string to a, which holds int at line 1, column 38".to_string())
+        );
+    }
+    
+    #[test]
+    fn test_if_expression_branch_types() {
+        let (result, _) = analyze(r#"func main() { let s = if 1 { "a" } else { "b" }; print(s); return 0; }"#);
+        assert_eq!(result, Ok(()));
+        
+        // `todo()` fits the other branch's type
+        let (result, _) = analyze(r#"func main() { let s = if 1 { "a" } else { todo() }; print(s); return 0; }"#);
+        assert_eq!(result, Ok(()));
+        
+        let (result, _) = analyze(r#"func main() { let s = if 1 { "a" } else { 0 }; return 0; }"#);
+        assert_eq!(
+            result,
+            Err("Type mismatch: if branches have different types, string and int at line 1, column 15".to_string())
+        );
+        
+        // Assigned in only one branch, so maybe not assigned after it
+        let (result, _) = analyze("func main() { let a; let b = if 1 { a = 1 } else { 2 }; return a + b; }");
+        assert_eq!(result, Err("Variable a may be read before it is assigned at line 1, column 64".to_string()));
+    }
+    
+    #[test]
+    fn test_range_outside_for_in() {
+        let (result, _) = analyze("func main() { for i in 0..3 { print(i); } return 0; }");
+        assert_eq!(result, Ok(()));
+        
+        let (result, _) = analyze("func main() { let r = 0..3; return 0; }");
+        assert_eq!(result, Err("Ranges can only be iterated over with for ... in at line 1, column 15".to_string()));
+        
+        // The loop variable is gone after the loop
+        let (result, _) = analyze("func main() { for i in 0..3 { print(i); } return i; }");
+        assert!(result.unwrap_err().starts_with("Undefined variable: i"));
+    }
+    
+    #[test]
+    fn test_no_warnings_for_normal_program() {
+        let source = r#"
+            func main() {
+                let i = 0;
+                while i < 3 {
+                    if i == 1 {
+                        return i;
+                    }
+                    i = i + 1;
+                }
+                return 0;
+            }
+        "#;
+        
+        let (result, warnings) = analyze(source);
+        assert!(result.is_ok());
+        assert!(warnings.is_empty(), "unexpected warnings: {:?}", warnings);
+    }
+    
+    #[test]
+    fn test_nested_function_cannot_capture_locals() {
+        let source = r#"
+            func main() {
+                let x = 1;
+                func helper() {
+                    return x;
+                }
+                return helper();
+            }
+        "#;
+        
+        let (result, _) = analyze(source);
+        assert_eq!(result, Err("Undefined variable: x at line 5, column 28".to_string()));
+    }
+    
+    #[test]
+    fn test_duplicate_nested_function() {
+        let source = r#"
+            func helper() {
+                return 1;
+            }
+            
+            func main() {
+                func helper() {
+                    return 2;
+                }
+                return helper();
+            }
+        "#;
+        
+        let (result, _) = analyze(source);
+        assert_eq!(
+            result,
+            Err("Duplicate function definition: helper (first defined at line 2, column 13) at line 7, column 17".to_string())
+        );
+    }
+    
+    #[test]
+    fn test_redeclaration_reports_both_locations() {
+        let source = r#"
+            func main() {
+                let total = 1;
+                if total {
+                    let total = 2;
+                }
+                let total = 3;
+                return total;
+            }
+        "#;
+        
+        let (result, _) = analyze(source);
+        assert_eq!(
+            result,
+            Err("Variable already declared in this scope: total (first declared at line 3, column 17) at line 7, column 17".to_string())
+        );
+        
+        // Parameters are declared where their names are written
+        let (result, _) = analyze("func f(a, b) { let b = 1; return a + b; }\nfunc main() { return f(1, 2); }");
+        assert_eq!(
+            result,
+            Err("Variable already declared in this scope: b (first declared at line 1, column 11) at line 1, column 16".to_string())
+        );
+        let (result, _) = analyze("func main() { let f = |x, x| x; return f(1, 2); }");
+        assert_eq!(result, Err("Duplicate parameter name: x at line 1, column 27".to_string()));
+    }
+    
+    #[test]
+    fn test_duplicate_function_reports_both_locations() {
+        let source = "func foo() { return 1; }\n\nfunc main() { return foo(); }\n  func foo() { return 2; }";
+        
+        let (result, _) = analyze(source);
+        assert_eq!(
+            result,
+            Err("Duplicate function definition: foo (first defined at line 1, column 1) at line 4, column 3".to_string())
+        );
+    }
+    
+    #[test]
+    fn test_mutual_recursion() {
+        let source = r#"
+            func is_even(n) {
+                if n == 0 {
+                    return 1;
+                }
+                return is_odd(n - 1);
+            }
+            
+            func is_odd(n) {
+                if n == 0 {
+                    return 0;
+                }
+                return is_even(n - 1);
+            }
+            
+            func main() {
+                return is_even(10);
+            }
+        "#;
+        
+        let (result, warnings) = analyze(source);
+        assert_eq!(result, Ok(()));
+        assert!(warnings.is_empty());
+    }
+    
+    #[test]
+    fn test_function_table_is_keyed_by_symbol() {
+        // Long names cost no more to look up than short ones: the table
+        // only stores 4-byte symbols
+        let long_name = format!("helper_{}", "x".repeat(200));
+        let source = format!(
+            "func {0}(a) {{ return a; }} func main() {{ return {0}(1); }}",
+            long_name
+        );
+        let program = crate::parse(&source).unwrap();
+        
+        let mut analyzer = SemanticAnalyzer::new();
+        analyzer.analyze(&program).unwrap();
+        
+        let key: &Symbol = analyzer.functions.keys().find(|k| **k == long_name.as_str()).unwrap();
+        assert_eq!(std::mem::size_of_val(key), 4);
+        assert_eq!(*key, Symbol::intern(&long_name));
+        assert_eq!(analyzer.functions[key].param_count, 1);
+    }
+    
+    #[test]
+    fn test_tail_expression_counts_as_return() {
+        let source = r#"
+            func f(x) {
+                if x {
+                    return 1;
+                }
+                x + 2
+            }
+            
+            func main() {
+                return 0;
+                f(1)
+            }
+        "#;
+        
+        let (result, warnings) = analyze(source);
+        assert!(result.is_ok());
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].message, "unreachable code after return");
+    }
+    
+    #[test]
+    fn test_infinite_loop_warning() {
+        let source = r#"
+            func main() {
+                let x = 0;
+                while 1 {
+                    x = x + 1;
+                }
+                return x;
+            }
+        "#;
+        
+        let (result, warnings) = analyze(source);
+        assert!(result.is_ok());
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].message.starts_with("infinite loop"));
+        assert_eq!(warnings[0].span, Some(Span { line: 4, column: 17 }));
+    }
+    
+    #[test]
+    fn test_loop_with_break_is_not_infinite() {
+        let source = r#"
+            func main() {
+                let x = 0;
+                while 1 {
+                    if x > 5 {
+                        break;
+                    }
+                    x = x + 1;
+                }
+                while 2 - 1 {
+                    return x;
+                }
+                return x;
+            }
+        "#;
+        
+        let (result, warnings) = analyze(source);
+        assert!(result.is_ok());
+        assert!(warnings.is_empty(), "{:?}", warnings);
+    }
+    
+    #[test]
+    fn test_break_in_inner_loop_does_not_end_outer() {
+        let source = r#"
+            func main() {
+                do {
+                    while 1 {
+                        break;
+                    }
+                } while 3 > 2;
+                return 0;
+            }
+        "#;
+        
+        let (result, warnings) = analyze(source);
+        assert!(result.is_ok());
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].span, Some(Span { line: 3, column: 17 }));
+    }
+    
+    #[test]
+    fn test_break_outside_loop() {
+        let (result, _) = analyze("func main() { break; return 0; }");
+        assert_eq!(result, Err("break outside of a loop at line 1, column 15".to_string()));
+        
+        let (result, _) = analyze("func main() { if 1 { continue; } return 0; }");
+        assert_eq!(result, Err("continue outside of a loop at line 1, column 22".to_string()));
+    }
+    
+    #[test]
+    fn test_loop_labels() {
+        let (result, _) = analyze("func main() { while 1 { break outer; } return 0; }");
+        assert_eq!(result, Err("Unknown loop label: outer at line 1, column 25".to_string()));
+        
+        let (result, _) = analyze("func main() { a: while 1 { a: do { break a; } while 1; } return 0; }");
+        assert_eq!(
+            result,
+            Err("Loop label a is already used by an enclosing loop at line 1, column 28".to_string())
+        );
+        
+        // A label can be reused once its loop has ended
+        let (result, _) = analyze("func main() { a: while 0 { } a: while 0 { continue a; } return 0; }");
+        assert_eq!(result, Ok(()));
+        
+        let (result, _) = analyze("func main() { continue a; return 0; }");
+        assert_eq!(result, Err("continue outside of a loop at line 1, column 15".to_string()));
+    }
+    
+    #[test]
+    fn test_labeled_break_ends_outer_loop() {
+        let source = r#"
+            func main() {
+                outer: while 1 {
+                    do {
+                        while 1 {
+                            break outer;
+                        }
+                    } while 1;
+                }
+                return 0;
+            }
+        "#;
+        
+        let (result, warnings) = analyze(source);
+        assert!(result.is_ok());
+        assert!(warnings.is_empty(), "{:?}", warnings);
+    }
+    
+    #[test]
+    fn test_break_values() {
+        let (result, _) = analyze("func main() { let x = 1; while 1 { break x; } return 0; }");
+        assert_eq!(
+            result,
+            Err("break with a value is only allowed inside loop at line 1, column 36".to_string())
+        );
+        
+        // The value goes to the loop the label names
+        let (result, _) = analyze("func main() { a: while 1 { let x = loop { break a 1; }; } return 0; }");
+        assert_eq!(
+            result,
+            Err("break with a value is only allowed inside loop at line 1, column 43".to_string())
+        );
+        let (result, _) = analyze("func main() { return a: loop { while 1 { break a 1; } }; }");
+        assert_eq!(result, Ok(()));
+        
+        let (result, _) = analyze("func main() { return loop { break || 1; }; }");
+        assert_eq!(
+            result,
+            Err("Type mismatch: break value must be int, found closure at line 1, column 29".to_string())
+        );
+        
+        // Loops around a closure don't enclose its body
+        let (result, _) = analyze("func main() { return loop { let f = || loop { break; }; break 1; }; }");
+        assert_eq!(result, Ok(()));
+        let (result, _) = analyze("func main() { return a: loop { let f = || loop { break a; }; break 1; }; }");
+        assert_eq!(result, Err("Unknown loop label: a at line 1, column 50".to_string()));
+        
+        let (result, _) = analyze("func main() { return loop { func f() { return 1; } break f(); }; }");
+        assert_eq!(
+            result,
+            Err("Functions cannot be declared inside a loop expression at line 1, column 29".to_string())
+        );
+    }
+    
+    #[test]
+    fn test_builtin_names_are_reserved() {
+        let (result, _) = analyze("func print(x) { return x; }\nfunc main() { return print(1); }");
+        assert_eq!(
+            result,
+            Err("Function name print is reserved for a builtin at line 1, column 1".to_string())
+        );
+        
+        let (result, _) = analyze("func main() {\n    func max(a, b) { return a; }\n    return max(1, 2);\n}");
+        assert_eq!(
+            result,
+            Err("Function name max is reserved for a builtin at line 2, column 5".to_string())
+        );
+        
+        let (result, _) = analyze("func maximum(a, b) { return a; }\nfunc main() { return maximum(1, 2); }");
+        assert_eq!(result, Ok(()));
+    }
+    
+    #[test]
+    fn test_destructure_checks() {
+        let (result, _) = analyze("func main() { let (a, b) = [1, 2, 3]; return a; }");
+        assert_eq!(
+            result,
+            Err("Cannot destructure 3 elements into 2 names at line 1, column 15".to_string())
+        );
+        
+        let (result, _) = analyze("func main() { let (a, a) = [1, 2]; return a; }");
+        assert_eq!(
+            result,
+            Err("Variable bound twice in destructuring: a at line 1, column 15".to_string())
+        );
+        
+        let (result, _) = analyze("func main() { let (a, b) = 7; return a; }");
+        assert_eq!(
+            result,
+            Err("Can only destructure an array literal at line 1, column 15".to_string())
+        );
+        
+        let (result, _) = analyze("func main() { let a = [1, 2]; return a; }");
+        assert_eq!(
+            result,
+            Err("Array literals can only be destructured with let (...) at line 1, column 15".to_string())
+        );
+    }
+    
+    #[test]
+    fn test_len_takes_an_array() {
+        let (result, _) = analyze("func main() { return len([1, 2, 3]); }");
+        assert_eq!(result, Ok(()));
+        
+        let (result, _) = analyze("func main() { let a = 3; return len(a); }");
+        assert_eq!(result, Err("Type mismatch: len() takes an array literal, not int at line 1, column 33".to_string()));
+        
+        let (result, _) = analyze(r#"func main() { return len("abc"); }"#);
+        assert_eq!(result, Err("Type mismatch: len() takes an array literal, not string at line 1, column 22".to_string()));
+        
+        let (result, _) = analyze("func main() { return len(); }");
+        assert_eq!(result, Err("len() requires exactly 1 argument at line 1, column 22".to_string()));
+        
+        let (result, _) = analyze(r#"func main() { return len([1, "a"]); }"#);
+        assert_eq!(
+            result,
+            Err("Type mismatch: len() takes an array of integers, not one holding string at line 1, column 22".to_string())
+        );
+        
+        let (result, _) = analyze("func main() { return len([1, || 2]); }");
+        assert_eq!(
+            result,
+            Err("Type mismatch: len() takes an array of integers, not one holding closure at line 1, column 22".to_string())
+        );
+    }
+    
+    #[test]
+    fn test_static_checks() {
+        let (result, _) = analyze("func main() { static n = 2 * 3; n = n + 1; return n; }");
+        assert!(result.is_ok());
+        
+        let (result, _) = analyze("func main() { let x = 1; static n = x; return n; }");
+        assert_eq!(
+            result,
+            Err("Static initializer must be a constant expression at line 1, column 26".to_string())
+        );
+        
+        let (result, _) = analyze("func main() { let n = 1; static n = 2; return n; }");
+        assert!(result.unwrap_err().starts_with("Variable already declared in this scope: n"));
+    }
+    
+    #[test]
+    fn test_default_parameters() {
+        let (result, _) = analyze("func f(a, b = 2) { return a + b; } func main() { return f(1) + f(1, 2); }");
+        assert!(result.is_ok(), "{:?}", result);
+        
+        let (result, _) = analyze("func f(a = 1, b) { return a + b; } func main() { return f(1, 2); }");
+        assert_eq!(
+            result,
+            Err("Parameter b of function f needs a default value because an earlier parameter has one at line 1, column 1".to_string())
+        );
+        
+        let (result, _) = analyze("func f(a, b = 2) { return a + b; } func main() { return f(); }");
+        assert_eq!(result, Err("Function f expects 1 to 2 arguments, got 0 at line 1, column 57".to_string()));
+        
+        let (result, _) = analyze("func f(a, b = 2) { return a + b; } func main() { return f(1, 2, 3); }");
+        assert_eq!(result, Err("Function f expects 1 to 2 arguments, got 3 at line 1, column 57".to_string()));
+        
+        let (result, _) = analyze("func f(a, b = a) { return a + b; } func main() { return f(1); }");
+        assert_eq!(
+            result,
+            Err("Default value of parameter b cannot refer to variables, found a at line 1, column 15".to_string())
+        );
+        
+        let (result, _) = analyze("func f(a, b = (a = 2)) { return a + b; } func main() { return f(1); }");
+        assert_eq!(
+            result,
+            Err("Default value of parameter b cannot refer to variables, found a at line 1, column 16".to_string())
+        );
+    }
+    
+    #[test]
+    fn test_variadic_functions() {
+        let (result, _) = analyze("func f(a, b...) { return a + args_count(); } func main() { return f(1) + f(1, 2, 3); }");
+        assert!(result.is_ok(), "{:?}", result);
+        
+        let (result, _) = analyze("func f(a, b...) { return a; } func main() { return f(); }");
+        assert_eq!(result, Err("Function f expects at least 1 arguments, got 0 at line 1, column 52".to_string()));
+        
+        let (result, _) = analyze("func main() { return args_get(0); }");
+        assert_eq!(
+            result,
+            Err("args_get() can only be used in a function with a variadic parameter at line 1, column 22".to_string())
+        );
+        
+        let (result, _) = analyze("func f(a...) { let g = || args_count(); return g(); } func main() { return f(); }");
+        assert_eq!(
+            result,
+            Err("args_count() can only be used in a function with a variadic parameter at line 1, column 27".to_string())
+        );
+        
+        let (result, _) = analyze("func main(args...) { return 0; }");
+        assert_eq!(
+            result,
+            Err("main function must have no parameters or a single argc parameter at line 1, column 1".to_string())
+        );
+    }
+    
+    #[test]
+    fn test_type_inference() {
+        let (result, _) = analyze(
+            "func apply(f, x) { return f(x); }
+             func main() {
+                 let k = 3;
+                 let (scale, n) = [|x| x * k, -k];
+                 let g = scale;
+                 g = |x| x + 1;
+                 return apply(g, n) + scale(2) as i8;
+             }",
+        );
+        assert!(result.is_ok(), "{:?}", result);
+        
+        let (result, _) = analyze("func main() { let f = |x| x; return f + 1; }");
+        assert_eq!(result, Err("Type mismatch: cannot apply + to closure and int at line 1, column 30".to_string()));
+        
+        let (result, _) = analyze("func main() { let (a, b) = [[1] == 2, 3]; return a; }");
+        assert_eq!(result, Err("Type mismatch: cannot apply == to array and int at line 1, column 15".to_string()));
+        
+        let (result, _) = analyze("func main() { let f = || 1; return !f; }");
+        assert_eq!(result, Err("Type mismatch: cannot apply ! to closure at line 1, column 29".to_string()));
+        
+        let (result, _) = analyze("func main() { let n = 0; n = |x| x; return n; }");
+        assert_eq!(
+            result,
+            Err("Type mismatch: cannot assign closure to n, which holds int at line 1, column 26".to_string())
+        );
+    }
+    
+    #[test]
+    fn test_calls_through_variables() {
+        let (result, _) = analyze("func main() { let x = 5; return x(1); }");
+        assert_eq!(
+            result,
+            Err("Cannot call x: it holds int, not a closure at line 1, column 33".to_string())
+        );
+        
+        let (result, _) = analyze("func main() { let f = |a| a + 1; let x = 0; return x(41); }");
+        assert_eq!(
+            result,
+            Err("Cannot call x: it holds int, not a closure at line 1, column 52".to_string())
+        );
+        
+        let (result, _) = analyze(r#"func main() { let s = "a"; for i in 0..2 { s(i); } return 0; }"#);
+        assert_eq!(
+            result,
+            Err("Cannot call s: it holds string, not a closure at line 1, column 44".to_string())
+        );
+        
+        // Calls and loads may return closures, and an assignment later in
+        // a loop can still reach an earlier call
+        let (result, _) = analyze(
+            "func make(n) { return |m| n * m; }
+             func main() {
+                 let triple = make(3);
+                 let g = load(alloc(8));
+                 let h = 0;
+                 let i = 0;
+                 while i < 2 {
+                     if i == 1 { print(h(1)); }
+                     h = make(i);
+                     i = i + 1;
+                 }
+                 return triple(7) + g(1);
+             }",
+        );
+        assert!(result.is_ok(), "{:?}", result);
+    }
+}
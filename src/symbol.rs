@@ -0,0 +1,130 @@
+//! Interned identifiers.
+//!
+//! Every identifier is stored once in a process-wide `Interner` and passed
+//! around as a `Symbol`, a 4-byte index that is cheap to copy, compare and
+//! hash. The interned strings live for the rest of the process.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{LazyLock, RwLock};
+
+/// An interned identifier
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+/// Maps identifier strings to symbols and back
+#[derive(Default)]
+pub struct Interner {
+    ids: HashMap<&'static str, Symbol>,
+    names: Vec<&'static str>,
+}
+
+static INTERNER: LazyLock<RwLock<Interner>> = LazyLock::new(|| RwLock::new(Interner::default()));
+
+impl Interner {
+    fn intern(&mut self, name: &str) -> Symbol {
+        // Another thread may have interned it since the caller looked
+        if let Some(&symbol) = self.ids.get(name) {
+            return symbol;
+        }
+        
+        let symbol = Symbol(self.names.len() as u32);
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        self.names.push(name);
+        self.ids.insert(name, symbol);
+        symbol
+    }
+}
+
+impl Symbol {
+    /// The symbol for `name`, interning it on first use.
+    ///
+    /// Interning a new name leaks a copy of it, which is never freed, so
+    /// that `as_str` can hand out `&'static str`. Memory grows with the
+    /// number of distinct names a process sees, which stays small for
+    /// source identifiers but not for arbitrary strings.
+    ///
+    /// Names already interned are found under a shared read lock; only a
+    /// new name takes the write lock.
+    pub fn intern(name: &str) -> Symbol {
+        if let Some(&symbol) = INTERNER.read().unwrap().ids.get(name) {
+            return symbol;
+        }
+        INTERNER.write().unwrap().intern(name)
+    }
+    
+    pub fn as_str(self) -> &'static str {
+        INTERNER.read().unwrap().names[self.0 as usize]
+    }
+    
+    /// The raw index of this symbol in the interner
+    pub fn as_u32(self) -> u32 {
+        self.0
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Symbol {
+        Symbol::intern(name)
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_interning_is_stable() {
+        let a = Symbol::intern("counter");
+        let b = Symbol::intern(&String::from("counter"));
+        let c = Symbol::intern("count");
+        
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_eq!(a.as_str(), "counter");
+        assert_eq!(c.to_string(), "count");
+        assert!(a == "counter");
+    }
+    
+    #[test]
+    fn test_symbols_are_integer_keys() {
+        assert_eq!(std::mem::size_of::<Symbol>(), 4);
+        
+        // Interning many names hands out dense indices and finds them again
+        let symbols: Vec<Symbol> = (0..10_000)
+            .map(|i| Symbol::intern(&format!("name_{}", i)))
+            .collect();
+        for (i, symbol) in symbols.iter().enumerate() {
+            assert_eq!(Symbol::intern(&format!("name_{}", i)), *symbol);
+        }
+        
+        let mut indices: Vec<u32> = symbols.iter().map(|s| s.as_u32()).collect();
+        indices.sort();
+        indices.dedup();
+        assert_eq!(indices.len(), symbols.len());
+    }
+}
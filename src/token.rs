@@ -1,22 +1,40 @@
+use crate::symbol::Symbol;
+
 /// Token types for the Edust language
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {
     // Literals
     Number(i64),
-    Ident(String),
+    /// `2.5`, `.5`, `5.` or `1e9`. The lexer reads them, but values are
+    /// integers, so the parser rejects them.
+    Float(f64),
+    Ident(Symbol),
+    Str(String),
     
     // Keywords
     Func,
     Let,
+    Static,
     If,
     Else,
     While,
+    Do,
+    For,
+    In,
     Return,
+    Break,
+    Continue,
+    As,
+    Loop,
+    Import,
     
     // Operators
     Plus,       // +
     Minus,      // -
+    PlusPlus,   // ++
+    MinusMinus, // --
     Star,       // *
+    StarStar,   // **
     Slash,      // /
     Percent,    // %
     
@@ -31,6 +49,7 @@ pub enum TokenType {
     // Logical
     And,        // &&
     Or,         // ||
+    Pipe,       // |
     Bang,       // !
     
     // Assignment
@@ -41,13 +60,46 @@ pub enum TokenType {
     RParen,     // )
     LBrace,     // {
     RBrace,     // }
+    LBracket,   // [
+    RBracket,   // ]
     Comma,      // ,
+    Colon,      // :
     Semicolon,  // ;
+    Ellipsis,   // ...
+    DotDot,     // ..
     
     // Special
     Eof,
 }
 
+/// Every keyword and the token it lexes to. The lexer and
+/// `TokenType::keyword` both read this, so a keyword is added in one place.
+pub const KEYWORDS: &[(&str, TokenType)] = &[
+    ("func", TokenType::Func),
+    ("let", TokenType::Let),
+    ("static", TokenType::Static),
+    ("if", TokenType::If),
+    ("else", TokenType::Else),
+    ("while", TokenType::While),
+    ("do", TokenType::Do),
+    ("for", TokenType::For),
+    ("in", TokenType::In),
+    ("return", TokenType::Return),
+    ("break", TokenType::Break),
+    ("continue", TokenType::Continue),
+    ("as", TokenType::As),
+    ("loop", TokenType::Loop),
+    ("import", TokenType::Import),
+];
+
+impl TokenType {
+    /// The word a keyword token is spelled with, or `None` for any other
+    /// token
+    pub fn keyword(&self) -> Option<&'static str> {
+        KEYWORDS.iter().find(|(_, typ)| typ == self).map(|(word, _)| *word)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Token {
     pub typ: TokenType,
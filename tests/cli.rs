@@ -0,0 +1,382 @@
+//! End-to-end tests that run the `edustc` binary as a subprocess
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+/// Writes `source` to a temporary file and runs `edustc` on it
+fn run_program(name: &str, source: &str) -> Output {
+    run_program_with_args(name, source, &[])
+}
+
+/// Like `run_program`, passing extra command-line arguments after the file
+fn run_program_with_args(name: &str, source: &str, args: &[&str]) -> Output {
+    run_program_with_options(name, source, &[], args)
+}
+
+/// Like `run_program_with_args`, also passing compiler options before the file
+fn run_program_with_options(name: &str, source: &str, options: &[&str], args: &[&str]) -> Output {
+    let path: PathBuf = std::env::temp_dir().join(format!("edust_cli_{}_{}.edust", name, std::process::id()));
+    fs::write(&path, source).expect("failed to write test program");
+    
+    let output = Command::new(env!("CARGO_BIN_EXE_edustc"))
+        .args(options)
+        .arg(&path)
+        .args(args)
+        .output()
+        .expect("failed to run edustc");
+    
+    let _ = fs::remove_file(&path);
+    output
+}
+
+#[test]
+fn test_failing_assert_aborts() {
+    let source = "func main() {\n    let x = 4;\n    assert(x == 5);\n    return 0;\n}\n";
+    
+    let output = run_program("assert", source);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    
+    assert!(!output.status.success());
+    assert!(stderr.contains("assertion failed at line 3"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_failing_assert_eq_shows_values() {
+    let source = "func main() {\n    let x = 4;\n    assert_eq(x, 4);\n    assert_eq(x + 1, 0 - 2);\n    return 0;\n}\n";
+    
+    let output = run_program("assert_eq", source);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    
+    assert!(!output.status.success());
+    assert!(stderr.contains("assertion failed: 5 != -2 at line 4"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_closure_arity_mismatch_aborts() {
+    let source = "func main() {\n    let add = |a, b| a + b;\n    return add(1);\n}\n";
+    
+    let output = run_program("closure_arity", source);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("closure called with the wrong number of arguments at line 3"),
+        "stderr: {}",
+        stderr
+    );
+}
+
+#[test]
+fn test_calling_a_non_closure_aborts() {
+    let source = "func apply(g) {\n    return g(1);\n}\nfunc main() {\n    return apply(5);\n}\n";
+    
+    let output = run_program("not_a_closure", source);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    
+    assert!(!output.status.success());
+    assert!(stderr.contains("called a value that is not a closure at line 2"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_exit_sets_status() {
+    let source = r#"
+        func main() {
+            print(1);
+            exit(7);
+            print(2);
+            return 0;
+        }
+    "#;
+    
+    let output = run_program("exit", source);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    
+    assert_eq!(output.status.code(), Some(7));
+    assert_eq!(stdout, "1\n");
+}
+
+#[test]
+fn test_panic_prints_message_and_aborts() {
+    let source = r#"
+        func main() {
+            print(1);
+            panic("bad state");
+            print(2);
+        }
+    "#;
+    
+    let output = run_program("panic", source);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    
+    assert!(!output.status.success());
+    assert_eq!(stdout, "1\n");
+    assert!(stderr.contains("panic: bad state"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_todo_aborts_with_line() {
+    let source = "func helper() {\n    return 1 + todo();\n}\n\nfunc main() {\n    print(1);\n    unimplemented();\n}\n";
+    
+    let output = run_program("todo", source);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    
+    assert!(!output.status.success());
+    assert_eq!(stdout, "1\n");
+    assert!(stderr.contains("not yet implemented at line 7"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_exit_in_nested_expression() {
+    let source = r#"
+        func main() {
+            let x = 1 + exit(3);
+            return x;
+        }
+    "#;
+    
+    let output = run_program("exit_nested", source);
+    assert_eq!(output.status.code(), Some(3));
+}
+
+#[test]
+fn test_main_receives_argc() {
+    let source = "func main(argc) { return argc; }";
+    
+    let output = run_program_with_args("argc", source, &["a", "b"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    
+    // The source file counts as the first argument
+    assert!(stdout.contains("Program exited with code: 3"), "stdout: {}", stdout);
+}
+
+#[test]
+fn test_recursion_limit() {
+    let source = r#"
+        func forever(n) {
+            return forever(n + 1) + 1;
+        }
+        
+        func main() {
+            return forever(0);
+        }
+    "#;
+    
+    let output = run_program_with_options("recursion", source, &["--recursion-limit", "1000"], &[]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    
+    assert!(!output.status.success());
+    assert!(stderr.contains("maximum recursion depth exceeded"), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_recursion_within_limit() {
+    let source = r#"
+        func depth(n) {
+            if n == 0 {
+                return 0;
+            }
+            return depth(n - 1) + 1;
+        }
+        
+        func main(argc) {
+            return depth(98) + argc;
+        }
+    "#;
+    
+    // main plus 99 calls to depth is exactly at the limit
+    let output = run_program_with_options("recursion_ok", source, &["--recursion-limit", "100"], &["x"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    
+    assert_eq!(output.status.code(), Some(100));
+    assert!(stdout.contains("Program exited with code: 100"), "stdout: {}", stdout);
+}
+
+#[test]
+fn test_time_reports_phases() {
+    let source = "func main() {\n    print(5);\n    return 0;\n}\n";
+    
+    let output = run_program_with_options("time", source, &["--time"], &[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    
+    assert!(output.status.success());
+    assert!(stdout.starts_with("5\n"), "stdout: {}", stdout);
+    for phase in ["lex:", "parse:", "analyze:", "codegen:"] {
+        assert!(stderr.contains(phase), "stderr: {}", stderr);
+    }
+}
+
+#[test]
+fn test_emit_cfg_prints_dot() {
+    let source = "func main() {\n    let x = 1;\n    print(x);\n    if x > 0 {\n        return 1;\n    }\n    return 0;\n}\n";
+    
+    let output = run_program_with_options("emit_cfg", source, &["--emit-cfg"], &[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    
+    // The program is compiled but not run
+    assert!(output.status.success());
+    assert!(stdout.starts_with("digraph cfg {\n"), "stdout: {}", stdout);
+    assert!(stdout.contains("\"main.block0\" -> \"main.block1\" [label=\"true\"];"), "stdout: {}", stdout);
+    assert!(!stdout.contains("Program exited"), "stdout: {}", stdout);
+}
+
+#[test]
+fn test_diagnostics_json() {
+    let source = "func main() {\n    let x = 1;\n    x = x;\n    x = \"s\";\n    return x;\n}\n";
+    
+    let output = run_program_with_options("diagnostics_json", source, &["--diagnostics-json"], &[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let diagnostics: serde_json::Value = serde_json::from_str(&stdout).expect("stdout is JSON");
+    
+    // Errors make the check fail, and the program doesn't run
+    assert_eq!(output.status.code(), Some(1));
+    let expected = serde_json::json!([
+        {
+            "severity": "warning",
+            "message": "self-assignment has no effect",
+            "line": 3,
+            "column": 5
+        },
+        {
+            "severity": "error",
+            "message": "Semantic error: Type mismatch: cannot assign string to x, which holds int",
+            "line": 4,
+            "column": 5
+        }
+    ]);
+    assert_eq!(diagnostics, expected);
+    
+    // Errors from within expressions point at the expression
+    let source = "func main() {\n    return 1 + y;\n}\n";
+    let output = run_program_with_options("diagnostics_json_undefined", source, &["--diagnostics-json"], &[]);
+    let diagnostics: serde_json::Value =
+        serde_json::from_str(&String::from_utf8_lossy(&output.stdout)).expect("stdout is JSON");
+    let expected = serde_json::json!([
+        {
+            "severity": "error",
+            "message": "Semantic error: Undefined variable: y",
+            "line": 2,
+            "column": 16
+        }
+    ]);
+    assert_eq!(diagnostics, expected);
+    
+    // A clean program has none
+    let source = "func main() { return 0; }";
+    let output = run_program_with_options("diagnostics_json_clean", source, &["--diagnostics-json"], &[]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "[]\n");
+}
+
+#[test]
+fn test_main_return_value_is_exit_status() {
+    let source = r#"
+        func main() {
+            return 3;
+        }
+    "#;
+    
+    let output = run_program("status", source);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    
+    assert_eq!(output.status.code(), Some(3));
+    assert!(stdout.contains("Program exited with code: 3"), "stdout: {}", stdout);
+    
+    // Like a shell, only the low 8 bits survive
+    let source = r#"
+        func main() {
+            return 256 + 42;
+        }
+    "#;
+    
+    let output = run_program("status_wrapped", source);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    
+    assert_eq!(output.status.code(), Some(42));
+    assert!(stdout.contains("Program exited with code: 298"), "stdout: {}", stdout);
+}
+
+#[test]
+fn test_compile_error_names_file_line_and_column() {
+    let source = "func main() {\n    let x = 1\n    return x;\n}\n";
+    let output = run_program("syntax_error", source);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    
+    assert_eq!(output.status.code(), Some(1));
+    
+    // `file:line:col: error: message`, with the file as given on the command line
+    let (file, rest) = stderr.split_once(".edust:").expect("no file name");
+    assert!(file.contains("edust_cli_syntax_error_"), "stderr: {}", stderr);
+    let mut parts = rest.splitn(3, ':');
+    let line = parts.next().unwrap();
+    let column = parts.next().unwrap();
+    assert_eq!((line, column), ("3", "5"), "stderr: {}", stderr);
+    assert!(parts.next().unwrap().starts_with(" error: Parser error: "), "stderr: {}", stderr);
+}
+
+#[test]
+fn test_source_from_stdin() {
+    let source = r#"
+        func main(argc) {
+            print(argc);
+            return 5;
+        }
+    "#;
+    
+    let mut child = Command::new(env!("CARGO_BIN_EXE_edustc"))
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run edustc");
+    child.stdin.take().unwrap().write_all(source.as_bytes()).unwrap();
+    
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    
+    assert_eq!(output.status.code(), Some(5));
+    assert!(stdout.starts_with("1\n"), "stdout: {}", stdout);
+    assert!(stdout.contains("Program exited with code: 5"), "stdout: {}", stdout);
+}
+
+#[test]
+fn test_read_int_or() {
+    let source = r#"
+        func main() {
+            let a = read_int_or(-1);
+            let b = read_int_or(-2);
+            let c = read_int_or(-3);
+            print(a, b, c);
+            return 0;
+        }
+    "#;
+    let path: PathBuf = std::env::temp_dir().join(format!("edust_cli_read_{}.edust", std::process::id()));
+    fs::write(&path, source).unwrap();
+    
+    let run_with_input = |input: &str| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_edustc"))
+            .arg(&path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("failed to run edustc");
+        child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+        let output = child.wait_with_output().unwrap();
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    
+    // Empty stdin: every read falls back to its default
+    let stdout = run_with_input("");
+    assert!(stdout.starts_with("-1 -2 -3\n"), "stdout: {}", stdout);
+    
+    // A line that isn't a number also gives the default
+    let stdout = run_with_input(" 42 \nforty\n");
+    assert!(stdout.starts_with("42 -2 -3\n"), "stdout: {}", stdout);
+    
+    let _ = fs::remove_file(&path);
+}